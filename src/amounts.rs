@@ -0,0 +1,187 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::thai::WORD_BREAK;
+
+// Documents whose total is scored below this are flagged for manual review.
+pub const REVIEW_THRESHOLD: f64 = 0.6;
+// Thai VAT rate, used to cross-check VAT against the net amount.
+const VAT_RATE: f64 = 0.07;
+const TOLERANCE: f64 = 0.011;
+
+const NUMBER: &str = r"([0-9]{1,3}(?:,[0-9]{3})+(?:\.[0-9]{1,2})?|[0-9]+(?:\.[0-9]{1,2})?)";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Field {
+    Total,
+    Vat,
+    Net,
+}
+
+// A labelled amount pattern. `strength` is the base confidence of a match: specific
+// labels such as "รวมทั้งสิ้น" are worth more than a bare "รวม".
+#[derive(Clone)]
+pub struct Rule {
+    pub field: Field,
+    pub label: Regex,
+    pub strength: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Amount {
+    pub value: f64,
+    pub confidence: f64,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Extraction {
+    pub total: Option<Amount>,
+    pub vat: Option<Amount>,
+    pub net: Option<Amount>,
+    pub needs_review: bool,
+}
+
+impl Extraction {
+    pub fn confidence(&self) -> f64 {
+        self.total.as_ref().map(|a| a.confidence).unwrap_or(0.0)
+    }
+}
+
+pub fn default_rules() -> Vec<Rule> {
+    let rule = |field, label: &str, strength| Rule {
+        field,
+        label: Regex::new(label).expect("built-in amount rule"),
+        strength,
+    };
+    vec![
+        rule(Field::Total, r"(?i)(รวมทั้งสิ้น|จำนวนเงินรวมทั้งสิ้น|grand\s*total|total\s*amount)", 0.7),
+        rule(Field::Total, r"(?i)(จำนวนเงินรวม|ยอดรวม|ยอดชำระ|รวมเงิน|amount\s*due)", 0.5),
+        rule(Field::Vat, r"(?i)(ภาษีมูลค่าเพิ่ม|vat)(\s*7\s*%)?", 0.6),
+        rule(Field::Net, r"(?i)(มูลค่าสินค้า(และ|/)?บริการ|มูลค่าก่อนภาษี|ราคาก่อนภาษี|ยอดก่อนภาษี|sub\s*total|before\s*vat)", 0.6),
+    ]
+}
+
+#[derive(Clone)]
+pub struct Extractor {
+    rules: Vec<(Rule, Regex)>,
+}
+
+impl Extractor {
+    pub fn new(rules: Vec<Rule>) -> Extractor {
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                // The amount must follow its label on the same line, after at most a few
+                // separator characters (":", "บาท", spaces, ...).
+                let full = Regex::new(&format!(r"(?:{})[^0-9\n]{{0,40}}?{}", rule.label.as_str(), NUMBER)).expect("label regex is valid");
+                (rule, full)
+            })
+            .collect();
+        Extractor { rules }
+    }
+
+    // Find the total, VAT and net amounts in `text` and score each one. `reference`
+    // is an amount from structured data (the search API, XML) to cross-check against.
+    pub fn extract(&self, text: &str, reference: Option<f64>) -> Extraction {
+        let text: String = text.chars().filter(|c| *c != WORD_BREAK).collect();
+        let mut extraction = Extraction {
+            total: self.best(&text, Field::Total),
+            vat: self.best(&text, Field::Vat),
+            net: self.best(&text, Field::Net),
+            needs_review: false,
+        };
+        cross_check(&mut extraction, reference);
+        extraction.needs_review = extraction.confidence() < REVIEW_THRESHOLD;
+        extraction
+    }
+
+    fn best(&self, text: &str, field: Field) -> Option<Amount> {
+        let mut candidates: Vec<Amount> = Vec::new();
+        for (rule, regex) in self.rules.iter().filter(|(rule, _)| rule.field == field) {
+            for captures in regex.captures_iter(text) {
+                let whole = captures.get(0).map(|m| m.as_str()).unwrap_or_default();
+                let raw = captures.get(captures.len() - 1).map(|m| m.as_str()).unwrap_or_default();
+                let label = whole.strip_suffix(raw).unwrap_or(whole);
+                let Ok(value) = raw.replace(',', "").parse::<f64>() else {
+                    continue;
+                };
+                let mut amount = Amount { value, confidence: rule.strength, reasons: vec![format!("labelled \"{}\"", label.trim())] };
+                if well_formed(raw) {
+                    amount.confidence += 0.1;
+                    amount.reasons.push("two decimals".to_string());
+                }
+                candidates.push(amount);
+            }
+        }
+
+        let mut best = candidates
+            .iter()
+            .max_by(|a, b| a.confidence.total_cmp(&b.confidence))?
+            .clone();
+        let conflicting = candidates
+            .iter()
+            .filter(|c| c.confidence >= best.confidence - 0.2 && (c.value - best.value).abs() > TOLERANCE)
+            .count();
+        if conflicting > 0 {
+            best.confidence -= 0.2;
+            best.reasons.push(format!("{} conflicting candidate(s)", conflicting));
+        }
+        Some(best)
+    }
+}
+
+impl Default for Extractor {
+    fn default() -> Self {
+        Extractor::new(default_rules())
+    }
+}
+
+// "1,234.50" or "45.00": written the way amounts are printed on invoices.
+fn well_formed(raw: &str) -> bool {
+    raw.split_once('.').is_some_and(|(_, decimals)| decimals.len() == 2)
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() <= TOLERANCE.max(b.abs() * 0.0001)
+}
+
+// Amounts that agree with each other (net + VAT = total, VAT = 7% of net) or with
+// structured data are much more likely to be right; disagreement is penalized.
+fn cross_check(extraction: &mut Extraction, reference: Option<f64>) {
+    if let (Some(total), Some(vat), Some(net)) = (&extraction.total, &extraction.vat, &extraction.net) {
+        let consistent = close(net.value + vat.value, total.value);
+        for amount in [&mut extraction.total, &mut extraction.vat, &mut extraction.net].into_iter().flatten() {
+            if consistent {
+                amount.confidence += 0.2;
+                amount.reasons.push("net + VAT = total".to_string());
+            } else {
+                amount.confidence -= 0.2;
+                amount.reasons.push("net + VAT does not add up to total".to_string());
+            }
+        }
+    }
+    if let (Some(vat), Some(net)) = (&extraction.vat, &extraction.net) {
+        if close(vat.value, (net.value * VAT_RATE * 100.0).round() / 100.0) {
+            let reason = "VAT is 7% of net".to_string();
+            for amount in [&mut extraction.vat, &mut extraction.net].into_iter().flatten() {
+                amount.confidence += 0.1;
+                amount.reasons.push(reason.clone());
+            }
+        }
+    }
+    if let (Some(total), Some(reference)) = (&mut extraction.total, reference) {
+        if close(total.value, reference) {
+            total.confidence += 0.2;
+            total.reasons.push("matches structured data".to_string());
+        } else {
+            total.confidence -= 0.3;
+            total.reasons.push(format!("structured data says {:.2}", reference));
+        }
+    }
+
+    for amount in [&mut extraction.total, &mut extraction.vat, &mut extraction.net].into_iter().flatten() {
+        amount.confidence = (amount.confidence.clamp(0.0, 1.0) * 100.0).round() / 100.0;
+    }
+}
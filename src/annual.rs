@@ -0,0 +1,223 @@
+use crate::api;
+use crate::config::Config;
+use crate::export::{self, Record};
+use crate::lang::Lang;
+use crate::merge::{self, Part};
+use crate::query::Row;
+use crate::report;
+use crate::store;
+use chrono::{Datelike, NaiveDate, Utc};
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::{DecodePrivateKey, EncodePublicKey, LineEnding};
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::RsaPrivateKey;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+// One year of documents as a single ZIP for an external auditor, made only of what is
+// on disk: the PDFs from the store, their details from the index or state file.
+pub struct YearOptions {
+    pub year: i32,
+    pub store: PathBuf,
+    pub output: PathBuf,
+    // PEM RSA private key (PKCS#8 or PKCS#1) to sign SHA256SUMS with.
+    pub sign_key: Option<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+pub struct YearSummary {
+    pub documents: usize,
+    pub months: usize,
+    // In the index or state but not in the store, so left out.
+    pub missing: Vec<String>,
+    // SHA-256 of the signing key's public key (DER), for the auditor to check.
+    pub signer: Option<String>,
+}
+
+struct PackageFile {
+    path: String,
+    size: u64,
+    sha256: String,
+}
+
+// The ZIP being written, with a checksum of every file put in.
+struct Package {
+    zip: ZipWriter<File>,
+    root: String,
+    files: Vec<PackageFile>,
+}
+
+impl Package {
+    fn add(&mut self, path: &str, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        self.zip.start_file(format!("{}/{}", self.root, path), SimpleFileOptions::default())?;
+        self.zip.write_all(data)?;
+        self.files.push(PackageFile { path: path.to_string(), size: data.len() as u64, sha256: format!("{:x}", Sha256::digest(data)) });
+        Ok(())
+    }
+}
+
+// `rows` are every document of the year, with what the store knows of them
+// attached. Per month the package has the PDFs, merged into one with a cover page,
+// a CSV register and a statement; for the year a register, a statement by month,
+// manifest.json and SHA256SUMS, signed with `sign_key` if given.
+pub fn write(rows: Vec<Row>, config: &Config, opts: &YearOptions) -> Result<YearSummary, Box<dyn std::error::Error>> {
+    let key = opts.sign_key.as_deref().map(read_key).transpose()?;
+    let mut summary = YearSummary::default();
+    let mut rows: Vec<Row> = rows.into_iter().filter(|row| row.stored.is_some() || {
+        summary.missing.push(format!("{}/{}", row.tax_id, row.doc_no));
+        false
+    }).collect();
+    for missing in &summary.missing {
+        warn!("{} is not in the store; left out of the package", missing);
+    }
+    if rows.is_empty() {
+        return Err(format!("No stored documents dated {}", opts.year).into());
+    }
+    rows.sort_by(|a, b| api::doc_date(&a.item).cmp(&api::doc_date(&b.item)).then_with(|| a.tax_id.cmp(&b.tax_id)).then_with(|| a.doc_no.cmp(&b.doc_no)));
+
+    let mut records = export::records(&rows, config);
+    let tmp = opts.output.with_extension("tmp");
+    let root = opts.output.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| format!("exat-etax-{}", opts.year));
+    let mut package = Package { zip: ZipWriter::new(File::create(&tmp)?), root, files: Vec::new() };
+
+    let mut months: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+    let mut documents = Vec::new();
+    let mut sources = Vec::new();
+    for (i, (row, record)) in rows.iter().zip(records.iter_mut()).enumerate() {
+        let version = row.stored.as_ref().expect("rows without a stored version were left out");
+        let source = opts.store.join(&version.path);
+        let data = std::fs::read(&source)?;
+        // The store's own record is the reference; a changed file has no place here.
+        let sha256 = format!("{:x}", Sha256::digest(&data));
+        if sha256 != version.sha256 {
+            return Err(format!("{} in the store does not match its checksum; run `exat-etax import` or restore a backup first", version.path.display()).into());
+        }
+        let month = record.doc_date.expect("the year's documents are selected by date").month();
+        let path = format!("{}/documents/{}/{}.pdf", month_dir(opts.year, month), store::sanitize(&row.tax_id), store::sanitize(&row.doc_no));
+        package.add(&path, &data)?;
+        documents.push(json!({
+            "taxId": row.tax_id,
+            "docNo": row.doc_no,
+            "docDate": record.doc_date,
+            "docType": record.doc_type,
+            "total": record.total,
+            "path": path,
+            "sha256": sha256,
+            "storedAt": version.stored_at,
+            "sourceArchive": version.source_archive,
+            "supersedes": version.supersedes,
+        }));
+        record.file_path = path;
+        sources.push(source);
+        months.entry(month).or_default().push(i);
+    }
+
+    for (month, indices) in &months {
+        let dir = month_dir(opts.year, *month);
+        let month_records: Vec<Record> = indices.iter().map(|i| records[*i].clone()).collect();
+        let month_sources: Vec<&Path> = indices.iter().map(|i| sources[*i].as_path()).collect();
+        package.add(&format!("{}/register.csv", dir), &csv(&month_records)?)?;
+        let first = NaiveDate::from_ymd_opt(opts.year, *month, 1).expect("a month of the year");
+        package.add(&format!("{}/statement.pdf", dir), &report::render(&report::statement(&month_records, first, Lang::En), report::Format::Pdf)?)?;
+        package.add(&format!("{}/documents.pdf", dir), &merged(&month_records, &month_sources, &dir)?)?;
+    }
+    package.add("register.csv", &csv(&records)?)?;
+    package.add("statement.pdf", &report::render(&report::combined(&records, Lang::En), report::Format::Pdf)?)?;
+    package.add("README.txt", readme(opts.year, records.len(), key.is_some()).as_bytes())?;
+
+    let manifest = json!({
+        "generator": concat!("exat-etax ", env!("CARGO_PKG_VERSION")),
+        "year": opts.year,
+        "createdAt": Utc::now(),
+        "taxIds": rows.iter().map(|r| r.tax_id.as_str()).collect::<BTreeSet<_>>(),
+        "documents": documents,
+        "missing": summary.missing,
+        "files": package.files.iter().map(|f| json!({ "path": f.path, "size": f.size, "sha256": f.sha256 })).collect::<Vec<_>>(),
+    });
+    package.add("manifest.json", serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    let checksums: String = package.files.iter().map(|f| format!("{}  {}\n", f.sha256, f.path)).collect();
+    package.add("SHA256SUMS", checksums.as_bytes())?;
+    if let Some(key) = &key {
+        let signature = rsa::pkcs1v15::SigningKey::<Sha256>::new(key.clone()).sign(checksums.as_bytes());
+        let public = key.to_public_key().to_public_key_der()?;
+        package.add("SHA256SUMS.sig", &signature.to_vec())?;
+        package.add("signing-key.pem", key.to_public_key().to_public_key_pem(LineEnding::LF)?.as_bytes())?;
+        summary.signer = Some(format!("{:x}", Sha256::digest(public.as_bytes())));
+    }
+
+    package.zip.finish()?;
+    std::fs::rename(&tmp, &opts.output)?;
+    summary.documents = records.len();
+    summary.months = months.len();
+    info!("Wrote {} document(s) of {} to {}", summary.documents, opts.year, opts.output.display());
+    Ok(summary)
+}
+
+fn month_dir(year: i32, month: u32) -> String {
+    format!("{}-{:02}", year, month)
+}
+
+fn csv(records: &[Record]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut out = Vec::new();
+    export::write_csv(records, &mut out)?;
+    Ok(out)
+}
+
+// The month's PDFs in register order, after a cover page listing them.
+fn merged(records: &[Record], sources: &[&Path], dir: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut lines = vec![format!("EXAT e-Tax documents, {}", dir), String::new()];
+    let mut parts = Vec::new();
+    let mut total = 0.0;
+    for (record, source) in records.iter().zip(sources) {
+        let date = record.doc_date.map(|d| d.to_string()).unwrap_or_default();
+        match Part::new(format!("{} {}", date, record.doc_no), &std::fs::read(source)?) {
+            Ok(part) => parts.push(part),
+            Err(e) => {
+                warn!("{} is not a readable PDF ({}); only in {}", record.doc_no, e, record.file_path);
+                continue;
+            }
+        }
+        total += record.total.unwrap_or(0.0);
+        lines.push(format!("{}   {}   {}   {}   {}", date, record.tax_id, record.doc_no, record.doc_type, record.total.map(|t| format!("{:.2}", t)).unwrap_or_default()));
+    }
+    lines.push(String::new());
+    lines.push(format!("{} document(s), {:.2} THB", parts.len(), total));
+    merge::merge(parts, Some(&lines))
+}
+
+fn readme(year: i32, documents: usize, signed: bool) -> String {
+    let mut text = format!(
+        "EXAT e-Tax documents {year}\n\
+         \n\
+         {documents} document(s), assembled by exat-etax from its document store.\n\
+         \n\
+         register.csv       every document of the year, with its amounts and the path of its PDF here\n\
+         statement.pdf      totals by month and company\n\
+         {year}-MM/           per month: documents/<taxId>/<docNo>.pdf, documents.pdf (all of\n\
+         \x20                  them in one file, after a list), register.csv and statement.pdf\n\
+         manifest.json      every document and file, with its SHA-256\n\
+         SHA256SUMS         check with: sha256sum -c SHA256SUMS\n"
+    );
+    if signed {
+        text.push_str(
+            "SHA256SUMS.sig     RSA signature of SHA256SUMS; check with:\n\
+             \x20                  openssl dgst -sha256 -verify signing-key.pem -signature SHA256SUMS.sig SHA256SUMS\n\
+             signing-key.pem    public key of the signer; compare its fingerprint with the one you were given\n",
+        );
+    }
+    text
+}
+
+fn read_key(path: &Path) -> Result<RsaPrivateKey, Box<dyn std::error::Error>> {
+    let pem = std::fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    RsaPrivateKey::from_pkcs8_pem(&pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&pem))
+        .map_err(|_| format!("{} is not an unencrypted PEM RSA private key", path.display()).into())
+}
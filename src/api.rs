@@ -0,0 +1,673 @@
+use crate::cache;
+use crate::dates;
+use crate::interrupt::{self, Interrupted};
+use crate::lang::{self, Lang};
+use crate::logging;
+use crate::metrics;
+use crate::recording;
+use crate::throttle;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, info, trace, warn};
+
+const API_URL_SEARCH: &str = "https://etax.exat.co.th/backend/api/search/reprint";
+const API_URL_DOWNLOAD: &str = "https://etax.exat.co.th/backend/api/download/zipFiles";
+
+// Safety net against a server that keeps reporting more pages.
+const MAX_PAGES: u64 = 1000;
+
+// `--wait-for-service`: how long to keep polling a backend that is down.
+static WAIT_FOR_SERVICE: OnceLock<Duration> = OnceLock::new();
+const FIRST_POLL: Duration = Duration::from_secs(15);
+const MAX_POLL: Duration = Duration::from_secs(300);
+
+// The backend answering that it cannot serve the request, often with HTTP 200.
+pub enum ServiceError {
+    // `{"status": "ERROR", "message": ...}` instead of a result.
+    Api { message: String },
+    // An HTML page, with its title if it has one, as during maintenance.
+    Maintenance { title: Option<String> },
+    // HTTP 502, 503 or 504 to a search or download.
+    Unavailable { request: &'static str, status: reqwest::StatusCode },
+}
+
+impl std::fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self, lang::lang()) {
+            (ServiceError::Api { message }, Lang::En) => write!(f, "EXAT e-Tax returned an error: {}", message),
+            (ServiceError::Api { message }, Lang::Th) => write!(f, "EXAT e-Tax แจ้งข้อผิดพลาด: {}", message),
+            (ServiceError::Maintenance { title: Some(title) }, Lang::En) => write!(f, "EXAT e-Tax is unavailable, it answered with a page titled {:?}", title),
+            (ServiceError::Maintenance { title: Some(title) }, Lang::Th) => write!(f, "EXAT e-Tax ไม่พร้อมให้บริการ ได้รับหน้าเว็บชื่อ {:?}", title),
+            (ServiceError::Maintenance { title: None }, Lang::En) => write!(f, "EXAT e-Tax is unavailable, it answered with an HTML page"),
+            (ServiceError::Maintenance { title: None }, Lang::Th) => write!(f, "EXAT e-Tax ไม่พร้อมให้บริการ ได้รับหน้าเว็บ HTML"),
+            (ServiceError::Unavailable { request, status }, Lang::En) => write!(f, "{} failed: HTTP {}", request, status),
+            (ServiceError::Unavailable { request, status }, Lang::Th) => write!(f, "{} ไม่สำเร็จ: HTTP {}", request, status),
+        }
+    }
+}
+
+// As the message, like the string errors `main` returns and prints with Debug.
+impl std::fmt::Debug for ServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+pub fn set_wait_for_service(max_wait: Duration) {
+    let _ = WAIT_FOR_SERVICE.set(max_wait);
+}
+
+// The error a response body stands for, if it is an error envelope or an HTML page
+// rather than what was asked for.
+fn service_error(body: &[u8]) -> Option<ServiceError> {
+    let text = String::from_utf8_lossy(body.get(..body.len().min(64 * 1024))?);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    if text.starts_with('<') {
+        return Some(ServiceError::Maintenance { title: html_title(text) });
+    }
+    let json: Value = serde_json::from_slice(body).ok()?;
+    if !json["status"].as_str().is_some_and(|s| s.eq_ignore_ascii_case("ERROR")) {
+        return None;
+    }
+    let message = match &json["message"] {
+        Value::String(s) if !s.trim().is_empty() => s.trim().to_string(),
+        Value::Null => "no message".to_string(),
+        other => other.to_string(),
+    };
+    Some(ServiceError::Api { message })
+}
+
+fn html_title(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let start = lower.find("<title")?;
+    let start = start + lower[start..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let title = html[start..end].split_whitespace().collect::<Vec<_>>().join(" ");
+    (!title.is_empty()).then(|| title.chars().take(200).collect())
+}
+
+fn http_error(request: &'static str, status: reqwest::StatusCode) -> Box<dyn std::error::Error> {
+    match status {
+        reqwest::StatusCode::BAD_GATEWAY | reqwest::StatusCode::SERVICE_UNAVAILABLE | reqwest::StatusCode::GATEWAY_TIMEOUT => ServiceError::Unavailable { request, status }.into(),
+        _ => format!("{} failed: HTTP {}", request, status).into(),
+    }
+}
+
+// Worth waiting out: a maintenance page, 502/503/504, or no connection at all. An
+// error envelope is an answer (a bad tax ID, say) and stays one however long we wait.
+fn transient(e: &(dyn std::error::Error + 'static)) -> bool {
+    match e.downcast_ref::<ServiceError>() {
+        Some(ServiceError::Api { .. }) => false,
+        Some(ServiceError::Maintenance { .. } | ServiceError::Unavailable { .. }) => true,
+        None => e.downcast_ref::<reqwest::Error>().is_some_and(|e| e.is_connect() || e.is_timeout()),
+    }
+}
+
+// Run `request` again while the backend or the network is down, with growing
+// pauses, until it answers or `--wait-for-service` has passed. Without that option
+// the first error is returned.
+async fn waiting_for_service<T, F, Fut>(request: F) -> Result<T, Box<dyn std::error::Error>>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = Result<T, Box<dyn std::error::Error>>>,
+{
+    let started = Instant::now();
+    let mut delay = FIRST_POLL;
+    loop {
+        // A block, so that the error is not held across the pause and the future
+        // stays Send.
+        let pause = {
+            let (e, max_wait) = match (request().await, WAIT_FOR_SERVICE.get()) {
+                (Err(e), Some(max_wait)) if transient(e.as_ref()) => (e, *max_wait),
+                (result, _) => return result,
+            };
+            let left = max_wait.saturating_sub(started.elapsed());
+            if left.is_zero() {
+                warn!("Gave up waiting for EXAT e-Tax after {}", humantime::format_duration(max_wait));
+                return Err(e);
+            }
+            // Counted as an error once, by the caller, if it never comes back.
+            metrics::retry();
+            let pause = delay.min(left);
+            warn!("{}; trying again in {}", e, humantime::format_duration(Duration::from_secs(pause.as_secs().max(1))));
+            pause
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(pause) => {}
+            _ = interrupt::requested() => return Err(Interrupted.into()),
+        }
+        delay = (delay * 2).min(MAX_POLL);
+    }
+}
+
+// The EXAT e-Tax backend as the rest of the program sees it. `HttpApi` talks to the
+// real service; tests substitute canned responses.
+pub trait EtaxApi {
+    // One search page as the raw response body. `extra` carries pagination parameters
+    // for pages after the first.
+    async fn fetch_tax_documents(&self, tax_id: &str, doc_date_from: &str, doc_date_to: &str, extra: &[(String, String)]) -> Result<String, Box<dyn std::error::Error>>;
+
+    // With `part`, the body is also written to that file as it arrives, so an aborted
+    // download leaves a recognizable partial file rather than a truncated ZIP.
+    async fn download_zip(&self, listfile_json: &str, part: Option<&Path>) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+}
+
+// Requests go through the shared rate limiter and are recorded or replayed when
+// `--record`/`--replay` is in effect.
+pub struct HttpApi {
+    client: Client,
+}
+
+impl HttpApi {
+    pub fn new() -> Result<HttpApi, reqwest::Error> {
+        Ok(HttpApi { client: Client::builder().build()? })
+    }
+}
+
+impl EtaxApi for HttpApi {
+    async fn fetch_tax_documents(&self, tax_id: &str, doc_date_from: &str, doc_date_to: &str, extra: &[(String, String)]) -> Result<String, Box<dyn std::error::Error>> {
+        let request = json!({ "taxId": tax_id, "docDateFrom": doc_date_from, "docDateTo": doc_date_to, "extra": extra });
+        if let Some(body) = recording::replay("search", &request, "json") {
+            return Ok(String::from_utf8_lossy(&body?).into_owned());
+        }
+
+        let mut params = std::collections::HashMap::new();
+        params.insert("taxId", tax_id);
+        params.insert("docDateFrom", doc_date_from);
+        params.insert("docDateTo", doc_date_to);
+        params.insert("smartCardNo", "null");
+        for (key, value) in extra {
+            params.insert(key, value);
+        }
+
+        debug!(url = API_URL_SEARCH, tax_id = %logging::mask(tax_id), doc_date_from, doc_date_to, ?extra, "POST search");
+        let started = Instant::now();
+        let response = throttle::send(|| self.client.post(API_URL_SEARCH).form(&params)).await?;
+        log_response(&response, started);
+        if !response.status().is_success() {
+            return Err(http_error("Search", response.status()));
+        }
+
+        let body = response.text().await?;
+        trace!(body = %body, "Search response body");
+        recording::record("search", &request, "json", body.as_bytes())?;
+        Ok(body)
+    }
+
+    async fn download_zip(&self, listfile_json: &str, part: Option<&Path>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let request = json!({ "listfile": listfile_json, "type": "PDF" });
+        if let Some(content) = recording::replay("download", &request, "zip") {
+            let content = content?;
+            if let Some(path) = part {
+                tokio::fs::write(path, &content).await?;
+            }
+            return Ok(content);
+        }
+
+        let form = || {
+            reqwest::multipart::Form::new()
+                .text("listfile", listfile_json.to_string())
+                .text("type", "PDF")
+        };
+
+        debug!(url = API_URL_DOWNLOAD, bytes = listfile_json.len(), "POST download");
+        let started = Instant::now();
+        let mut response = throttle::send(|| self.client.post(API_URL_DOWNLOAD).multipart(form())).await?;
+        log_response(&response, started);
+        if !response.status().is_success() {
+            return Err(http_error("Download", response.status()));
+        }
+
+        let mut file = match part {
+            Some(path) => Some(tokio::fs::File::create(path).await?),
+            None => None,
+        };
+        let mut content = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if let Some(file) = &mut file {
+                file.write_all(&chunk).await?;
+            }
+            content.extend_from_slice(&chunk);
+            throttle::received(chunk.len()).await;
+        }
+        if let Some(file) = &mut file {
+            file.flush().await?;
+        }
+        debug!(bytes = content.len(), elapsed_ms = started.elapsed().as_millis() as u64, "Download complete");
+        // An error page in place of the ZIP.
+        if !content.starts_with(b"PK") {
+            if let Some(e) = service_error(&content) {
+                return Err(e.into());
+            }
+        }
+        recording::record("download", &request, "zip", &content)?;
+
+        Ok(content)
+    }
+}
+
+pub async fn download_zip(api: &impl EtaxApi, listfile_json: &str, part: Option<&Path>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let result = waiting_for_service(|| api.download_zip(listfile_json, part)).await;
+    match &result {
+        Ok(content) => metrics::add_bytes(content.len()),
+        Err(_) => metrics::api_error(),
+    }
+    result
+}
+
+fn log_response(response: &reqwest::Response, started: Instant) {
+    debug!(status = %response.status(), elapsed_ms = started.elapsed().as_millis() as u64, "Response received");
+    for (name, value) in response.headers() {
+        let value = value.to_str().unwrap_or("<binary>");
+        trace!(header = %name, value = %logging::sanitize_header(name.as_str(), value));
+    }
+}
+
+// Pagination metadata of a search response, under whichever names the server used so
+// the same names can be sent back for the next page.
+#[derive(Debug, Default)]
+struct Pagination {
+    total: Option<u64>,
+    total_pages: Option<u64>,
+    page: Option<(String, u64)>,
+    size: Option<(String, u64)>,
+}
+
+const TOTAL_KEYS: &[&str] = &["totalCount", "totalRecords", "totalElements", "totalItems", "recordsTotal", "total"];
+const TOTAL_PAGES_KEYS: &[&str] = &["totalPages", "totalPage", "pageCount"];
+const PAGE_KEYS: &[&str] = &["pageNo", "pageNumber", "page", "currentPage", "pageIndex"];
+const SIZE_KEYS: &[&str] = &["pageSize", "size", "limit", "perPage"];
+
+fn pagination(json: &Value) -> Pagination {
+    // Metadata sits next to the list or in a nested object.
+    let scopes: Vec<&Value> = std::iter::once(json)
+        .chain(["pagination", "paging", "meta", "page"].iter().map(|k| &json[*k]).filter(|v| v.is_object()))
+        .collect();
+    let find = |keys: &[&str]| -> Option<(String, u64)> {
+        scopes.iter().find_map(|scope| {
+            keys.iter().find_map(|key| {
+                let value = &scope[*key];
+                value.as_u64().or_else(|| value.as_str().and_then(|s| s.parse().ok())).map(|n| (key.to_string(), n))
+            })
+        })
+    };
+    Pagination {
+        total: find(TOTAL_KEYS).map(|(_, n)| n),
+        total_pages: find(TOTAL_PAGES_KEYS).map(|(_, n)| n),
+        page: find(PAGE_KEYS),
+        size: find(SIZE_KEYS),
+    }
+}
+
+fn parse_page(body: &str) -> Result<(Vec<Value>, Pagination), Box<dyn std::error::Error>> {
+    if let Some(e) = service_error(body.as_bytes()) {
+        return Err(e.into());
+    }
+    let json: Value = serde_json::from_str(body)?;
+    let items = json["reprintList"].as_array().ok_or("Search response has no reprintList")?.clone();
+    Ok((items, pagination(&json)))
+}
+
+// Search, answered from the cache when it is enabled and has a fresh result.
+pub async fn search(api: &impl EtaxApi, tax_id: &str, doc_date_from: &str, doc_date_to: &str) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let key = vec![API_URL_SEARCH.to_string(), tax_id.to_string(), doc_date_from.to_string(), doc_date_to.to_string(), "smartCardNo=null".to_string()];
+    if let Some(items) = cache::get(&key) {
+        info!("Using cached search result ({} document(s)); pass --no-cache to search again", items.len());
+        return Ok(items);
+    }
+    let items = search_live(api, tax_id, doc_date_from, doc_date_to).await?;
+    cache::put(&key, &items);
+    Ok(items)
+}
+
+pub async fn search_live(api: &impl EtaxApi, tax_id: &str, doc_date_from: &str, doc_date_to: &str) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    waiting_for_service(|| search_with(api, tax_id, doc_date_from, doc_date_to)).await.inspect_err(|_| metrics::api_error())
+}
+
+// Search and follow pagination until every reported document was collected. Warns
+// when the server's total doesn't match what came back.
+pub async fn search_with(api: &impl EtaxApi, tax_id: &str, doc_date_from: &str, doc_date_to: &str) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let body = api.fetch_tax_documents(tax_id, doc_date_from, doc_date_to, &[]).await?;
+    let (first, mut meta) = parse_page(&body)?;
+    let total = meta.total;
+    // Overlapping pages must not produce duplicates.
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut items: Vec<Value> = Vec::new();
+    let mut add = |page: Vec<Value>, items: &mut Vec<Value>| {
+        let before = items.len();
+        items.extend(page.into_iter().filter(|item| seen.insert(item.to_string())));
+        items.len() - before
+    };
+    add(first, &mut items);
+
+    let mut pages = 1;
+    while let Some((page_key, page)) = meta.page.clone() {
+        let next = page + 1;
+        let more = match (total, meta.total_pages) {
+            (Some(total), _) => (items.len() as u64) < total,
+            // Page numbers start at 0 or 1.
+            (None, Some(last)) if page == 0 => next < last,
+            (None, Some(last)) => next <= last,
+            (None, None) => false,
+        };
+        if !more || pages >= MAX_PAGES {
+            break;
+        }
+
+        let mut extra = vec![(page_key.clone(), next.to_string())];
+        if let Some((size_key, size)) = &meta.size {
+            extra.push((size_key.clone(), size.to_string()));
+        }
+        debug!(page = next, collected = items.len(), ?total, "Fetching next search page");
+        let body = api.fetch_tax_documents(tax_id, doc_date_from, doc_date_to, &extra).await?;
+        let (page_items, page_meta) = parse_page(&body)?;
+        pages += 1;
+        if add(page_items, &mut items) == 0 {
+            // The server ignored the page parameter or ran out early.
+            break;
+        }
+        meta = Pagination { page: page_meta.page.or(Some((page_key, next))), ..page_meta };
+    }
+
+    if let Some(total) = total {
+        if total != items.len() as u64 {
+            warn!(
+                "Search reported {} document(s) but {} were returned after {} page(s); the result is incomplete. Narrow the date range to get everything",
+                total,
+                items.len(),
+                pages
+            );
+        } else if pages > 1 {
+            debug!(pages, total, "Collected every page");
+        }
+    }
+    Ok(items)
+}
+
+pub fn build_listfile(items: &[Value]) -> Result<String, serde_json::Error> {
+    let listfile: Vec<_> = items.iter().map(|item| {
+        json!({
+            "invoiceHdr_id": item["invoiceHdrId"],
+            "docNo": item["docNo"],
+            "fileType": item["fileType"],
+            "filePathPDF": item["filePath"],
+            "fileNamePDF": item["fileName"],
+            "docType": item["docType"]
+        })
+    }).collect();
+
+    serde_json::to_string(&listfile)
+}
+
+// The API returns docNo as a JSON string; fall back to its raw JSON form so odd
+// payloads still produce a stable key.
+pub fn doc_no(item: &Value) -> String {
+    match item["docNo"].as_str() {
+        Some(s) => s.to_string(),
+        None => item["docNo"].to_string(),
+    }
+}
+
+// The reprint list doesn't document an amount field; accept the names seen in
+// EXAT payloads, as numbers or numeric strings.
+pub fn amount(item: &Value) -> Option<f64> {
+    ["totalAmount", "amount", "grandTotal", "netAmount"]
+        .iter()
+        .map(|key| &item[*key])
+        .find_map(|value| match value {
+            Value::Number(n) => n.as_f64(),
+            Value::String(s) => s.replace(',', "").trim().parse().ok(),
+            _ => None,
+        })
+}
+
+// docDate has been seen both as a formatted string and as epoch milliseconds.
+pub fn doc_date(item: &Value) -> Option<NaiveDate> {
+    date_value(&item["docDate"])
+}
+
+fn date_value(value: &Value) -> Option<NaiveDate> {
+    match value {
+        Value::Number(n) => {
+            let millis = n.as_i64()?;
+            Some(DateTime::from_timestamp_millis(millis)?.with_timezone(&dates::timezone()).date_naive())
+        }
+        Value::String(s) => {
+            let s = s.trim();
+            if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+                return Some(datetime.with_timezone(&dates::timezone()).date_naive());
+            }
+            ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%d/%m/%Y %H:%M:%S", "%d/%m/%Y %H:%M"]
+                .iter()
+                .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok().map(|dt| dt.date()))
+                .or_else(|| ["%Y-%m-%d", "%d/%m/%Y"].iter().find_map(|f| NaiveDate::parse_from_str(s, f).ok()))
+        }
+        _ => None,
+    }
+}
+
+// A document another one refers to, with its date when the referring one gives it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reference {
+    pub doc_no: String,
+    #[serde(default)]
+    pub doc_date: Option<NaiveDate>,
+}
+
+// The documents an item refers to, such as the invoice a credit or debit note
+// corrects. Like amounts, this isn't documented for the reprint list; accept the
+// names seen, each a docNo or a list of them, and `references` as written by us.
+pub fn references(item: &Value) -> Vec<Reference> {
+    let own = doc_no(item);
+    let mut found: Vec<Reference> = Vec::new();
+    let mut add = |doc_no: &str, doc_date: Option<NaiveDate>| {
+        let doc_no = doc_no.trim();
+        if doc_no.is_empty() || doc_no == own || found.iter().any(|r| r.doc_no == doc_no) {
+            return;
+        }
+        found.push(Reference { doc_no: doc_no.to_string(), doc_date });
+    };
+    for (key, date_key) in [("refDocNo", "refDocDate"), ("referenceDocNo", "referenceDocDate"), ("originalDocNo", "originalDocDate"), ("refInvoiceNo", "refInvoiceDate")] {
+        let date = date_value(&item[date_key]);
+        match &item[key] {
+            Value::String(s) => s.split([',', ';']).for_each(|doc_no| add(doc_no, date)),
+            Value::Array(values) => values.iter().filter_map(Value::as_str).for_each(|doc_no| add(doc_no, date)),
+            _ => {}
+        }
+    }
+    for reference in item["references"].as_array().into_iter().flatten() {
+        if let Some(doc_no) = reference["docNo"].as_str() {
+            add(doc_no, date_value(&reference["docDate"]));
+        }
+    }
+    found
+}
+
+pub fn text_field(item: &Value, key: &str) -> Option<String> {
+    match &item[key] {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::EtaxApi;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::path::Path;
+
+    // Hands out canned search pages and downloads in order and remembers the
+    // pagination parameters each search request carried.
+    pub struct MockApi {
+        pages: RefCell<VecDeque<Result<String, String>>>,
+        downloads: RefCell<VecDeque<Result<Vec<u8>, String>>>,
+        pub requests: RefCell<Vec<Vec<(String, String)>>>,
+    }
+
+    impl MockApi {
+        pub fn new(pages: Vec<Result<&str, &str>>) -> MockApi {
+            MockApi {
+                pages: RefCell::new(pages.into_iter().map(|p| p.map(str::to_string).map_err(str::to_string)).collect()),
+                downloads: RefCell::new(VecDeque::new()),
+                requests: RefCell::new(Vec::new()),
+            }
+        }
+
+        pub fn with_downloads(self, downloads: Vec<Result<Vec<u8>, &str>>) -> MockApi {
+            *self.downloads.borrow_mut() = downloads.into_iter().map(|d| d.map_err(str::to_string)).collect();
+            self
+        }
+    }
+
+    impl EtaxApi for MockApi {
+        async fn fetch_tax_documents(&self, _tax_id: &str, _from: &str, _to: &str, extra: &[(String, String)]) -> Result<String, Box<dyn std::error::Error>> {
+            self.requests.borrow_mut().push(extra.to_vec());
+            self.pages.borrow_mut().pop_front().expect("no more canned pages").map_err(Into::into)
+        }
+
+        async fn download_zip(&self, _listfile_json: &str, part: Option<&Path>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+            let content = self.downloads.borrow_mut().pop_front().expect("no more canned downloads")?;
+            if let Some(part) = part {
+                std::fs::write(part, &content)?;
+            }
+            Ok(content)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockApi;
+    use super::*;
+
+    async fn search(api: &MockApi) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+        search_with(api, "0105500000000", "2026-10-01 00:00:00", "2026-10-31 23:59:59").await
+    }
+
+    #[tokio::test]
+    async fn follows_pages_until_the_total_is_reached() {
+        let api = MockApi::new(vec![
+            Ok(r#"{"reprintList":[{"docNo":"A1"},{"docNo":"A2"}],"totalCount":3,"pageNo":1,"pageSize":2}"#),
+            Ok(r#"{"reprintList":[{"docNo":"A2"},{"docNo":"A3"}],"totalCount":3,"pageNo":2,"pageSize":2}"#),
+        ]);
+        let items = search(&api).await.unwrap();
+        let numbers: Vec<String> = items.iter().map(doc_no).collect();
+        assert_eq!(numbers, ["A1", "A2", "A3"]);
+        assert_eq!(
+            *api.requests.borrow(),
+            [vec![], vec![("pageNo".to_string(), "2".to_string()), ("pageSize".to_string(), "2".to_string())]]
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_when_a_page_adds_nothing() {
+        let page = r#"{"reprintList":[{"docNo":"A1"}],"totalCount":5,"pageNo":1}"#;
+        let api = MockApi::new(vec![Ok(page), Ok(page)]);
+        assert_eq!(search(&api).await.unwrap().len(), 1);
+        assert_eq!(api.requests.borrow().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn empty_list_is_no_documents() {
+        let api = MockApi::new(vec![Ok(r#"{"reprintList":[]}"#)]);
+        assert!(search(&api).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn malformed_json_is_an_error() {
+        let api = MockApi::new(vec![Ok("<html>Service Unavailable</html>")]);
+        assert!(search(&api).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn missing_list_is_an_error() {
+        let api = MockApi::new(vec![Ok(r#"{"message":"error"}"#)]);
+        assert_eq!(search(&api).await.unwrap_err().to_string(), "Search response has no reprintList");
+    }
+
+    #[tokio::test]
+    async fn error_envelope_is_a_service_error() {
+        let api = MockApi::new(vec![Ok(r#"{"status":"ERROR","message":"Invalid tax ID"}"#)]);
+        let e = search(&api).await.unwrap_err();
+        assert!(matches!(e.downcast_ref::<ServiceError>(), Some(ServiceError::Api { message }) if message == "Invalid tax ID"));
+        // Whatever the locale of the machine running the tests.
+        lang::set_lang(Lang::En);
+        assert_eq!(e.to_string(), "EXAT e-Tax returned an error: Invalid tax ID");
+    }
+
+    #[tokio::test]
+    async fn maintenance_page_is_a_service_error() {
+        let page = "<!DOCTYPE html>\n<html><head><TITLE>\n  ปิดปรับปรุงระบบ  </TITLE></head><body>...</body></html>";
+        let api = MockApi::new(vec![Ok(page)]);
+        let e = search(&api).await.unwrap_err();
+        assert!(matches!(e.downcast_ref::<ServiceError>(), Some(ServiceError::Maintenance { title: Some(title) }) if title == "ปิดปรับปรุงระบบ"));
+    }
+
+    #[tokio::test]
+    async fn an_error_envelope_is_not_waited_out() {
+        set_wait_for_service(Duration::from_secs(2 * 3600));
+        // A second request would find no page left and panic.
+        let api = MockApi::new(vec![Ok(r#"{"status":"ERROR","message":"Invalid tax ID"}"#)]);
+        let e = waiting_for_service(|| search(&api)).await.unwrap_err();
+        assert!(matches!(e.downcast_ref::<ServiceError>(), Some(ServiceError::Api { .. })));
+        assert_eq!(api.requests.borrow().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn outages_and_lost_connections_are_waited_out() {
+        assert!(transient(&ServiceError::Maintenance { title: None }));
+        assert!(transient(&ServiceError::Unavailable { request: "Search", status: reqwest::StatusCode::SERVICE_UNAVAILABLE }));
+        assert!(!transient(&ServiceError::Api { message: "Invalid tax ID".to_string() }));
+        // Nothing listens on port 1.
+        let refused = Client::new().get("http://127.0.0.1:1/").send().await.unwrap_err();
+        assert!(transient(&refused));
+        let parse: Box<dyn std::error::Error> = "Search response has no reprintList".into();
+        assert!(!transient(parse.as_ref()));
+    }
+
+    #[test]
+    fn only_error_bodies_are_service_errors() {
+        assert!(service_error(b"PK\x03\x04").is_none());
+        assert!(service_error(br#"{"status":"OK","reprintList":[]}"#).is_none());
+        assert!(matches!(service_error(br#"{"status":"error"}"#), Some(ServiceError::Api { message }) if message == "no message"));
+        assert!(matches!(service_error(b"\xef\xbb\xbf <html><body>Down</body></html>"), Some(ServiceError::Maintenance { title: None })));
+    }
+
+    #[test]
+    fn references_are_read_from_every_known_field() {
+        let date = |s| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok();
+        let item = json!({ "docNo": "CN1", "refDocNo": "INV1, INV2;CN1", "refDocDate": "2026-09-20 00:00:00", "originalDocNo": ["INV2", "INV3"] });
+        let found = references(&item);
+        assert_eq!(found.iter().map(|r| r.doc_no.as_str()).collect::<Vec<_>>(), ["INV1", "INV2", "INV3"]);
+        assert_eq!(found[0].doc_date, date("2026-09-20"));
+        assert_eq!(found[2].doc_date, None);
+
+        let item = json!({ "docNo": "CN2", "references": [{ "docNo": "INV4", "docDate": "2026-08-01" }] });
+        assert_eq!(references(&item), [Reference { doc_no: "INV4".to_string(), doc_date: date("2026-08-01") }]);
+        assert!(references(&json!({ "docNo": "INV5" })).is_empty());
+    }
+
+    #[tokio::test]
+    async fn http_errors_are_passed_on() {
+        let api = MockApi::new(vec![Err("Search failed: HTTP 500 Internal Server Error")]);
+        assert_eq!(search(&api).await.unwrap_err().to_string(), "Search failed: HTTP 500 Internal Server Error");
+
+        let api = MockApi::new(vec![
+            Ok(r#"{"reprintList":[{"docNo":"A1"}],"totalCount":2,"pageNo":1}"#),
+            Err("Search failed: HTTP 500 Internal Server Error"),
+        ]);
+        assert!(search(&api).await.is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use crate::dates;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{Cursor, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+// Write a downloaded ZIP either to the user-supplied filename or to a generated,
+// never-overwritten `TaxDocuments_...` name inside `output_dir`.
+pub fn save_archive(content: &[u8], tax_id: &str, doc_date_from: &str, doc_date_to: &str, custom_filename: Option<&str>, output_dir: Option<&Path>) -> std::io::Result<PathBuf> {
+    let (path, _) = reserve_archive(tax_id, doc_date_from, doc_date_to, custom_filename, output_dir)?;
+    File::create(&path)?.write_all(content)?;
+    Ok(path)
+}
+
+// Pick the archive path before downloading. A generated name is claimed with an empty
+// file so a concurrent run can't take it; the content goes to `part_path` first and
+// is renamed over it by `finish_archive`. Also returns whether the placeholder was
+// created, which a custom name never is.
+pub fn reserve_archive(tax_id: &str, doc_date_from: &str, doc_date_to: &str, custom_filename: Option<&str>, output_dir: Option<&Path>) -> std::io::Result<(PathBuf, bool)> {
+    match custom_filename {
+        Some(name) => Ok((
+            match output_dir {
+                Some(dir) => dir.join(name),
+                None => PathBuf::from(name),
+            },
+            false,
+        )),
+        None => {
+            let now = Utc::now().with_timezone(&dates::timezone());
+            let base = format!("TaxDocuments_{}_{}_{}_{}", tax_id, doc_date_from, doc_date_to, now.format("%Y%m%d%H%M%S"));
+            let base = match output_dir {
+                Some(dir) => dir.join(base),
+                None => PathBuf::from(base),
+            };
+            Ok((create_unique_file(&base, "zip")?.1, true))
+        }
+    }
+}
+
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+pub fn finish_archive(path: &Path) -> std::io::Result<()> {
+    std::fs::rename(part_path(path), path)
+}
+
+// Undo `reserve_archive` after a failed or interrupted download: the empty
+// placeholder goes if this run created it (whatever else is at `path` is left alone)
+// and so does the partial file, unless it should be kept for inspection.
+pub fn abandon_archive(path: &Path, placeholder: bool, keep_partial: bool) -> std::io::Result<()> {
+    if placeholder && std::fs::metadata(path).is_ok_and(|m| m.len() == 0) {
+        std::fs::remove_file(path)?;
+    }
+    let part = part_path(path);
+    if !keep_partial && part.exists() {
+        std::fs::remove_file(part)?;
+    }
+    Ok(())
+}
+
+// Create `<base>.<ext>`, falling back to `<base>_1.<ext>`, `<base>_2.<ext>`, ... if it
+// already exists. `create_new` makes the existence check and creation atomic, so two
+// runs within the same second never overwrite each other's archive.
+pub fn create_unique_file(base: &Path, ext: &str) -> std::io::Result<(File, PathBuf)> {
+    let mut counter = 0u32;
+    loop {
+        let filename = if counter == 0 {
+            format!("{}.{}", base.display(), ext)
+        } else {
+            format!("{}_{}.{}", base.display(), counter, ext)
+        };
+        let path = PathBuf::from(filename);
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((file, path)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => counter += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub const MANIFEST_DIR: &str = "_manifest/";
+
+// Append `_manifest/manifest.json`, `_manifest/summary.json` and
+// `_manifest/SHA256SUMS` to a downloaded ZIP so the archive documents itself: which
+// search produced it, what each entry is, and the checksum of every file.
+pub fn embed_manifest(content: Vec<u8>, documents: &[Value], summary: Value) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let entries = checksum_entries(&content)?;
+
+    let manifest = json!({
+        "documents": documents.iter().map(|item| json!({
+            "docNo": item["docNo"],
+            "docDate": item["docDate"],
+            "docType": item["docType"],
+            "fileName": item["fileName"],
+        })).collect::<Vec<_>>(),
+        "files": entries.iter().map(|entry| json!({
+            "name": entry.name,
+            "size": entry.size,
+            "sha256": entry.sha256,
+        })).collect::<Vec<_>>(),
+    });
+    let checksums: String = entries
+        .iter()
+        .map(|entry| format!("{}  {}\n", entry.sha256, entry.name))
+        .collect();
+
+    let mut writer = ZipWriter::new_append(Cursor::new(content))?;
+    let options = SimpleFileOptions::default();
+    writer.start_file(format!("{}manifest.json", MANIFEST_DIR), options)?;
+    writer.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    writer.start_file(format!("{}summary.json", MANIFEST_DIR), options)?;
+    writer.write_all(serde_json::to_string_pretty(&summary)?.as_bytes())?;
+    writer.start_file(format!("{}SHA256SUMS", MANIFEST_DIR), options)?;
+    writer.write_all(checksums.as_bytes())?;
+
+    Ok(writer.finish()?.into_inner())
+}
+
+pub struct Entry {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+// Every file entry except an embedded manifest.
+pub fn entries(content: &[u8]) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+    let mut zip = ZipArchive::new(Cursor::new(content))?;
+    let mut entries = Vec::new();
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        if entry.is_dir() || entry.name().starts_with(MANIFEST_DIR) {
+            continue;
+        }
+        let mut data = Vec::new();
+        std::io::Read::read_to_end(&mut entry, &mut data)?;
+        entries.push(Entry { name: entry.name().to_string(), data });
+    }
+    Ok(entries)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EntryChecksum {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+// Checksum every file entry, skipping any previously embedded manifest.
+pub fn checksum_entries(content: &[u8]) -> Result<Vec<EntryChecksum>, Box<dyn std::error::Error>> {
+    let mut zip = ZipArchive::new(Cursor::new(content))?;
+    let mut entries = Vec::new();
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        if entry.is_dir() || entry.name().starts_with(MANIFEST_DIR) {
+            continue;
+        }
+        let mut hasher = Sha256::new();
+        let size = std::io::copy(&mut entry, &mut hasher)?;
+        entries.push(EntryChecksum {
+            name: entry.name().to_string(),
+            size,
+            sha256: format!("{:x}", hasher.finalize()),
+        });
+    }
+    Ok(entries)
+}
@@ -0,0 +1,390 @@
+use crate::archive;
+use crate::index::Index;
+use age::secrecy::SecretString;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+const MANIFEST_FILE: &str = "backup.json";
+const FORMAT_VERSION: u32 = 1;
+const STORE_INDEX_FILE: &str = "store.json";
+
+// A backup is a ZIP encrypted with age (https://age-encryption.org) under a
+// passphrase, so it can also be opened with the `age` tool alone. `backup.json`
+// lists where each part came from and the SHA-256 of every entry.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    created_at: DateTime<Utc>,
+    index: Option<PathBuf>,
+    state: Option<PathBuf>,
+    store: Option<PathBuf>,
+    // Whether the store's PDFs and text files are included, or only store.json.
+    documents: bool,
+    files: Vec<archive::EntryChecksum>,
+}
+
+pub struct BackupOptions {
+    pub output: PathBuf,
+    pub index: Option<PathBuf>,
+    pub state: Option<PathBuf>,
+    pub store: Option<PathBuf>,
+    pub documents: bool,
+    pub passphrase: SecretString,
+}
+
+pub struct RestoreOptions {
+    pub input: PathBuf,
+    // Where to restore each part; the path it was backed up from by default.
+    pub index: Option<PathBuf>,
+    pub state: Option<PathBuf>,
+    pub store: Option<PathBuf>,
+    // Overwrite what is already there.
+    pub force: bool,
+    pub passphrase: SecretString,
+}
+
+#[derive(Debug, Default)]
+pub struct RestoreSummary {
+    pub index: Option<PathBuf>,
+    pub state: Option<PathBuf>,
+    pub store: Option<PathBuf>,
+    pub files: usize,
+}
+
+pub fn backup(opts: &BackupOptions) -> Result<Vec<archive::EntryChecksum>, Box<dyn std::error::Error>> {
+    if opts.index.is_none() && opts.state.is_none() && opts.store.is_none() {
+        return Err("Nothing to back up; pass --index, --state or --store".into());
+    }
+    // The plain ZIP is assembled next to the output, readable by this user only, and
+    // removed once encrypted.
+    let plain = archive::part_path(&opts.output).with_extension("zip.part");
+    let _ = fs::remove_file(&plain);
+    let result = create_private(&plain).map_err(|e| format!("{}: {}", plain.display(), e).into()).and_then(|file| write_zip(opts, file, &plain)).and_then(|files| {
+        encrypt(&plain, &opts.output, opts.passphrase.clone())?;
+        Ok(files)
+    });
+    let _ = fs::remove_file(&plain);
+    result
+}
+
+fn write_zip(opts: &BackupOptions, file: File, path: &Path) -> Result<Vec<archive::EntryChecksum>, Box<dyn std::error::Error>> {
+    let mut zip = ZipWriter::new(file);
+    let mut files = Vec::new();
+
+    if let Some(index) = &opts.index {
+        if !index.is_file() {
+            return Err(format!("Index {} does not exist", index.display()).into());
+        }
+        // A consistent copy even while a watch is writing to it.
+        let snapshot = path.with_extension("sqlite.part");
+        let _ = fs::remove_file(&snapshot);
+        // VACUUM INTO fills an empty file, keeping its permissions.
+        create_private(&snapshot)?;
+        let added = Index::open(index).and_then(|index| Ok(index.snapshot(&snapshot)?)).and_then(|()| add_file(&mut zip, "index.sqlite", &snapshot));
+        let _ = fs::remove_file(&snapshot);
+        files.push(added?);
+    }
+    if let Some(state) = &opts.state {
+        files.push(add_file(&mut zip, "state.json", state)?);
+    }
+    if let Some(store) = &opts.store {
+        if !store.join(STORE_INDEX_FILE).is_file() {
+            return Err(format!("Store {} has no {}", store.display(), STORE_INDEX_FILE).into());
+        }
+        let mut paths = Vec::new();
+        if opts.documents {
+            walk(store, &mut paths)?;
+        } else {
+            paths.push(store.join(STORE_INDEX_FILE));
+        }
+        for path in paths {
+            let relative = path.strip_prefix(store)?.to_string_lossy().replace('\\', "/");
+            // Leftovers of an interrupted save.
+            if relative.ends_with(".tmp") {
+                continue;
+            }
+            files.push(add_file(&mut zip, &format!("store/{}", relative), &path)?);
+        }
+    }
+
+    let manifest = Manifest {
+        version: FORMAT_VERSION,
+        created_at: Utc::now(),
+        index: opts.index.as_deref().map(absolute),
+        state: opts.state.as_deref().map(absolute),
+        store: opts.store.as_deref().map(absolute),
+        documents: opts.documents,
+        files,
+    };
+    zip.start_file(MANIFEST_FILE, SimpleFileOptions::default())?;
+    zip.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    zip.finish()?;
+    Ok(manifest.files)
+}
+
+// Copy a file into the ZIP, hashing it on the way; PDFs are large enough not to be
+// read into memory whole.
+fn add_file(zip: &mut ZipWriter<File>, name: &str, path: &Path) -> Result<archive::EntryChecksum, Box<dyn std::error::Error>> {
+    let mut input = BufReader::new(File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?);
+    zip.start_file(name, SimpleFileOptions::default().large_file(true))?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    let mut size = 0u64;
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        zip.write_all(&buf[..n])?;
+        size += n as u64;
+    }
+    Ok(archive::EntryChecksum { name: name.to_string(), size, sha256: format!("{:x}", hasher.finalize()) })
+}
+
+fn encrypt(plain: &Path, output: &Path, passphrase: SecretString) -> Result<(), Box<dyn std::error::Error>> {
+    let part = archive::part_path(output);
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = age::Encryptor::with_user_passphrase(passphrase).wrap_output(File::create(&part)?)?;
+        io::copy(&mut File::open(plain)?, &mut writer)?;
+        writer.finish()?.sync_all()?;
+        Ok(())
+    })();
+    match result {
+        Ok(()) => Ok(fs::rename(&part, output)?),
+        Err(e) => {
+            let _ = fs::remove_file(&part);
+            Err(e)
+        }
+    }
+}
+
+// The backup is read as it is decrypted (age streams can seek), so no plain copy of
+// it is ever written.
+pub fn restore(opts: &RestoreOptions) -> Result<RestoreSummary, Box<dyn std::error::Error>> {
+    let reader = decrypt(&opts.input, opts.passphrase.clone())?;
+    let zip = ZipArchive::new(reader).map_err(|e| format!("Cannot decrypt {}, it is damaged: {}", opts.input.display(), e))?;
+    extract(opts, zip)
+}
+
+fn decrypt(input: &Path, passphrase: SecretString) -> Result<impl Read + Seek, Box<dyn std::error::Error>> {
+    let decryptor = age::Decryptor::new(BufReader::new(File::open(input).map_err(|e| format!("{}: {}", input.display(), e))?))
+        .map_err(|e| format!("{} is not an exat-etax backup: {}", input.display(), e))?;
+    let identity = age::scrypt::Identity::new(passphrase);
+    let reader = decryptor.decrypt(std::iter::once(&identity as &dyn age::Identity)).map_err(|e| match e {
+        age::DecryptError::DecryptionFailed | age::DecryptError::NoMatchingKeys => format!("Cannot decrypt {}; wrong passphrase?", input.display()),
+        e => format!("Cannot decrypt {}: {}", input.display(), e),
+    })?;
+    Ok(reader)
+}
+
+fn extract(opts: &RestoreOptions, mut zip: ZipArchive<impl Read + Seek>) -> Result<RestoreSummary, Box<dyn std::error::Error>> {
+    let manifest: Manifest = serde_json::from_reader(zip.by_name(MANIFEST_FILE).map_err(|_| format!("{} has no {}", opts.input.display(), MANIFEST_FILE))?)?;
+    if manifest.version > FORMAT_VERSION {
+        return Err(format!("{} was written by a newer exat-etax (format {}); upgrade exat-etax", opts.input.display(), manifest.version).into());
+    }
+    info!("Restoring backup of {}", manifest.created_at.to_rfc3339());
+
+    // Decide every destination, and refuse to overwrite any, before writing one.
+    let target = |given: &Option<PathBuf>, original: &Option<PathBuf>| given.clone().or_else(|| original.clone());
+    let summary = RestoreSummary {
+        index: manifest.index.as_ref().and_then(|_| target(&opts.index, &manifest.index)),
+        state: manifest.state.as_ref().and_then(|_| target(&opts.state, &manifest.state)),
+        store: manifest.store.as_ref().and_then(|_| target(&opts.store, &manifest.store)),
+        files: 0,
+    };
+    let destination = |name: &str| -> Option<PathBuf> {
+        match name {
+            "index.sqlite" => summary.index.clone(),
+            "state.json" => summary.state.clone(),
+            _ => {
+                let relative = name.strip_prefix("store/")?;
+                // No absolute paths or `..` out of the store.
+                let relative = Path::new(relative);
+                relative.components().all(|c| matches!(c, std::path::Component::Normal(_))).then(|| summary.store.as_ref().map(|s| s.join(relative)))?
+            }
+        }
+    };
+    if !opts.force {
+        let existing: Vec<PathBuf> = [&summary.index, &summary.state]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .chain(summary.store.iter().map(|s| s.join(STORE_INDEX_FILE)))
+            .filter(|path| path.exists())
+            .collect();
+        if !existing.is_empty() {
+            return Err(format!("{} already exist(s); pass --force to overwrite", existing.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", ")).into());
+        }
+    }
+
+    let mut restored = 0;
+    for file in &manifest.files {
+        let Some(path) = destination(&file.name) else {
+            warn!("Skipping {}: not a path this backup can restore", file.name);
+            continue;
+        };
+        let mut entry = zip.by_name(&file.name).map_err(|_| format!("{} lists {} but does not contain it", opts.input.display(), file.name))?;
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let part = archive::part_path(&path);
+        let mut hasher = Sha256::new();
+        let mut output = File::create(&part)?;
+        let mut buf = [0u8; 64 * 1024];
+        loop {
+            let n = match entry.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) => {
+                    let _ = fs::remove_file(&part);
+                    return Err(format!("Cannot read {} from {}, it is damaged: {}", file.name, opts.input.display(), e).into());
+                }
+            };
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            output.write_all(&buf[..n])?;
+        }
+        if format!("{:x}", hasher.finalize()) != file.sha256 {
+            let _ = fs::remove_file(&part);
+            return Err(format!("{} in {} does not match its checksum; the backup is damaged", file.name, opts.input.display()).into());
+        }
+        fs::rename(&part, &path)?;
+        restored += 1;
+    }
+    if let Some(store) = summary.store.as_ref().filter(|_| !manifest.documents) {
+        warn!("The backup has no documents; {} lists files that are not restored", store.join(STORE_INDEX_FILE).display());
+    }
+    Ok(RestoreSummary { files: restored, ..summary })
+}
+
+// A new file only this user can read, for the unencrypted parts of a backup.
+fn create_private(path: &Path) -> io::Result<File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?.map(|e| e.map(|e| e.path())).collect::<Result<_, _>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("exat-etax-backup-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn a_backup_restores_to_other_paths_byte_for_byte() {
+        let dir = temp_dir("round-trip");
+        let store = dir.join("store");
+        fs::create_dir_all(store.join("0105551234567/E1")).unwrap();
+        fs::write(store.join(STORE_INDEX_FILE), br#"{"documents":{}}"#).unwrap();
+        fs::write(store.join("0105551234567/E1/daa292a5ee005c69_E1.pdf"), b"%PDF-1.4\n\x00\xff binary").unwrap();
+        fs::write(dir.join("state.json"), br#"{"taxIds":{}}"#).unwrap();
+
+        let backup_path = dir.join("backup.age");
+        let files = backup(&BackupOptions {
+            output: backup_path.clone(),
+            index: None,
+            state: Some(dir.join("state.json")),
+            store: Some(store.clone()),
+            documents: true,
+            passphrase: "correct horse".to_string().into(),
+        })
+        .unwrap();
+        assert_eq!(files.len(), 3);
+        // Nothing unencrypted is left behind.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+
+        let restored = dir.join("restored");
+        let options = |passphrase: &str| RestoreOptions {
+            input: backup_path.clone(),
+            index: None,
+            state: Some(restored.join("state.json")),
+            store: Some(restored.join("store")),
+            force: false,
+            passphrase: passphrase.to_string().into(),
+        };
+        assert!(restore(&options("wrong horse")).unwrap_err().to_string().contains("wrong passphrase"));
+        let summary = restore(&options("correct horse")).unwrap();
+        assert_eq!(summary.files, 3);
+        for (original, copy) in [
+            (dir.join("state.json"), restored.join("state.json")),
+            (store.join(STORE_INDEX_FILE), restored.join("store").join(STORE_INDEX_FILE)),
+            (store.join("0105551234567/E1/daa292a5ee005c69_E1.pdf"), restored.join("store/0105551234567/E1/daa292a5ee005c69_E1.pdf")),
+        ] {
+            assert_eq!(fs::read(&original).unwrap(), fs::read(&copy).unwrap(), "{}", copy.display());
+        }
+        // Restoring over what is there needs --force.
+        assert!(restore(&options("correct horse")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unencrypted_parts_are_private_and_never_reuse_a_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = temp_dir("private");
+        let path = dir.join("backup.age.zip.part");
+        create_private(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(create_private(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entries_outside_the_store_are_not_restored() {
+        let dir = temp_dir("escape");
+        let plain = dir.join("backup.zip");
+        let mut zip = ZipWriter::new(File::create(&plain).unwrap());
+        zip.start_file("store/../x", SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"outside").unwrap();
+        let manifest = Manifest {
+            version: FORMAT_VERSION,
+            created_at: Utc::now(),
+            index: None,
+            state: None,
+            store: Some(dir.join("store")),
+            documents: true,
+            files: vec![archive::EntryChecksum { name: "store/../x".to_string(), size: 7, sha256: format!("{:x}", Sha256::digest(b"outside")) }],
+        };
+        zip.start_file(MANIFEST_FILE, SimpleFileOptions::default()).unwrap();
+        zip.write_all(serde_json::to_string(&manifest).unwrap().as_bytes()).unwrap();
+        zip.finish().unwrap();
+
+        let options = RestoreOptions { input: plain.clone(), index: None, state: None, store: Some(dir.join("store")), force: false, passphrase: String::new().into() };
+        assert_eq!(extract(&options, ZipArchive::new(File::open(&plain).unwrap()).unwrap()).unwrap().files, 0);
+        assert!(!dir.join("x").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+use crate::api::EtaxApi;
+use crate::dates;
+use crate::interrupt::{self, Interrupted};
+use crate::run::{self, RunOptions};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use tokio::sync::Semaphore;
+use tracing::{error, info};
+
+// One line of a jobs file. Dates are YYYY-MM-DD (default: today); `output` is a
+// directory for the ZIP or, when it ends in `.zip`, the file to write.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Job {
+    #[serde(alias = "taxId", alias = "taxID")]
+    pub tax_id: String,
+    #[serde(default)]
+    pub since: Option<String>,
+    #[serde(default)]
+    pub until: Option<String>,
+    #[serde(default)]
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobReport {
+    pub job: usize,
+    pub tax_id: String,
+    pub since: String,
+    pub until: String,
+    pub ok: bool,
+    pub found: usize,
+    pub downloaded: usize,
+    pub archive: Option<String>,
+    pub reissued: Vec<String>,
+    // Left to the next run, as the download failed; see `RunOptions::metadata_only_fallback`.
+    pub deferred: Vec<String>,
+    pub review: Vec<String>,
+    // Cost centers that went over their monthly budget.
+    pub over_budget: Vec<String>,
+    pub error: Option<String>,
+}
+
+// Read jobs from a file, or stdin for "-". JSON (one object per line, or an array of
+// objects) and CSV are accepted; CSV columns are taxId,since,until,output, with an
+// optional header row naming them.
+pub fn read_jobs(path: &str) -> Result<Vec<Job>, Box<dyn std::error::Error>> {
+    read_records(path)
+}
+
+// Records of any kind starting with a tax ID, in the formats of a jobs file.
+pub fn read_records<T: DeserializeOwned>(path: &str) -> Result<Vec<T>, Box<dyn std::error::Error>> {
+    let mut content = String::new();
+    if path == "-" {
+        std::io::stdin().read_to_string(&mut content)?;
+    } else {
+        content = std::fs::read_to_string(path)?;
+    }
+
+    let trimmed = content.trim_start();
+    if trimmed.starts_with('[') {
+        return Ok(serde_json::from_str(trimmed)?);
+    }
+    if trimmed.starts_with('{') {
+        return content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| serde_json::from_str(line).map_err(|e| format!("{} line {}: {}", path, i + 1, e).into()))
+            .collect();
+    }
+
+    // Without a header the first field is a tax ID.
+    let has_header = trimmed.split([',', '\n']).next().is_some_and(|first| !first.trim().chars().all(|c| c.is_ascii_digit()));
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(has_header)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .from_reader(content.as_bytes());
+    reader
+        .deserialize()
+        .enumerate()
+        .map(|(i, record)| record.map_err(|e| format!("{} record {}: {}", path, i + 1, e).into()))
+        .collect()
+}
+
+// Run every job, at most `concurrency` at a time, and print one JSON report line per
+// job on stdout as it finishes. `base` supplies everything a job doesn't override.
+// Returns the number of failed jobs.
+pub async fn run<A: EtaxApi + 'static>(api: A, jobs: Vec<Job>, base: RunOptions, concurrency: usize) -> Result<usize, Box<dyn std::error::Error>> {
+    if concurrency > 1 && base.state.is_some() {
+        // Each run rewrites the whole state file, so concurrent runs would lose updates.
+        return Err("--state cannot be used with --concurrency above 1; use --index instead".into());
+    }
+
+    // Jobs run on one thread, so the store and index updates of a job, which don't
+    // await, never interleave with those of another.
+    let local = tokio::task::LocalSet::new();
+    let semaphore = Rc::new(Semaphore::new(concurrency.max(1)));
+    let api = Rc::new(api);
+    let base = Rc::new(base);
+    let total = jobs.len();
+
+    let mut handles = Vec::new();
+    for (i, job) in jobs.into_iter().enumerate() {
+        let semaphore = semaphore.clone();
+        let api = api.clone();
+        let base = base.clone();
+        handles.push(local.spawn_local(async move {
+            let _permit = semaphore.acquire().await.expect("semaphore is never closed");
+            // After Ctrl-C the running jobs stop and the rest are reported as skipped.
+            if interrupt::is_requested() {
+                let mut report = JobReport::new(i + 1, &job);
+                report.error = Some("skipped: interrupted".to_string());
+                println!("{}", serde_json::to_string(&report).expect("report serializes"));
+                return false;
+            }
+            info!("Job {}/{}: {}", i + 1, total, crate::logging::mask(&job.tax_id));
+            let report = run_job(api.as_ref(), i + 1, &job, &base).await;
+            if let Some(e) = &report.error {
+                error!("Job {} failed: {}", i + 1, e);
+            }
+            println!("{}", serde_json::to_string(&report).expect("report serializes"));
+            report.ok
+        }));
+    }
+
+    let failed = local
+        .run_until(async {
+            let mut failed = 0;
+            for handle in handles {
+                if !handle.await.unwrap_or(false) {
+                    failed += 1;
+                }
+            }
+            failed
+        })
+        .await;
+    if interrupt::is_requested() {
+        return Err(Interrupted.into());
+    }
+    Ok(failed)
+}
+
+impl JobReport {
+    fn new(number: usize, job: &Job) -> JobReport {
+        JobReport {
+            job: number,
+            tax_id: job.tax_id.clone(),
+            since: job.since.clone().unwrap_or_default(),
+            until: job.until.clone().unwrap_or_default(),
+            ok: false,
+            found: 0,
+            downloaded: 0,
+            archive: None,
+            reissued: Vec::new(),
+            deferred: Vec::new(),
+            review: Vec::new(),
+            over_budget: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+async fn run_job(api: &impl EtaxApi, number: usize, job: &Job, base: &RunOptions) -> JobReport {
+    let mut report = JobReport::new(number, job);
+
+    match options(job, base) {
+        Ok(opts) => match run::run(api, &opts).await {
+            Ok(summary) => {
+                report.ok = true;
+                report.found = summary.found;
+                report.downloaded = summary.downloaded.len();
+                report.archive = summary.archive.map(|p| p.display().to_string());
+                report.reissued = summary.reissued;
+                report.deferred = summary.deferred;
+                report.review = summary.review;
+                report.over_budget = summary.over_budget.into_iter().map(|a| format!("{} {}", a.cost_center, a.month)).collect();
+            }
+            Err(e) => report.error = Some(e.to_string()),
+        },
+        Err(e) => report.error = Some(e.to_string()),
+    }
+    report
+}
+
+fn options(job: &Job, base: &RunOptions) -> Result<RunOptions, Box<dyn std::error::Error>> {
+    let mut opts = base.clone();
+    opts.tax_id = job.tax_id.clone();
+    let date = |value: &Option<String>, start_of_day| {
+        let value = value.as_deref().unwrap_or("");
+        dates::parse_date(value, start_of_day).map_err(|e| format!("Invalid date {:?}: {}", value, e))
+    };
+    opts.since = date(&job.since, true)?;
+    opts.until = date(&job.until, false)?;
+    // The report is the output; per-document listings would interleave between jobs.
+    opts.quiet = true;
+
+    if let Some(output) = job.output.as_deref().filter(|o| !o.is_empty()) {
+        if output.to_lowercase().ends_with(".zip") {
+            let path = Path::new(output);
+            opts.filename = path.file_name().map(|n| n.to_string_lossy().to_string());
+            opts.output_dir = path.parent().filter(|p| !p.as_os_str().is_empty()).map(Path::to_path_buf);
+        } else {
+            opts.output_dir = Some(PathBuf::from(output));
+        }
+    }
+    if let Some(dir) = &opts.output_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+    Ok(opts)
+}
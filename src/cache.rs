@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+// On-disk cache of search results (`--cache-ttl`), so iterating on the local side
+// doesn't repeat the same search against the portal. Off unless a TTL is given.
+static CACHE: OnceLock<Cache> = OnceLock::new();
+
+struct Cache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    key: Vec<String>,
+    stored_at: DateTime<Utc>,
+    items: Vec<Value>,
+}
+
+// `$XDG_CACHE_HOME/exat-etax`, `~/.cache/exat-etax`, or `%LOCALAPPDATA%\exat-etax`.
+pub fn default_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .unwrap_or_default();
+    base.join("exat-etax")
+}
+
+pub fn enable(dir: PathBuf, ttl: Duration) {
+    let _ = CACHE.set(Cache { dir, ttl });
+}
+
+// Every search parameter goes into the key, so a different range or filter is a
+// different entry.
+fn path(dir: &Path, key: &[String]) -> PathBuf {
+    let digest = Sha256::digest(key.join("\n").as_bytes());
+    let hex: String = digest.iter().take(16).map(|b| format!("{:02x}", b)).collect();
+    dir.join(format!("search-{}.json", hex))
+}
+
+pub fn get(key: &[String]) -> Option<Vec<Value>> {
+    let cache = CACHE.get()?;
+    let content = std::fs::read_to_string(path(&cache.dir, key)).ok()?;
+    let entry: Entry = serde_json::from_str(&content).ok()?;
+    let age = (Utc::now() - entry.stored_at).to_std().unwrap_or_default();
+    if entry.key != key || age > cache.ttl {
+        return None;
+    }
+    debug!(age_s = age.as_secs(), "Search answered from cache");
+    Some(entry.items)
+}
+
+// A cache that can't be written only costs the next run a request.
+pub fn put(key: &[String], items: &[Value]) {
+    let Some(cache) = CACHE.get() else {
+        return;
+    };
+    let entry = Entry { key: key.to_vec(), stored_at: Utc::now(), items: items.to_vec() };
+    let result = std::fs::create_dir_all(&cache.dir)
+        .map_err(|e| e.to_string())
+        .and_then(|_| serde_json::to_string(&entry).map_err(|e| e.to_string()))
+        .and_then(|json| std::fs::write(path(&cache.dir, key), json).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        warn!("Cannot write the search cache in {}: {}", cache.dir.display(), e);
+    }
+}
+
+// Returns the number of entries removed.
+pub fn clear(dir: &Path) -> std::io::Result<usize> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut removed = 0;
+    for entry in entries {
+        let path = entry?.path();
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if name.starts_with("search-") && name.ends_with(".json") {
+            std::fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
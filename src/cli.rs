@@ -0,0 +1,963 @@
+use crate::dates;
+use crate::diff;
+use crate::export;
+use crate::lang;
+use crate::plan;
+use crate::profile;
+use crate::query;
+use crate::report;
+use crate::s3::S3Target;
+use crate::watch::Schedule;
+use clap::builder::styling::{AnsiColor, Styles};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use chrono::NaiveDate;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const DEFAULT_STATE_FILE: &str = "exat-etax-state.json";
+pub const DEFAULT_HOLDS_DIR: &str = "holds";
+
+const STYLES: Styles = Styles::styled()
+    .header(AnsiColor::Yellow.on_default().bold())
+    .usage(AnsiColor::Yellow.on_default().bold())
+    .literal(AnsiColor::Green.on_default().bold())
+    .placeholder(AnsiColor::Cyan.on_default())
+    .error(AnsiColor::Red.on_default().bold())
+    .valid(AnsiColor::Green.on_default())
+    .invalid(AnsiColor::Yellow.on_default());
+
+// Values are checked as the command line is parsed, so a bad date or a missing file
+// is reported with the usage before anything is searched or written.
+#[derive(Parser)]
+#[command(
+    name = "exat-etax",
+    about = "Tax Document Service: download e-tax invoices from EXAT and keep them for accounting",
+    version,
+    subcommand_negates_reqs = true,
+    override_usage = "exat-etax [OPTIONS] <TAX_ID> [FILENAME]\n       exat-etax [OPTIONS] <COMMAND>",
+    styles = STYLES
+)]
+pub struct Cli {
+    // The bare form, from before subcommands, is kept as `search --download`.
+    #[command(flatten)]
+    pub search: SearchArgs,
+    /// Prevent downloading ZIP file
+    #[arg(long)]
+    pub no_download: bool,
+    // Last, as its help heading holds for the arguments after it.
+    #[command(flatten)]
+    pub global: GlobalArgs,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Args)]
+#[command(next_help_heading = "Global options")]
+pub struct GlobalArgs {
+    /// Log request/response details to stderr (-vv for more)
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+    /// Only report errors; suitable for cron
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Emit diagnostics as JSON lines on stderr
+    #[arg(long, global = true)]
+    pub log_json: bool,
+    /// Limit requests to the EXAT backend; 429 responses are always retried
+    #[arg(long, global = true, env = "EXAT_ETAX_MAX_REQUESTS_PER_MINUTE", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_requests_per_minute: Option<u32>,
+    /// Download no faster than this many bytes per second, e.g. 500k or 2M (k, M and G are multiples of 1024)
+    #[arg(long, global = true, env = "EXAT_ETAX_LIMIT_RATE", value_parser = parse_rate)]
+    pub limit_rate: Option<u64>,
+    /// While EXAT is unreachable or answers with a maintenance page or HTTP 502-504, keep trying for up to this long, e.g. 2h
+    #[arg(long, global = true, env = "EXAT_ETAX_WAIT_FOR_SERVICE", value_parser = parse_duration)]
+    pub wait_for_service: Option<Duration>,
+    /// Timezone of the dates searched, shown and put in file names (default: Asia/Bangkok, as EXAT)
+    #[arg(long, global = true, env = "EXAT_ETAX_TIMEZONE", value_parser = dates::parse_timezone)]
+    pub timezone: Option<chrono_tz::Tz>,
+    /// Language of table headers, reports and error messages (default: from LANG)
+    #[arg(long, global = true, env = "EXAT_ETAX_LANG", value_enum)]
+    pub lang: Option<lang::Lang>,
+    /// When another run holds the state, index, store or output directory, wait for it (at most this long, if given) instead of failing
+    #[arg(long, global = true, env = "EXAT_ETAX_WAIT_LOCK", num_args = 0..=1, value_parser = parse_duration)]
+    pub wait_lock: Option<Option<Duration>>,
+    /// Don't lock the state, index, store and output directory; for file systems without locks
+    #[arg(long, global = true, conflicts_with = "wait_lock")]
+    pub no_lock: bool,
+    /// Reuse search results younger than this, e.g. 10m
+    #[arg(long, global = true, env = "EXAT_ETAX_CACHE_TTL", value_parser = parse_duration)]
+    pub cache_ttl: Option<Duration>,
+    /// Always search the portal, even with --cache-ttl
+    #[arg(long, global = true)]
+    pub no_cache: bool,
+    /// Search cache directory (default: ~/.cache/exat-etax)
+    #[arg(long, global = true, env = "EXAT_ETAX_CACHE_DIR")]
+    pub cache_dir: Option<PathBuf>,
+    /// Save every raw API response into this directory
+    #[arg(long, global = true)]
+    pub record: Option<PathBuf>,
+    /// Answer API requests from a --record directory instead of the network
+    #[arg(long, global = true, conflicts_with = "record", value_parser = existing_dir)]
+    pub replay: Option<PathBuf>,
+    /// Configuration file (default: exat-etax.toml if present)
+    #[arg(long, global = true, env = "EXAT_ETAX_CONFIG", value_parser = existing_path)]
+    pub config: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Search documents and list them; with --download, also download them
+    Search {
+        #[command(flatten)]
+        search: SearchArgs,
+        /// Download the documents found as a ZIP
+        #[arg(short, long)]
+        download: bool,
+    },
+    /// Search documents and download them as a ZIP
+    Download(SearchArgs),
+    /// Download what is new since the last sync, as recorded in the state file
+    Sync(SyncArgs),
+    /// Repeatedly search and download new documents on a schedule
+    Watch(WatchArgs),
+    /// Run many searches/downloads from a jobs file (JSON or CSV: taxId,since,until,output)
+    Batch(BatchArgs),
+    /// Search, then pick the documents to download in an interactive table
+    Tui(TuiArgs),
+    /// Print the normalized text of a PDF as the extraction pipeline sees it
+    Text(TextArgs),
+    /// Search documents recorded locally, optionally as they were known on a past date
+    Query(QueryArgs),
+    /// Export recorded documents with amounts and cost centers for accounting
+    Export(ExportArgs),
+    /// Write a monthly statement of recorded documents: totals per day and document type, and the VAT
+    Report(ReportArgs),
+    /// Package one year of stored documents for an external auditor: per-month PDFs, merged PDFs, CSV registers, statements, manifest and signed checksums
+    ExportYear(ExportYearArgs),
+    /// Compare two sets of documents and list those added, removed or changed, e.g. reissued
+    Diff(DiffArgs),
+    /// Add PDFs and ZIPs downloaded by hand to the store, index and state
+    Import(ImportArgs),
+    /// Move an archive kept under your own folder convention into the store and index, and list what it lacks
+    Migrate(MigrateArgs),
+    /// Read ETDA e-Tax invoice XML and print its seller, buyer, line items, VAT and references
+    Parse(ParseArgs),
+    /// Check the XAdES signatures and certificate chains of e-Tax invoice XML
+    Verify(VerifyArgs),
+    /// Write the index, state and store manifest, and optionally the documents, to one encrypted file
+    Backup(BackupArgs),
+    /// Put the contents of a backup back in place
+    Restore(RestoreArgs),
+    /// Create entries for recorded documents in an accounting system, with the PDF attached
+    Push(PushArgs),
+    /// Serve over HTTP: a REST API to search and download, and for the index a document viewer and polling triggers for Zapier and Make
+    Serve(ServeArgs),
+    /// Manage the search cache
+    #[command(subcommand_required = true, arg_required_else_help = true)]
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Keep tax IDs in the OS keyring under a name, for --profile
+    #[command(subcommand_required = true, arg_required_else_help = true)]
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommand,
+    },
+    /// Freeze search results and documents into immutable, hash-chained legal holds
+    #[command(subcommand_required = true, arg_required_else_help = true)]
+    Hold(HoldArgs),
+    /// Download the documents queued by runs whose download failed, once the backend is back
+    FlushDownloads(FlushArgs),
+    /// Schedule nightly syncs of many tax IDs within a time window and request budget
+    Plan(PlanArgs),
+    /// Tools for demos and integration tests that need no real taxpayer data
+    #[command(subcommand_required = true, arg_required_else_help = true)]
+    Dev {
+        #[command(subcommand)]
+        command: DevCommand,
+    },
+    /// Print the tab completion script for a shell
+    Completions {
+        /// Shell to complete in
+        shell: clap_complete::Shell,
+    },
+    /// Print the man page, or write one page per subcommand into a directory
+    Manpage {
+        /// Directory to write exat-etax.1 and a page for each subcommand to
+        #[arg(short, long)]
+        output_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+pub struct SearchArgs {
+    /// Tax identification number
+    // Only optional so that a subcommand or --profile can stand in for it.
+    #[arg(required_unless_present = "profile", value_parser = parse_tax_id)]
+    pub tax_id: Option<String>,
+    /// Profile whose tax ID (kept in the OS keyring by `profile add`) to use instead of giving it
+    #[arg(long, env = "EXAT_ETAX_PROFILE", value_parser = profile::validate_name)]
+    pub profile: Option<String>,
+    /// Start date of the search (default: today)
+    #[arg(short = 'S', long, value_parser = parse_date)]
+    pub since: Option<NaiveDate>,
+    /// End date of the search (default: today)
+    #[arg(short = 'U', long, value_parser = parse_date)]
+    pub until: Option<NaiveDate>,
+    /// State file; only documents not downloaded before are fetched
+    #[arg(long)]
+    pub state: Option<PathBuf>,
+    /// SQLite index recording every document; also skips documents fetched before
+    #[arg(long)]
+    pub index: Option<PathBuf>,
+    #[command(flatten)]
+    pub searched: SearchedArgs,
+    #[command(flatten)]
+    pub download: DownloadArgs,
+    /// Also write the downloaded PDFs, by date and number, as one PDF
+    #[arg(long, value_name = "FILE")]
+    pub merge_pdf: Option<PathBuf>,
+    /// Start the merged PDF with a list of its documents
+    #[arg(long, requires = "merge_pdf")]
+    pub cover_page: bool,
+    /// When the run ends, write its counters (documents, bytes, API errors, retries, last success) to this JSON file
+    #[arg(long)]
+    pub summary_json: Option<PathBuf>,
+    /// Custom filename for the downloaded ZIP (optional)
+    pub filename: Option<String>,
+}
+
+// Shared by `sync` and `watch`, which runs a sync on every tick.
+#[derive(Args)]
+pub struct SyncArgs {
+    /// Tax identification number
+    #[arg(required_unless_present = "profile", value_parser = parse_tax_id)]
+    pub tax_id: Option<String>,
+    /// Profile whose tax ID (kept in the OS keyring by `profile add`) to use instead of giving it
+    #[arg(long, env = "EXAT_ETAX_PROFILE", value_parser = profile::validate_name)]
+    pub profile: Option<String>,
+    /// Start date of the first search when the state is empty (default: today)
+    #[arg(short = 'S', long, value_parser = parse_date)]
+    pub since: Option<NaiveDate>,
+    /// State file tracking downloaded documents
+    #[arg(long, default_value = DEFAULT_STATE_FILE)]
+    pub state: PathBuf,
+    /// SQLite index recording every document
+    #[arg(long)]
+    pub index: Option<PathBuf>,
+    /// Directory to write downloaded ZIP files to
+    #[arg(short, long)]
+    pub output_dir: Option<PathBuf>,
+    #[command(flatten)]
+    pub searched: SearchedArgs,
+    #[command(flatten)]
+    pub download: DownloadArgs,
+    /// When the sync ends (in watch mode, every cycle), write the counters (documents, bytes, API errors, retries, last success) to this JSON file
+    #[arg(long)]
+    pub summary_json: Option<PathBuf>,
+    /// Only search and log what was found
+    #[arg(long)]
+    pub no_download: bool,
+}
+
+#[derive(Args)]
+pub struct FlushArgs {
+    /// Queue file written by --queue
+    pub queue: PathBuf,
+    /// Only download the documents of this tax ID
+    #[arg(long, value_parser = parse_tax_id)]
+    pub tax_id: Option<String>,
+    /// State file; marks the documents as downloaded, and skips those downloaded since they were queued
+    #[arg(long)]
+    pub state: Option<PathBuf>,
+    /// SQLite index recording every document; also skips documents fetched since they were queued
+    #[arg(long)]
+    pub index: Option<PathBuf>,
+    /// Directory to write downloaded ZIP files to
+    #[arg(short, long)]
+    pub output_dir: Option<PathBuf>,
+    #[command(flatten)]
+    pub download: DownloadArgs,
+    /// When the flush ends, write the counters (documents, bytes, API errors, retries, last success) to this JSON file
+    #[arg(long)]
+    pub summary_json: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct WatchArgs {
+    #[command(flatten)]
+    pub sync: SyncArgs,
+    /// Interval between cycles, e.g. 30m or 24h (default: 24h)
+    #[arg(long, conflicts_with = "cron", value_parser = Schedule::parse_every)]
+    pub every: Option<Schedule>,
+    /// Cron expression in local time (see --timezone), e.g. "0 6 * * *"
+    #[arg(long, value_parser = Schedule::parse_cron)]
+    pub cron: Option<Schedule>,
+    /// Only start cycles within this daily window in local time (see --timezone), e.g. 01:00-06:00
+    #[arg(long, value_parser = plan::Window::parse)]
+    pub only_between: Option<plan::Window>,
+    /// Serve Prometheus metrics on this address at /metrics, e.g. 127.0.0.1:9090
+    #[arg(long)]
+    pub metrics_listen: Option<SocketAddr>,
+}
+
+#[derive(Args)]
+pub struct BatchArgs {
+    /// Jobs file, or - for stdin
+    #[arg(default_value = "-")]
+    pub jobs: String,
+    /// Number of jobs to run at the same time
+    #[arg(short = 'j', long, default_value = "1", value_parser = parse_concurrency)]
+    pub concurrency: usize,
+    /// State file; only documents not downloaded before are fetched
+    #[arg(long)]
+    pub state: Option<PathBuf>,
+    /// SQLite index recording every document; also skips documents fetched before
+    #[arg(long)]
+    pub index: Option<PathBuf>,
+    #[command(flatten)]
+    pub searched: SearchedArgs,
+    #[command(flatten)]
+    pub download: DownloadArgs,
+    /// When every job has ended, write the counters (documents, bytes, API errors, retries, last success) to this JSON file
+    #[arg(long)]
+    pub summary_json: Option<PathBuf>,
+    /// Only search
+    #[arg(long)]
+    pub no_download: bool,
+}
+
+#[derive(Args)]
+pub struct TuiArgs {
+    /// Tax identification number
+    #[arg(required_unless_present = "profile", value_parser = parse_tax_id)]
+    pub tax_id: Option<String>,
+    /// Profile whose tax ID (kept in the OS keyring by `profile add`) to use instead of giving it
+    #[arg(long, env = "EXAT_ETAX_PROFILE", value_parser = profile::validate_name)]
+    pub profile: Option<String>,
+    /// Start date of the search (default: today)
+    #[arg(short = 'S', long, value_parser = parse_date)]
+    pub since: Option<NaiveDate>,
+    /// End date of the search (default: today)
+    #[arg(short = 'U', long, value_parser = parse_date)]
+    pub until: Option<NaiveDate>,
+    /// Directory to write the ZIP file to
+    #[arg(short, long)]
+    pub output_dir: Option<PathBuf>,
+    /// Custom filename for the downloaded ZIP (optional)
+    pub filename: Option<String>,
+}
+
+#[derive(Args)]
+pub struct TextArgs {
+    /// PDF file
+    #[arg(value_parser = existing_path)]
+    pub file: PathBuf,
+    /// Print the extracted amounts (with confidence), plates, plazas and routes as JSON instead
+    #[arg(long)]
+    pub extract: bool,
+    #[command(flatten)]
+    pub thai: ThaiArgs,
+}
+
+// Which recorded documents `query`, `export`, `report` and `push` work on.
+#[derive(Args)]
+pub struct DocumentArgs {
+    /// State file to read
+    #[arg(long, default_value = DEFAULT_STATE_FILE)]
+    pub state: PathBuf,
+    /// Read the SQLite index instead of the state file
+    #[arg(long, value_parser = existing_path)]
+    pub index: Option<PathBuf>,
+    /// Document store to read extracted amounts, plates, plazas, routes and cards from
+    #[arg(long, value_parser = existing_dir)]
+    pub store: Option<PathBuf>,
+    /// Only show documents of this tax identification number
+    #[arg(long, value_parser = parse_tax_id)]
+    pub tax_id: Option<String>,
+    /// Only documents dated on or after this date (YYYY-MM-DD)
+    #[arg(short = 'S', long, value_parser = parse_date)]
+    pub since: Option<NaiveDate>,
+    /// Only documents dated on or before this date (YYYY-MM-DD)
+    #[arg(short = 'U', long, value_parser = parse_date)]
+    pub until: Option<NaiveDate>,
+    /// Only documents of this docType
+    #[arg(long = "type")]
+    pub doc_type: Option<String>,
+    /// Only documents with at least this amount
+    #[arg(long)]
+    pub min_amount: Option<f64>,
+    /// Only documents with at most this amount
+    #[arg(long)]
+    pub max_amount: Option<f64>,
+    /// Show what was known at the end of this date (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    pub as_of: Option<NaiveDate>,
+}
+
+#[derive(Args)]
+pub struct QueryArgs {
+    #[command(flatten)]
+    pub documents: DocumentArgs,
+    /// Print document count and total per vehicle, plaza, route or cost center
+    #[arg(long)]
+    pub group_by: Option<query::GroupBy>,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    #[command(flatten)]
+    pub documents: DocumentArgs,
+    /// Output format
+    #[arg(short, long, default_value = "csv")]
+    pub format: export::Format,
+    /// File to write (default: stdout); a directory for ubl
+    #[arg(short, long, required_if_eq_any = [("format", "xlsx"), ("format", "ubl")])]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct ReportArgs {
+    #[command(flatten)]
+    pub documents: DocumentArgs,
+    /// Month to report (YYYY-MM)
+    #[arg(long, required_unless_present = "combined", conflicts_with_all = ["since", "until", "combined"], value_parser = parse_month)]
+    pub month: Option<NaiveDate>,
+    /// Report every month (or those of --since/--until) per provider and company instead
+    #[arg(long)]
+    pub combined: bool,
+    /// Output format
+    #[arg(short, long, default_value = "md")]
+    pub format: report::Format,
+    /// File to write (default: stdout)
+    #[arg(short, long, required_if_eq("format", "pdf"))]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct ExportYearArgs {
+    /// Year of the documents' dates; Thai years (e.g. 2567) are accepted
+    pub year: i32,
+    /// Document store to take the PDFs from
+    #[arg(long, value_parser = existing_dir)]
+    pub store: PathBuf,
+    /// State file to read the documents of the year from
+    #[arg(long, default_value = DEFAULT_STATE_FILE)]
+    pub state: PathBuf,
+    /// Read the SQLite index instead of the state file
+    #[arg(long, value_parser = existing_path)]
+    pub index: Option<PathBuf>,
+    /// Only documents of this tax identification number
+    #[arg(long, value_parser = parse_tax_id)]
+    pub tax_id: Option<String>,
+    /// ZIP file to write (default: exat-etax-<year>.zip)
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// PEM RSA private key to sign the package's SHA256SUMS with
+    #[arg(long, value_parser = existing_path)]
+    pub sign_key: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum TextOrJson {
+    Text,
+    Json,
+}
+
+#[derive(Args)]
+pub struct DiffArgs {
+    /// Index or state file (optionally @YYYY-MM-DD, as known then), saved search JSON, or live:YYYY-MM-DD..YYYY-MM-DD
+    #[arg(value_parser = diff::Source::parse)]
+    pub old: diff::Source,
+    /// The same kinds of source, compared against <old>
+    #[arg(value_parser = diff::Source::parse)]
+    pub new: diff::Source,
+    /// Tax identification number; required by live: searches
+    #[arg(long, value_parser = parse_tax_id)]
+    pub tax_id: Option<String>,
+    /// Only documents dated on or after this date (YYYY-MM-DD)
+    #[arg(short = 'S', long, value_parser = parse_date)]
+    pub since: Option<NaiveDate>,
+    /// Only documents dated on or before this date (YYYY-MM-DD)
+    #[arg(short = 'U', long, value_parser = parse_date)]
+    pub until: Option<NaiveDate>,
+    /// A line per difference, or one JSON object
+    #[arg(short, long, default_value = "text")]
+    pub format: TextOrJson,
+    /// Fail when the two differ
+    #[arg(long)]
+    pub exit_code: bool,
+}
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// Directory to import, searched recursively
+    #[arg(value_parser = existing_dir)]
+    pub dir: PathBuf,
+    /// Tax identification number of files whose path names none
+    #[arg(long, value_parser = parse_tax_id)]
+    pub tax_id: Option<String>,
+    /// Document store to add the PDFs to
+    #[arg(long)]
+    pub store: Option<PathBuf>,
+    /// SQLite index to record the documents in
+    #[arg(long)]
+    pub index: Option<PathBuf>,
+    /// State file to mark the documents downloaded in
+    #[arg(long)]
+    pub state: Option<PathBuf>,
+    #[command(flatten)]
+    pub thai: ThaiArgs,
+}
+
+// The same targets as `import`, described for an archive being moved.
+#[derive(Args)]
+#[command(
+    mut_arg("dir", |a| a.help("Root of the existing archive")),
+    mut_arg("tax_id", |a| a.help("Tax identification number, when the layout has no {taxId}")),
+    mut_arg("store", |a| a.help("Document store to move the PDFs into"))
+)]
+pub struct MigrateArgs {
+    #[command(flatten)]
+    pub import: ImportArgs,
+    /// Where files are under the root, e.g. "{year}/{month}/{docNo}.pdf"; also {day}, {taxId}, {fileName} and *
+    #[arg(long)]
+    pub from_layout: String,
+    /// Delete each file once the store holds it
+    #[arg(long, requires = "store")]
+    pub remove_source: bool,
+    /// Also search the portal for the months the archive covers
+    #[arg(long)]
+    pub check_portal: bool,
+    /// Print the files that would be written and removed, and change nothing
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ParseFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Args)]
+pub struct ParseArgs {
+    /// ZIP file, directory, XML file or PDF with the XML attached
+    #[arg(required = true, value_parser = existing_path)]
+    pub input: Vec<PathBuf>,
+    /// One JSON object per invoice, or one CSV row per line item
+    #[arg(short, long, default_value = "json")]
+    pub format: ParseFormat,
+    /// File to write (default: stdout)
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    /// ZIP file, directory, XML file or PDF with the XML attached
+    #[arg(required = true, value_parser = existing_path)]
+    pub input: Vec<PathBuf>,
+    /// Trusted CA certificate (PEM bundle or DER); repeat for several
+    #[arg(long, required = true, value_parser = existing_path)]
+    pub ca: Vec<PathBuf>,
+    /// A line per document, or a JSON report per document with every check
+    #[arg(short, long, default_value = "text")]
+    pub format: TextOrJson,
+    /// File to write the report to (default: stdout)
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct PassphraseArgs {
+    /// Passphrase of the backup (default: ask)
+    #[arg(long, env = "EXAT_ETAX_BACKUP_PASSPHRASE", hide_env_values = true)]
+    pub passphrase: Option<String>,
+}
+
+#[derive(Args)]
+pub struct BackupArgs {
+    /// Backup file to write, e.g. etax-2026-10.age
+    pub output: PathBuf,
+    /// SQLite index to include
+    #[arg(long, value_parser = existing_path)]
+    pub index: Option<PathBuf>,
+    /// State file to include
+    #[arg(long, value_parser = existing_path)]
+    pub state: Option<PathBuf>,
+    /// Document store whose store.json to include
+    #[arg(long, value_parser = existing_dir)]
+    pub store: Option<PathBuf>,
+    /// Also include every PDF and text file of the store
+    #[arg(long, requires = "store")]
+    pub documents: bool,
+    #[command(flatten)]
+    pub passphrase: PassphraseArgs,
+}
+
+#[derive(Args)]
+pub struct RestoreArgs {
+    /// Backup file written by backup
+    #[arg(value_parser = existing_path)]
+    pub input: PathBuf,
+    /// Where to restore the index (default: where it was backed up from)
+    #[arg(long)]
+    pub index: Option<PathBuf>,
+    /// Where to restore the state file (default: where it was backed up from)
+    #[arg(long)]
+    pub state: Option<PathBuf>,
+    /// Where to restore the store (default: where it was backed up from)
+    #[arg(long)]
+    pub store: Option<PathBuf>,
+    /// Overwrite an existing index, state file or store.json
+    #[arg(long)]
+    pub force: bool,
+    #[command(flatten)]
+    pub passphrase: PassphraseArgs,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Target {
+    #[value(name = "quickbooks")]
+    QuickBooks,
+    #[value(name = "flowaccount")]
+    FlowAccount,
+    Peak,
+}
+
+impl Target {
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::QuickBooks => "quickbooks",
+            Target::FlowAccount => "flowaccount",
+            Target::Peak => "peak",
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct PushArgs {
+    /// Accounting system
+    pub target: Target,
+    /// Configured [<target>.<profile>] to push to
+    #[arg(long, default_value = "default")]
+    pub profile: String,
+    #[command(flatten)]
+    pub documents: DocumentArgs,
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub listen: SocketAddr,
+    /// SQLite index for the viewer and polling triggers, and to record searches in
+    #[arg(long, value_parser = existing_path)]
+    pub index: Option<PathBuf>,
+    /// Key clients must send
+    #[arg(long, env = "EXAT_ETAX_API_KEY", hide_env_values = true)]
+    pub api_key: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Remove every cached search result
+    Clear,
+}
+
+#[derive(Subcommand)]
+pub enum ProfileCommand {
+    /// Add a profile, or replace the one of that name
+    Add {
+        /// Name to give --profile
+        #[arg(value_parser = profile::validate_name)]
+        name: String,
+        /// Tax identification number; asked for when left out, so that it stays out of shell history
+        #[arg(long, value_parser = parse_tax_id)]
+        tax_id: Option<String>,
+    },
+    /// Remove a profile from the keyring
+    Remove {
+        /// Name of the profile
+        #[arg(value_parser = profile::validate_name)]
+        name: String,
+    },
+    /// List the profiles, with their tax IDs masked
+    List,
+}
+
+#[derive(Args)]
+pub struct PlanArgs {
+    /// Profiles file, or - for stdin (JSON or CSV: taxId,documents,name)
+    #[arg(default_value = "-")]
+    pub profiles: String,
+    /// Nightly window in local time (see --timezone), e.g. 01:00-05:00
+    #[arg(long, value_parser = plan::Window::parse)]
+    pub window: plan::Window,
+    /// Requests allowed over the whole window (default: what --max-requests-per-minute allows)
+    #[arg(long)]
+    pub budget: Option<u64>,
+    /// Search results per page, to estimate the requests of a search
+    #[arg(long, default_value = "50", value_parser = parse_concurrency)]
+    pub page_size: usize,
+    /// SQLite index to estimate the documents of profiles that don't give them
+    #[arg(long)]
+    pub index: Option<PathBuf>,
+    /// Output format
+    #[arg(short, long, default_value = "text")]
+    pub format: PlanFormat,
+    /// Options for each sync of --format cron, after --
+    #[arg(last = true)]
+    pub sync_args: Vec<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum PlanFormat {
+    Text,
+    Json,
+    Cron,
+}
+
+#[derive(Subcommand)]
+pub enum DevCommand {
+    /// Fill a new store (and optionally an index and state) with fake documents
+    GenSample(GenSampleArgs),
+}
+
+#[derive(Args)]
+pub struct GenSampleArgs {
+    /// Store directory to create; must not hold documents yet
+    #[arg(long)]
+    pub store: PathBuf,
+    /// SQLite index to create alongside
+    #[arg(long)]
+    pub index: Option<PathBuf>,
+    /// State file to create alongside
+    #[arg(long)]
+    pub state: Option<PathBuf>,
+    /// Number of months to generate, up to --until
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(1..=120))]
+    pub months: u32,
+    /// Last month to generate (YYYY-MM, default: this month, up to today)
+    #[arg(long, value_parser = parse_month)]
+    pub until: Option<NaiveDate>,
+    /// Number of companies (tax IDs)
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..=6))]
+    pub companies: u32,
+    /// Documents per company and month
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..=1000))]
+    pub documents: u32,
+    /// Seed; the same seed and options generate the same documents
+    #[arg(long, default_value_t = 1)]
+    pub seed: u64,
+}
+
+#[derive(Args)]
+pub struct HoldArgs {
+    /// Directory containing the holds
+    #[arg(long, global = true, default_value = DEFAULT_HOLDS_DIR)]
+    pub holds_dir: PathBuf,
+    #[command(subcommand)]
+    pub command: HoldCommand,
+}
+
+#[derive(Subcommand)]
+pub enum HoldCommand {
+    /// Search, download and freeze the result as a new hold
+    Create {
+        /// Name of the hold
+        name: String,
+        /// Tax identification number
+        #[arg(value_parser = parse_tax_id)]
+        tax_id: String,
+        /// Start date of the search (default: today)
+        #[arg(short = 'S', long, value_parser = parse_date)]
+        since: Option<NaiveDate>,
+        /// End date of the search (default: today)
+        #[arg(short = 'U', long, value_parser = parse_date)]
+        until: Option<NaiveDate>,
+    },
+    /// List existing holds
+    List,
+    /// Re-hash every hold and check the chain
+    Verify,
+}
+
+// What becomes of downloaded documents, the same for every command that downloads.
+#[derive(Args)]
+pub struct DownloadArgs {
+    /// Add _manifest/ entries (manifest, run summary, checksums) to each ZIP
+    #[arg(long)]
+    pub embed_manifest: bool,
+    /// manifest.json recording the SHA-256 of every downloaded PDF; warns when one differs from an earlier copy
+    #[arg(long)]
+    pub manifest: Option<PathBuf>,
+    /// Keep the .part file of an interrupted or failed download
+    #[arg(long)]
+    pub keep_partial: bool,
+    /// Also extract documents into this store, keeping re-issued versions
+    #[arg(long)]
+    pub store: Option<PathBuf>,
+    #[command(flatten)]
+    pub thai: ThaiArgs,
+    #[command(flatten)]
+    pub upload: UploadArgs,
+    #[command(flatten)]
+    pub notify: NotifyArgs,
+}
+
+// For commands that download what they search for.
+#[derive(Args)]
+pub struct SearchedArgs {
+    /// When the download fails, still record what the search found and leave the documents to the next run
+    #[arg(long)]
+    pub metadata_only_fallback: bool,
+    /// Queue the documents of a failed download in this file for flush-downloads; implies --metadata-only-fallback
+    #[arg(long)]
+    pub queue: Option<PathBuf>,
+    /// Also download the documents that those found refer to, such as the invoice a credit note corrects, whatever their date
+    #[arg(long)]
+    pub include_related: bool,
+}
+
+#[derive(Args)]
+pub struct ThaiArgs {
+    /// Mark Thai word boundaries (U+200B) in extracted text
+    #[arg(long)]
+    pub thai_segment: bool,
+    /// Extra words for Thai segmentation, one per line
+    #[arg(long, requires = "thai_segment", value_parser = existing_path)]
+    pub thai_dict: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct UploadArgs {
+    /// Upload downloaded archives to S3-compatible storage, e.g. s3://bucket/prefix
+    #[arg(long, value_parser = parse_upload)]
+    pub upload: Option<S3Target>,
+    /// Upload the individual PDFs instead of the ZIP
+    #[arg(long, requires = "upload")]
+    pub upload_pdfs: bool,
+    /// Delete the local ZIP after a successful upload
+    #[arg(long, requires = "upload")]
+    pub delete_local: bool,
+}
+
+#[derive(Args)]
+pub struct NotifyArgs {
+    /// POST a JSON description of newly downloaded documents to this URL
+    #[arg(long)]
+    pub notify_url: Option<String>,
+    /// Run this command with the new-documents JSON on stdin
+    #[arg(long)]
+    pub notify_cmd: Option<String>,
+    /// Send a LINE message summarizing new documents, with this Messaging API channel access token
+    #[arg(long, env = "EXAT_ETAX_LINE_CHANNEL_TOKEN", hide_env_values = true, requires = "line_to")]
+    pub line_channel_token: Option<String>,
+    /// LINE user, group or room ID to push messages to
+    #[arg(long, env = "EXAT_ETAX_LINE_TO")]
+    pub line_to: Option<String>,
+    /// Message template for LINE
+    #[arg(long, requires = "line_channel_token")]
+    pub line_template: Option<String>,
+    /// Send a Telegram message summarizing new documents
+    #[arg(long, env = "EXAT_ETAX_TELEGRAM_BOT_TOKEN", hide_env_values = true, requires = "telegram_chat_id")]
+    pub telegram_bot_token: Option<String>,
+    /// Telegram chat to send messages to
+    #[arg(long, env = "EXAT_ETAX_TELEGRAM_CHAT_ID")]
+    pub telegram_chat_id: Option<String>,
+    /// Message template for Telegram
+    #[arg(long, requires = "telegram_bot_token")]
+    pub telegram_template: Option<String>,
+    /// Per-document line template used by {documents} in chat messages
+    #[arg(long)]
+    pub document_template: Option<String>,
+}
+
+pub(crate) fn parse_tax_id(s: &str) -> Result<String, String> {
+    if s.len() == 13 && s.chars().all(|c| c.is_ascii_digit()) {
+        Ok(s.to_string())
+    } else {
+        Err("a tax ID is 13 digits".to_string())
+    }
+}
+
+pub(crate) fn parse_date(s: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|e| format!("not a YYYY-MM-DD date ({})", e))
+}
+
+// The first day of a YYYY-MM month.
+fn parse_month(s: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(&format!("{}-01", s), "%Y-%m-%d").map_err(|_| "not a YYYY-MM month".to_string())
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    humantime::parse_duration(s).map_err(|e| e.to_string())
+}
+
+// `--limit-rate`: bytes per second, with an optional k, M or G suffix as curl takes it.
+fn parse_rate(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, multiplier) = match s.chars().last().map(|c| c.to_ascii_lowercase()) {
+        Some('k') => (&s[..s.len() - 1], 1 << 10),
+        Some('m') => (&s[..s.len() - 1], 1 << 20),
+        Some('g') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n.is_finite() && n * multiplier as f64 >= 1.0 => Ok((n * multiplier as f64) as u64),
+        _ => Err(format!("invalid rate {:?}; use bytes per second, e.g. 500k or 2M", s)),
+    }
+}
+
+fn parse_concurrency(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err("expected a positive number".to_string()),
+    }
+}
+
+fn parse_upload(s: &str) -> Result<S3Target, String> {
+    S3Target::parse(s).map_err(|e| e.to_string())
+}
+
+fn existing_path(s: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(s);
+    if path.exists() {
+        Ok(path)
+    } else {
+        Err("no such file or directory".to_string())
+    }
+}
+
+fn existing_dir(s: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(s);
+    if path.is_dir() {
+        Ok(path)
+    } else {
+        Err("no such directory".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn arguments_are_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn rates_take_curl_suffixes() {
+        assert_eq!(parse_rate("200000"), Ok(200_000));
+        assert_eq!(parse_rate("500k"), Ok(512_000));
+        assert_eq!(parse_rate("1.5M"), Ok(1_572_864));
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("fast").is_err());
+    }
+}
@@ -0,0 +1,362 @@
+use crate::amounts::{self, Field, Rule};
+use crate::cost_center::{Allocation, CostCenter};
+use crate::fleet::{self, Pattern};
+use crate::hooks::{self, Step};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// Looked for in the working directory when `--config` is not given.
+pub const DEFAULT_CONFIG_FILE: &str = "exat-etax.toml";
+
+// Optional TOML configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub extraction: Extraction,
+    pub cost_centers: Vec<CostCenter>,
+    // Cost center of documents no rule matches.
+    pub default_cost_center: Option<String>,
+    pub export: Export,
+    // QuickBooks Online companies for `push quickbooks`, by profile name.
+    pub quickbooks: BTreeMap<String, QuickBooks>,
+    // FlowAccount and PEAK businesses for `push flowaccount` / `push peak`.
+    pub flowaccount: BTreeMap<String, FlowAccount>,
+    pub peak: BTreeMap<String, Peak>,
+    // Steps run on every downloaded document; see `hooks`.
+    pub hooks: Vec<Step>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Export {
+    pub ledger: Ledger,
+    pub ubl: Ubl,
+    pub sap: Sap,
+}
+
+// Accounts used by `export --format ledger`. Each document's cost center is appended
+// to the expense account, e.g. `Expenses:Tolls:SALES`.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ledger {
+    pub expense_account: String,
+    pub vat_account: String,
+    pub payment_account: String,
+    pub commodity: String,
+    pub payee: String,
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Ledger {
+            expense_account: "Expenses:Tolls".to_string(),
+            vat_account: "Assets:VAT:Input".to_string(),
+            payment_account: "Assets:EasyPass".to_string(),
+            commodity: "THB".to_string(),
+            payee: "EXAT".to_string(),
+        }
+    }
+}
+
+// Parties of `export --format ubl` invoices. The customer's tax ID is the one the
+// documents were searched under.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ubl {
+    pub supplier_name: String,
+    pub supplier_tax_id: Option<String>,
+    pub customer_name: Option<String>,
+}
+
+impl Default for Ubl {
+    fn default() -> Self {
+        Ubl { supplier_name: "Expressway Authority of Thailand".to_string(), supplier_tax_id: None, customer_name: None }
+    }
+}
+
+// An OAuth app authorized for one QuickBooks Online company:
+//
+//     [quickbooks.default]
+//     realm_id = "9130000000000000"
+//     client_id = "AB..."
+//     refresh_token_file = "qbo-default.token"
+//     vendor_id = "58"
+//     expense_account_id = "77"
+//     payment_account_id = "41"
+//
+// The client secret and refresh token may instead come from the environment; see
+// `quickbooks::connect`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuickBooks {
+    pub realm_id: String,
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    #[serde(default)]
+    pub refresh_token_file: Option<PathBuf>,
+    #[serde(default)]
+    pub sandbox: bool,
+    #[serde(default)]
+    pub entry: QuickBooksEntry,
+    pub vendor_id: String,
+    pub expense_account_id: String,
+    // The bank or credit card account an expense is paid from.
+    #[serde(default)]
+    pub payment_account_id: Option<String>,
+    #[serde(default)]
+    pub tax_code_id: Option<String>,
+    // QuickBooks class ID per cost center.
+    #[serde(default)]
+    pub classes: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuickBooksEntry {
+    #[default]
+    Expense,
+    Bill,
+}
+
+// Vendor name of expenses created in FlowAccount and PEAK when none is configured.
+fn exat_name() -> String {
+    "การทางพิเศษแห่งประเทศไทย".to_string()
+}
+
+// A FlowAccount Open API app (client credentials) for one business:
+//
+//     [flowaccount.default]
+//     client_id = "..."
+//
+// The client secret may instead come from the environment; see `flowaccount::connect`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlowAccount {
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    #[serde(default)]
+    pub sandbox: bool,
+    // Overrides the production/sandbox API base URL.
+    #[serde(default)]
+    pub api_url: Option<String>,
+    #[serde(default = "exat_name")]
+    pub contact_name: String,
+    #[serde(default)]
+    pub contact_tax_id: Option<String>,
+}
+
+// A PEAK Open API connection for one business. EXAT must exist as a contact and the
+// expense account in the chart of accounts:
+//
+//     [peak.default]
+//     connect_id = "..."
+//     contact_code = "C00012"
+//     account_code = "530405"
+//
+// The connect key, password and user token may instead come from the environment;
+// see `peak::connect`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Peak {
+    pub connect_id: String,
+    #[serde(default)]
+    pub connect_key: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub user_token: Option<String>,
+    #[serde(default)]
+    pub sandbox: bool,
+    #[serde(default)]
+    pub api_url: Option<String>,
+    pub contact_code: String,
+    pub account_code: String,
+}
+
+// Flat-file layout of `export --format sap`, for an SAP FI document import. Each
+// column's value is a template over the export columns ({docNo}, {docDate}, {total},
+// {net}, {vat}, {costCenter}, ...) and literal text, so company code, vendor and G/L
+// account are set there:
+//
+//     [[export.sap.columns]]
+//     name = "BUKRS"
+//     value = "1000"
+//
+// Configured columns replace the default layout entirely.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sap {
+    pub delimiter: char,
+    pub header: bool,
+    // chrono format of {docDate}.
+    pub date_format: String,
+    pub decimal_separator: String,
+    pub columns: Vec<SapColumn>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SapColumn {
+    pub name: String,
+    pub value: String,
+}
+
+impl Default for Sap {
+    fn default() -> Self {
+        let column = |name: &str, value: &str| SapColumn { name: name.to_string(), value: value.to_string() };
+        Sap {
+            delimiter: ';',
+            header: true,
+            date_format: "%d.%m.%Y".to_string(),
+            decimal_separator: ".".to_string(),
+            columns: vec![
+                column("BLDAT", "{docDate}"),
+                column("BUDAT", "{docDate}"),
+                column("BLART", "KR"),
+                column("BUKRS", "1000"),
+                column("WAERS", "THB"),
+                column("XBLNR", "{docNo}"),
+                column("LIFNR", "EXAT"),
+                column("WRBTR", "{total}"),
+                column("WMWST", "{vat}"),
+                column("MWSKZ", "V7"),
+                column("HKONT", "640000"),
+                column("KOSTL", "{costCenter}"),
+                column("SGTXT", "{docType} {plates}"),
+            ],
+        }
+    }
+}
+
+// Extraction rules, so a change in EXAT's PDF layout can be handled without a new
+// build:
+//
+//     [[extraction.rules]]
+//     field = "total"
+//     pattern = '(?i)ยอดเงินสุทธิ'
+//     strength = 0.7
+//
+//     [[extraction.plazas]]
+//     pattern = 'Plaza:\s*(\w+)'
+//
+//     [[extraction.routes]]
+//     pattern = 'Sirat'
+//     name = "ทางพิเศษศรีรัช"
+//
+// Rules are added to the built-in ones unless `replace_defaults = true`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Extraction {
+    pub replace_defaults: bool,
+    // Amount rules.
+    pub rules: Vec<RuleConfig>,
+    pub plates: Vec<PatternConfig>,
+    pub plazas: Vec<PatternConfig>,
+    pub routes: Vec<PatternConfig>,
+    pub cards: Vec<PatternConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    pub field: Field,
+    // Regex matching the label printed before the value.
+    pub pattern: String,
+    #[serde(default = "default_strength")]
+    pub strength: f64,
+}
+
+// The value is `name` if given, else the first capture group, else the whole match.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatternConfig {
+    pub pattern: String,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+fn default_strength() -> f64 {
+    0.6
+}
+
+impl Config {
+    // An explicit path must exist; the default file is optional.
+    pub fn load(path: Option<&Path>) -> Result<Config, Box<dyn std::error::Error>> {
+        let (path, required) = match path {
+            Some(path) => (path, true),
+            None => (Path::new(DEFAULT_CONFIG_FILE), false),
+        };
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => return Ok(Config::default()),
+            Err(e) => return Err(format!("Cannot read {}: {}", path.display(), e).into()),
+        };
+        let config: Config = toml::from_str(&content).map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        // Compile the rules now so a bad pattern fails at startup, not mid-run.
+        config.extraction.amounts()?;
+        config.extraction.fleet()?;
+        if let Some(rule) = config.cost_centers.iter().find(|c| c.budget.is_some_and(|b| b.is_nan() || b < 0.0)) {
+            return Err(format!("cost center {}: budget must not be negative", rule.name).into());
+        }
+        config.export.sap.validate()?;
+        hooks::validate(&config.hooks)?;
+        Ok(config)
+    }
+
+    pub fn allocation(&self) -> Allocation {
+        Allocation { rules: self.cost_centers.clone(), default: self.default_cost_center.clone() }
+    }
+}
+
+impl Extraction {
+    pub fn amounts(&self) -> Result<amounts::Extractor, Box<dyn std::error::Error>> {
+        let mut rules = if self.replace_defaults { Vec::new() } else { amounts::default_rules() };
+        for (i, rule) in self.rules.iter().enumerate() {
+            if !(0.0..=1.0).contains(&rule.strength) {
+                return Err(format!("extraction rule {}: strength must be between 0 and 1", i + 1).into());
+            }
+            let label = Regex::new(&rule.pattern).map_err(|e| format!("extraction rule {}: {}", i + 1, e))?;
+            rules.push(Rule { field: rule.field, label, strength: rule.strength });
+        }
+        Ok(amounts::Extractor::new(rules))
+    }
+
+    // Configured patterns are tried before the built-in ones.
+    pub fn fleet(&self) -> Result<fleet::Extractor, Box<dyn std::error::Error>> {
+        let patterns = |kind: &str, configured: &[PatternConfig], defaults: Vec<Pattern>| -> Result<Vec<Pattern>, Box<dyn std::error::Error>> {
+            let mut patterns = Vec::new();
+            for (i, p) in configured.iter().enumerate() {
+                let regex = Regex::new(&p.pattern).map_err(|e| format!("extraction {} pattern {}: {}", kind, i + 1, e))?;
+                patterns.push(Pattern { regex, name: p.name.clone() });
+            }
+            if !self.replace_defaults {
+                patterns.extend(defaults);
+            }
+            Ok(patterns)
+        };
+        Ok(fleet::Extractor {
+            plates: patterns("plate", &self.plates, fleet::default_plates())?,
+            plazas: patterns("plaza", &self.plazas, fleet::default_plazas())?,
+            routes: patterns("route", &self.routes, fleet::default_routes())?,
+            cards: patterns("card", &self.cards, fleet::default_cards())?,
+        })
+    }
+}
+
+impl Sap {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.delimiter.is_ascii() {
+            return Err("export.sap.delimiter must be a single ASCII character".into());
+        }
+        for column in &self.columns {
+            if let Some(unknown) = crate::export::unknown_placeholder(&column.value) {
+                return Err(format!("export.sap column {}: unknown placeholder {{{}}}", column.name, unknown).into());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use crate::fleet::{self, Fleet};
+use serde::{Deserialize, Serialize};
+
+// A cost-center rule from the configuration file:
+//
+//     [[cost_centers]]
+//     name = "SALES"
+//     plates = ["1กข 1234", "2ขค 5678"]
+//     cards = ["1234 5678 9012 3456"]
+//     budget = 15000
+//
+// A document belongs to the first rule with any matching tax ID, plate, card, plaza
+// or route. `budget` is a monthly limit in THB; see `Allocation::budget`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CostCenter {
+    pub name: String,
+    #[serde(default)]
+    pub tax_ids: Vec<String>,
+    #[serde(default)]
+    pub plates: Vec<String>,
+    #[serde(default)]
+    pub cards: Vec<String>,
+    #[serde(default)]
+    pub plazas: Vec<String>,
+    #[serde(default)]
+    pub routes: Vec<String>,
+    #[serde(default)]
+    pub budget: Option<f64>,
+}
+
+impl CostCenter {
+    fn matches(&self, tax_id: &str, fleet: Option<&Fleet>) -> bool {
+        if self.tax_ids.iter().any(|t| t == tax_id) {
+            return true;
+        }
+        let Some(fleet) = fleet else {
+            return false;
+        };
+        // Written the way the extractor normalizes them, so "1 กข-1234" in the config
+        // still matches.
+        self.plates.iter().any(|p| fleet.plates.contains(&fleet::normalize_plate(p)))
+            || self.cards.iter().any(|c| fleet.cards.contains(&fleet::normalize_card(c)))
+            || self.plazas.iter().any(|p| fleet.plazas.contains(p))
+            || self.routes.iter().any(|r| fleet.routes.contains(r))
+    }
+}
+
+pub fn assign<'a>(rules: &'a [CostCenter], default: Option<&'a str>, tax_id: &str, fleet: Option<&Fleet>) -> Option<&'a str> {
+    rules
+        .iter()
+        .find(|rule| rule.matches(tax_id, fleet))
+        .map(|rule| rule.name.as_str())
+        .or(default)
+}
+
+// The configured rules and fallback, owned so they can travel with run options.
+#[derive(Debug, Clone, Default)]
+pub struct Allocation {
+    pub rules: Vec<CostCenter>,
+    pub default: Option<String>,
+}
+
+impl Allocation {
+    pub fn has_budgets(&self) -> bool {
+        self.rules.iter().any(|rule| rule.budget.is_some())
+    }
+
+    pub fn assign(&self, tax_id: &str, fleet: Option<&Fleet>) -> Option<&str> {
+        assign(&self.rules, self.default.as_deref(), tax_id, fleet)
+    }
+
+    // Several rules may share a name; the first one with a budget sets it.
+    pub fn budget(&self, name: &str) -> Option<f64> {
+        self.rules.iter().filter(|rule| rule.name == name).find_map(|rule| rule.budget)
+    }
+}
+
+// A cost center whose month-to-date total went over its budget.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetAlert {
+    pub cost_center: String,
+    pub month: String,
+    pub budget: f64,
+    pub total: f64,
+}
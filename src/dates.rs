@@ -0,0 +1,124 @@
+use chrono::{DateTime, LocalResult, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::sync::OnceLock;
+
+pub const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+pub const ONLY_DATE_FORMAT: &str = "%Y%m%d";
+
+// EXAT works in Thai local time, and so does everything here unless `--timezone`
+// says otherwise: the dates sent to the API and read from its results, the days of
+// --since/--until and "today", cron schedules and the timestamps in file names.
+pub const DEFAULT_TIMEZONE: Tz = chrono_tz::Asia::Bangkok;
+
+static TIMEZONE: OnceLock<Tz> = OnceLock::new();
+
+pub fn set_timezone(timezone: Tz) {
+    let _ = TIMEZONE.set(timezone);
+}
+
+pub fn timezone() -> Tz {
+    TIMEZONE.get().copied().unwrap_or(DEFAULT_TIMEZONE)
+}
+
+// `--timezone`, an IANA name such as Asia/Bangkok.
+pub fn parse_timezone(name: &str) -> Result<Tz, String> {
+    name.trim().parse().map_err(|_| format!("unknown timezone {:?}; use an IANA name such as Asia/Bangkok", name))
+}
+
+pub fn today() -> NaiveDate {
+    Utc::now().with_timezone(&timezone()).date_naive()
+}
+
+// Parse a YYYY-MM-DD date (empty means today) into the first or last second of that
+// day in local time.
+pub fn parse_date(date_str: &str, start_of_day: bool) -> Result<DateTime<Utc>, chrono::ParseError> {
+    let mut date = today();
+    if !date_str.is_empty() {
+        date = NaiveDate::parse_from_str(date_str, "%Y-%m-%d")?;
+    }
+
+    Ok(day_bound(date, start_of_day))
+}
+
+pub fn day_bound(date: NaiveDate, start_of_day: bool) -> DateTime<Utc> {
+    bound_in(&timezone(), date, start_of_day)
+}
+
+// The first or last second of `date` in `timezone`. Where clocks go back over
+// midnight the day starts at the first and ends at the last of the repeated times;
+// where they skip it, the day starts when they land.
+fn bound_in(timezone: &Tz, date: NaiveDate, start_of_day: bool) -> DateTime<Utc> {
+    let time = if start_of_day { NaiveTime::MIN } else { NaiveTime::from_hms_opt(23, 59, 59).expect("valid time") };
+    let local = date.and_time(time);
+    let datetime = match timezone.from_local_datetime(&local) {
+        LocalResult::Single(datetime) => datetime,
+        LocalResult::Ambiguous(first, last) => if start_of_day { first } else { last },
+        LocalResult::None => {
+            let step = chrono::Duration::minutes(if start_of_day { 15 } else { -15 });
+            (1..=4 * 24)
+                .find_map(|i| timezone.from_local_datetime(&(local + step * i)).earliest())
+                .expect("Invalid local datetime")
+        }
+    };
+
+    datetime.with_timezone(&Utc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn bangkok_days_are_seven_hours_ahead() {
+        assert_eq!(bound_in(&DEFAULT_TIMEZONE, date("2026-10-14"), true), utc("2026-10-13T17:00:00Z"));
+        assert_eq!(bound_in(&DEFAULT_TIMEZONE, date("2026-10-14"), false), utc("2026-10-14T16:59:59Z"));
+        // No daylight saving: the same offset on the days Europe and America change.
+        for day in ["2026-03-08", "2026-03-29", "2026-10-25", "2026-11-01"] {
+            let start = bound_in(&DEFAULT_TIMEZONE, date(day), true);
+            let end = bound_in(&DEFAULT_TIMEZONE, date(day), false);
+            assert_eq!((end - start).num_seconds(), 24 * 3600 - 1, "{}", day);
+            assert_eq!(start.with_timezone(&DEFAULT_TIMEZONE).format(DATE_FORMAT).to_string(), format!("{} 00:00:00", day));
+        }
+    }
+
+    #[test]
+    fn utc_times_format_in_bangkok() {
+        let at = utc("2026-12-31T18:30:00Z");
+        assert_eq!(at.with_timezone(&DEFAULT_TIMEZONE).format(DATE_FORMAT).to_string(), "2027-01-01 01:30:00");
+        assert_eq!(at.with_timezone(&DEFAULT_TIMEZONE).format(ONLY_DATE_FORMAT).to_string(), "20270101");
+        assert_eq!(at.with_timezone(&DEFAULT_TIMEZONE).date_naive(), date("2027-01-01"));
+    }
+
+    #[test]
+    fn other_timezones_follow_their_rules() {
+        let london = parse_timezone("Europe/London").unwrap();
+        assert_eq!(bound_in(&london, date("2026-01-15"), true), utc("2026-01-15T00:00:00Z"));
+        assert_eq!(bound_in(&london, date("2026-07-15"), true), utc("2026-07-14T23:00:00Z"));
+        // Clocks go forward at 01:00 on 29 March, so that day is an hour short.
+        let start = bound_in(&london, date("2026-03-29"), true);
+        let end = bound_in(&london, date("2026-03-29"), false);
+        assert_eq!((end - start).num_seconds(), 23 * 3600 - 1);
+    }
+
+    #[test]
+    fn a_day_starting_in_a_gap_starts_when_clocks_land() {
+        // Cuba skips from 00:00 to 01:00 on the second Sunday of March.
+        let havana = parse_timezone("America/Havana").unwrap();
+        assert_eq!(bound_in(&havana, date("2026-03-08"), true), utc("2026-03-08T05:00:00Z"));
+    }
+
+    #[test]
+    fn unknown_timezones_are_rejected() {
+        assert!(parse_timezone("Asia/Bangkok").is_ok());
+        assert!(parse_timezone("GMT+7").is_err());
+        assert!(parse_timezone("Bangkok").is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use crate::api::{self, EtaxApi};
+use crate::dates::{self, DATE_FORMAT};
+use crate::index::Index;
+use crate::query::{self, Filter};
+use crate::state::State;
+use chrono::NaiveDate;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use tracing::info;
+
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+// One side of a diff.
+#[derive(Clone)]
+pub enum Source {
+    // An index or state file, as known at the end of `as_of` (default: now), or a
+    // saved search result: a hold's search.json, a --record response or JSON lines.
+    File { path: PathBuf, as_of: Option<NaiveDate> },
+    // A search of the portal over the range.
+    Live { since: NaiveDate, until: NaiveDate },
+}
+
+impl Source {
+    // `live:2026-10-01..2026-10-31`, `etax.sqlite@2026-10-01`, `search.json`
+    pub fn parse(spec: &str) -> Result<Source, String> {
+        let date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| format!("Invalid date {:?} in {:?}; use YYYY-MM-DD", s, spec));
+        if let Some(range) = spec.strip_prefix("live:") {
+            let (since, until) = range.split_once("..").ok_or_else(|| format!("Invalid {:?}; use live:YYYY-MM-DD..YYYY-MM-DD", spec))?;
+            return Ok(Source::Live { since: date(since)?, until: date(until)? });
+        }
+        // A path may itself contain an @.
+        match spec.rsplit_once('@').and_then(|(path, as_of)| Some((path, date(as_of).ok()?))) {
+            Some((path, as_of)) if !path.is_empty() => Ok(Source::File { path: PathBuf::from(path), as_of: Some(as_of) }),
+            _ => Ok(Source::File { path: PathBuf::from(spec), as_of: None }),
+        }
+    }
+}
+
+// The search result items of a source by docNo. `filter` narrows index and state
+// files by tax ID and every source by date.
+pub async fn load(api: &impl EtaxApi, source: &Source, filter: &Filter) -> Result<BTreeMap<String, Value>, Box<dyn std::error::Error>> {
+    let items = match source {
+        Source::Live { since, until } => {
+            let tax_id = filter.tax_id.as_deref().ok_or("A live: search needs --tax-id")?;
+            let from = dates::day_bound(*since, true).with_timezone(&dates::timezone()).format(DATE_FORMAT).to_string();
+            let to = dates::day_bound(*until, false).with_timezone(&dates::timezone()).format(DATE_FORMAT).to_string();
+            info!("Searching documents from {} to {}", from, to);
+            api::search(api, tax_id, &from, &to).await?
+        }
+        Source::File { path, as_of } => {
+            let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let filter = Filter { as_of: as_of.map(|d| dates::day_bound(d, false)), ..filter.clone() };
+            if data.starts_with(SQLITE_HEADER) {
+                query::from_index(&Index::open(path)?, &filter)?.into_iter().map(|row| row.item).collect()
+            } else {
+                let json: Result<Value, _> = serde_json::from_slice(&data);
+                match json {
+                    Ok(Value::Object(object)) if object.contains_key("tax_ids") => {
+                        query::from_state(&serde_json::from_value::<State>(Value::Object(object))?, &filter).into_iter().map(|row| row.item).collect()
+                    }
+                    _ if as_of.is_some() => return Err(format!("{}: @date only applies to an index or state file", path.display()).into()),
+                    Ok(Value::Array(items)) => items,
+                    Ok(Value::Object(object)) if object.contains_key("reprintList") => object["reprintList"].as_array().cloned().unwrap_or_default(),
+                    Ok(_) => return Err(format!("{}: expected an array of search results", path.display()).into()),
+                    // JSON lines, one item each.
+                    Err(_) => String::from_utf8_lossy(&data)
+                        .lines()
+                        .filter(|line| !line.trim().is_empty())
+                        .map(serde_json::from_str)
+                        .collect::<Result<_, _>>()
+                        .map_err(|e| format!("{}: {}", path.display(), e))?,
+                }
+            }
+        }
+    };
+    let date = |item: &Value| api::doc_date(item);
+    Ok(items
+        .into_iter()
+        .filter(|item| filter.since.is_none_or(|since| date(item).is_some_and(|d| d >= since)) && filter.until.is_none_or(|until| date(item).is_some_and(|d| d <= until)))
+        .map(|item| (api::doc_no(&item), item))
+        .collect())
+}
+
+pub struct Change {
+    pub doc_no: String,
+    // (field, old, new); Null where a field is missing on one side.
+    pub fields: Vec<(String, Value, Value)>,
+}
+
+#[derive(Default)]
+pub struct Diff {
+    pub added: Vec<Value>,
+    pub removed: Vec<Value>,
+    pub changed: Vec<Change>,
+    pub unchanged: usize,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "added": self.added,
+            "removed": self.removed,
+            "changed": self.changed.iter().map(|c| json!({
+                "docNo": c.doc_no,
+                "changes": c.fields.iter().map(|(field, old, new)| json!({ "field": field, "old": old, "new": new })).collect::<Vec<_>>(),
+            })).collect::<Vec<_>>(),
+            "unchanged": self.unchanged,
+        })
+    }
+}
+
+// Documents only in `new` are added, only in `old` removed; a document in both is
+// changed when any field of its search result differs, e.g. a reissue's fileName.
+pub fn diff(old: &BTreeMap<String, Value>, new: &BTreeMap<String, Value>) -> Diff {
+    let mut result = Diff::default();
+    for (doc_no, item) in new {
+        let Some(before) = old.get(doc_no) else {
+            result.added.push(item.clone());
+            continue;
+        };
+        let fields = changed_fields(before, item);
+        if fields.is_empty() {
+            result.unchanged += 1;
+        } else {
+            result.changed.push(Change { doc_no: doc_no.clone(), fields });
+        }
+    }
+    result.removed = old.iter().filter(|(doc_no, _)| !new.contains_key(*doc_no)).map(|(_, item)| item.clone()).collect();
+    result
+}
+
+fn changed_fields(old: &Value, new: &Value) -> Vec<(String, Value, Value)> {
+    let (Some(old), Some(new)) = (old.as_object(), new.as_object()) else {
+        return if old == new { Vec::new() } else { vec![(String::new(), old.clone(), new.clone())] };
+    };
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    keys.into_iter()
+        .filter_map(|key| {
+            let (before, after) = (old.get(key).unwrap_or(&Value::Null), new.get(key).unwrap_or(&Value::Null));
+            (before != after).then(|| (key.clone(), before.clone(), after.clone()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::mock::MockApi;
+    use std::path::Path;
+
+    fn fixture(name: &str) -> Source {
+        Source::parse(&format!("{}/testdata/diff/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn two_saved_searches_differ_by_added_removed_and_changed_documents() {
+        let api = MockApi::new(Vec::new());
+        let old = load(&api, &fixture("september.json"), &Filter::default()).await.unwrap();
+        let new = load(&api, &fixture("october.jsonl"), &Filter::default()).await.unwrap();
+        let diff = diff(&old, &new);
+
+        assert_eq!(diff.added.iter().map(api::doc_no).collect::<Vec<_>>(), ["A4"]);
+        assert_eq!(diff.removed.iter().map(api::doc_no).collect::<Vec<_>>(), ["A3"]);
+        assert_eq!(diff.unchanged, 1);
+        let [change] = &diff.changed[..] else { panic!("one change expected") };
+        assert_eq!(change.doc_no, "A2");
+        assert_eq!(change.fields, [("fileName".to_string(), json!("A2.pdf"), json!("A2-reissued.pdf")), ("status".to_string(), Value::Null, json!("R"))]);
+        assert_eq!(diff.to_json()["changed"][0]["changes"][1], json!({ "field": "status", "old": null, "new": "R" }));
+    }
+
+    #[tokio::test]
+    async fn a_state_file_is_read_as_it_was_on_a_date() {
+        let api = MockApi::new(Vec::new());
+        let path = format!("{}/testdata/diff/state.json", env!("CARGO_MANIFEST_DIR"));
+        let then = load(&api, &Source::parse(&format!("{}@2026-10-02", path)).unwrap(), &Filter::default()).await.unwrap();
+        let now = load(&api, &Source::parse(&path).unwrap(), &Filter::default()).await.unwrap();
+        assert_eq!(then.keys().collect::<Vec<_>>(), ["A1", "A2"]);
+        assert_eq!(then["A2"]["fileName"], "A2.pdf");
+
+        let diff = diff(&then, &now);
+        assert_eq!(diff.added.iter().map(api::doc_no).collect::<Vec<_>>(), ["A4"]);
+        assert_eq!(diff.changed.iter().map(|c| c.doc_no.as_str()).collect::<Vec<_>>(), ["A2"]);
+        assert!(diff.removed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sources_are_narrowed_by_date_and_a_search_has_no_past() {
+        let api = MockApi::new(Vec::new());
+        let filter = Filter { since: NaiveDate::from_ymd_opt(2026, 9, 29), until: NaiveDate::from_ymd_opt(2026, 9, 30), ..Filter::default() };
+        let items = load(&api, &fixture("september.json"), &filter).await.unwrap();
+        assert_eq!(items.keys().collect::<Vec<_>>(), ["A2", "A3"]);
+
+        let path = format!("{}/testdata/diff/september.json@2026-10-01", env!("CARGO_MANIFEST_DIR"));
+        let e = load(&api, &Source::parse(&path).unwrap(), &Filter::default()).await.err().unwrap();
+        assert!(e.to_string().ends_with("september.json: @date only applies to an index or state file"), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn a_live_source_searches_its_range() {
+        let api = MockApi::new(vec![Ok(r#"{"reprintList":[{"docNo":"A4","docDate":"2026-10-01 10:00:00"}],"totalCount":1,"pageNo":1,"pageSize":50}"#)]);
+        let source = Source::parse("live:2026-10-01..2026-10-31").unwrap();
+        assert!(load(&api, &source, &Filter::default()).await.err().unwrap().to_string().contains("--tax-id"));
+        let filter = Filter { tax_id: Some("0105551234567".to_string()), ..Filter::default() };
+        assert_eq!(load(&api, &source, &filter).await.unwrap().keys().collect::<Vec<_>>(), ["A4"]);
+    }
+
+    #[test]
+    fn sources_are_parsed_from_their_spec() {
+        assert!(matches!(Source::parse("live:2026-10-01..2026-10-31"), Ok(Source::Live { since, until }) if since.to_string() == "2026-10-01" && until.to_string() == "2026-10-31"));
+        assert!(matches!(Source::parse("etax.sqlite@2026-10-01"), Ok(Source::File { path, as_of: Some(_) }) if path == Path::new("etax.sqlite")));
+        // An @ that isn't followed by a date is part of the path.
+        assert!(matches!(Source::parse("backups/me@work/state.json"), Ok(Source::File { path, as_of: None }) if path == Path::new("backups/me@work/state.json")));
+        assert!(Source::parse("live:2026-10-01").is_err());
+        assert!(Source::parse("live:2026-10-01..yesterday").is_err());
+    }
+}
@@ -0,0 +1,375 @@
+use crate::archive::Entry;
+use lopdf::{Document, Object};
+use roxmltree::Node;
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+
+// An ETDA e-Tax invoice (ขมธอ. 3-2560, the Thai profile of UN/CEFACT Cross Industry
+// Invoice), normalized for accounting ingestion. Amounts are as stated in the XML.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Invoice {
+    // The file, or `archive.zip!entry`, the XML came from.
+    pub source: String,
+    pub document_id: String,
+    pub type_code: String,
+    pub name: String,
+    pub issue_date: String,
+    pub currency: String,
+    pub seller: Party,
+    pub buyer: Party,
+    pub references: Vec<Reference>,
+    pub notes: Vec<String>,
+    pub lines: Vec<Line>,
+    pub vat: Vec<VatBreakdown>,
+    pub line_total: Option<f64>,
+    pub tax_basis_total: Option<f64>,
+    pub tax_total: Option<f64>,
+    pub grand_total: Option<f64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Party {
+    pub name: String,
+    pub tax_id: String,
+    // "00000" is the head office.
+    pub branch: String,
+    pub address: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reference {
+    pub id: String,
+    pub issue_date: String,
+    pub type_code: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Line {
+    pub line_id: String,
+    pub product_id: String,
+    pub name: String,
+    pub quantity: Option<f64>,
+    pub unit_code: String,
+    pub unit_price: Option<f64>,
+    pub net_amount: Option<f64>,
+    pub vat_rate: Option<f64>,
+    pub vat_amount: Option<f64>,
+    pub total_amount: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VatBreakdown {
+    pub rate: Option<f64>,
+    pub basis: Option<f64>,
+    pub amount: Option<f64>,
+}
+
+// Elements are matched by local name, so any namespace prefixes work.
+fn child<'a, 'i>(node: Node<'a, 'i>, name: &str) -> Option<Node<'a, 'i>> {
+    node.children().find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn children<'a, 'i>(node: Node<'a, 'i>, name: &'a str) -> impl Iterator<Item = Node<'a, 'i>> + 'a {
+    node.children().filter(move |n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn path<'a, 'i>(node: Node<'a, 'i>, names: &[&str]) -> Option<Node<'a, 'i>> {
+    names.iter().try_fold(node, |node, name| child(node, name))
+}
+
+fn text(node: Node<'_, '_>, names: &[&str]) -> String {
+    path(node, names).and_then(|n| n.text()).map(|t| t.trim().to_string()).unwrap_or_default()
+}
+
+fn number(node: Node<'_, '_>, names: &[&str]) -> Option<f64> {
+    text(node, names).replace(',', "").parse().ok()
+}
+
+fn party(node: Option<Node<'_, '_>>) -> Party {
+    let Some(node) = node else {
+        return Party::default();
+    };
+    // The registration ID is the 13-digit tax ID followed by the 5-digit branch.
+    let registration = text(node, &["SpecifiedTaxRegistration", "ID"]);
+    let (tax_id, branch) = match registration.len() {
+        18 if registration.is_ascii() => (registration[..13].to_string(), registration[13..].to_string()),
+        _ => (registration, String::new()),
+    };
+    let address = path(node, &["PostalTradeAddress"])
+        .map(|address| {
+            ["LineOne", "LineTwo", "LineThree", "LineFour", "LineFive", "CitySubDivisionName", "CityName", "CountrySubDivisionID", "PostcodeCode"]
+                .iter()
+                .map(|name| text(address, &[name]))
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .unwrap_or_default();
+    Party { name: text(node, &["Name"]), tax_id, branch, address }
+}
+
+pub fn parse(xml: &str, source: &str) -> Result<Invoice, Box<dyn std::error::Error>> {
+    let doc = roxmltree::Document::parse(xml)?;
+    let root = doc.root_element();
+    let header = child(root, "ExchangedDocument").ok_or("not an e-Tax invoice: no ExchangedDocument")?;
+    let transaction = child(root, "SupplyChainTradeTransaction").ok_or("not an e-Tax invoice: no SupplyChainTradeTransaction")?;
+    let agreement = child(transaction, "ApplicableHeaderTradeAgreement");
+    let settlement = child(transaction, "ApplicableHeaderTradeSettlement");
+    let summation = settlement.and_then(|s| child(s, "SpecifiedTradeSettlementHeaderMonetarySummation"));
+
+    let mut references = Vec::new();
+    for container in [agreement, settlement].into_iter().flatten() {
+        for node in container.children().filter(|n| n.is_element() && n.tag_name().name().ends_with("ReferencedDocument")) {
+            references.push(Reference {
+                id: text(node, &["IssuerAssignedID"]),
+                issue_date: text(node, &["IssueDateTime"]),
+                type_code: text(node, &["ReferenceTypeCode"]),
+            });
+        }
+    }
+
+    let lines = children(transaction, "IncludedSupplyChainTradeLineItem")
+        .map(|item| {
+            let settlement = child(item, "SpecifiedLineTradeSettlement");
+            let tax = settlement.and_then(|s| child(s, "ApplicableTradeTax"));
+            let totals = settlement.and_then(|s| child(s, "SpecifiedTradeSettlementLineMonetarySummation"));
+            Line {
+                line_id: text(item, &["AssociatedDocumentLineDocument", "LineID"]),
+                product_id: text(item, &["SpecifiedTradeProduct", "ID"]),
+                name: text(item, &["SpecifiedTradeProduct", "Name"]),
+                quantity: number(item, &["SpecifiedLineTradeDelivery", "BilledQuantity"]),
+                unit_code: path(item, &["SpecifiedLineTradeDelivery", "BilledQuantity"]).and_then(|n| n.attribute("unitCode")).unwrap_or_default().to_string(),
+                unit_price: number(item, &["SpecifiedLineTradeAgreement", "GrossPriceProductTradePrice", "ChargeAmount"]),
+                net_amount: totals.and_then(|t| number(t, &["NetLineTotalAmount"])),
+                vat_rate: tax.and_then(|t| number(t, &["CalculatedRate"])),
+                vat_amount: tax.and_then(|t| number(t, &["CalculatedAmount"])),
+                total_amount: totals.and_then(|t| number(t, &["NetIncludingTaxesLineTotalAmount"])),
+            }
+        })
+        .collect();
+
+    let vat = settlement
+        .into_iter()
+        .flat_map(|s| children(s, "ApplicableTradeTax"))
+        .map(|tax| VatBreakdown { rate: number(tax, &["CalculatedRate"]), basis: number(tax, &["BasisAmount"]), amount: number(tax, &["CalculatedAmount"]) })
+        .collect();
+
+    Ok(Invoice {
+        source: source.to_string(),
+        document_id: text(header, &["ID"]),
+        type_code: text(header, &["TypeCode"]),
+        name: text(header, &["Name"]),
+        issue_date: text(header, &["IssueDateTime"]),
+        currency: settlement.map(|s| text(s, &["InvoiceCurrencyCode"])).unwrap_or_default(),
+        seller: party(agreement.and_then(|a| child(a, "SellerTradeParty"))),
+        buyer: party(agreement.and_then(|a| child(a, "BuyerTradeParty"))),
+        references,
+        notes: children(header, "IncludedNote")
+            .map(|note| [text(note, &["Subject"]), text(note, &["Content"])].into_iter().filter(|part| !part.is_empty()).collect::<Vec<_>>().join(": "))
+            .filter(|note| !note.is_empty())
+            .collect(),
+        lines,
+        vat,
+        line_total: summation.and_then(|s| number(s, &["LineTotalAmount"])),
+        tax_basis_total: summation.and_then(|s| number(s, &["TaxBasisTotalAmount"])),
+        tax_total: summation.and_then(|s| number(s, &["TaxTotalAmount"])),
+        grand_total: summation.and_then(|s| number(s, &["GrandTotalAmount"])),
+    })
+}
+
+// XML files attached to a PDF/A-3, which is how e-Tax invoices usually carry theirs.
+pub fn pdf_attachments(pdf: &[u8]) -> Vec<Entry> {
+    let Ok(doc) = Document::load_mem(pdf) else {
+        return Vec::new();
+    };
+    let resolve = |object: &Object| -> Option<Object> {
+        match object {
+            Object::Reference(id) => doc.get_object(*id).ok().cloned(),
+            other => Some(other.clone()),
+        }
+    };
+    let mut attachments = Vec::new();
+    let Some(names) = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Names").ok().and_then(&resolve))
+        .and_then(|names| names.as_dict().ok()?.get(b"EmbeddedFiles").ok().and_then(&resolve))
+        .and_then(|tree| tree.as_dict().ok()?.get(b"Names").ok().and_then(&resolve))
+    else {
+        return attachments;
+    };
+    let Ok(names) = names.as_array() else {
+        return attachments;
+    };
+    // [name1, filespec1, name2, filespec2, ...]
+    for pair in names.chunks(2) {
+        let [name, spec] = pair else { continue };
+        let name = name.as_str().map(|n| String::from_utf8_lossy(n).to_string()).unwrap_or_default();
+        let content = resolve(spec)
+            .and_then(|spec| spec.as_dict().ok()?.get(b"EF").ok().and_then(&resolve))
+            .and_then(|ef| ef.as_dict().ok()?.get(b"F").ok().and_then(&resolve))
+            .and_then(|stream| stream.as_stream().ok().map(|s| s.decompressed_content().unwrap_or_else(|_| s.content.clone())));
+        if let Some(content) = content {
+            if name.to_lowercase().ends_with(".xml") {
+                attachments.push(Entry { name, data: content });
+            }
+        }
+    }
+    attachments
+}
+
+// Every XML in `input`: a ZIP, a directory (searched recursively), an XML file or a
+// PDF with XML attachments, each named after where it was found.
+pub fn collect(input: &Path) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+    let mut found = Vec::new();
+    if input.is_dir() {
+        let mut entries: Vec<_> = std::fs::read_dir(input)?.collect::<Result<_, _>>()?;
+        entries.sort_by_key(|e| e.path());
+        for entry in entries {
+            found.extend(collect(&entry.path())?);
+        }
+        return Ok(found);
+    }
+    let name = input.display().to_string();
+    let lower = name.to_lowercase();
+    if lower.ends_with(".zip") {
+        for entry in crate::archive::entries(&std::fs::read(input)?)? {
+            found.extend(classify(format!("{}!{}", name, entry.name), entry.data));
+        }
+    } else if lower.ends_with(".xml") || lower.ends_with(".pdf") {
+        found.extend(classify(name, std::fs::read(input)?));
+    }
+    Ok(found)
+}
+
+fn classify(source: String, data: Vec<u8>) -> Vec<Entry> {
+    let lower = source.to_lowercase();
+    if lower.ends_with(".xml") {
+        vec![Entry { name: source, data }]
+    } else if lower.ends_with(".pdf") {
+        pdf_attachments(&data).into_iter().map(|entry| Entry { name: format!("{}!{}", source, entry.name), data: entry.data }).collect()
+    } else {
+        Vec::new()
+    }
+}
+
+const CSV_COLUMNS: &[&str] = &[
+    "source", "documentId", "typeCode", "issueDate", "currency", "sellerName", "sellerTaxId", "sellerBranch", "buyerName", "buyerTaxId", "buyerBranch",
+    "references", "lineId", "productId", "name", "quantity", "unitCode", "unitPrice", "netAmount", "vatRate", "vatAmount", "totalAmount", "grandTotal",
+];
+
+// One JSON object per line.
+pub fn write_json(invoices: &[Invoice], mut out: impl Write) -> Result<(), Box<dyn std::error::Error>> {
+    for invoice in invoices {
+        writeln!(out, "{}", serde_json::to_string(invoice)?)?;
+    }
+    Ok(())
+}
+
+// One row per line item, with the invoice's details repeated.
+pub fn write_csv(invoices: &[Invoice], out: impl Write) -> Result<(), Box<dyn std::error::Error>> {
+    let amount = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record(CSV_COLUMNS)?;
+    for invoice in invoices {
+        let references = invoice.references.iter().map(|r| r.id.as_str()).collect::<Vec<_>>().join("; ");
+        for line in &invoice.lines {
+            writer.write_record([
+                invoice.source.clone(),
+                invoice.document_id.clone(),
+                invoice.type_code.clone(),
+                invoice.issue_date.clone(),
+                invoice.currency.clone(),
+                invoice.seller.name.clone(),
+                invoice.seller.tax_id.clone(),
+                invoice.seller.branch.clone(),
+                invoice.buyer.name.clone(),
+                invoice.buyer.tax_id.clone(),
+                invoice.buyer.branch.clone(),
+                references.clone(),
+                line.line_id.clone(),
+                line.product_id.clone(),
+                line.name.clone(),
+                amount(line.quantity),
+                line.unit_code.clone(),
+                amount(line.unit_price),
+                amount(line.net_amount),
+                amount(line.vat_rate),
+                amount(line.vat_amount),
+                amount(line.total_amount),
+                amount(invoice.grand_total),
+            ])?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_invoice_is_read_in_full() {
+        let invoice = parse(include_str!("../testdata/etda/invoice.xml"), "invoice.xml").unwrap();
+        assert_eq!((invoice.document_id.as_str(), invoice.type_code.as_str(), invoice.name.as_str()), ("ET2026100001", "388", "ใบกำกับภาษี"));
+        assert_eq!((invoice.issue_date.as_str(), invoice.currency.as_str()), ("2026-10-01T10:15:00", "THB"));
+        assert_eq!((invoice.seller.tax_id.as_str(), invoice.seller.branch.as_str()), ("0994000159999", "00000"));
+        assert_eq!(invoice.seller.address, "2100 New Petchburi Road Bangkok 10310");
+        assert_eq!((invoice.buyer.name.as_str(), invoice.buyer.tax_id.as_str(), invoice.buyer.branch.as_str()), ("Buyer Logistics Co., Ltd.", "0105551234567", "00001"));
+        assert_eq!(invoice.notes, ["Vehicle: 1กข 1234"]);
+        assert!(invoice.references.is_empty());
+
+        let [line] = &invoice.lines[..] else { panic!("one line expected, got {:?}", invoice.lines) };
+        assert_eq!((line.line_id.as_str(), line.product_id.as_str(), line.name.as_str(), line.unit_code.as_str()), ("1", "TOLL-01", "Expressway toll", "C62"));
+        assert_eq!((line.quantity, line.unit_price, line.net_amount), (Some(20.0), Some(50.0), Some(1000.0)));
+        assert_eq!((line.vat_rate, line.vat_amount, line.total_amount), (Some(7.0), Some(70.0), Some(1070.0)));
+        // Thousands separators are dropped.
+        let [vat] = &invoice.vat[..] else { panic!("one VAT breakdown expected") };
+        assert_eq!((vat.rate, vat.basis, vat.amount), (Some(7.0), Some(1000.0), Some(70.0)));
+        assert_eq!((invoice.line_total, invoice.tax_basis_total, invoice.tax_total, invoice.grand_total), (Some(1000.0), Some(1000.0), Some(70.0), Some(1070.0)));
+
+        let mut csv = Vec::new();
+        write_csv(&[invoice], &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 2);
+        assert!(csv.lines().nth(1).unwrap().starts_with("invoice.xml,ET2026100001,388,2026-10-01T10:15:00,THB,"));
+    }
+
+    #[test]
+    fn a_credit_note_keeps_the_invoice_it_corrects() {
+        let note = parse(include_str!("../testdata/etda/credit-note.xml"), "credit-note.xml").unwrap();
+        assert_eq!((note.document_id.as_str(), note.type_code.as_str()), ("CN2026100001", "81"));
+        let [reference] = &note.references[..] else { panic!("one reference expected, got {:?}", note.references) };
+        assert_eq!((reference.id.as_str(), reference.issue_date.as_str(), reference.type_code.as_str()), ("ET2026090042", "2026-09-28T18:30:00", "388"));
+        // A note without a subject is its content alone.
+        assert_eq!(note.notes, ["Toll charged twice"]);
+        assert!(note.lines.is_empty());
+        assert_eq!((note.line_total, note.tax_total, note.grand_total), (None, Some(3.5), Some(53.5)));
+    }
+
+    #[test]
+    fn missing_optional_elements_are_left_empty() {
+        let invoice = parse(include_str!("../testdata/etda/minimal.xml"), "minimal.xml").unwrap();
+        assert_eq!(invoice.document_id, "ET2026100002");
+        assert_eq!(invoice.name, "");
+        assert_eq!(invoice.currency, "");
+        // Without a branch the registration ID is the tax ID as given.
+        assert_eq!((invoice.seller.tax_id.as_str(), invoice.seller.branch.as_str(), invoice.seller.address.as_str()), ("0994000159999", "", ""));
+        assert_eq!((invoice.buyer.name.as_str(), invoice.buyer.tax_id.as_str()), ("", ""));
+        assert!(invoice.notes.is_empty() && invoice.references.is_empty() && invoice.lines.is_empty() && invoice.vat.is_empty());
+        assert_eq!(invoice.grand_total, None);
+    }
+
+    #[test]
+    fn a_document_that_is_not_an_invoice_is_refused() {
+        let e = parse("<Invoice><ID>1</ID></Invoice>", "ubl.xml").unwrap_err();
+        assert_eq!(e.to_string(), "not an e-Tax invoice: no ExchangedDocument");
+    }
+}
@@ -0,0 +1,370 @@
+use crate::api;
+use crate::config::{Config, Ledger, Sap, Ubl};
+use crate::cost_center::Allocation;
+use crate::query::Row;
+use crate::ubl;
+use chrono::NaiveDate;
+use std::io::Write;
+use std::path::Path;
+use tracing::warn;
+
+const VAT_RATE: f64 = 0.07;
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    Csv,
+    Xlsx,
+    Ledger,
+    Ubl,
+    Sap,
+}
+
+// One exported document, flattened for accounting systems.
+#[derive(Clone)]
+pub struct Record {
+    pub tax_id: String,
+    pub doc_no: String,
+    pub doc_date: Option<NaiveDate>,
+    pub doc_type: String,
+    pub file_name: String,
+    pub total: Option<f64>,
+    pub net: Option<f64>,
+    pub vat: Option<f64>,
+    pub cost_center: String,
+    pub plates: Vec<String>,
+    pub plazas: Vec<String>,
+    pub routes: Vec<String>,
+    pub cards: Vec<String>,
+    pub needs_review: bool,
+    pub file_path: String,
+    pub sha256: String,
+}
+
+enum Cell {
+    Text(String),
+    Amount(Option<f64>),
+}
+
+const COLUMNS: &[&str] = &[
+    "taxId", "docNo", "docDate", "docType", "fileName", "total", "net", "vat", "costCenter", "plates", "plazas", "routes",
+    "cards", "needsReview", "filePath", "sha256",
+];
+
+impl Record {
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            Cell::Text(self.tax_id.clone()),
+            Cell::Text(self.doc_no.clone()),
+            Cell::Text(self.doc_date.map(|d| d.to_string()).unwrap_or_default()),
+            Cell::Text(self.doc_type.clone()),
+            Cell::Text(self.file_name.clone()),
+            Cell::Amount(self.total),
+            Cell::Amount(self.net),
+            Cell::Amount(self.vat),
+            Cell::Text(self.cost_center.clone()),
+            Cell::Text(self.plates.join("; ")),
+            Cell::Text(self.plazas.join("; ")),
+            Cell::Text(self.routes.join("; ")),
+            Cell::Text(self.cards.join("; ")),
+            Cell::Text(if self.needs_review { "yes" } else { "" }.to_string()),
+            Cell::Text(self.file_path.clone()),
+            Cell::Text(self.sha256.clone()),
+        ]
+    }
+
+    // Every column as the CSV has it, e.g. for templates.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        COLUMNS.iter().copied().zip(self.cells().into_iter().map(Cell::text)).collect()
+    }
+}
+
+impl Cell {
+    fn text(self) -> String {
+        match self {
+            Cell::Text(text) => text,
+            Cell::Amount(amount) => amount.map(|a| format!("{:.2}", a)).unwrap_or_default(),
+        }
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+// The total is what the search API reported, falling back to the amount read from
+// the PDF. Net and VAT come from the PDF when they were read with confidence and add
+// up to the total; otherwise they are derived from the VAT-inclusive total.
+pub fn records(rows: &[Row], config: &Config) -> Vec<Record> {
+    let allocation = config.allocation();
+    rows.iter().map(|row| record(row, &allocation)).collect()
+}
+
+pub fn record(row: &Row, allocation: &Allocation) -> Record {
+    let stored = row.stored.as_ref();
+    let amounts = stored.and_then(|s| s.amounts.as_ref());
+    let fleet = stored.and_then(|s| s.fleet.clone()).unwrap_or_default();
+    let total = api::amount(&row.item).or_else(|| amounts.and_then(|a| a.total.as_ref()).map(|t| t.value));
+    let trusted = amounts.filter(|a| !a.needs_review);
+    let (net, vat) = match (trusted.and_then(|a| a.net.as_ref()), trusted.and_then(|a| a.vat.as_ref()), total) {
+        (Some(net), Some(vat), total) if total.is_none_or(|t| (net.value + vat.value - t).abs() < 0.005) => (Some(net.value), Some(vat.value)),
+        (_, _, Some(total)) => {
+            let net = round(total / (1.0 + VAT_RATE));
+            (Some(net), Some(round(total - net)))
+        }
+        _ => (None, None),
+    };
+
+    Record {
+        tax_id: row.tax_id.clone(),
+        doc_no: row.doc_no.clone(),
+        doc_date: api::doc_date(&row.item),
+        doc_type: api::text_field(&row.item, "docType").unwrap_or_default(),
+        file_name: api::text_field(&row.item, "fileName").unwrap_or_default(),
+        total,
+        net,
+        vat,
+        cost_center: allocation.assign(&row.tax_id, stored.and_then(|s| s.fleet.as_ref())).unwrap_or_default().to_string(),
+        plates: fleet.plates,
+        plazas: fleet.plazas,
+        routes: fleet.routes,
+        cards: fleet.cards,
+        needs_review: amounts.is_some_and(|a| a.needs_review),
+        file_path: row.file_path.clone().unwrap_or_default(),
+        sha256: row.sha256.clone().unwrap_or_default(),
+    }
+}
+
+// Write to `output`, or stdout for the text formats. UBL writes one file per document
+// into the `output` directory.
+pub fn write(records: &[Record], format: Format, output: Option<&Path>, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        Format::Xlsx => return write_xlsx(records, output.ok_or("XLSX export needs --output")?),
+        Format::Ubl => return write_ubl(records, &config.export.ubl, output.ok_or("UBL export needs --output")?),
+        _ => {}
+    }
+    let out: Box<dyn Write> = match output {
+        Some(path) => Box::new(std::fs::File::create(path)?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match format {
+        Format::Ledger => write_ledger(records, &config.export.ledger, out),
+        Format::Sap => write_sap(records, &config.export.sap, out),
+        _ => write_csv(records, out),
+    }
+}
+
+pub fn write_csv(records: &[Record], out: impl Write) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record(COLUMNS)?;
+    for record in records {
+        writer.write_record(record.cells().into_iter().map(Cell::text))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// The first `{name}` in `template` that is not an export column.
+pub fn unknown_placeholder(template: &str) -> Option<&str> {
+    template
+        .split('{')
+        .skip(1)
+        .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+        .find(|name| !COLUMNS.contains(name))
+}
+
+fn write_sap(records: &[Record], sap: &Sap, out: impl Write) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = csv::WriterBuilder::new().delimiter(sap.delimiter as u8).from_writer(out);
+    if sap.header {
+        writer.write_record(sap.columns.iter().map(|c| &c.name))?;
+    }
+    for record in records {
+        let values: Vec<String> = COLUMNS
+            .iter()
+            .zip(record.cells())
+            .map(|(name, cell)| match cell {
+                Cell::Amount(amount) => amount.map(|a| format!("{:.2}", a).replace('.', &sap.decimal_separator)).unwrap_or_default(),
+                Cell::Text(_) if *name == "docDate" => record.doc_date.map(|d| d.format(&sap.date_format).to_string()).unwrap_or_default(),
+                Cell::Text(text) => text,
+            })
+            .collect();
+        writer.write_record(sap.columns.iter().map(|column| {
+            let mut value = column.value.clone();
+            for (name, cell) in COLUMNS.iter().zip(&values) {
+                value = value.replace(&format!("{{{}}}", name), cell);
+            }
+            value.trim().to_string()
+        }))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_xlsx(records: &[Record], path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut workbook = rust_xlsxwriter::Workbook::new();
+    let sheet = workbook.add_worksheet().set_name("Documents")?;
+    let header = rust_xlsxwriter::Format::new().set_bold();
+    let money = rust_xlsxwriter::Format::new().set_num_format("#,##0.00");
+
+    for (col, name) in COLUMNS.iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, *name, &header)?;
+    }
+    for (i, record) in records.iter().enumerate() {
+        let row = i as u32 + 1;
+        for (col, cell) in record.cells().into_iter().enumerate() {
+            match cell {
+                Cell::Text(text) => sheet.write_string(row, col as u16, text)?,
+                Cell::Amount(Some(amount)) => sheet.write_number_with_format(row, col as u16, amount, &money)?,
+                Cell::Amount(None) => sheet,
+            };
+        }
+    }
+    sheet.set_freeze_panes(1, 0)?;
+    sheet.autofit();
+    workbook.save(path)?;
+    Ok(())
+}
+
+// Plain-text accounting (ledger-cli / hledger) journal: one transaction per document,
+// net to the cost center's expense account, VAT to the input VAT account.
+fn write_ledger(records: &[Record], ledger: &Ledger, mut out: impl Write) -> Result<(), Box<dyn std::error::Error>> {
+    let mut records: Vec<&Record> = records.iter().collect();
+    records.sort_by(|a, b| a.doc_date.cmp(&b.doc_date).then_with(|| a.doc_no.cmp(&b.doc_no)));
+
+    for record in records {
+        let (Some(date), Some(total), Some(net), Some(vat)) = (record.doc_date, record.total, record.net, record.vat) else {
+            writeln!(out, "; skipped {} {}: no date or amount\n", record.tax_id, record.doc_no)?;
+            continue;
+        };
+        let expense = match record.cost_center.as_str() {
+            "" => ledger.expense_account.clone(),
+            cost_center => format!("{}:{}", ledger.expense_account, cost_center),
+        };
+        let flag = if record.needs_review { "!" } else { "*" };
+
+        writeln!(out, "{} {} {} | {} ({})", date, flag, ledger.payee, record.doc_no, record.doc_type)?;
+        writeln!(out, "    ; taxId: {}", record.tax_id)?;
+        if !record.cost_center.is_empty() {
+            writeln!(out, "    ; CostCenter: {}", record.cost_center)?;
+        }
+        if !record.file_name.is_empty() {
+            writeln!(out, "    ; file: {}", record.file_name)?;
+        }
+        writeln!(out, "    {:<40} {:>12.2} {}", expense, net, ledger.commodity)?;
+        if vat != 0.0 {
+            writeln!(out, "    {:<40} {:>12.2} {}", ledger.vat_account, vat, ledger.commodity)?;
+        }
+        writeln!(out, "    {:<40} {:>12.2} {}\n", ledger.payment_account, -total, ledger.commodity)?;
+    }
+    Ok(())
+}
+
+fn write_ubl(records: &[Record], config: &Ubl, dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(dir)?;
+    let mut skipped = 0;
+    for record in records {
+        match ubl::invoice(record, config) {
+            Some(xml) => std::fs::write(dir.join(ubl::file_name(record)), xml)?,
+            None => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        warn!("{} document(s) without a date or amount were not exported", skipped);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use serde_json::Value;
+
+    // INV-001 is in the store with amounts read from the PDF, INV-002 only has the
+    // total the search reported, and INV/003 has no amount at all.
+    fn rows() -> Vec<Row> {
+        let documents: Vec<Value> = serde_json::from_str(include_str!("../testdata/export/documents.json")).unwrap();
+        documents
+            .into_iter()
+            .map(|document| Row {
+                tax_id: document["taxId"].as_str().unwrap().to_string(),
+                doc_no: api::doc_no(&document["item"]),
+                item: document["item"].clone(),
+                first_seen: Utc::now(),
+                changed_at: None,
+                file_path: None,
+                sha256: None,
+                stored: serde_json::from_value(document["stored"].clone()).unwrap(),
+                references: Vec::new(),
+                referenced_by: Vec::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn net_and_vat_are_read_from_the_pdf_or_derived_from_the_total() {
+        let records = records(&rows(), &Config::default());
+        let amounts: Vec<_> = records.iter().map(|r| (r.doc_no.as_str(), r.total, r.net, r.vat)).collect();
+        assert_eq!(amounts, [("INV-001", Some(100.0), Some(93.46), Some(6.54)), ("INV-002", Some(1070.0), Some(1000.0), Some(70.0)), ("INV/003", None, None, None)]);
+        assert_eq!((records[0].plates.as_slice(), records[0].routes.as_slice()), (&["1กข 1234".to_string()][..], &["Chalong Rat".to_string()][..]));
+        assert_eq!(records[1].doc_date, NaiveDate::from_ymd_opt(2026, 10, 2));
+    }
+
+    #[test]
+    fn csv_has_one_row_per_document() {
+        let mut csv = Vec::new();
+        write_csv(&records(&rows(), &Config::default()), &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], COLUMNS.join(","));
+        assert_eq!(lines[2], "0105551234567,INV-002,2026-10-02,Receipt & tax invoice,INV-002.pdf,1070.00,1000.00,70.00,,,,,,,,");
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn ubl_writes_one_invoice_per_document_with_an_amount() {
+        let dir = std::env::temp_dir().join(format!("exat-etax-ubl-{}", std::process::id()));
+        write_ubl(&records(&rows(), &Config::default()), &Ubl::default(), &dir).unwrap();
+        let mut files: Vec<String> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().to_string()).collect();
+        files.sort();
+        assert_eq!(files, ["0105551234567_INV-001.xml", "0105551234567_INV-002.xml"]);
+        assert_eq!(std::fs::read_to_string(dir.join(&files[0])).unwrap(), include_str!("../testdata/export/0105551234567_INV-001.xml"));
+        assert_eq!(std::fs::read_to_string(dir.join(&files[1])).unwrap(), include_str!("../testdata/export/0105551234567_INV-002.xml"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sap_uses_the_default_layout() {
+        let mut out = Vec::new();
+        write_sap(&records(&rows(), &Config::default()), &Sap::default(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), include_str!("../testdata/export/sap.csv"));
+    }
+
+    #[test]
+    fn sap_columns_are_templates_over_the_export_columns() {
+        let column = |name: &str, value: &str| crate::config::SapColumn { name: name.to_string(), value: value.to_string() };
+        let sap = Sap {
+            delimiter: '\t',
+            header: false,
+            date_format: "%Y%m%d".to_string(),
+            decimal_separator: ",".to_string(),
+            columns: vec![column("BUKRS", "2000"), column("BLDAT", "{docDate}"), column("XBLNR", "EXAT-{docNo}"), column("WRBTR", "{total}"), column("SGTXT", " {plates} ")],
+        };
+        let mut out = Vec::new();
+        write_sap(&records(&rows(), &Config::default()), &sap, &mut out).unwrap();
+        // Values are trimmed, and a missing amount or date is left empty.
+        assert_eq!(String::from_utf8(out).unwrap(), "2000\t20261001\tEXAT-INV-001\t100,00\t1กข 1234\n2000\t20261002\tEXAT-INV-002\t1070,00\t\n2000\t20261003\tEXAT-INV/003\t\t\n");
+    }
+
+    #[test]
+    fn only_export_columns_are_placeholders() {
+        assert_eq!(unknown_placeholder("{docNo} {total} EXAT"), None);
+        assert_eq!(unknown_placeholder("{docNo} {vendor}"), Some("vendor"));
+        assert!(Sap::default().columns.iter().all(|c| unknown_placeholder(&c.value).is_none()));
+    }
+
+    #[test]
+    fn ubl_file_names_are_safe() {
+        let records = records(&rows(), &Config::default());
+        assert_eq!(ubl::file_name(&records[2]), "0105551234567_INV_003.xml");
+    }
+}
@@ -0,0 +1,150 @@
+use crate::thai::WORD_BREAK;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+// Vehicle and route details printed on toll documents, for allocating costs to
+// vehicles or routes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Fleet {
+    #[serde(default)]
+    pub plates: Vec<String>,
+    #[serde(default)]
+    pub plazas: Vec<String>,
+    #[serde(default)]
+    pub routes: Vec<String>,
+    // Smart card (Easy Pass) numbers, digits only.
+    #[serde(default)]
+    pub cards: Vec<String>,
+}
+
+// A pattern yields `name` when given, otherwise its first capture group (or the whole
+// match without groups).
+#[derive(Clone)]
+pub struct Pattern {
+    pub regex: Regex,
+    pub name: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Extractor {
+    pub plates: Vec<Pattern>,
+    pub plazas: Vec<Pattern>,
+    pub routes: Vec<Pattern>,
+    pub cards: Vec<Pattern>,
+}
+
+fn pattern(regex: &str, name: Option<&str>) -> Pattern {
+    Pattern {
+        regex: Regex::new(regex).expect("built-in fleet pattern"),
+        name: name.map(str::to_string),
+    }
+}
+
+// Thai plates: an optional leading digit, one or two consonants and up to four digits,
+// e.g. "1กข 1234" or "กข-123", after a "ทะเบียน" / "plate" label.
+pub fn default_plates() -> Vec<Pattern> {
+    vec![pattern(r"(?i)(?:เลขทะเบียน|ทะเบียนรถ|ทะเบียน|license\s*plate|plate\s*(?:no\.?|number)?)\s*[:：]?\s*([0-9]?\s?[ก-ฮ]{1,2}[\s-]?[0-9]{1,4})", None)]
+}
+
+// "ด่านเก็บเงินดินแดง", "ด่านฯ ดินแดง", "Toll plaza: Din Daeng".
+pub fn default_plazas() -> Vec<Pattern> {
+    vec![
+        pattern(r"ด่าน(?:เก็บเงิน|ฯ)?[ \t]*[:：]?[ \t]*([ก-ฮเ-ไ][ก-๙]*)", None),
+        pattern(r"(?i)(?:toll\s*)?plaza\s*[:：]\s*([a-z][a-z0-9 ]*[a-z0-9])", None),
+    ]
+}
+
+// The expressways EXAT operates, under their Thai names whichever way they're written.
+pub fn default_routes() -> Vec<Pattern> {
+    vec![
+        pattern(r"(?i)เฉลิมมหานคร|chaloem\s*maha\s*nakhon", Some("ทางพิเศษเฉลิมมหานคร")),
+        pattern(r"(?i)ศรีรัช\s*[-–]\s*วงแหวน|si\s*rat\s*[-–]\s*outer\s*ring", Some("ทางพิเศษศรีรัช-วงแหวนรอบนอกกรุงเทพมหานคร")),
+        pattern(r"(?i)ศรีรัช|si\s*rat\b", Some("ทางพิเศษศรีรัช")),
+        pattern(r"(?i)ฉลองรัช|chalong\s*rat", Some("ทางพิเศษฉลองรัช")),
+        pattern(r"(?i)บูรพาวิถี|burapha\s*withi", Some("ทางพิเศษบูรพาวิถี")),
+        pattern(r"(?i)อุดรรัถยา|udon\s*ratthaya", Some("ทางพิเศษอุดรรัถยา")),
+        pattern(r"(?i)บางนา\s*[-–]\s*อาจณรงค์|bang\s*na\s*[-–]\s*at\s*narong", Some("ทางพิเศษสายบางนา-อาจณรงค์")),
+        pattern(r"(?i)กาญจนาภิเษก|บางพลี\s*[-–]\s*สุขสวัสดิ์|kanchanaphisek", Some("ทางพิเศษกาญจนาภิเษก")),
+        pattern(r"(?i)พระราม\s*(?:3|๓)\s*[-–]\s*ดาวคะนอง|rama\s*(?:3|iii)\s*[-–]\s*dao\s*khanong", Some("ทางพิเศษพระราม 3-ดาวคะนอง-วงแหวนรอบนอกกรุงเทพมหานคร")),
+    ]
+}
+
+// "หมายเลขบัตร 1234 5678 9012", "Card No.: 1234-5678-9012".
+pub fn default_cards() -> Vec<Pattern> {
+    vec![pattern(r"(?i)(?:หมายเลขบัตร|เลขที่บัตร|บัตรเลขที่|easy\s*pass\s*(?:no\.?|number)?|card\s*(?:no\.?|number))\s*[:：]?\s*([0-9]{4,20}(?:[ -][0-9]{4})*)", None)]
+}
+
+impl Default for Extractor {
+    fn default() -> Self {
+        Extractor { plates: default_plates(), plazas: default_plazas(), routes: default_routes(), cards: default_cards() }
+    }
+}
+
+impl Extractor {
+    pub fn extract(&self, text: &str) -> Fleet {
+        let text: String = text.chars().filter(|c| *c != WORD_BREAK).collect();
+
+        let mut plates = Vec::new();
+        for plate in find(&self.plates, &text) {
+            push_unique(&mut plates, normalize_plate(&plate));
+        }
+
+        // Route patterns go from specific to general; text matched by one is removed so
+        // "ศรีรัช-วงแหวน" doesn't also count as plain "ศรีรัช".
+        let mut routes = Vec::new();
+        let mut remaining = text.clone();
+        for pattern in &self.routes {
+            for captures in pattern.regex.captures_iter(&remaining) {
+                push_unique(&mut routes, value(pattern, &captures));
+            }
+            remaining = pattern.regex.replace_all(&remaining, " ").into_owned();
+        }
+
+        // A bare "ด่านเก็บเงิน" heading is not a plaza name.
+        let plazas = find(&self.plazas, &text).into_iter().filter(|p| !p.starts_with("เก็บเงิน")).collect();
+
+        let mut cards = Vec::new();
+        for card in find(&self.cards, &text) {
+            push_unique(&mut cards, normalize_card(&card));
+        }
+
+        Fleet { plates, plazas, routes, cards }
+    }
+}
+
+fn find(patterns: &[Pattern], text: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    for pattern in patterns {
+        for captures in pattern.regex.captures_iter(text) {
+            push_unique(&mut found, value(pattern, &captures));
+        }
+    }
+    found
+}
+
+fn value(pattern: &Pattern, captures: &regex::Captures<'_>) -> String {
+    if let Some(name) = &pattern.name {
+        return name.clone();
+    }
+    let m = captures.get(1).or_else(|| captures.get(0)).map(|m| m.as_str()).unwrap_or_default();
+    m.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn push_unique(values: &mut Vec<String>, value: String) {
+    if !value.is_empty() && !values.contains(&value) {
+        values.push(value);
+    }
+}
+
+// "1 กข-1234" -> "1กข 1234", the way plates are usually written.
+pub fn normalize_plate(raw: &str) -> String {
+    let compact: String = raw.chars().filter(|c| !c.is_whitespace() && *c != '-').collect();
+    match compact.char_indices().rev().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, c)) if i + c.len_utf8() < compact.len() => format!("{} {}", &compact[..i + c.len_utf8()], &compact[i + c.len_utf8()..]),
+        _ => compact,
+    }
+}
+
+pub fn normalize_card(raw: &str) -> String {
+    raw.chars().filter(char::is_ascii_digit).collect()
+}
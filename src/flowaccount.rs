@@ -0,0 +1,121 @@
+use crate::config::FlowAccount;
+use crate::export::Record;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::path::Path;
+
+const PRODUCTION_URL: &str = "https://openapi.flowaccount.com/v1";
+const SANDBOX_URL: &str = "https://openapi.flowaccount.com/test";
+
+const CLIENT_SECRET_ENV: &str = "EXAT_ETAX_FLOWACCOUNT_CLIENT_SECRET";
+
+// A FlowAccount business, authorized for this run.
+pub struct Connection {
+    client: Client,
+    base: String,
+    access_token: String,
+}
+
+pub async fn connect(profile: &FlowAccount) -> Result<Connection, Box<dyn std::error::Error>> {
+    let client_secret = std::env::var(CLIENT_SECRET_ENV)
+        .ok()
+        .or_else(|| profile.client_secret.clone())
+        .ok_or(format!("FlowAccount client secret missing; set {} or client_secret", CLIENT_SECRET_ENV))?;
+    let base = match &profile.api_url {
+        Some(url) => url.trim_end_matches('/').to_string(),
+        None => if profile.sandbox { SANDBOX_URL } else { PRODUCTION_URL }.to_string(),
+    };
+
+    let client = Client::builder().build()?;
+    let response = client
+        .post(format!("{}/token", base))
+        .form(&[
+            ("grant_type", "client_credentials"),
+            ("scope", "flowaccount-api"),
+            ("client_id", profile.client_id.as_str()),
+            ("client_secret", client_secret.as_str()),
+        ])
+        .send()
+        .await?;
+    let status = response.status();
+    let body: Value = response.json().await.unwrap_or(Value::Null);
+    if !status.is_success() {
+        return Err(format!("FlowAccount authorization failed ({}): {}", status, body["error"].as_str().unwrap_or("unknown error")).into());
+    }
+    let access_token = body["access_token"].as_str().ok_or("FlowAccount returned no access token")?.to_string();
+    Ok(Connection { client, base, access_token })
+}
+
+// The body of the expense created for a document. Toll documents are VAT-inclusive.
+fn expense(record: &Record, profile: &FlowAccount) -> Result<Value, Box<dyn std::error::Error>> {
+    let total = record.total.ok_or("no amount")?;
+    let date = record.doc_date.ok_or("no document date")?;
+    let vat = record.vat.unwrap_or(0.0);
+
+    let mut description = format!("EXAT {} {}", record.doc_type, record.doc_no);
+    if !record.plates.is_empty() {
+        description.push_str(&format!(" ({})", record.plates.join(", ")));
+    }
+    let mut notes = format!("taxId {}; file {}", record.tax_id, record.file_name);
+    if !record.cost_center.is_empty() {
+        notes.push_str(&format!("; cost center {}", record.cost_center));
+    }
+
+    Ok(json!({
+        "contactName": profile.contact_name,
+        "contactTaxId": profile.contact_tax_id.clone().unwrap_or_default(),
+        "publishedOn": date.to_string(),
+        "dueDate": date.to_string(),
+        "reference": record.doc_no,
+        "isVatInclusive": true,
+        "items": [{
+            "type": 1,
+            "name": "Expressway toll",
+            "description": description,
+            "quantity": 1,
+            "unitName": "",
+            "pricePerUnit": total,
+            "total": total,
+            "vatRate": if vat == 0.0 { 0 } else { 7 },
+        }],
+        "subTotal": total,
+        "totalAfterDiscount": total,
+        "vatAmount": vat,
+        "grandTotal": total,
+        "internalNotes": notes,
+    }))
+}
+
+impl Connection {
+    async fn check(response: reqwest::Response) -> Result<Value, Box<dyn std::error::Error>> {
+        let status = response.status();
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+        // Failures can also come back as 200 with `"status": false`.
+        if !status.is_success() || body["status"] == json!(false) {
+            return Err(format!("FlowAccount returned {}: {}", status, body["message"].as_str().unwrap_or("no details")).into());
+        }
+        Ok(body)
+    }
+
+    // Create the expense for a document and attach its PDF. Returns the expense's ID.
+    pub async fn push(&self, record: &Record, pdf: Option<&Path>, profile: &FlowAccount) -> Result<String, Box<dyn std::error::Error>> {
+        let response = self.client.post(format!("{}/expenses", self.base)).bearer_auth(&self.access_token).json(&expense(record, profile)?).send().await?;
+        let created = Self::check(response).await?;
+        let id = match &created["data"]["recordId"] {
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => s.clone(),
+            _ => return Err("FlowAccount response has no expense ID".into()),
+        };
+
+        if let Some(pdf) = pdf {
+            let file_name = match record.file_name.as_str() {
+                "" => format!("{}.pdf", record.doc_no),
+                name => name.to_string(),
+            };
+            let form = reqwest::multipart::Form::new().part("file", reqwest::multipart::Part::bytes(std::fs::read(pdf)?).file_name(file_name).mime_str("application/pdf")?);
+            let response = self.client.post(format!("{}/expenses/{}/attachment", self.base, id)).bearer_auth(&self.access_token).multipart(form).send().await?;
+            Self::check(response).await.map_err(|e| format!("Expense {} created, but attaching the PDF failed: {}", id, e))?;
+        }
+        Ok(id)
+    }
+}
@@ -0,0 +1,280 @@
+use crate::api::{self, EtaxApi};
+use crate::archive;
+use crate::dates::{self, DATE_FORMAT};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use tracing::info;
+
+const CHAIN_FILE: &str = "CHAIN";
+const RECORD_FILE: &str = "HOLD.json";
+const SEARCH_FILE: &str = "search.json";
+const ARCHIVE_FILE: &str = "documents.zip";
+
+// A legal hold is a frozen copy of one search result and its documents. Holds live in
+// their own directory, are written once and made read-only, and every record embeds
+// the hash of the previous hold, so `CHAIN` forms an append-only hash chain in which
+// any later edit to a hold (or removal of one) is detectable. Truncating the last line
+// of `CHAIN` is not: no later hold links to the newest one, so it drops out unnoticed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HoldRecord {
+    pub name: String,
+    pub tax_id: String,
+    pub doc_date_from: String,
+    pub doc_date_to: String,
+    pub created_at: DateTime<Utc>,
+    pub documents: usize,
+    pub files: Vec<HeldFile>,
+    pub previous: Option<String>,
+    // SHA-256 of this record serialized with `hash` set to None.
+    pub hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeldFile {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+pub async fn create(api: &impl EtaxApi, holds_dir: &Path, name: &str, tax_id: &str, since: DateTime<Utc>, until: DateTime<Utc>) -> Result<HoldRecord, Box<dyn std::error::Error>> {
+    validate_name(name)?;
+    fs::create_dir_all(holds_dir)?;
+    let dir = holds_dir.join(name);
+    // create_dir (not create_dir_all) fails if the hold already exists: holds are
+    // never overwritten or extended.
+    fs::create_dir(&dir).map_err(|e| format!("Cannot create hold {}: {}", dir.display(), e))?;
+
+    // A hold that failed half-way must not look like a (tampered) real one.
+    match freeze(api, holds_dir, &dir, name, tax_id, since, until).await {
+        Ok(record) => Ok(record),
+        Err(e) => {
+            let _ = fs::remove_dir_all(&dir);
+            Err(e)
+        }
+    }
+}
+
+async fn freeze(api: &impl EtaxApi, holds_dir: &Path, dir: &Path, name: &str, tax_id: &str, since: DateTime<Utc>, until: DateTime<Utc>) -> Result<HoldRecord, Box<dyn std::error::Error>> {
+    let timezone = dates::timezone();
+    let doc_date_from = since.with_timezone(&timezone).format(DATE_FORMAT).to_string();
+    let doc_date_to = until.with_timezone(&timezone).format(DATE_FORMAT).to_string();
+
+    info!("Searching documents from {} to {} for hold {}", doc_date_from, doc_date_to, name);
+    // A hold records what the portal returns now, never a cached answer.
+    let items = api::search_live(api, tax_id, &doc_date_from, &doc_date_to).await?;
+    fs::write(dir.join(SEARCH_FILE), serde_json::to_string_pretty(&items)?)?;
+
+    if !items.is_empty() {
+        let content = api::download_zip(api, &api::build_listfile(&items)?, None).await?;
+        fs::write(dir.join(ARCHIVE_FILE), &content)?;
+    }
+
+    let record = HoldRecord {
+        name: name.to_string(),
+        tax_id: tax_id.to_string(),
+        doc_date_from,
+        doc_date_to,
+        created_at: Utc::now(),
+        documents: items.len(),
+        files: Vec::new(),
+        previous: None,
+        hash: None,
+    };
+    seal(holds_dir, dir, record)
+}
+
+// Hash what is in `dir`, link the record to the chain and make the hold read-only.
+fn seal(holds_dir: &Path, dir: &Path, mut record: HoldRecord) -> Result<HoldRecord, Box<dyn std::error::Error>> {
+    record.files = hash_files(dir)?;
+    record.previous = last_chain_hash(holds_dir)?;
+    record.hash = Some(record_hash(&record)?);
+
+    fs::write(dir.join(RECORD_FILE), serde_json::to_string_pretty(&record)?)?;
+    let mut chain = OpenOptions::new().create(true).append(true).open(holds_dir.join(CHAIN_FILE))?;
+    writeln!(chain, "{} {}", record.hash.as_deref().unwrap_or_default(), record.name)?;
+
+    for entry in fs::read_dir(dir)? {
+        set_readonly(&entry?.path())?;
+    }
+    set_readonly(dir)?;
+
+    Ok(record)
+}
+
+pub fn list(holds_dir: &Path) -> Result<Vec<HoldRecord>, Box<dyn std::error::Error>> {
+    read_chain(holds_dir)?
+        .iter()
+        .map(|(_, name)| load_record(&holds_dir.join(name)))
+        .collect()
+}
+
+// Walk the chain from the first hold, re-hashing every record and held file. Returns
+// one message per problem found; an empty list means the chain is intact.
+pub fn verify(holds_dir: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut problems = Vec::new();
+    let mut previous: Option<String> = None;
+
+    for (chain_hash, name) in read_chain(holds_dir)? {
+        let dir = holds_dir.join(&name);
+        let record = match load_record(&dir) {
+            Ok(record) => record,
+            Err(e) => {
+                problems.push(format!("{}: cannot read hold record: {}", name, e));
+                previous = Some(chain_hash);
+                continue;
+            }
+        };
+
+        let actual = record_hash(&record)?;
+        if record.hash.as_deref() != Some(actual.as_str()) || actual != chain_hash {
+            problems.push(format!("{}: record hash mismatch", name));
+        }
+        if record.previous != previous {
+            problems.push(format!("{}: chain link to previous hold is broken", name));
+        }
+
+        let files = hash_files(&dir)?;
+        for held in &record.files {
+            match files.iter().find(|f| f.name == held.name) {
+                Some(f) if f.sha256 == held.sha256 => {}
+                Some(_) => problems.push(format!("{}: {} was modified", name, held.name)),
+                None => problems.push(format!("{}: {} is missing", name, held.name)),
+            }
+        }
+        for f in &files {
+            if !record.files.iter().any(|held| held.name == f.name) {
+                problems.push(format!("{}: unexpected file {}", name, f.name));
+            }
+        }
+
+        previous = Some(chain_hash);
+    }
+
+    Ok(problems)
+}
+
+fn validate_name(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let valid = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid hold name {:?}: use letters, digits, '-', '_' and '.'", name).into())
+    }
+}
+
+fn record_hash(record: &HoldRecord) -> Result<String, serde_json::Error> {
+    let mut unhashed = record.clone();
+    unhashed.hash = None;
+    Ok(format!("{:x}", Sha256::digest(serde_json::to_vec(&unhashed)?)))
+}
+
+// Hash the search result, the archive and every document inside the archive, so
+// verification pinpoints which PDF changed rather than just "the ZIP differs".
+fn hash_files(dir: &Path) -> Result<Vec<HeldFile>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    for name in [SEARCH_FILE, ARCHIVE_FILE] {
+        let path = dir.join(name);
+        if !path.exists() {
+            continue;
+        }
+        let content = fs::read(&path)?;
+        files.push(HeldFile {
+            name: name.to_string(),
+            size: content.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(&content)),
+        });
+        if name == ARCHIVE_FILE {
+            for entry in archive::checksum_entries(&content)? {
+                files.push(HeldFile {
+                    name: format!("{}/{}", ARCHIVE_FILE, entry.name),
+                    size: entry.size,
+                    sha256: entry.sha256,
+                });
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn load_record(dir: &Path) -> Result<HoldRecord, Box<dyn std::error::Error>> {
+    Ok(serde_json::from_str(&fs::read_to_string(dir.join(RECORD_FILE))?)?)
+}
+
+fn read_chain(holds_dir: &Path) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    let content = match fs::read_to_string(holds_dir.join(CHAIN_FILE)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(hash, name)| (hash.to_string(), name.to_string()))
+        .collect())
+}
+
+fn last_chain_hash(holds_dir: &Path) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    Ok(read_chain(holds_dir)?.pop().map(|(hash, _)| hash))
+}
+
+fn set_readonly(path: &Path) -> std::io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hold(holds_dir: &Path, name: &str, search: &str) {
+        let dir = holds_dir.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(SEARCH_FILE), search).unwrap();
+        let record = HoldRecord {
+            name: name.to_string(),
+            tax_id: "0105551234567".to_string(),
+            doc_date_from: "2026-10-01 00:00:00".to_string(),
+            doc_date_to: "2026-10-14 23:59:59".to_string(),
+            created_at: Utc::now(),
+            documents: 0,
+            files: Vec::new(),
+            previous: None,
+            hash: None,
+        };
+        seal(holds_dir, &dir, record).unwrap();
+    }
+
+    fn set_writable(path: &Path) {
+        let mut permissions = fs::metadata(path).unwrap().permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(path, permissions).unwrap();
+    }
+
+    #[test]
+    fn an_edited_hold_is_reported() {
+        let holds_dir = std::env::temp_dir().join(format!("exat-etax-holds-{}", std::process::id()));
+        fs::create_dir_all(&holds_dir).unwrap();
+        hold(&holds_dir, "audit-2026", "[]");
+        hold(&holds_dir, "dispute-e1", r#"[{"docNo":"E1"}]"#);
+        assert!(verify(&holds_dir).unwrap().is_empty());
+
+        let search = holds_dir.join("dispute-e1").join(SEARCH_FILE);
+        set_writable(&search);
+        fs::write(&search, r#"[{"docNo":"E2"}]"#).unwrap();
+        assert_eq!(verify(&holds_dir).unwrap(), vec!["dispute-e1: search.json was modified".to_string()]);
+
+        for name in ["audit-2026", "dispute-e1"] {
+            set_writable(&holds_dir.join(name));
+        }
+        fs::remove_dir_all(&holds_dir).unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+use crate::export::{self, Record};
+use crate::notify;
+use crate::store;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tracing::debug;
+
+// Steps run on each downloaded document once it is stored and indexed, to wire in
+// OCR, a virus scanner or an ERP import. The document's PDF is written next to its
+// ZIP, and the steps then work on that file in order:
+//
+//     [[hooks]]
+//     step = "exec"
+//     command = 'clamscan --no-summary "$EXAT_ETAX_FILE"'
+//     timeout = "5m"
+//
+//     [[hooks]]
+//     step = "rename"
+//     to = "{docDate}_{docNo}.pdf"
+//
+//     [[hooks]]
+//     step = "move"
+//     to = "/srv/ap-inbox/{costCenter}"
+//
+// `rename` and `move` take templates over the export columns ({taxId}, {docNo},
+// {docDate}, {costCenter}, ...). `exec` runs a shell command with the file in
+// EXAT_ETAX_FILE, every export column in EXAT_ETAX_<COLUMN> (EXAT_ETAX_DOC_NO, ...)
+// and all of that as JSON on stdin; exiting non-zero fails the step. A failed step
+// ends the document's pipeline and leaves its file where the step before put it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "step", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Step {
+    // Another file name in the same directory.
+    Rename { to: String },
+    // Into this directory, created when missing.
+    Move { to: String },
+    // Compress into <file>.gz, which replaces the file.
+    Gzip {},
+    Exec {
+        command: String,
+        #[serde(default, deserialize_with = "duration")]
+        timeout: Option<Duration>,
+    },
+}
+
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    humantime::parse_duration(&text).map(Some).map_err(|e| serde::de::Error::custom(format!("invalid timeout {:?}: {}", text, e)))
+}
+
+impl Step {
+    fn name(&self) -> &'static str {
+        match self {
+            Step::Rename { .. } => "rename",
+            Step::Move { .. } => "move",
+            Step::Gzip {} => "gzip",
+            Step::Exec { .. } => "exec",
+        }
+    }
+}
+
+// Check templates at startup, so a typo fails before anything is downloaded.
+pub fn validate(steps: &[Step]) -> Result<(), Box<dyn std::error::Error>> {
+    for (i, step) in steps.iter().enumerate() {
+        match step {
+            Step::Rename { to } | Step::Move { to } => {
+                if let Some(unknown) = export::unknown_placeholder(to) {
+                    return Err(format!("hook {} ({}): unknown placeholder {{{}}}", i + 1, step.name(), unknown).into());
+                }
+                if matches!(step, Step::Rename { .. }) && (to.contains('/') || to.contains('\\')) {
+                    return Err(format!("hook {} (rename): {:?} is not a file name; use move for another directory", i + 1, to).into());
+                }
+            }
+            Step::Exec { command, .. } if command.trim().is_empty() => return Err(format!("hook {} (exec): the command is empty", i + 1).into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Write `pdf` into `dir` and run `steps` on it; returns where the file ended up.
+pub async fn run(steps: &[Step], dir: &Path, record: &Record, item: &Value, archive: &Path, pdf: &[u8]) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let fields = record.fields();
+    let name = if record.file_name.is_empty() { format!("{}.pdf", record.doc_no) } else { record.file_name.clone() };
+    let mut path = dir.join(store::sanitize(&name));
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, pdf)?;
+    std::fs::rename(&tmp, &path)?;
+
+    for (i, step) in steps.iter().enumerate() {
+        path = apply(step, &path, &fields, item, archive).await.map_err(|e| format!("hook {} ({}): {}", i + 1, step.name(), e))?;
+        debug!("Hook {} ({}) done for {}: {}", i + 1, step.name(), record.doc_no, path.display());
+    }
+    Ok(path)
+}
+
+async fn apply(step: &Step, path: &Path, fields: &[(&str, String)], item: &Value, archive: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    match step {
+        Step::Rename { to } => {
+            let renamed = path.with_file_name(fill(to, fields));
+            std::fs::rename(path, &renamed)?;
+            Ok(renamed)
+        }
+        Step::Move { to } => {
+            let dir = PathBuf::from(fill(to, fields));
+            std::fs::create_dir_all(&dir).map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+            let moved = dir.join(path.file_name().unwrap_or_default());
+            // A rename cannot cross file systems; a copy can.
+            if std::fs::rename(path, &moved).is_err() {
+                std::fs::copy(path, &moved)?;
+                std::fs::remove_file(path)?;
+            }
+            Ok(moved)
+        }
+        Step::Gzip {} => {
+            let mut name = path.as_os_str().to_owned();
+            name.push(".gz");
+            let compressed = PathBuf::from(name);
+            let tmp = compressed.with_extension("tmp");
+            let mut encoder = GzEncoder::new(File::create(&tmp)?, Compression::default());
+            std::io::copy(&mut File::open(path)?, &mut encoder)?;
+            encoder.finish()?;
+            std::fs::rename(&tmp, &compressed)?;
+            std::fs::remove_file(path)?;
+            Ok(compressed)
+        }
+        Step::Exec { command, timeout } => {
+            exec(command, *timeout, path, fields, item, archive).await?;
+            Ok(path.to_path_buf())
+        }
+    }
+}
+
+// `{name}` replaced by the export column, made safe for a file name.
+fn fill(template: &str, fields: &[(&str, String)]) -> String {
+    let mut value = template.to_string();
+    for (name, field) in fields {
+        value = value.replace(&format!("{{{}}}", name), &store::sanitize(field));
+    }
+    value
+}
+
+async fn exec(command: &str, timeout: Option<Duration>, path: &Path, fields: &[(&str, String)], item: &Value, archive: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut payload: Map<String, Value> = fields.iter().map(|(name, value)| (name.to_string(), json!(value))).collect();
+    payload.insert("path".to_string(), json!(path));
+    payload.insert("archive".to_string(), json!(archive));
+    payload.insert("item".to_string(), item.clone());
+
+    let mut shell = notify::shell(command);
+    shell.env("EXAT_ETAX_FILE", path).env("EXAT_ETAX_ARCHIVE", archive);
+    for (name, value) in fields {
+        shell.env(format!("EXAT_ETAX_{}", env_name(name)), value);
+    }
+    // Its output goes to stderr, so that ours stays a list of archives and documents.
+    let mut child = shell.stdin(Stdio::piped()).stdout(Stdio::from(std::io::stderr())).kill_on_drop(true).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that doesn't read its stdin may have closed it already.
+        if let Err(e) = stdin.write_all(serde_json::to_string(&payload)?.as_bytes()).await {
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                return Err(e.into());
+            }
+        }
+    }
+
+    let status = match timeout {
+        Some(limit) => tokio::time::timeout(limit, child.wait()).await.map_err(|_| format!("still running after {}; stopped", humantime::format_duration(limit)))??,
+        None => child.wait().await?,
+    };
+    if !status.success() {
+        return Err(format!("exited with {}", status).into());
+    }
+    Ok(())
+}
+
+// docNo -> DOC_NO
+fn env_name(column: &str) -> String {
+    let mut name = String::new();
+    for c in column.chars() {
+        if c.is_ascii_uppercase() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_uppercase());
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn steps(toml: &str) -> Result<Vec<Step>, toml::de::Error> {
+        #[derive(Deserialize)]
+        struct Config {
+            hooks: Vec<Step>,
+        }
+        toml::from_str::<Config>(toml).map(|config| config.hooks)
+    }
+
+    #[test]
+    fn unknown_steps_fields_and_placeholders_are_rejected() {
+        assert!(steps("[[hooks]]\nstep = \"upload\"").is_err());
+        assert!(steps("[[hooks]]\nstep = \"gzip\"\nlevel = 9").is_err());
+        assert!(steps("[[hooks]]\nstep = \"exec\"\ncommand = \"true\"\ntimeout = \"soon\"").is_err());
+
+        let error = validate(&steps("[[hooks]]\nstep = \"gzip\"\n[[hooks]]\nstep = \"rename\"\nto = \"{docNumber}.pdf\"").unwrap()).unwrap_err();
+        assert_eq!(error.to_string(), "hook 2 (rename): unknown placeholder {docNumber}");
+        assert!(validate(&steps("[[hooks]]\nstep = \"rename\"\nto = \"a/{docNo}.pdf\"").unwrap()).is_err());
+        assert!(validate(&steps("[[hooks]]\nstep = \"exec\"\ncommand = \" \"").unwrap()).is_err());
+        assert!(validate(&steps("[[hooks]]\nstep = \"move\"\nto = \"/srv/{costCenter}\"").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn placeholders_are_filled_safely_and_columns_named_for_the_environment() {
+        let fields = [("docNo", "A/1 2".to_string()), ("docDate", "2026-10-14".to_string())];
+        assert_eq!(fill("{docDate}_{docNo}.pdf", &fields), "2026-10-14_A_1_2.pdf");
+        assert_eq!(fill("{docNo}", &[("docNo", "..".to_string())]), "_");
+        assert_eq!(env_name("docNo"), "DOC_NO");
+        assert_eq!(env_name("taxId"), "TAX_ID");
+    }
+
+    #[tokio::test]
+    async fn a_renamed_file_can_be_gzipped() {
+        let dir = std::env::temp_dir().join(format!("exat-etax-hooks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pdf = b"%PDF-1.4 not much of a document";
+        std::fs::write(dir.join("E1.pdf"), pdf).unwrap();
+        let fields = [("docNo", "E1".to_string()), ("docDate", "2026-10-14".to_string())];
+
+        let mut path = dir.join("E1.pdf");
+        for step in steps("[[hooks]]\nstep = \"rename\"\nto = \"{docDate}_{docNo}.pdf\"\n[[hooks]]\nstep = \"gzip\"").unwrap() {
+            path = apply(&step, &path, &fields, &Value::Null, &dir.join("a.zip")).await.unwrap();
+        }
+        assert_eq!(path, dir.join("2026-10-14_E1.pdf.gz"));
+        assert!(!dir.join("E1.pdf").exists() && !dir.join("2026-10-14_E1.pdf").exists());
+        let mut unzipped = Vec::new();
+        GzDecoder::new(File::open(&path).unwrap()).read_to_end(&mut unzipped).unwrap();
+        assert_eq!(unzipped, pdf);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,448 @@
+use crate::amounts::Extractor;
+use crate::api;
+use crate::archive;
+use crate::fleet;
+use crate::index::{Fetched, Index, IndexedDocument};
+use crate::lock;
+use crate::query::Filter;
+use crate::state::State;
+use crate::store::{self, Store, StoreOutcome};
+use crate::text::{self, TextPipeline};
+use crate::thai::Segmenter;
+use chrono::{DateTime, NaiveDate, Utc};
+use regex::Regex;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+pub struct ImportOptions {
+    pub dir: PathBuf,
+    // For files whose path names no tax ID.
+    pub tax_id: Option<String>,
+    pub store: Option<PathBuf>,
+    pub index: Option<PathBuf>,
+    pub state: Option<PathBuf>,
+    pub thai_segment: Option<Vec<String>>,
+    pub amounts: Extractor,
+    pub fleet: fleet::Extractor,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub imported: usize,
+    // Already in the store or fetched according to the index.
+    pub known: usize,
+    // Imported under a docNo the index already knew from a search.
+    pub matched: usize,
+    pub skipped: usize,
+}
+
+// One PDF found on disk, loose or inside a ZIP.
+pub struct Found {
+    pub tax_id: String,
+    pub file_name: String,
+    pub data: Vec<u8>,
+    // The file it was read from: the PDF itself or its ZIP.
+    pub source: PathBuf,
+    pub in_archive: bool,
+    // The docNo and date a folder layout says the file has, if it says.
+    pub doc_no: Option<String>,
+    pub doc_date: Option<NaiveDate>,
+}
+
+// Where an imported PDF ended up.
+pub struct Imported {
+    pub doc_no: String,
+    // Already in the store, or fetched according to the index.
+    pub known: bool,
+    pub stored_path: Option<PathBuf>,
+}
+
+// Bring PDFs downloaded by hand before the tool was adopted into the store, index and
+// state, as if they had been downloaded by a sync.
+pub async fn import(opts: &ImportOptions) -> Result<ImportSummary, Box<dyn std::error::Error>> {
+    let mut importer = Importer::open(opts, false).await?;
+    let mut files = Vec::new();
+    walk(&opts.dir, &mut files)?;
+    let mut found = Vec::new();
+    for path in files {
+        let Some(tax_id) = path_tax_id(&path).or_else(|| opts.tax_id.clone()) else {
+            warn!("Skipping {}: no tax ID in its path; pass --tax-id", path.display());
+            importer.summary.skipped += 1;
+            continue;
+        };
+        match read(&path, &tax_id) {
+            Ok(files) => found.extend(files),
+            Err(e) => {
+                warn!("Skipping {}: {}", path.display(), e);
+                importer.summary.skipped += 1;
+            }
+        }
+    }
+
+    // Portal ZIPs keep the original file names, so their entries go first and a
+    // renamed copy of one is recognized by its content.
+    found.sort_by_key(|file| !file.in_archive);
+    for file in found {
+        importer.add(file)?;
+    }
+    importer.finish()
+}
+
+// The PDFs of a loose PDF or a ZIP.
+pub fn read(path: &Path, tax_id: &str) -> Result<Vec<Found>, Box<dyn std::error::Error>> {
+    let data = std::fs::read(path)?;
+    let found = |file_name: String, data: Vec<u8>, in_archive: bool| Found {
+        tax_id: tax_id.to_string(),
+        file_name,
+        data,
+        source: path.to_path_buf(),
+        in_archive,
+        doc_no: None,
+        doc_date: None,
+    };
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("zip")) {
+        return Ok(archive::entries(&data)?
+            .into_iter()
+            .filter(|e| text::is_pdf(&e.data))
+            .map(|entry| found(Path::new(&entry.name).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(), entry.data, true))
+            .collect());
+    }
+    if !text::is_pdf(&data) {
+        return Err("not a PDF".into());
+    }
+    Ok(vec![found(path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(), data, false)])
+}
+
+// The store, index and state being imported into. Each PDF is matched to a document
+// the index knows from a search by its layout docNo, content hash, file name, then a
+// known docNo appearing in its text; others are keyed by their file stem like
+// unlisted ZIP entries. Copies of a document already stored or imported are skipped.
+pub struct Importer {
+    store: Option<Store>,
+    store_dir: Option<PathBuf>,
+    index: Option<Index>,
+    state: Option<State>,
+    state_path: Option<PathBuf>,
+    pipeline: TextPipeline,
+    amounts: Extractor,
+    // taxId/sha256 -> docNo of everything imported so far.
+    imported: HashMap<String, String>,
+    now: DateTime<Utc>,
+    // Nothing is saved; see `Store::open_dry_run` and `Index::open_copy`.
+    dry_run: bool,
+    pub summary: ImportSummary,
+    _lock: Option<lock::Guard>,
+}
+
+impl Importer {
+    pub async fn open(opts: &ImportOptions, dry_run: bool) -> Result<Importer, Box<dyn std::error::Error>> {
+        // Held until the store, index and state are saved; a dry run only reads them.
+        let lock = if dry_run {
+            None
+        } else {
+            let mut targets: Vec<lock::Target> = [&opts.state, &opts.index].into_iter().flatten().map(|p| lock::Target::File(p)).collect();
+            targets.extend(opts.store.as_deref().map(lock::Target::Dir));
+            Some(lock::acquire(&targets).await?)
+        };
+        let mut pipeline = TextPipeline::default();
+        if let Some(words) = &opts.thai_segment {
+            pipeline.segmenter = Some(Segmenter::new(words));
+        }
+        let store = match &opts.store {
+            Some(dir) => {
+                let mut store = if dry_run { Store::open_dry_run(dir)? } else { Store::open(dir)? };
+                if let Some(words) = &opts.thai_segment {
+                    store.text.segmenter = Some(Segmenter::new(words));
+                }
+                store.amounts = opts.amounts.clone();
+                store.fleet = opts.fleet.clone();
+                Some(store)
+            }
+            None => None,
+        };
+        Ok(Importer {
+            store,
+            store_dir: opts.store.clone(),
+            index: opts.index.as_deref().map(if dry_run { Index::open_copy } else { Index::open }).transpose()?,
+            state: opts.state.as_deref().map(State::load).transpose()?,
+            state_path: opts.state.clone(),
+            pipeline,
+            amounts: opts.amounts.clone(),
+            imported: HashMap::new(),
+            now: Utc::now(),
+            dry_run,
+            summary: ImportSummary::default(),
+            _lock: lock,
+        })
+    }
+
+    pub fn index(&self) -> Option<&Index> {
+        self.index.as_ref()
+    }
+
+    // Downloaded before, according to the index or the state.
+    pub fn is_recorded(&self, tax_id: &str, doc_no: &str) -> Result<bool, rusqlite::Error> {
+        if let Some(index) = &self.index {
+            if index.is_fetched(tax_id, doc_no)? {
+                return Ok(true);
+            }
+        }
+        Ok(self.state.as_ref().and_then(|state| state.tax_ids.get(tax_id)).is_some_and(|state| state.downloaded.contains(doc_no)))
+    }
+
+    pub fn add(&mut self, file: Found) -> Result<Imported, Box<dyn std::error::Error>> {
+        let sha256 = format!("{:x}", Sha256::digest(&file.data));
+        let copy_key = format!("{}/{}", file.tax_id, sha256);
+        if let Some(doc_no) = self.imported.get(&copy_key).cloned().or_else(|| stored_doc_no(self.store.as_ref(), &file.tax_id, &sha256)) {
+            debug!("{} is a copy of {} {}", file.source.display(), file.tax_id, doc_no);
+            self.summary.known += 1;
+            let stored_path = self.stored_path(&file.tax_id, &doc_no);
+            return Ok(Imported { doc_no, known: true, stored_path });
+        }
+        let known = match &self.index {
+            Some(index) => index.query(&Filter { tax_id: Some(file.tax_id.clone()), ..Filter::default() })?,
+            None => Vec::new(),
+        };
+        let text = self.pipeline.extract(&file.data).ok();
+        let matched = match &file.doc_no {
+            Some(doc_no) => known.iter().find(|d| &d.doc_no == doc_no),
+            None => match_document(&known, &file.file_name, &sha256, text.as_deref()),
+        };
+        let doc_no = matched.map(|d| d.doc_no.clone()).or_else(|| file.doc_no.clone()).unwrap_or_else(|| file_stem(&file.file_name));
+        self.imported.insert(copy_key, doc_no.clone());
+        let item = match matched {
+            Some(document) => document.item.clone(),
+            None => imported_item(&doc_no, &file.file_name, text.as_deref(), file.doc_date, &self.amounts),
+        };
+
+        let mut already = false;
+        let mut file_path = (!file.in_archive).then(|| file.source.clone());
+        if let Some(store) = &mut self.store {
+            let outcome = store.add(&file.tax_id, &doc_no, &file.file_name, &file.data, api::amount(&item), Some(&file.source))?;
+            already = matches!(outcome, StoreOutcome::Duplicate { .. });
+            if let StoreOutcome::Reissued { previous, current, .. } = &outcome {
+                warn!("{} {} differs from the stored version: {} superseded by {}", file.tax_id, doc_no, store::short_hash(previous), store::short_hash(current));
+            }
+            file_path = self.stored_path(&file.tax_id, &doc_no);
+        }
+        if let Some(index) = &self.index {
+            if index.is_fetched(&file.tax_id, &doc_no)? {
+                already = true;
+            } else {
+                if matched.is_none() {
+                    index.observe(&file.tax_id, &item, self.now)?;
+                }
+                index.record_fetch(&file.tax_id, &doc_no, &Fetched {
+                    archive_path: &file.source,
+                    file_path: file_path.as_deref(),
+                    sha256: Some(&sha256),
+                    size: Some(file.data.len() as u64),
+                })?;
+            }
+        }
+        if let Some(state) = &mut self.state {
+            let state = state.tax_id(&file.tax_id);
+            state.observe(doc_no.clone(), &item, self.now);
+            state.downloaded.insert(doc_no.clone());
+        }
+
+        if already {
+            debug!("{} {} from {} was already recorded", file.tax_id, doc_no, file.source.display());
+            self.summary.known += 1;
+        } else {
+            info!("Imported {} {} from {}", file.tax_id, doc_no, file.source.display());
+            self.summary.imported += 1;
+            if matched.is_some() {
+                self.summary.matched += 1;
+            }
+        }
+        let stored_path = if self.store.is_some() { file_path } else { None };
+        Ok(Imported { doc_no, known: already, stored_path })
+    }
+
+    fn stored_path(&self, tax_id: &str, doc_no: &str) -> Option<PathBuf> {
+        let current = self.store.as_ref()?.index.documents.get(&format!("{}/{}", tax_id, doc_no))?.current()?;
+        Some(self.store_dir.as_ref()?.join(&current.path))
+    }
+
+    // What a dry run would have written to the store: (path, bytes).
+    pub fn planned(&self) -> &[(PathBuf, u64)] {
+        self.store.as_ref().and_then(|s| s.planned.as_deref()).unwrap_or_default()
+    }
+
+    pub fn finish(self) -> Result<ImportSummary, Box<dyn std::error::Error>> {
+        if self.dry_run {
+            return Ok(self.summary);
+        }
+        if let Some(store) = &self.store {
+            store.save()?;
+        }
+        if let (Some(state), Some(path)) = (&self.state, &self.state_path) {
+            state.save(path)?;
+        }
+        Ok(self.summary)
+    }
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)?.map(|e| e.map(|e| e.path())).collect::<Result<_, _>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("pdf") || e.eq_ignore_ascii_case("zip")) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Portal downloads are named `TaxDocuments_<taxId>_...`; folders are often named
+// after the tax ID too. The nearest 13-digit number wins.
+fn path_tax_id(path: &Path) -> Option<String> {
+    let pattern = Regex::new(r"(?:^|[^0-9])([0-9]{13})(?:[^0-9]|$)").expect("valid regex");
+    path.iter().rev().find_map(|part| pattern.captures(&part.to_string_lossy()).map(|c| c[1].to_string()))
+}
+
+// The docNo of a stored document with this content, under any file name.
+fn stored_doc_no(store: Option<&Store>, tax_id: &str, sha256: &str) -> Option<String> {
+    store?.index.documents.values().find(|d| d.tax_id == tax_id && d.versions.iter().any(|v| v.sha256 == sha256)).map(|d| d.doc_no.clone())
+}
+
+fn match_document<'a>(known: &'a [IndexedDocument], file_name: &str, sha256: &str, text: Option<&str>) -> Option<&'a IndexedDocument> {
+    known
+        .iter()
+        .find(|d| d.sha256.as_deref() == Some(sha256))
+        .or_else(|| known.iter().find(|d| api::text_field(&d.item, "fileName").as_deref() == Some(file_name)))
+        .or_else(|| {
+            let text = text?;
+            // The longest docNo first, so one that contains another isn't shadowed.
+            let mut candidates: Vec<&IndexedDocument> = known.iter().filter(|d| d.doc_no.len() >= 6 && text.contains(&d.doc_no)).collect();
+            candidates.sort_by_key(|d| std::cmp::Reverse(d.doc_no.len()));
+            candidates.into_iter().next()
+        })
+}
+
+// What the search API would have said about a document it never listed, as far as
+// the PDF tells.
+fn imported_item(doc_no: &str, file_name: &str, text: Option<&str>, doc_date: Option<NaiveDate>, amounts: &Extractor) -> Value {
+    let mut item = json!({ "docNo": doc_no, "fileName": file_name, "imported": true });
+    if let Some(total) = text.and_then(|text| amounts.extract(text, None).total) {
+        item["totalAmount"] = json!(total.value);
+    }
+    if let Some(date) = doc_date.or_else(|| text.and_then(text_date)) {
+        item["docDate"] = json!(date.to_string());
+    }
+    item
+}
+
+// The first dd/mm/yyyy date in the text; receipts print Buddhist-era years.
+fn text_date(text: &str) -> Option<NaiveDate> {
+    let pattern = Regex::new(r"\b([0-9]{1,2})/([0-9]{1,2})/([0-9]{4})\b").expect("valid regex");
+    let date = pattern.captures_iter(text).find_map(|c| {
+        let year: i32 = c[3].parse().ok()?;
+        let year = if year > 2400 { year - 543 } else { year };
+        NaiveDate::from_ymd_opt(year, c[2].parse().ok()?, c[1].parse().ok()?)
+    });
+    date
+}
+
+fn file_stem(file_name: &str) -> String {
+    Path::new(file_name).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_else(|| file_name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const RECEIPT: &[u8] = include_bytes!("../testdata/import/receipt.pdf");
+    const UNLISTED: &[u8] = include_bytes!("../testdata/import/unlisted.pdf");
+    const TAX_ID: &str = "0105551234567";
+
+    fn options(dir: &Path) -> ImportOptions {
+        ImportOptions {
+            dir: dir.join("scans"),
+            tax_id: None,
+            store: Some(dir.join("store")),
+            index: Some(dir.join("index.sqlite")),
+            state: Some(dir.join("state.json")),
+            thai_segment: None,
+            amounts: Extractor::default(),
+            fleet: fleet::Extractor::default(),
+        }
+    }
+
+    // A receipt the index knows from a search, saved by hand under a scanner's name
+    // and copied once, another the portal never listed, a PDF in a folder naming no tax
+    // ID and something that isn't a PDF at all.
+    fn scans(dir: &Path) {
+        let _ = std::fs::remove_dir_all(dir);
+        let scans = dir.join("scans").join(TAX_ID);
+        std::fs::create_dir_all(&scans).unwrap();
+        std::fs::create_dir_all(dir.join("scans/misc")).unwrap();
+        std::fs::write(scans.join("scan-001.pdf"), RECEIPT).unwrap();
+        std::fs::write(scans.join("scan-001 (1).pdf"), RECEIPT).unwrap();
+        std::fs::write(scans.join("unlisted.pdf"), UNLISTED).unwrap();
+        std::fs::write(scans.join("notes.pdf"), "not a PDF").unwrap();
+        std::fs::write(dir.join("scans/misc/other.pdf"), RECEIPT).unwrap();
+
+        let item = json!({ "docNo": "EXA2026090042", "docDate": "2026-09-28 18:30:00", "fileName": "EXA2026090042.pdf", "totalAmount": "107.00" });
+        Index::open(&dir.join("index.sqlite")).unwrap().observe(TAX_ID, &item, Utc::now()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn scans_are_matched_to_searched_documents_and_copies_are_skipped() {
+        let dir = std::env::temp_dir().join(format!("exat-etax-import-{}", std::process::id()));
+        scans(&dir);
+        let summary = import(&options(&dir)).await.unwrap();
+        assert_eq!((summary.imported, summary.matched, summary.known, summary.skipped), (2, 1, 1, 2));
+
+        // The receipt was matched by the docNo in its text; the other is keyed by its file stem.
+        let index = Index::open(&dir.join("index.sqlite")).unwrap();
+        assert!(index.is_fetched(TAX_ID, "EXA2026090042").unwrap());
+        let unlisted = index.query(&Filter { tax_id: Some(TAX_ID.to_string()), ..Filter::default() }).unwrap().into_iter().find(|d| d.doc_no == "unlisted").unwrap();
+        assert_eq!((unlisted.item["docDate"].as_str(), unlisted.item["imported"].as_bool()), (Some("2026-09-03"), Some(true)));
+        assert_eq!(api::amount(&unlisted.item), Some(45.0));
+        let state = State::load(&dir.join("state.json")).unwrap();
+        assert_eq!(state.tax_ids[TAX_ID].downloaded.iter().collect::<Vec<_>>(), ["EXA2026090042", "unlisted"]);
+        let store = Store::open(&dir.join("store")).unwrap();
+        let mut stored: Vec<&String> = store.index.documents.keys().collect();
+        stored.sort();
+        assert_eq!(stored, ["0105551234567/EXA2026090042", "0105551234567/unlisted"]);
+
+        // Importing again finds everything recorded.
+        let again = import(&options(&dir)).await.unwrap();
+        assert_eq!((again.imported, again.known, again.skipped), (0, 3, 2));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_the_pdfs_of_a_zip_are_read() {
+        let dir = std::env::temp_dir().join(format!("exat-etax-import-zip-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("TaxDocuments_0105551234567_20260928.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        for (name, data) in [("docs/EXA2026090042.pdf", RECEIPT), ("readme.txt", b"hello".as_slice())] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let found = read(&path, TAX_ID).unwrap();
+        let [file] = &found[..] else { panic!("one PDF expected") };
+        assert_eq!((file.file_name.as_str(), file.in_archive, file.source.as_path()), ("EXA2026090042.pdf", true, path.as_path()));
+        assert_eq!(path_tax_id(&path).as_deref(), Some(TAX_ID));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn receipt_dates_are_read_in_either_era() {
+        assert_eq!(text_date("Date 28/09/2569"), NaiveDate::from_ymd_opt(2026, 9, 28));
+        assert_eq!(text_date("ref 12/345/6789 issued 1/10/2026"), NaiveDate::from_ymd_opt(2026, 10, 1));
+        assert_eq!(path_tax_id(Path::new("/archive/0105551234567/2026/01055512345678.pdf")).as_deref(), Some(TAX_ID));
+    }
+}
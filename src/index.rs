@@ -0,0 +1,510 @@
+use crate::api::{self, Reference};
+use crate::query::Filter;
+use crate::upgrade;
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use rusqlite::backup::Progress;
+use rusqlite::{params, Connection, DatabaseName, OptionalExtension, Transaction, TransactionBehavior};
+use serde_json::Value;
+use std::path::Path;
+use tracing::info;
+
+// Optional SQLite index (`--index db.sqlite`) of every document ever seen. `documents`
+// holds one row per document with where and when it was fetched; every distinct
+// version the search API returned is kept in `document_versions` so queries can be
+// answered as of any past moment. It also serves as the dedup store for sync runs.
+// `cost_center_charges` books each downloaded document to a cost center and month so
+// month-to-date spending can be checked against budgets; `budget_alerts` remembers
+// which overruns were already announced. `pushes` records the entries created in
+// accounting systems so a document is never pushed twice. `document_references` links
+// a document to those it refers to, such as a credit note to the invoice it corrects.
+// The rowid of `documents` grows with every new document and serves as the cursor of
+// `new_since`.
+pub struct Index {
+    conn: Connection,
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexedDocument {
+    pub tax_id: String,
+    pub doc_no: String,
+    pub item: Value,
+    pub first_seen_at: DateTime<Utc>,
+    pub observed_at: DateTime<Utc>,
+    pub fetched_at: Option<DateTime<Utc>>,
+    pub archive_path: Option<String>,
+    pub file_path: Option<String>,
+    pub sha256: Option<String>,
+}
+
+// Where a downloaded document ended up.
+pub struct Fetched<'a> {
+    pub archive_path: &'a Path,
+    pub file_path: Option<&'a Path>,
+    pub sha256: Option<&'a str>,
+    pub size: Option<u64>,
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS documents (
+    tax_id TEXT NOT NULL,
+    doc_no TEXT NOT NULL,
+    first_seen_at TEXT NOT NULL,
+    last_seen_at TEXT NOT NULL,
+    fetched_at TEXT,
+    archive_path TEXT,
+    file_path TEXT,
+    sha256 TEXT,
+    size INTEGER,
+    PRIMARY KEY (tax_id, doc_no)
+);
+CREATE TABLE IF NOT EXISTS document_versions (
+    tax_id TEXT NOT NULL,
+    doc_no TEXT NOT NULL,
+    observed_at TEXT NOT NULL,
+    doc_date TEXT,
+    doc_type TEXT,
+    amount REAL,
+    file_name TEXT,
+    item TEXT NOT NULL,
+    FOREIGN KEY (tax_id, doc_no) REFERENCES documents (tax_id, doc_no)
+);
+CREATE INDEX IF NOT EXISTS document_versions_doc ON document_versions (tax_id, doc_no, observed_at);
+CREATE INDEX IF NOT EXISTS document_versions_date ON document_versions (doc_date);
+CREATE TABLE IF NOT EXISTS cost_center_charges (
+    tax_id TEXT NOT NULL,
+    doc_no TEXT NOT NULL,
+    cost_center TEXT NOT NULL,
+    month TEXT NOT NULL,
+    amount REAL NOT NULL,
+    PRIMARY KEY (tax_id, doc_no)
+);
+CREATE INDEX IF NOT EXISTS cost_center_charges_month ON cost_center_charges (cost_center, month);
+CREATE TABLE IF NOT EXISTS budget_alerts (
+    cost_center TEXT NOT NULL,
+    month TEXT NOT NULL,
+    budget REAL NOT NULL,
+    total REAL NOT NULL,
+    alerted_at TEXT NOT NULL,
+    PRIMARY KEY (cost_center, month, budget)
+);
+CREATE TABLE IF NOT EXISTS pushes (
+    target TEXT NOT NULL,
+    tax_id TEXT NOT NULL,
+    doc_no TEXT NOT NULL,
+    remote_id TEXT NOT NULL,
+    pushed_at TEXT NOT NULL,
+    PRIMARY KEY (target, tax_id, doc_no)
+);
+";
+
+// Schema changes are appended here and never edited, so an index of any age can be
+// brought up to date; `PRAGMA user_version` counts the ones it has been through.
+// Version 0 is a new file or an index from before versioning, and SCHEMA only
+// creates what is missing, so it serves as the first step for both.
+const MIGRATIONS: &[&str] = &[
+    SCHEMA,
+    "CREATE TABLE IF NOT EXISTS document_references (
+         tax_id TEXT NOT NULL,
+         doc_no TEXT NOT NULL,
+         referenced_doc_no TEXT NOT NULL,
+         referenced_doc_date TEXT,
+         PRIMARY KEY (tax_id, doc_no, referenced_doc_no)
+     );
+     CREATE INDEX IF NOT EXISTS document_references_referenced ON document_references (tax_id, referenced_doc_no);",
+];
+const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+impl Index {
+    pub fn open(path: &Path) -> Result<Index, Box<dyn std::error::Error>> {
+        let conn = Connection::open(path)?;
+        let version = user_version(&conn)?;
+        if version > SCHEMA_VERSION {
+            return Err(upgrade::too_new("Index", path, version, SCHEMA_VERSION));
+        }
+        if version < SCHEMA_VERSION {
+            migrate(&conn, Some(path))?;
+        }
+        Ok(Index { conn })
+    }
+
+    // An in-memory copy of the index at `path` (empty if there is none yet), for dry
+    // runs: nothing done to it is written back, and the file is not upgraded.
+    pub fn open_copy(path: &Path) -> Result<Index, Box<dyn std::error::Error>> {
+        let mut conn = Connection::open_in_memory()?;
+        if path.exists() {
+            conn.restore(DatabaseName::Main, path, None::<fn(Progress)>)?;
+        }
+        let version = user_version(&conn)?;
+        if version > SCHEMA_VERSION {
+            return Err(upgrade::too_new("Index", path, version, SCHEMA_VERSION));
+        }
+        if version < SCHEMA_VERSION {
+            info!("Index {} would be upgraded from schema version {} to {} first", path.display(), version, SCHEMA_VERSION);
+            migrate(&conn, None)?;
+        }
+        Ok(Index { conn })
+    }
+
+    // A consistent copy of the whole index at `to`, which must not exist yet.
+    pub fn snapshot(&self, to: &Path) -> Result<(), rusqlite::Error> {
+        self.conn.execute("VACUUM INTO ?1", params![to.to_string_lossy()])?;
+        Ok(())
+    }
+
+    pub fn is_fetched(&self, tax_id: &str, doc_no: &str) -> Result<bool, rusqlite::Error> {
+        let fetched: Option<Option<String>> = self
+            .conn
+            .query_row("SELECT fetched_at FROM documents WHERE tax_id = ?1 AND doc_no = ?2", params![tax_id, doc_no], |row| row.get(0))
+            .optional()?;
+        Ok(matches!(fetched, Some(Some(_))))
+    }
+
+    // Record a search result item. Returns true when the document was already known
+    // in a different version, i.e. it changed server-side.
+    pub fn observe(&self, tax_id: &str, item: &Value, at: DateTime<Utc>) -> Result<bool, rusqlite::Error> {
+        let doc_no = api::doc_no(item);
+        self.record_references(tax_id, &doc_no, &api::references(item))?;
+        let item_json = item.to_string();
+        let latest: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT item FROM document_versions WHERE tax_id = ?1 AND doc_no = ?2 ORDER BY observed_at DESC, rowid DESC LIMIT 1",
+                params![tax_id, doc_no],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        self.conn.execute(
+            "INSERT INTO documents (tax_id, doc_no, first_seen_at, last_seen_at) VALUES (?1, ?2, ?3, ?3)
+             ON CONFLICT (tax_id, doc_no) DO UPDATE SET last_seen_at = excluded.last_seen_at",
+            params![tax_id, doc_no, timestamp(at)],
+        )?;
+
+        if latest.as_deref() == Some(item_json.as_str()) {
+            return Ok(false);
+        }
+        self.conn.execute(
+            "INSERT INTO document_versions (tax_id, doc_no, observed_at, doc_date, doc_type, amount, file_name, item)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                tax_id,
+                doc_no,
+                timestamp(at),
+                api::doc_date(item).map(|d| d.to_string()),
+                api::text_field(item, "docType"),
+                api::amount(item),
+                api::text_field(item, "fileName"),
+                item_json,
+            ],
+        )?;
+        Ok(latest.is_some())
+    }
+
+    pub fn record_fetch(&self, tax_id: &str, doc_no: &str, fetched: &Fetched<'_>) -> Result<(), rusqlite::Error> {
+        self.conn.execute(
+            "UPDATE documents SET fetched_at = ?3, archive_path = ?4, file_path = ?5, sha256 = ?6, size = ?7
+             WHERE tax_id = ?1 AND doc_no = ?2",
+            params![
+                tax_id,
+                doc_no,
+                timestamp(Utc::now()),
+                fetched.archive_path.display().to_string(),
+                fetched.file_path.map(|p| p.display().to_string()),
+                fetched.sha256,
+                fetched.size,
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Book a document to a cost center for `month` (YYYY-MM). A re-fetched document
+    // replaces its earlier charge instead of being counted twice.
+    pub fn charge(&self, tax_id: &str, doc_no: &str, cost_center: &str, month: &str, amount: f64) -> Result<(), rusqlite::Error> {
+        self.conn.execute(
+            "INSERT INTO cost_center_charges (tax_id, doc_no, cost_center, month, amount) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (tax_id, doc_no) DO UPDATE SET cost_center = excluded.cost_center, month = excluded.month, amount = excluded.amount",
+            params![tax_id, doc_no, cost_center, month, amount],
+        )?;
+        Ok(())
+    }
+
+    pub fn month_total(&self, cost_center: &str, month: &str) -> Result<f64, rusqlite::Error> {
+        self.conn.query_row(
+            "SELECT COALESCE(SUM(amount), 0) FROM cost_center_charges WHERE cost_center = ?1 AND month = ?2",
+            params![cost_center, month],
+            |row| row.get(0),
+        )
+    }
+
+    // Record an overrun. Returns false when it was already announced for this month
+    // and budget; changing the budget re-arms the alert.
+    pub fn record_alert(&self, cost_center: &str, month: &str, budget: f64, total: f64) -> Result<bool, rusqlite::Error> {
+        let inserted = self.conn.execute(
+            "INSERT OR IGNORE INTO budget_alerts (cost_center, month, budget, total, alerted_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![cost_center, month, budget, total, timestamp(Utc::now())],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    // Links are only added: a reference seen once, in a search result or in the XML of
+    // a download, stays.
+    pub fn record_references(&self, tax_id: &str, doc_no: &str, references: &[Reference]) -> Result<(), rusqlite::Error> {
+        for reference in references {
+            self.conn.execute(
+                "INSERT INTO document_references (tax_id, doc_no, referenced_doc_no, referenced_doc_date) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (tax_id, doc_no, referenced_doc_no) DO UPDATE SET referenced_doc_date = coalesce(excluded.referenced_doc_date, referenced_doc_date)",
+                params![tax_id, doc_no, reference.doc_no, reference.doc_date.map(|d| d.to_string())],
+            )?;
+        }
+        Ok(())
+    }
+
+    // What `doc_no` refers to, and when no date was given with a reference, the date
+    // of the referenced document if it is in the index.
+    pub fn references(&self, tax_id: &str, doc_no: &str) -> Result<Vec<Reference>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT r.referenced_doc_no, coalesce(r.referenced_doc_date,
+                        (SELECT v.doc_date FROM document_versions v WHERE v.tax_id = r.tax_id AND v.doc_no = r.referenced_doc_no
+                         ORDER BY v.observed_at DESC, v.rowid DESC LIMIT 1))
+             FROM document_references r WHERE r.tax_id = ?1 AND r.doc_no = ?2 ORDER BY r.referenced_doc_no",
+        )?;
+        let rows = stmt.query_map(params![tax_id, doc_no], |row| {
+            let date: Option<String> = row.get(1)?;
+            Ok(Reference { doc_no: row.get(0)?, doc_date: date.and_then(|d| NaiveDate::parse_from_str(&d, "%Y-%m-%d").ok()) })
+        })?;
+        rows.collect()
+    }
+
+    // The documents referring to `doc_no`.
+    pub fn referenced_by(&self, tax_id: &str, doc_no: &str) -> Result<Vec<String>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare("SELECT doc_no FROM document_references WHERE tax_id = ?1 AND referenced_doc_no = ?2 ORDER BY doc_no")?;
+        let rows = stmt.query_map(params![tax_id, doc_no], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    pub fn pushed(&self, target: &str, tax_id: &str, doc_no: &str) -> Result<Option<String>, rusqlite::Error> {
+        self.conn
+            .query_row(
+                "SELECT remote_id FROM pushes WHERE target = ?1 AND tax_id = ?2 AND doc_no = ?3",
+                params![target, tax_id, doc_no],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    pub fn record_push(&self, target: &str, tax_id: &str, doc_no: &str, remote_id: &str) -> Result<(), rusqlite::Error> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO pushes (target, tax_id, doc_no, remote_id, pushed_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![target, tax_id, doc_no, remote_id, timestamp(Utc::now())],
+        )?;
+        Ok(())
+    }
+
+    // Documents first seen after `cursor` (all of them for None), oldest first and at
+    // most `limit`, each in its latest version and with its cursor. Without a cursor
+    // the most recent `limit` are returned.
+    pub fn new_since(&self, cursor: Option<i64>, tax_id: Option<&str>, limit: usize) -> Result<Vec<(i64, IndexedDocument)>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM (
+                 SELECT d.rowid AS seq, d.tax_id, d.doc_no, v.item, d.first_seen_at, v.observed_at, d.fetched_at, d.archive_path, d.file_path, d.sha256
+                 FROM documents d JOIN document_versions v ON v.rowid = (SELECT w.rowid FROM document_versions w
+                                                                       WHERE w.tax_id = d.tax_id AND w.doc_no = d.doc_no
+                                                                       ORDER BY w.observed_at DESC, w.rowid DESC LIMIT 1)
+                 WHERE (?1 IS NULL OR d.rowid > ?1) AND (?2 IS NULL OR d.tax_id = ?2)
+                 ORDER BY CASE WHEN ?1 IS NULL THEN -d.rowid ELSE d.rowid END
+                 LIMIT ?3)
+             ORDER BY seq",
+        )?;
+        let rows = stmt.query_map(params![cursor, tax_id, limit as i64], |row| {
+            Ok((
+                row.get(0)?,
+                IndexedDocument {
+                    tax_id: row.get(1)?,
+                    doc_no: row.get(2)?,
+                    item: serde_json::from_str(&row.get::<_, String>(3)?).unwrap_or(Value::Null),
+                    first_seen_at: parse_time(&row.get::<_, String>(4)?)?,
+                    observed_at: parse_time(&row.get::<_, String>(5)?)?,
+                    fetched_at: row.get::<_, Option<String>>(6)?.map(|t| parse_time(&t)).transpose()?,
+                    archive_path: row.get(7)?,
+                    file_path: row.get(8)?,
+                    sha256: row.get(9)?,
+                },
+            ))
+        })?;
+        rows.collect()
+    }
+
+    // Every document matching the filter, each in the version current at `as_of`
+    // (default: now); documents first seen after `as_of` are left out.
+    pub fn query(&self, filter: &Filter) -> Result<Vec<IndexedDocument>, Box<dyn std::error::Error>> {
+        let as_of = timestamp(filter.as_of.unwrap_or_else(Utc::now));
+        let mut stmt = self.conn.prepare(
+            "SELECT v.tax_id, v.doc_no, v.item, d.first_seen_at, v.observed_at, d.fetched_at, d.archive_path, d.file_path, d.sha256
+             FROM document_versions v JOIN documents d ON d.tax_id = v.tax_id AND d.doc_no = v.doc_no
+             WHERE v.rowid = (SELECT w.rowid FROM document_versions w
+                              WHERE w.tax_id = v.tax_id AND w.doc_no = v.doc_no AND w.observed_at <= ?1
+                              ORDER BY w.observed_at DESC, w.rowid DESC LIMIT 1)
+               AND (?2 IS NULL OR v.tax_id = ?2)
+               AND (?3 IS NULL OR v.doc_date >= ?3)
+               AND (?4 IS NULL OR v.doc_date <= ?4)
+               AND (?5 IS NULL OR v.doc_type = ?5)
+               AND (?6 IS NULL OR v.amount >= ?6)
+               AND (?7 IS NULL OR v.amount <= ?7)
+             ORDER BY v.doc_date, v.doc_no",
+        )?;
+
+        let rows = stmt.query_map(
+            params![
+                as_of,
+                filter.tax_id,
+                filter.since.map(|d| d.to_string()),
+                filter.until.map(|d| d.to_string()),
+                filter.doc_type,
+                filter.min_amount,
+                filter.max_amount,
+            ],
+            |row| {
+                Ok(IndexedDocument {
+                    tax_id: row.get(0)?,
+                    doc_no: row.get(1)?,
+                    item: serde_json::from_str(&row.get::<_, String>(2)?).unwrap_or(Value::Null),
+                    first_seen_at: parse_time(&row.get::<_, String>(3)?)?,
+                    observed_at: parse_time(&row.get::<_, String>(4)?)?,
+                    fetched_at: row.get::<_, Option<String>>(5)?.map(|t| parse_time(&t)).transpose()?,
+                    archive_path: row.get(6)?,
+                    file_path: row.get(7)?,
+                    sha256: row.get(8)?,
+                })
+            },
+        )?;
+
+        let mut documents = Vec::new();
+        for row in rows {
+            let mut document = row?;
+            // A document fetched after `as_of` wasn't on disk back then.
+            if let (Some(fetched_at), Some(as_of)) = (document.fetched_at, filter.as_of) {
+                if fetched_at > as_of {
+                    document.fetched_at = None;
+                    document.archive_path = None;
+                    document.file_path = None;
+                    document.sha256 = None;
+                }
+            }
+            documents.push(document);
+        }
+        Ok(documents)
+    }
+}
+
+fn user_version(conn: &Connection) -> rusqlite::Result<u32> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+// Copy the index at `path` aside and run the outstanding migrations in one
+// transaction, which another process opening the same file waits for.
+fn migrate(conn: &Connection, path: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let version = user_version(&tx)?;
+    if version >= SCHEMA_VERSION {
+        return Ok(());
+    }
+    let tables: i64 = tx.query_row("SELECT count(*) FROM sqlite_master", [], |row| row.get(0))?;
+    if let Some(path) = path.filter(|_| tables > 0) {
+        let backup = upgrade::backup_path(path, version);
+        // VACUUM can't run inside a transaction; a plain copy is consistent while the lock is held.
+        std::fs::copy(path, &backup)?;
+        info!("Upgrading index {} from schema version {} to {}; the old one is kept as {}", path.display(), version, SCHEMA_VERSION, backup.display());
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        tx.execute_batch(migration)?;
+    }
+    tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    tx.commit()?;
+    Ok(())
+}
+
+// Fixed-width UTC timestamps so they also sort correctly as text in SQL.
+fn timestamp(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn parse_time(value: &str) -> rusqlite::Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("exat-etax-index-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn v0_index(path: &Path) {
+        Connection::open(path).unwrap().execute_batch(include_str!("../testdata/index/v0.sql")).unwrap();
+    }
+
+    #[test]
+    fn an_index_from_before_versioning_is_upgraded_and_kept_aside() {
+        let dir = dir("upgrade");
+        let path = dir.join("index.sqlite");
+        v0_index(&path);
+
+        let index = Index::open(&path).unwrap();
+        assert_eq!(user_version(&index.conn).unwrap(), SCHEMA_VERSION);
+        assert!(index.is_fetched("0105551234567", "INV-001").unwrap());
+        // The table the upgrade added is in use.
+        let reference = Reference { doc_no: "INV-001".to_string(), doc_date: None };
+        index.record_references("0105551234567", "CN-001", &[reference]).unwrap();
+        let references = index.references("0105551234567", "CN-001").unwrap();
+        assert_eq!((references[0].doc_no.as_str(), references[0].doc_date), ("INV-001", NaiveDate::from_ymd_opt(2026, 9, 1)));
+        drop(index);
+
+        let backup = dir.join("index.sqlite.v0.bak");
+        assert_eq!(user_version(&Connection::open(&backup).unwrap()).unwrap(), 0);
+        // Opening it again finds nothing to do.
+        Index::open(&path).unwrap();
+        assert!(!dir.join("index.sqlite.v0.1.bak").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_new_index_starts_at_the_latest_version_without_a_backup() {
+        let dir = dir("new");
+        let index = Index::open(&dir.join("index.sqlite")).unwrap();
+        assert_eq!(user_version(&index.conn).unwrap(), SCHEMA_VERSION);
+        assert!(!index.observe("0105551234567", &json!({ "docNo": "INV-001", "docDate": "2026-10-01 10:00:00" }), Utc::now()).unwrap());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_dry_run_copy_is_upgraded_in_memory_only() {
+        let dir = dir("copy");
+        let path = dir.join("index.sqlite");
+        v0_index(&path);
+        let index = Index::open_copy(&path).unwrap();
+        assert_eq!(user_version(&index.conn).unwrap(), SCHEMA_VERSION);
+        assert!(index.is_fetched("0105551234567", "INV-001").unwrap());
+        assert_eq!(user_version(&Connection::open(&path).unwrap()).unwrap(), 0);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_index_from_a_newer_version_is_refused() {
+        let dir = dir("newer");
+        let path = dir.join("index.sqlite");
+        Connection::open(&path).unwrap().pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+        let e = Index::open(&path).err().unwrap();
+        assert!(e.to_string().ends_with(&format!("has version {}, but this exat-etax only knows up to {}; upgrade exat-etax", SCHEMA_VERSION + 1, SCHEMA_VERSION)), "{}", e);
+        assert_eq!(user_version(&Connection::open(&path).unwrap()).unwrap(), SCHEMA_VERSION + 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
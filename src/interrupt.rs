@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use tokio::sync::Notify;
+use tracing::warn;
+
+// Exit status after Ctrl-C, as shells report a SIGINT death (128 + 2).
+pub const EXIT_CODE: i32 = 130;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+fn notify() -> &'static Notify {
+    static NOTIFY: OnceLock<Notify> = OnceLock::new();
+    NOTIFY.get_or_init(Notify::new)
+}
+
+// Take over Ctrl-C: the first one lets the run stop at a safe point and clean up
+// (see `requested`), a second one exits right away.
+pub fn install() {
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if REQUESTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(EXIT_CODE);
+            }
+            warn!("Interrupted; cleaning up (press Ctrl-C again to quit immediately)");
+            notify().notify_waiters();
+        }
+    });
+}
+
+pub fn is_requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+// Completes once Ctrl-C was pressed; meant to be raced against long operations.
+pub async fn requested() {
+    let notified = notify().notified();
+    if is_requested() {
+        return;
+    }
+    notified.await;
+}
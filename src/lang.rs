@@ -0,0 +1,83 @@
+use chrono::{Datelike, NaiveDate};
+use std::sync::OnceLock;
+
+// The language of headers, reports and errors. Monthly statements are read by Thai
+// accounting staff, who date in the Buddhist era (2569 for 2026) and by Thai month
+// names; the data itself (docNo, types, file names) is shown as EXAT sends it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Lang {
+    En,
+    Th,
+}
+
+const MONTHS: [&str; 12] = ["มกราคม", "กุมภาพันธ์", "มีนาคม", "เมษายน", "พฤษภาคม", "มิถุนายน", "กรกฎาคม", "สิงหาคม", "กันยายน", "ตุลาคม", "พฤศจิกายน", "ธันวาคม"];
+const SHORT_MONTHS: [&str; 12] = ["ม.ค.", "ก.พ.", "มี.ค.", "เม.ย.", "พ.ค.", "มิ.ย.", "ก.ค.", "ส.ค.", "ก.ย.", "ต.ค.", "พ.ย.", "ธ.ค."];
+
+impl Lang {
+    // The first of LC_ALL, LC_MESSAGES and LANG that is set, as gettext reads them:
+    // th_TH.UTF-8 and the like are Thai, anything else English.
+    pub fn from_env() -> Lang {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .map(|value| if value.to_ascii_lowercase().starts_with("th") { Lang::Th } else { Lang::En })
+            .unwrap_or(Lang::En)
+    }
+
+    pub fn pick(self, en: &'static str, th: &'static str) -> &'static str {
+        match self {
+            Lang::En => en,
+            Lang::Th => th,
+        }
+    }
+
+    // 2026-10-14, or 14/10/2569
+    pub fn date(self, date: NaiveDate) -> String {
+        match self {
+            Lang::En => date.to_string(),
+            Lang::Th => format!("{:02}/{:02}/{}", date.day(), date.month(), date.year() + 543),
+        }
+    }
+
+    // October 2026, or ตุลาคม 2569
+    pub fn month(self, date: NaiveDate) -> String {
+        match self {
+            Lang::En => date.format("%B %Y").to_string(),
+            Lang::Th => format!("{} {}", MONTHS[date.month0() as usize], date.year() + 543),
+        }
+    }
+
+    // 2026-10, or ต.ค. 2569, for tables of months.
+    pub fn short_month(self, date: NaiveDate) -> String {
+        match self {
+            Lang::En => date.format("%Y-%m").to_string(),
+            Lang::Th => format!("{} {}", SHORT_MONTHS[date.month0() as usize], date.year() + 543),
+        }
+    }
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+pub fn set_lang(lang: Lang) {
+    let _ = LANG.set(lang);
+}
+
+// `--lang`, or the locale's.
+pub fn lang() -> Lang {
+    *LANG.get_or_init(Lang::from_env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thai_dates_are_in_the_buddhist_era() {
+        let date = NaiveDate::from_ymd_opt(2026, 10, 14).unwrap();
+        assert_eq!(Lang::Th.date(date), "14/10/2569");
+        assert_eq!(Lang::Th.month(date), "ตุลาคม 2569");
+        assert_eq!(Lang::Th.short_month(date), "ต.ค. 2569");
+        assert_eq!(Lang::En.month(date), "October 2026");
+    }
+}
@@ -0,0 +1,216 @@
+use crate::dates::{self, DATE_FORMAT};
+use crate::interrupt::{self, Interrupted};
+use crate::lang::{self, Lang};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+// Runs that write the same state file, index, store or output directory take a lock
+// on it first (an OS file lock on `<file>.lock`, or `.exat-etax.lock` in a
+// directory), so two cron jobs don't clobber each other's downloads and state. The
+// OS releases it when the process ends, however it ends.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Policy {
+    // `--no-lock`
+    pub disabled: bool,
+    // `--wait-lock`: None fails right away, Some(None) waits as long as it takes.
+    pub wait: Option<Option<Duration>>,
+}
+
+static POLICY: OnceLock<Policy> = OnceLock::new();
+
+pub fn set_policy(policy: Policy) {
+    let _ = POLICY.set(policy);
+}
+
+fn policy() -> Policy {
+    POLICY.get().copied().unwrap_or_default()
+}
+
+// Locks this process holds, so that a batch job or a flush inside a watch cycle
+// shares the lock of its caller instead of waiting for itself.
+static HELD: Mutex<Option<HashMap<PathBuf, (File, usize)>>> = Mutex::new(None);
+
+// What a run locks: a file, or a directory it writes into.
+pub enum Target<'a> {
+    File(&'a Path),
+    Dir(&'a Path),
+}
+
+impl Target<'_> {
+    fn lock_path(&self) -> std::io::Result<PathBuf> {
+        let path = match self {
+            Target::File(file) => {
+                let name = file.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                file.with_file_name(format!("{}.lock", name))
+            }
+            Target::Dir(dir) => {
+                std::fs::create_dir_all(dir)?;
+                dir.join(".exat-etax.lock")
+            }
+        };
+        std::path::absolute(path)
+    }
+}
+
+// Released when dropped.
+#[derive(Debug)]
+pub struct Guard {
+    paths: Vec<PathBuf>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+        let held = held.get_or_insert_with(HashMap::new);
+        for path in &self.paths {
+            if let Some((_, count)) = held.get_mut(path) {
+                *count -= 1;
+                if *count == 0 {
+                    held.remove(path);
+                    debug!("Released {}", path.display());
+                }
+            }
+        }
+    }
+}
+
+// Lock every target, or none: with --wait-lock wait for the others to finish,
+// otherwise fail naming the one holding it. Waiting gives up on Ctrl-C.
+pub async fn acquire(targets: &[Target<'_>]) -> Result<Guard, Box<dyn std::error::Error>> {
+    acquire_with(targets, policy()).await
+}
+
+async fn acquire_with(targets: &[Target<'_>], policy: Policy) -> Result<Guard, Box<dyn std::error::Error>> {
+    let mut guard = Guard { paths: Vec::new() };
+    if policy.disabled {
+        return Ok(guard);
+    }
+    let mut paths = targets.iter().map(Target::lock_path).collect::<std::io::Result<Vec<_>>>()?;
+    // Always in the same order, so two runs waiting for each other's locks can't deadlock.
+    paths.sort();
+    paths.dedup();
+
+    let started = Instant::now();
+    for path in paths {
+        let mut announced = false;
+        loop {
+            match try_lock(&path)? {
+                None => break,
+                Some(holder) => {
+                    let waited_long_enough = match policy.wait {
+                        None => true,
+                        Some(None) => false,
+                        Some(Some(limit)) => started.elapsed() >= limit,
+                    };
+                    if waited_long_enough {
+                        let holder = if holder.is_empty() { String::new() } else { format!(" ({})", holder) };
+                        let lang = lang::lang();
+                        let hint = if policy.wait.is_some() { "" } else { lang.pick("; use --wait-lock to wait for it to finish", " ใช้ --wait-lock เพื่อรอจนเสร็จ") };
+                        return Err(match lang {
+                            Lang::En => format!("Another exat-etax is using {}{}{}", path.display(), holder, hint),
+                            Lang::Th => format!("exat-etax อีกตัวกำลังใช้ {}{}{}", path.display(), holder, hint),
+                        }
+                        .into());
+                    }
+                    if !announced {
+                        info!("Waiting for {}, locked by another exat-etax{}", path.display(), if holder.is_empty() { String::new() } else { format!(" ({})", holder) });
+                        announced = true;
+                    }
+                    tokio::select! {
+                        _ = tokio::time::sleep(Duration::from_millis(250)) => {}
+                        _ = interrupt::requested() => return Err(Interrupted.into()),
+                    }
+                }
+            }
+        }
+        guard.paths.push(path);
+    }
+    Ok(guard)
+}
+
+// Take `path` for this process, or return who holds it (their note, if readable).
+fn try_lock(path: &Path) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+    let held = held.get_or_insert_with(HashMap::new);
+    if let Some((_, count)) = held.get_mut(path) {
+        *count += 1;
+        return Ok(None);
+    }
+
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path).map_err(|e| format!("Cannot create {}: {}", path.display(), e))?;
+    match file.try_lock() {
+        Ok(()) => {
+            // Who has it, for the error of the next one to try.
+            file.set_len(0)?;
+            file.rewind()?;
+            writeln!(file, "pid {} since {}", std::process::id(), Utc::now().with_timezone(&dates::timezone()).format(DATE_FORMAT))?;
+            debug!("Locked {}", path.display());
+            held.insert(path.to_path_buf(), (file, 1));
+            Ok(None)
+        }
+        Err(TryLockError::WouldBlock) => {
+            let mut holder = String::new();
+            let _ = file.read_to_string(&mut holder);
+            Ok(Some(holder.trim().to_string()))
+        }
+        Err(TryLockError::Error(e)) => Err(format!("Cannot lock {}: {}", path.display(), e).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn runs_of_one_process_share_a_lock() {
+        let dir = std::env::temp_dir().join(format!("exat-etax-lock-{}", std::process::id()));
+        let state = dir.join("state.json");
+        let first = acquire_with(&[Target::Dir(&dir), Target::File(&state)], Policy::default()).await.unwrap();
+        let second = acquire_with(&[Target::File(&state)], Policy::default()).await.unwrap();
+        // Another process (here another open file) cannot have it meanwhile.
+        let other = File::open(dir.join("state.json.lock")).unwrap();
+        assert!(matches!(other.try_lock(), Err(TryLockError::WouldBlock)));
+        drop(first);
+        assert!(matches!(other.try_lock(), Err(TryLockError::WouldBlock)));
+        drop(second);
+        other.try_lock().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Another run (here another open file) holding the lock: fail naming it, give up
+    // after --wait-lock's limit, or wait until it lets go.
+    #[tokio::test]
+    async fn a_held_lock_fails_or_is_waited_for() {
+        lang::set_lang(Lang::En);
+        let dir = std::env::temp_dir().join(format!("exat-etax-lock-held-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state = dir.join("state.json");
+        let mut other = File::create(dir.join("state.json.lock")).unwrap();
+        other.try_lock().unwrap();
+        writeln!(other, "pid 1 since 2026-10-14 09:00:00").unwrap();
+
+        let e = acquire_with(&[Target::File(&state)], Policy::default()).await.unwrap_err().to_string();
+        assert!(e.contains("(pid 1 since 2026-10-14 09:00:00); use --wait-lock"), "{}", e);
+
+        let started = Instant::now();
+        let limited = Policy { disabled: false, wait: Some(Some(Duration::from_millis(300))) };
+        let e = acquire_with(&[Target::File(&state)], limited).await.unwrap_err().to_string();
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert!(e.contains("pid 1") && !e.contains("--wait-lock"), "{}", e);
+
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            drop(other);
+        });
+        let guard = acquire_with(&[Target::File(&state)], Policy { disabled: false, wait: Some(None) }).await.unwrap();
+        release.await.unwrap();
+        drop(guard);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,49 @@
+use tracing_subscriber::EnvFilter;
+
+// Diagnostics always go to stderr so stdout stays reserved for the actual results
+// (document listing, downloaded file paths) and can be piped or parsed safely.
+pub fn init(verbosity: u64, quiet: bool, json: bool) {
+    let level = if quiet {
+        "error"
+    } else {
+        match verbosity {
+            0 => "info",
+            1 => "debug",
+            _ => "trace",
+        }
+    };
+
+    // RUST_LOG wins when set; otherwise only our own crate gets the chosen level so
+    // -vv doesn't drown the output in hyper/reqwest internals.
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("warn,exat_etax={}", level)));
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_target(false);
+
+    if json {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
+
+// Keep only the last four characters of an identifier, e.g. "*********1234".
+pub fn mask(value: &str) -> String {
+    let keep = value.chars().count().saturating_sub(4);
+    value
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if i < keep { '*' } else { c })
+        .collect()
+}
+
+// Response headers that may carry session material are never written to the log.
+pub fn sanitize_header(name: &str, value: &str) -> String {
+    match name.to_ascii_lowercase().as_str() {
+        "set-cookie" | "cookie" | "authorization" | "proxy-authorization" => "<redacted>".to_string(),
+        _ => value.to_string(),
+    }
+}
@@ -1,136 +1,965 @@
-use chrono::Datelike;
-use chrono::Local;
-use clap::{App, Arg};
-use reqwest::{Client, Error as ReqwestError};
-use chrono::{DateTime, Utc, TimeZone, FixedOffset};
-use serde_json::{json, Value};
-use std::fs::File;
-use std::io::Write;
-
-const API_URL_SEARCH: &str = "https://etax.exat.co.th/backend/api/search/reprint";
-const API_URL_DOWNLOAD: &str = "https://etax.exat.co.th/backend/api/download/zipFiles";
-const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
-const ONLY_DATE_FORMAT: &str = "%Y%m%d";
+use clap::{CommandFactory, Parser};
+use cli::{Cli, Command};
+use std::path::{Path, PathBuf};
+use tracing::{debug, error, info, warn};
+
+mod amounts;
+mod annual;
+mod api;
+mod archive;
+mod backup;
+mod batch;
+mod cache;
+mod cli;
+mod config;
+mod cost_center;
+mod dates;
+mod diff;
+mod etda;
+mod export;
+mod fleet;
+mod flowaccount;
+mod hold;
+mod hooks;
+mod import;
+mod index;
+mod interrupt;
+mod lang;
+mod lock;
+mod logging;
+mod manifest;
+mod merge;
+mod metrics;
+mod migrate;
+mod notify;
+mod peak;
+mod plan;
+mod profile;
+mod prompt;
+mod query;
+mod queue;
+mod quickbooks;
+mod recording;
+mod report;
+mod run;
+mod s3;
+mod sample;
+mod serve;
+mod state;
+mod store;
+mod text;
+mod thai;
+mod throttle;
+mod tui;
+mod ubl;
+mod upgrade;
+mod viewer;
+mod watch;
+mod xades;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let matches = App::new("Tax Document Service")
-        .arg(Arg::with_name("taxID").required(true).help("Tax identification number"))
-        // Date format: YYYY-MM-DD
-        .arg(Arg::with_name("since").short("S").long("since").takes_value(true).help("Start date of the search (default: today)"))
-        .arg(Arg::with_name("until").short("U").long("until").takes_value(true).help("End date of the search (default: today)"))
-        .arg(Arg::with_name("noDownload").long("no-download").help("Prevent downloading ZIP file"))
-        .arg(Arg::with_name("filename").help("Custom filename for the downloaded ZIP (optional)"))
-        .get_matches();
+    let cli = match prompt::fallback_args()? {
+        Some(args) => Cli::parse_from(args),
+        None => Cli::parse(),
+    };
+
+    // Generated from the definition alone; no configuration needed.
+    match &cli.command {
+        Some(Command::Completions { shell }) => return run_completions(*shell),
+        Some(Command::Manpage { output_dir }) => return run_manpage(output_dir.as_deref()),
+        _ => {}
+    }
+
+    let global = &cli.global;
+    let quiet = global.quiet;
+    logging::init(global.verbose.into(), quiet, global.log_json);
+    if let Some(lang) = global.lang {
+        lang::set_lang(lang);
+    }
+    if let Some(timezone) = global.timezone {
+        dates::set_timezone(timezone);
+    }
+    lock::set_policy(lock::Policy { disabled: global.no_lock, wait: global.wait_lock });
+    let config = config::Config::load(global.config.as_deref())?;
+    if let Some(limit) = global.max_requests_per_minute {
+        throttle::set_max_per_minute(limit);
+    }
+    if let Some(rate) = global.limit_rate {
+        throttle::set_limit_rate(rate);
+    }
+    if let Some(max_wait) = global.wait_for_service {
+        api::set_wait_for_service(max_wait);
+    }
+
+    if let Some(dir) = &global.record {
+        recording::set(recording::Mode::Record(dir.clone()));
+    } else if let Some(dir) = &global.replay {
+        recording::set(recording::Mode::Replay(dir.clone()));
+    }
+    let cache_dir = global.cache_dir.clone().unwrap_or_else(cache::default_dir);
+    if let Some(ttl) = global.cache_ttl.filter(|_| !global.no_cache) {
+        cache::enable(cache_dir.clone(), ttl);
+    }
 
-    let tax_id = matches.value_of("taxID").unwrap();
-    let since_date_str = matches.value_of("since").unwrap_or("");
-    let until_date_str = matches.value_of("until").unwrap_or("");
-    let no_download = matches.is_present("noDownload");
-    let custom_filename = matches.value_of("filename");
+    let result = match &cli.command {
+        Some(Command::Search { search, download }) => with_summary(search.summary_json.as_deref(), run_search(search, &config, quiet, *download).await),
+        Some(Command::Download(args)) => with_summary(args.summary_json.as_deref(), run_search(args, &config, quiet, true).await),
+        Some(Command::Sync(args)) => with_summary(args.summary_json.as_deref(), run_sync(args, &config, quiet).await),
+        Some(Command::Watch(args)) => run_watch(args, &config, quiet).await,
+        Some(Command::Batch(args)) => with_summary(args.summary_json.as_deref(), run_batch(args, &config).await),
+        Some(Command::Tui(args)) => run_tui(args).await,
+        Some(Command::Text(args)) => run_text(args, &config),
+        Some(Command::Query(args)) => run_query(args, &config),
+        Some(Command::Export(args)) => run_export(args, &config),
+        Some(Command::Report(args)) => run_report(args, &config),
+        Some(Command::ExportYear(args)) => run_export_year(args, &config),
+        Some(Command::Profile { command }) => run_profile(command),
+        Some(Command::Hold(args)) => run_hold(args).await,
+        Some(Command::Diff(args)) => run_diff(args).await,
+        Some(Command::Import(args)) => run_import(args, &config).await,
+        Some(Command::Migrate(args)) => run_migrate(args, &config).await,
+        Some(Command::Parse(args)) => run_parse(args),
+        Some(Command::Verify(args)) => run_verify(args),
+        Some(Command::Backup(args)) => run_backup(args),
+        Some(Command::Restore(args)) => run_restore(args),
+        Some(Command::Push(args)) => run_push(args, &config).await,
+        Some(Command::Serve(args)) => run_serve(args).await,
+        Some(Command::Cache { command }) => run_cache(command, &cache_dir),
+        Some(Command::FlushDownloads(args)) => with_summary(args.summary_json.as_deref(), run_flush(args, &config, quiet).await),
+        Some(Command::Plan(args)) => run_plan(args, global),
+        Some(Command::Dev { command }) => run_dev(command),
+        Some(Command::Completions { .. } | Command::Manpage { .. }) => unreachable!("handled above"),
+        None => with_summary(cli.search.summary_json.as_deref(), run_search(&cli.search, &config, quiet, !cli.no_download).await),
+    };
+    // Cleanup is done by now; a distinct status tells scripts the run was cut short.
+    if result.as_ref().is_err_and(|e| e.is::<interrupt::Interrupted>()) {
+        eprintln!("{}", lang::lang().pick("Interrupted", "ถูกยกเลิก"));
+        std::process::exit(interrupt::EXIT_CODE);
+    }
+    // In English the error is returned and printed as Rust does, `Error: "..."`.
+    if let (Err(e), lang::Lang::Th) = (&result, lang::lang()) {
+        eprintln!("ข้อผิดพลาด: {}", e);
+        std::process::exit(1);
+    }
+    result
+}
 
-    let since_date = parse_date(since_date_str, true)?;
-    let until_date = parse_date(until_date_str, false)?;
+// `--summary-json` is written however the command ended, so monitoring also learns
+// of a failure.
+fn with_summary(path: Option<&Path>, result: Result<(), Box<dyn std::error::Error>>) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(path) = path {
+        let error = result.as_ref().err().map(|e| e.to_string());
+        if let Err(e) = metrics::write_summary(path, error.as_deref()) {
+            warn!("Cannot write {}: {}", path.display(), e);
+        }
+    }
+    result
+}
 
-    let offset = FixedOffset::east(7 * 3600); // GMT+0700
-    let doc_date_from = since_date.with_timezone(&offset).format(DATE_FORMAT).to_string();
-    let doc_date_to = until_date.with_timezone(&offset).format(DATE_FORMAT).to_string();
+// What every downloading command shares: the flags of `args`, and `searched` for
+// those that search. Each command sets its own tax ID, dates and files on top.
+fn run_options(args: &cli::DownloadArgs, searched: Option<&cli::SearchedArgs>, config: &config::Config, quiet: bool) -> Result<run::RunOptions, Box<dyn std::error::Error>> {
+    Ok(run::RunOptions {
+        tax_id: String::new(),
+        since: dates::day_bound(dates::today(), true),
+        until: dates::day_bound(dates::today(), false),
+        download: true,
+        filename: None,
+        output_dir: None,
+        state: None,
+        index: None,
+        embed_manifest: args.embed_manifest,
+        manifest: args.manifest.clone(),
+        keep_partial: args.keep_partial,
+        metadata_only_fallback: searched.is_some_and(|s| s.metadata_only_fallback || s.queue.is_some()),
+        queue: searched.and_then(|s| s.queue.clone()),
+        include_related: searched.is_some_and(|s| s.include_related),
+        store: args.store.clone(),
+        thai_segment: thai_segment(&args.thai)?,
+        amounts: config.extraction.amounts()?,
+        fleet: config.extraction.fleet()?,
+        allocation: config.allocation(),
+        hooks: config.hooks.clone(),
+        upload: upload(&args.upload),
+        merge: None,
+        notifier: notifier(&args.notify),
+        quiet,
+    })
+}
 
-    let doc_only_date_from = since_date.with_timezone(&offset).format(ONLY_DATE_FORMAT).to_string();
-    let doc_only_date_to = until_date.with_timezone(&offset).format(ONLY_DATE_FORMAT).to_string();
+async fn run_search(args: &cli::SearchArgs, config: &config::Config, quiet: bool, download: bool) -> Result<(), Box<dyn std::error::Error>> {
+    interrupt::install();
+    let opts = run::RunOptions {
+        tax_id: profile::tax_id(args.tax_id.as_deref(), args.profile.as_deref())?,
+        since: dates::day_bound(args.since.unwrap_or_else(dates::today), true),
+        until: dates::day_bound(args.until.unwrap_or_else(dates::today), false),
+        download,
+        filename: args.filename.clone(),
+        state: args.state.clone(),
+        index: args.index.clone(),
+        merge: args.merge_pdf.clone().map(|path| run::Merge { path, cover: args.cover_page }),
+        ..run_options(&args.download, Some(&args.searched), config, quiet)?
+    };
 
-    // Fetch tax document data
-    let response_body = fetch_tax_documents(tax_id, &doc_date_from, &doc_date_to).await?;
+    run::run(&api::HttpApi::new()?, &opts).await?;
+    Ok(())
+}
 
-    // Parse the response to extract necessary data
-    let invoice_data = parse_search_response(&response_body)?;
+fn sync_options(args: &cli::SyncArgs, config: &config::Config, quiet: bool) -> Result<watch::WatchOptions, Box<dyn std::error::Error>> {
+    Ok(watch::WatchOptions {
+        run: run::RunOptions {
+            tax_id: profile::tax_id(args.tax_id.as_deref(), args.profile.as_deref())?,
+            download: !args.no_download,
+            output_dir: args.output_dir.clone(),
+            index: args.index.clone(),
+            ..run_options(&args.download, Some(&args.searched), config, quiet)?
+        },
+        since: args.since,
+        state: args.state.clone(),
+        summary_json: args.summary_json.clone(),
+    })
+}
 
-    // Download ZIP file based on flag
-    if !no_download {
-        download_zip_file(&invoice_data, tax_id, &doc_only_date_from, &doc_only_date_to, custom_filename).await?;
+async fn run_sync(args: &cli::SyncArgs, config: &config::Config, quiet: bool) -> Result<(), Box<dyn std::error::Error>> {
+    interrupt::install();
+    watch::sync(&api::HttpApi::new()?, &sync_options(args, config, quiet)?).await?;
+    Ok(())
+}
+
+async fn run_watch(args: &cli::WatchArgs, config: &config::Config, quiet: bool) -> Result<(), Box<dyn std::error::Error>> {
+    interrupt::install();
+    let schedule = args.cron.clone().or_else(|| args.every.clone()).unwrap_or_default();
+    if let Some(listen) = args.metrics_listen {
+        metrics::listen(listen).await?;
     }
+    watch::watch(&api::HttpApi::new()?, sync_options(&args.sync, config, quiet)?, schedule, args.only_between).await
+}
 
+async fn run_batch(args: &cli::BatchArgs, config: &config::Config) -> Result<(), Box<dyn std::error::Error>> {
+    interrupt::install();
+    let jobs = batch::read_jobs(&args.jobs)?;
+    let base = run::RunOptions {
+        download: !args.no_download,
+        state: args.state.clone(),
+        index: args.index.clone(),
+        ..run_options(&args.download, Some(&args.searched), config, true)?
+    };
+
+    let total = jobs.len();
+    let failed = batch::run(api::HttpApi::new()?, jobs, base, args.concurrency).await?;
+    if failed > 0 {
+        return Err(match lang::lang() {
+            lang::Lang::En => format!("{} of {} job(s) failed", failed, total),
+            lang::Lang::Th => format!("งานไม่สำเร็จ {} จาก {} งาน", failed, total),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+async fn run_flush(args: &cli::FlushArgs, config: &config::Config, quiet: bool) -> Result<(), Box<dyn std::error::Error>> {
+    interrupt::install();
+    // queue::flush sets the queue and the fallback itself.
+    let base = run::RunOptions {
+        output_dir: args.output_dir.clone(),
+        state: args.state.clone(),
+        index: args.index.clone(),
+        ..run_options(&args.download, None, config, quiet)?
+    };
+
+    let flushed = queue::flush(&api::HttpApi::new()?, &args.queue, &base, args.tax_id.as_deref()).await?;
+    if flushed.remaining > 0 {
+        return Err(format!("Downloaded {} queued document(s); {} are still queued in {}", flushed.downloaded, flushed.remaining, args.queue.display()).into());
+    }
+    info!("Downloaded {} queued document(s)", flushed.downloaded);
     Ok(())
 }
 
-fn parse_date(date_str: &str, start_of_day: bool) -> Result<DateTime<Utc>, chrono::ParseError> {
-    let offset = FixedOffset::east_opt(7 * 3600).expect("Invalid timezone offset"); // Use east_opt
+async fn run_tui(args: &cli::TuiArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let tax_id = &profile::tax_id(args.tax_id.as_deref(), args.profile.as_deref())?;
+    let since = dates::day_bound(args.since.unwrap_or_else(dates::today), true);
+    let until = dates::day_bound(args.until.unwrap_or_else(dates::today), false);
+    let timezone = dates::timezone();
+    let format = |at: chrono::DateTime<chrono::Utc>, format: &str| at.with_timezone(&timezone).format(format).to_string();
 
-    let mut date = Utc::now().date_naive(); // Use Utc::now().date_naive()
-    if !date_str.is_empty() {
-        date = chrono::NaiveDate::parse_from_str(date_str, "%Y-%m-%d")?;
+    let api = api::HttpApi::new()?;
+    let items = api::search(&api, tax_id, &format(since, dates::DATE_FORMAT), &format(until, dates::DATE_FORMAT)).await?;
+    if items.is_empty() {
+        println!("{}", lang::lang().pick("No documents found", "ไม่พบเอกสาร"));
+        return Ok(());
     }
 
-    let datetime = if start_of_day {
-        offset.ymd(date.year(), date.month(), date.day()).and_hms(0, 0, 0)
+    let title = format!("{} {} {} {}", tax_id, format(since, "%Y-%m-%d"), lang::lang().pick("to", "ถึง"), format(until, "%Y-%m-%d"));
+    let Some(selected) = tui::select(&items, &title)? else {
+        return Ok(());
+    };
+    let content = api::download_zip(&api, &api::build_listfile(&selected)?, None).await?;
+    let path = archive::save_archive(
+        &content,
+        tax_id,
+        &format(since, dates::ONLY_DATE_FORMAT),
+        &format(until, dates::ONLY_DATE_FORMAT),
+        args.filename.as_deref(),
+        args.output_dir.as_deref(),
+    )?;
+    println!("{}", path.display());
+    Ok(())
+}
+
+fn run_text(args: &cli::TextArgs, config: &config::Config) -> Result<(), Box<dyn std::error::Error>> {
+    let pipeline = text::TextPipeline {
+        segmenter: thai_segment(&args.thai)?.map(|words| thai::Segmenter::new(&words)),
+    };
+    let data = std::fs::read(&args.file)?;
+    let text = pipeline.extract(&data)?;
+    if args.extract {
+        let extracted = serde_json::json!({
+            "amounts": config.extraction.amounts()?.extract(&text, None),
+            "fleet": config.extraction.fleet()?.extract(&text),
+        });
+        println!("{}", serde_json::to_string_pretty(&extracted)?);
     } else {
-        offset.ymd(date.year(), date.month(), date.day()).and_hms(23, 59, 59)
+        println!("{}", text);
+    }
+    Ok(())
+}
+
+// The recorded documents matching the filter arguments, with store details attached
+// when `--store` is given.
+fn documents(args: &cli::DocumentArgs) -> Result<Vec<query::Row>, Box<dyn std::error::Error>> {
+    let filter = query::Filter {
+        tax_id: args.tax_id.clone(),
+        since: args.since,
+        until: args.until,
+        doc_type: args.doc_type.clone(),
+        min_amount: args.min_amount,
+        max_amount: args.max_amount,
+        as_of: args.as_of.map(|d| dates::day_bound(d, false)),
+    };
+
+    let mut rows = match &args.index {
+        Some(path) => query::from_index(&index::Index::open(path)?, &filter)?,
+        None => query::from_state(&state::State::load(&args.state)?, &filter),
+    };
+    if let Some(dir) = &args.store {
+        query::with_store(&mut rows, &store::Store::open(dir)?.index);
+    }
+    Ok(rows)
+}
+
+fn run_query(args: &cli::QueryArgs, config: &config::Config) -> Result<(), Box<dyn std::error::Error>> {
+    let rows = documents(&args.documents)?;
+
+    if let Some(by) = args.group_by {
+        for group in query::group(&rows, by, config) {
+            println!("{}\t{}\t{:.2}", group.key, group.documents, group.total);
+        }
+        return Ok(());
+    }
+
+    for row in rows {
+        let changed = row.changed_at.map(|at| format!("changed {}", at.to_rfc3339())).unwrap_or_default();
+        let amount = api::amount(&row.item).map(|a| format!("{:.2}", a)).unwrap_or_default();
+        let mut details: Vec<String> = match row.stored.as_ref().and_then(|s| s.fleet.as_ref()) {
+            Some(f) => f.plates.iter().chain(&f.plazas).chain(&f.routes).chain(&f.cards).cloned().collect(),
+            None => Vec::new(),
+        };
+        if let Some(cost_center) = query::cost_center(&row, config) {
+            details.push(format!("cost center {}", cost_center));
+        }
+        if !row.references.is_empty() {
+            details.push(format!("refers to {}", row.references.iter().map(|r| r.doc_no.as_str()).collect::<Vec<_>>().join(" ")));
+        }
+        if !row.referenced_by.is_empty() {
+            details.push(format!("referenced by {}", row.referenced_by.join(" ")));
+        }
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\tfirst seen {}\t{}\t{}",
+            row.tax_id,
+            row.doc_no,
+            row.item["docDate"],
+            row.item["docType"],
+            amount,
+            row.item["fileName"],
+            row.file_path.unwrap_or_default(),
+            row.sha256.unwrap_or_default(),
+            row.first_seen.to_rfc3339(),
+            changed,
+            details.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+// The extra dictionary words when segmentation is enabled.
+fn thai_segment(args: &cli::ThaiArgs) -> Result<Option<Vec<String>>, Box<dyn std::error::Error>> {
+    if !args.thai_segment {
+        return Ok(None);
+    }
+    let words = match &args.thai_dict {
+        Some(path) => std::fs::read_to_string(path)?.lines().map(str::to_string).collect(),
+        None => Vec::new(),
     };
+    Ok(Some(words))
+}
 
-    Ok(datetime.with_timezone(&Utc))
+fn upload(args: &cli::UploadArgs) -> Option<run::Upload> {
+    Some(run::Upload {
+        target: args.upload.clone()?,
+        pdfs: args.upload_pdfs,
+        delete_local: args.delete_local,
+    })
 }
 
-async fn fetch_tax_documents(tax_id: &str, doc_date_from: &str, doc_date_to: &str) -> Result<String, ReqwestError> {
-    let client = Client::builder().build()?;
-    let mut params = std::collections::HashMap::new();
-    params.insert("taxId", tax_id);
-    params.insert("docDateFrom", doc_date_from);
-    params.insert("docDateTo", doc_date_to);
-    params.insert("smartCardNo", "null");
+fn notifier(args: &cli::NotifyArgs) -> notify::Notifier {
+    let template = |message: &Option<String>| notify::Template {
+        message: message.as_deref().unwrap_or(notify::DEFAULT_TEMPLATE).to_string(),
+        document: args.document_template.as_deref().unwrap_or(notify::DEFAULT_DOCUMENT_TEMPLATE).to_string(),
+    };
 
-    let response = client.post(API_URL_SEARCH)
-        .form(&params)
-        .send()
-        .await?;
+    notify::Notifier {
+        url: args.notify_url.clone(),
+        cmd: args.notify_cmd.clone(),
+        line: args.line_channel_token.as_ref().map(|token| notify::Line {
+            channel_token: token.clone(),
+            to: args.line_to.clone().expect("required by --line-channel-token"),
+            template: template(&args.line_template),
+        }),
+        telegram: args.telegram_bot_token.as_ref().map(|token| notify::Telegram {
+            token: token.clone(),
+            chat_id: args.telegram_chat_id.clone().expect("required by --telegram-bot-token"),
+            template: template(&args.telegram_template),
+        }),
+    }
+}
 
-    response.text().await
+// An accounting system configured under `[<target>.<profile>]`.
+enum Profile<'a> {
+    QuickBooks(&'a config::QuickBooks),
+    FlowAccount(&'a config::FlowAccount),
+    Peak(&'a config::Peak),
 }
 
-fn parse_search_response(response_body: &str) -> Result<String, serde_json::Error> {
-    let json_data: Value = serde_json::from_str(response_body)?;
-    let data = json_data["reprintList"].as_array().unwrap();
+enum Connection<'a> {
+    QuickBooks(quickbooks::Connection, &'a config::QuickBooks),
+    FlowAccount(flowaccount::Connection, &'a config::FlowAccount),
+    Peak(peak::Connection, &'a config::Peak),
+}
+
+impl<'a> Profile<'a> {
+    fn find(target: cli::Target, name: &str, config: &'a config::Config) -> Option<Profile<'a>> {
+        match target {
+            cli::Target::QuickBooks => config.quickbooks.get(name).map(Profile::QuickBooks),
+            cli::Target::FlowAccount => config.flowaccount.get(name).map(Profile::FlowAccount),
+            cli::Target::Peak => config.peak.get(name).map(Profile::Peak),
+        }
+    }
 
-    let listfile: Vec<_> = data.iter().map(|item| {
-        println!("docDate: {}, docNo: {}, fileName: {}", item["docDate"], item["docNo"], item["fileName"]);
-        json!({
-            "invoiceHdr_id": item["invoiceHdrId"],
-            "docNo": item["docNo"],
-            "fileType": item["fileType"],
-            "filePathPDF": item["filePath"],
-            "fileNamePDF": item["fileName"],
-            "docType": item["docType"]
+    async fn connect(self) -> Result<Connection<'a>, Box<dyn std::error::Error>> {
+        Ok(match self {
+            Profile::QuickBooks(profile) => Connection::QuickBooks(quickbooks::connect(profile).await?, profile),
+            Profile::FlowAccount(profile) => Connection::FlowAccount(flowaccount::connect(profile).await?, profile),
+            Profile::Peak(profile) => Connection::Peak(peak::connect(profile).await?, profile),
         })
-    }).collect();
+    }
+}
+
+impl Connection<'_> {
+    async fn push(&self, record: &export::Record, pdf: Option<&Path>) -> Result<String, Box<dyn std::error::Error>> {
+        match self {
+            Connection::QuickBooks(connection, profile) => connection.push(record, pdf, profile).await,
+            Connection::FlowAccount(connection, profile) => connection.push(record, pdf, profile).await,
+            Connection::Peak(connection, profile) => connection.push(record, pdf, profile).await,
+        }
+    }
+}
+
+// On a terminal the passphrase is asked for, twice when it is being chosen.
+fn passphrase(args: &cli::PassphraseArgs, confirm: bool) -> Result<age::secrecy::SecretString, Box<dyn std::error::Error>> {
+    if let Some(passphrase) = &args.passphrase {
+        return Ok(passphrase.clone().into());
+    }
+    if !std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        return Err("No passphrase; pass --passphrase or set EXAT_ETAX_BACKUP_PASSPHRASE".into());
+    }
+    let passphrase = rpassword::prompt_password("Passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err("The passphrase must not be empty".into());
+    }
+    if confirm && rpassword::prompt_password("Repeat passphrase: ")? != passphrase {
+        return Err("The passphrases do not match".into());
+    }
+    Ok(passphrase.into())
+}
+
+fn run_backup(args: &cli::BackupArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let opts = backup::BackupOptions {
+        output: args.output.clone(),
+        index: args.index.clone(),
+        state: args.state.clone(),
+        store: args.store.clone(),
+        documents: args.documents,
+        passphrase: passphrase(&args.passphrase, true)?,
+    };
+    let files = backup::backup(&opts)?;
+    println!("Backed up {} file(s), {} bytes, to {}", files.len(), files.iter().map(|f| f.size).sum::<u64>(), opts.output.display());
+    Ok(())
+}
+
+fn run_restore(args: &cli::RestoreArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let opts = backup::RestoreOptions {
+        input: args.input.clone(),
+        index: args.index.clone(),
+        state: args.state.clone(),
+        store: args.store.clone(),
+        force: args.force,
+        passphrase: passphrase(&args.passphrase, false)?,
+    };
+    let summary = backup::restore(&opts)?;
+    for (what, path) in [("index", &summary.index), ("state", &summary.state), ("store", &summary.store)] {
+        if let Some(path) = path {
+            println!("Restored {} to {}", what, path.display());
+        }
+    }
+    println!("Restored {} file(s)", summary.files);
+    Ok(())
+}
+
+// Push each matching document once; with --index, documents pushed before are
+// skipped and new ones recorded.
+async fn run_push(args: &cli::PushArgs, config: &config::Config) -> Result<(), Box<dyn std::error::Error>> {
+    let (system, name) = (args.target.name(), args.profile.as_str());
+    let profile = Profile::find(args.target, name, config).ok_or(format!("No [{}.{}] in the configuration", system, name))?;
+    let target = format!("{}:{}", system, name);
+    let rows = documents(&args.documents)?;
+    let records = export::records(&rows, config);
+    let index = args.documents.index.as_deref().map(index::Index::open).transpose()?;
+    if index.is_none() {
+        warn!("Without --index nothing records what was pushed; running again creates duplicates");
+    }
+    let store_dir = args.documents.store.as_deref();
+
+    let mut pending = Vec::new();
+    for (row, record) in rows.iter().zip(&records) {
+        match index.as_ref().map(|index| index.pushed(&target, &record.tax_id, &record.doc_no)).transpose()?.flatten() {
+            Some(id) => debug!("{} already pushed as {}", record.doc_no, id),
+            None => pending.push((row, record)),
+        }
+    }
+    if pending.is_empty() {
+        info!("Nothing to push");
+        return Ok(());
+    }
+
+    // Connecting uses up the QuickBooks refresh token, so it only happens when there is
+    // work.
+    let connection = profile.connect().await?;
+    let (mut pushed, mut failed) = (0, 0);
+    for (row, record) in pending {
+        let pdf = match (store_dir, &row.stored) {
+            (Some(dir), Some(stored)) => Some(dir.join(&stored.path)),
+            _ => row.file_path.as_ref().filter(|p| p.to_lowercase().ends_with(".pdf")).map(PathBuf::from),
+        };
+        if pdf.is_none() {
+            warn!("No PDF of {} on disk (use --store); pushing without an attachment", record.doc_no);
+        }
+        match connection.push(record, pdf.as_deref()).await {
+            Ok(id) => {
+                println!("{}\t{}\t{}", record.tax_id, record.doc_no, id);
+                if let Some(index) = &index {
+                    index.record_push(&target, &record.tax_id, &record.doc_no, &id)?;
+                }
+                pushed += 1;
+            }
+            Err(e) => {
+                error!("Pushing {} failed: {}", record.doc_no, e);
+                failed += 1;
+            }
+        }
+    }
+    info!("Pushed {} document(s) to {}", pushed, target);
+    if failed > 0 {
+        return Err(format!("{} document(s) could not be pushed", failed).into());
+    }
+    Ok(())
+}
+
+async fn run_serve(args: &cli::ServeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    interrupt::install();
+    serve::serve(api::HttpApi::new()?, serve::ServeOptions {
+        listen: args.listen,
+        index: args.index.clone(),
+        api_key: args.api_key.clone(),
+    })
+    .await
+}
+
+fn run_completions(shell: clap_complete::Shell) -> Result<(), Box<dyn std::error::Error>> {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+    Ok(())
+}
 
-    serde_json::to_string(&listfile)
+fn run_manpage(output_dir: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let command = Cli::command();
+    match output_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)?;
+            clap_mangen::generate_to(command, dir)?;
+            println!("Wrote the man pages to {}", dir.display());
+        }
+        None => clap_mangen::Man::new(command).render(&mut std::io::stdout())?,
+    }
+    Ok(())
 }
 
-async fn download_zip_file(listfile_json: &str, tax_id: &str, doc_date_from: &str, doc_date_to: &str, custom_filename: Option<&str>) -> Result<(), Box<dyn std::error::Error>> { // Change return type
-    let client = Client::builder().build()?;
+fn run_cache(command: &cli::CacheCommand, dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        cli::CacheCommand::Clear => {
+            let removed = cache::clear(dir)?;
+            println!("Removed {} cached search result(s) from {}", removed, dir.display());
+        }
+    }
+    Ok(())
+}
 
-    let form = reqwest::multipart::Form::new()
-        .text("listfile", listfile_json.to_string())
-        .text("type", "PDF");
+fn run_plan(args: &cli::PlanArgs, global: &cli::GlobalArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let profiles = plan::read_profiles(&args.profiles)?;
+    let opts = plan::PlanOptions {
+        window: args.window,
+        max_requests_per_minute: global.max_requests_per_minute.ok_or("Pass --max-requests-per-minute, the rate to plan the syncs at")?,
+        budget: args.budget,
+        page_size: args.page_size,
+        index: args.index.clone(),
+    };
+    let plan = plan::plan(&profiles, &opts)?;
+    match args.format {
+        cli::PlanFormat::Json => println!("{}", serde_json::to_string_pretty(&plan)?),
+        cli::PlanFormat::Cron => print!("{}", plan.crontab(&args.sync_args)),
+        cli::PlanFormat::Text => {
+            for slot in &plan.planned {
+                println!("{}-{}\t{}\t{} document(s)\t{} request(s)", slot.start.as_deref().unwrap_or_default(), slot.end.as_deref().unwrap_or_default(), slot.tax_id, slot.documents, slot.requests);
+            }
+            for slot in &plan.unplanned {
+                println!("NO FIT\t{}\t{} document(s)\t{} request(s)\t{}", slot.tax_id, slot.documents, slot.requests, slot.reason.as_deref().unwrap_or_default());
+            }
+            println!("Planned {} of {} profile(s) in {}: {} of {} request(s)", plan.planned.len(), profiles.len(), plan.window, plan.requests, plan.budget);
+        }
+    }
+    if !plan.unplanned.is_empty() {
+        return Err(format!("{} of {} profile(s) do not fit the window or budget", plan.unplanned.len(), profiles.len()).into());
+    }
+    Ok(())
+}
+
+fn run_dev(command: &cli::DevCommand) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        cli::DevCommand::GenSample(args) => {
+            let opts = sample::SampleOptions {
+                store: args.store.clone(),
+                index: args.index.clone(),
+                state: args.state.clone(),
+                until: args.until.unwrap_or_else(dates::today),
+                months: args.months,
+                companies: args.companies,
+                documents: args.documents,
+                seed: args.seed,
+            };
+            let summary = sample::generate(&opts)?;
+            for tax_id in &summary.tax_ids {
+                println!("{}", tax_id);
+            }
+            let range = summary.since.zip(summary.until).map(|(since, until)| format!(" from {} to {}", since, until)).unwrap_or_default();
+            println!("Generated {} sample document(s) for {} tax ID(s){}", summary.documents, summary.tax_ids.len(), range);
+            Ok(())
+        }
+    }
+}
+
+fn run_profile(command: &cli::ProfileCommand) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        cli::ProfileCommand::Add { name, tax_id } => {
+            let tax_id = match tax_id {
+                Some(tax_id) => tax_id.clone(),
+                None => cli::parse_tax_id(rpassword::prompt_password("Tax ID: ")?.trim())?,
+            };
+            profile::add(name, &profile::Profile { tax_id: tax_id.clone() })?;
+            println!("Saved profile {} ({}) in the OS keyring", name, logging::mask(&tax_id));
+        }
+        cli::ProfileCommand::Remove { name } => {
+            profile::remove(name)?;
+            println!("Removed profile {}", name);
+        }
+        cli::ProfileCommand::List => {
+            for name in profile::list()? {
+                match profile::get(&name) {
+                    Ok(found) => println!("{}\t{}", name, logging::mask(&found.tax_id)),
+                    Err(e) => println!("{}\t({})", name, e),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_hold(args: &cli::HoldArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let holds_dir = &args.holds_dir;
+
+    match &args.command {
+        cli::HoldCommand::Create { name, tax_id, since, until } => {
+            let since = dates::day_bound(since.unwrap_or_else(dates::today), true);
+            let until = dates::day_bound(until.unwrap_or_else(dates::today), false);
+            let record = hold::create(&api::HttpApi::new()?, holds_dir, name, tax_id, since, until).await?;
+            println!("{} {} ({} document(s))", record.hash.unwrap_or_default(), record.name, record.documents);
+        }
+        cli::HoldCommand::List => {
+            for record in hold::list(holds_dir)? {
+                println!("{}\t{}\t{} to {}\t{} document(s)", record.name, record.created_at.to_rfc3339(), record.doc_date_from, record.doc_date_to, record.documents);
+            }
+        }
+        cli::HoldCommand::Verify => {
+            let problems = hold::verify(holds_dir)?;
+            if !problems.is_empty() {
+                for problem in &problems {
+                    println!("{}", problem);
+                }
+                return Err(format!("{} problem(s) found in {}", problems.len(), holds_dir.display()).into());
+            }
+            println!("All holds intact");
+        }
+    }
+
+    Ok(())
+}
+
+fn run_export(args: &cli::ExportArgs, config: &config::Config) -> Result<(), Box<dyn std::error::Error>> {
+    let records = export::records(&documents(&args.documents)?, config);
+    export::write(&records, args.format, args.output.as_deref(), config)
+}
+
+fn run_report(args: &cli::ReportArgs, config: &config::Config) -> Result<(), Box<dyn std::error::Error>> {
+    let records = export::records(&documents(&args.documents)?, config);
+    let lang = match (args.format, lang::lang()) {
+        (report::Format::Pdf, lang::Lang::Th) => {
+            tracing::warn!("PDF statements are in English, as their font has no Thai; use -f html or -f md for Thai");
+            lang::Lang::En
+        }
+        (_, lang) => lang,
+    };
+    let statement = match args.month {
+        Some(month) => report::statement(&records, month, lang),
+        None => report::combined(&records, lang),
+    };
+    report::write(&statement, args.format, args.output.as_deref())
+}
 
-    let response = client.post(API_URL_DOWNLOAD)
-        .multipart(form)
-        .send()
-        .await?;
+fn run_export_year(args: &cli::ExportYearArgs, config: &config::Config) -> Result<(), Box<dyn std::error::Error>> {
+    let year = if args.year > 2400 { args.year - 543 } else { args.year };
+    let filter = query::Filter {
+        tax_id: args.tax_id.clone(),
+        since: chrono::NaiveDate::from_ymd_opt(year, 1, 1),
+        until: chrono::NaiveDate::from_ymd_opt(year, 12, 31),
+        ..query::Filter::default()
+    };
+    if filter.since.is_none() {
+        return Err(format!("{} is not a year", args.year).into());
+    }
+    let mut rows = match &args.index {
+        Some(path) => query::from_index(&index::Index::open(path)?, &filter)?,
+        None => query::from_state(&state::State::load(&args.state)?, &filter),
+    };
+    query::with_store(&mut rows, &store::Store::open(&args.store)?.index);
 
-    let content = response.bytes().await?;
+    let output = args.output.clone().unwrap_or_else(|| PathBuf::from(format!("exat-etax-{}.zip", year)));
+    let opts = annual::YearOptions { year, store: args.store.clone(), output: output.clone(), sign_key: args.sign_key.clone() };
+    let summary = annual::write(rows, config, &opts)?;
+    println!("{}: {} document(s) in {} month(s)", output.display(), summary.documents, summary.months);
+    if !summary.missing.is_empty() {
+        println!("{} document(s) not in the store were left out: {}", summary.missing.len(), summary.missing.join(", "));
+    }
+    if let Some(signer) = &summary.signer {
+        println!("Signed; public key SHA-256 {}", signer);
+    }
+    Ok(())
+}
 
-    let filename = match custom_filename {
-        Some(name) => name.to_string(),
-        None => {
-            let now = Local::now();
-            format!("TaxDocuments_{}_{}_{}_{}.zip", tax_id, doc_date_from, doc_date_to, now.format("%Y%m%d%H%M%S"))
+fn run_verify(args: &cli::VerifyArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut trusted = Vec::new();
+    for path in &args.ca {
+        trusted.extend(xades::load_trusted(&std::fs::read(path)?).map_err(|e| format!("Cannot read CA certificate {}: {}", path.display(), e))?);
+    }
+    let mut reports = Vec::new();
+    for input in &args.input {
+        let found = etda::collect(input)?;
+        if found.is_empty() {
+            warn!("No XML found in {}", input.display());
+        }
+        for xml in found {
+            let text = String::from_utf8_lossy(&xml.data);
+            reports.push(xades::verify(text.trim_start_matches('\u{feff}'), &xml.name, &trusted));
         }
+    }
+
+    let mut out: Box<dyn std::io::Write> = match &args.output {
+        Some(path) => Box::new(std::fs::File::create(path)?),
+        None => Box::new(std::io::stdout().lock()),
     };
+    for report in &reports {
+        match args.format {
+            cli::TextOrJson::Json => writeln!(out, "{}", serde_json::to_string(report)?)?,
+            _ if report.valid => writeln!(out, "PASS\t{}\t{}", report.source, report.signer.as_deref().unwrap_or_default())?,
+            _ => writeln!(out, "FAIL\t{}\t{}", report.source, report.failures().join("; "))?,
+        }
+    }
+    out.flush()?;
+    let failed = reports.iter().filter(|r| !r.valid).count();
+    if failed > 0 {
+        return Err(format!("{} of {} document(s) failed verification", failed, reports.len()).into());
+    }
+    Ok(())
+}
 
-    let mut file = File::create(&filename).map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
-    file.write_all(&content).map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
+async fn run_diff(args: &cli::DiffArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let filter = query::Filter { tax_id: args.tax_id.clone(), since: args.since, until: args.until, ..query::Filter::default() };
+    let api = api::HttpApi::new()?;
+    let old = diff::load(&api, &args.old, &filter).await?;
+    let new = diff::load(&api, &args.new, &filter).await?;
+    let result = diff::diff(&old, &new);
 
-    println!("Zip file downloaded successfully.");
+    if let cli::TextOrJson::Json = args.format {
+        println!("{}", serde_json::to_string_pretty(&result.to_json())?);
+    } else {
+        for (label, items) in [("ADDED", &result.added), ("REMOVED", &result.removed)] {
+            for item in items {
+                println!("{}\t{}\t{}\t{}", label, api::doc_no(item), item["docDate"], item["fileName"]);
+            }
+        }
+        for change in &result.changed {
+            let fields: Vec<String> = change.fields.iter().map(|(field, old, new)| format!("{}: {} -> {}", field, old, new)).collect();
+            println!("CHANGED\t{}\t{}", change.doc_no, fields.join("; "));
+        }
+        println!("{} added, {} removed, {} changed, {} unchanged", result.added.len(), result.removed.len(), result.changed.len(), result.unchanged);
+    }
+    if args.exit_code && !result.is_empty() {
+        return Err(format!("{} document(s) differ", result.added.len() + result.removed.len() + result.changed.len()).into());
+    }
+    Ok(())
+}
 
+async fn run_import(args: &cli::ImportArgs, config: &config::Config) -> Result<(), Box<dyn std::error::Error>> {
+    let opts = import_options(args, config)?;
+    interrupt::install();
+    let summary = import::import(&opts).await?;
+    println!(
+        "Imported {} document(s) ({} matched to search results), {} already recorded, {} file(s) skipped",
+        summary.imported, summary.matched, summary.known, summary.skipped
+    );
     Ok(())
 }
+
+async fn run_migrate(args: &cli::MigrateArgs, config: &config::Config) -> Result<(), Box<dyn std::error::Error>> {
+    let opts = migrate::MigrateOptions {
+        import: import_options(&args.import, config)?,
+        layout: args.from_layout.clone(),
+        remove_source: args.remove_source,
+        check_portal: args.check_portal,
+        dry_run: args.dry_run,
+    };
+    let summary = migrate::migrate(&api::HttpApi::new()?, &opts).await?;
+    if opts.dry_run {
+        return print_dry_run(&summary);
+    }
+    for path in &summary.unmatched {
+        println!("UNMATCHED\t{}", path.display());
+    }
+    for (tax_id, doc_no, date) in &summary.missing {
+        println!("MISSING\t{}\t{}\t{}", tax_id, doc_no, date.map(|d| d.to_string()).unwrap_or_default());
+    }
+    println!(
+        "Migrated {} document(s), {} already recorded, {} file(s) not matching the layout, {} missing{}",
+        summary.migrated,
+        summary.known,
+        summary.unmatched.len(),
+        summary.missing.len(),
+        if opts.remove_source { format!(", {} file(s) removed", summary.removed) } else { String::new() }
+    );
+    Ok(())
+}
+
+fn print_dry_run(summary: &migrate::MigrateSummary) -> Result<(), Box<dyn std::error::Error>> {
+    for path in &summary.unmatched {
+        println!("UNMATCHED\t{}", path.display());
+    }
+    for (tax_id, doc_no, date) in &summary.missing {
+        println!("MISSING\t{}\t{}\t{}", tax_id, doc_no, date.map(|d| d.to_string()).unwrap_or_default());
+    }
+    let (mut written, mut removed, mut dirs) = ((0, 0u64), (0, 0u64), 0);
+    for operation in &summary.operations {
+        match operation {
+            migrate::Operation::Write(path, size) => {
+                println!("WRITE\t{}\t{}", path.display(), size);
+                written = (written.0 + 1, written.1 + size);
+            }
+            migrate::Operation::Remove(path, size) => {
+                println!("REMOVE\t{}\t{}", path.display(), size);
+                removed = (removed.0 + 1, removed.1 + size);
+            }
+            migrate::Operation::RemoveDir(path) => {
+                println!("RMDIR\t{}", path.display());
+                dirs += 1;
+            }
+            migrate::Operation::Update(path) => println!("UPDATE\t{}", path.display()),
+        }
+    }
+    println!(
+        "Dry run: would migrate {} document(s) ({} already recorded), write {} file(s) ({} bytes), remove {} file(s) ({} bytes) and {} folder(s); nothing was changed",
+        summary.migrated, summary.known, written.0, written.1, removed.0, removed.1, dirs
+    );
+    Ok(())
+}
+
+// The store, index and state to import into, shared by import and migrate.
+fn import_options(args: &cli::ImportArgs, config: &config::Config) -> Result<import::ImportOptions, Box<dyn std::error::Error>> {
+    let opts = import::ImportOptions {
+        dir: args.dir.clone(),
+        tax_id: args.tax_id.clone(),
+        store: args.store.clone(),
+        index: args.index.clone(),
+        state: args.state.clone(),
+        thai_segment: thai_segment(&args.thai)?,
+        amounts: config.extraction.amounts()?,
+        fleet: config.extraction.fleet()?,
+    };
+    if opts.store.is_none() && opts.index.is_none() && opts.state.is_none() {
+        return Err("Nothing to import into; pass --store, --index or --state".into());
+    }
+    Ok(opts)
+}
+
+fn run_parse(args: &cli::ParseArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut invoices = Vec::new();
+    for input in &args.input {
+        let found = etda::collect(input)?;
+        if found.is_empty() {
+            warn!("No e-Tax invoice XML found in {}", input.display());
+        }
+        for xml in found {
+            match std::str::from_utf8(&xml.data).map_err(Into::into).and_then(|data| etda::parse(data.trim_start_matches('\u{feff}'), &xml.name)) {
+                Ok(invoice) => invoices.push(invoice),
+                Err(e) => warn!("Skipping {}: {}", xml.name, e),
+            }
+        }
+    }
+    let out: Box<dyn std::io::Write> = match &args.output {
+        Some(path) => Box::new(std::fs::File::create(path)?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match args.format {
+        cli::ParseFormat::Csv => etda::write_csv(&invoices, out),
+        cli::ParseFormat::Json => etda::write_json(&invoices, out),
+    }
+}
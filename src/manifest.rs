@@ -0,0 +1,66 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Every PDF downloaded over the runs that share the file: its checksum and the ZIP
+// it came in. Unlike the store it keeps no copies, only what is needed to notice
+// that a document came back with different content, whether reissued by EXAT or
+// corrupted on the way.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub documents: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    pub tax_id: String,
+    pub doc_no: String,
+    pub doc_date: Option<String>,
+    pub file_name: String,
+    pub size: u64,
+    pub sha256: String,
+    pub archive: PathBuf,
+    pub recorded_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum Recorded {
+    New,
+    // The same content as the latest entry; nothing is added.
+    Same,
+    // Added next to the previous entry, which is kept.
+    Changed { previous: Box<ManifestEntry> },
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Manifest, Box<dyn std::error::Error>> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(serde_json::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Manifest::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Like the state file, through a temp file so a crash never truncates it.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    // Compared with the latest entry of the same tax ID and docNo.
+    pub fn record(&mut self, entry: ManifestEntry) -> Recorded {
+        let previous = self.documents.iter().rev().find(|e| e.tax_id == entry.tax_id && e.doc_no == entry.doc_no);
+        let recorded = match previous {
+            None => Recorded::New,
+            Some(previous) if previous.sha256 == entry.sha256 => return Recorded::Same,
+            Some(previous) => Recorded::Changed { previous: Box::new(previous.clone()) },
+        };
+        self.documents.push(entry);
+        recorded
+    }
+}
@@ -0,0 +1,159 @@
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Bookmark, Dictionary, Document, Object, ObjectId, Stream};
+
+// Attributes a page takes from its ancestors in the page tree when it has none.
+const INHERITED: &[&[u8]] = &[b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+// A4 portrait, in points.
+const PAGE_WIDTH: i64 = 595;
+const PAGE_HEIGHT: i64 = 842;
+const LINES_PER_PAGE: usize = 48;
+
+// One input of `merge`: a parsed PDF and the title of its bookmark.
+pub struct Part {
+    pub title: String,
+    doc: Document,
+}
+
+impl Part {
+    pub fn new(title: String, pdf: &[u8]) -> Result<Part, lopdf::Error> {
+        Ok(Part { title, doc: Document::load_mem(pdf)? })
+    }
+}
+
+// Concatenate the PDFs in order, with a bookmark per part and, given `cover` lines,
+// cover pages listing them first.
+pub fn merge(parts: Vec<Part>, cover: Option<&[String]>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut merged = Document::with_version("1.5");
+    let pages_id = merged.new_object_id();
+    let mut kids: Vec<ObjectId> = Vec::new();
+
+    if let Some(lines) = cover {
+        kids.extend(text_pages(&mut merged, pages_id, lines, "Helvetica"));
+    }
+
+    for Part { title, mut doc } in parts {
+        doc.renumber_objects_with(merged.max_id + 1);
+        merged.max_id = doc.max_id;
+
+        let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+        for page_id in &pages {
+            let mut page = doc.get_dictionary(*page_id)?.clone();
+            for key in INHERITED {
+                if !page.has(key) {
+                    if let Some(value) = inherited(&doc, &page, key) {
+                        page.set(*key, value);
+                    }
+                }
+            }
+            page.set("Parent", pages_id);
+            doc.objects.insert(*page_id, Object::Dictionary(page));
+        }
+        if let Some(first) = pages.first() {
+            merged.add_bookmark(Bookmark::new(title, [0.0, 0.0, 0.0], 0, *first), None);
+        }
+
+        // The document's own catalog and page tree are replaced by the merged one.
+        for (id, object) in doc.objects {
+            match object.type_name().unwrap_or(b"") {
+                b"Catalog" | b"Pages" | b"Outlines" | b"Outline" => {}
+                _ => {
+                    merged.objects.insert(id, object);
+                }
+            }
+        }
+        kids.extend(pages);
+    }
+    if kids.is_empty() {
+        return Err("Nothing to merge".into());
+    }
+
+    merged.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as i64,
+            "Kids" => kids.into_iter().map(Object::Reference).collect::<Vec<_>>(),
+        }),
+    );
+    let catalog_id = merged.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id, "PageMode" => "UseOutlines" });
+    merged.trailer.set("Root", catalog_id);
+    if let Some(outline_id) = merged.build_outline() {
+        merged.get_dictionary_mut(catalog_id)?.set("Outlines", outline_id);
+    }
+    merged.compress();
+
+    let mut out = Vec::new();
+    merged.save_to(&mut out)?;
+    Ok(out)
+}
+
+fn inherited(doc: &Document, page: &Dictionary, key: &[u8]) -> Option<Object> {
+    let mut node = page.get(b"Parent").and_then(Object::as_reference).ok();
+    while let Some(id) = node {
+        let parent = doc.get_dictionary(id).ok()?;
+        if let Ok(value) = parent.get(key) {
+            return Some(value.clone());
+        }
+        node = parent.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+    None
+}
+
+// A PDF of nothing but `lines` in Courier, which keeps columns of up to 80
+// characters aligned.
+pub fn text_pdf(lines: &[String]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let kids = text_pages(&mut doc, pages_id, lines, "Courier");
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as i64,
+            "Kids" => kids.into_iter().map(Object::Reference).collect::<Vec<_>>(),
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+    doc.trailer.set("Root", catalog_id);
+    doc.compress();
+    let mut out = Vec::new();
+    doc.save_to(&mut out)?;
+    Ok(out)
+}
+
+// Standard 14 fonts only, so the lines are best kept to ASCII.
+fn text_pages(doc: &mut Document, pages_id: ObjectId, lines: &[String], font: &str) -> Vec<ObjectId> {
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => font,
+        "Encoding" => "WinAnsiEncoding",
+    });
+    let resources_id = doc.add_object(dictionary! { "Font" => dictionary! { "F1" => font_id } });
+
+    lines
+        .chunks(LINES_PER_PAGE)
+        .enumerate()
+        .map(|(n, chunk)| {
+            let mut operations = vec![Operation::new("BT", vec![]), Operation::new("TL", vec![15.into()]), Operation::new("Td", vec![50.into(), (PAGE_HEIGHT - 60).into()])];
+            for (i, line) in chunk.iter().enumerate() {
+                // The first line is the heading.
+                let size = if n == 0 && i == 0 { 14 } else { 10 };
+                operations.push(Operation::new("Tf", vec!["F1".into(), size.into()]));
+                operations.push(Operation::new("Tj", vec![Object::string_literal(line.as_str())]));
+                operations.push(Operation::new("T*", vec![]));
+            }
+            operations.push(Operation::new("ET", vec![]));
+            let content = Content { operations }.encode().expect("cover content encodes");
+            let content_id = doc.add_object(Stream::new(dictionary! {}, content));
+            doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content_id,
+                "Resources" => resources_id,
+                "MediaBox" => vec![0.into(), 0.into(), PAGE_WIDTH.into(), PAGE_HEIGHT.into()],
+            })
+        })
+        .collect()
+}
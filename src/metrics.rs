@@ -0,0 +1,135 @@
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::LazyLock;
+use tracing::{info, warn};
+
+// Counters of the whole process since it started, for `/metrics` in watch and serve
+// mode and `--summary-json` after a single run.
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+#[derive(Default)]
+struct Metrics {
+    runs: AtomicU64,
+    failed_runs: AtomicU64,
+    documents_found: AtomicU64,
+    documents_fetched: AtomicU64,
+    bytes_downloaded: AtomicU64,
+    api_errors: AtomicU64,
+    retries: AtomicU64,
+    // Unix seconds; 0 before the first successful run.
+    last_success: AtomicI64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    pub runs: u64,
+    pub failed_runs: u64,
+    pub documents_found: u64,
+    pub documents_fetched: u64,
+    pub bytes_downloaded: u64,
+    pub api_errors: u64,
+    pub retries: u64,
+    pub last_success: Option<DateTime<Utc>>,
+}
+
+// A search, whether the run then fails or not.
+pub fn add_found(documents: usize) {
+    METRICS.documents_found.fetch_add(documents as u64, Ordering::Relaxed);
+}
+
+pub fn record_run(fetched: usize, ok: bool) {
+    let m = &*METRICS;
+    m.runs.fetch_add(1, Ordering::Relaxed);
+    m.documents_fetched.fetch_add(fetched as u64, Ordering::Relaxed);
+    if ok {
+        m.last_success.store(Utc::now().timestamp(), Ordering::Relaxed);
+    } else {
+        m.failed_runs.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn add_bytes(bytes: usize) {
+    METRICS.bytes_downloaded.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+pub fn api_error() {
+    METRICS.api_errors.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn retry() {
+    METRICS.retries.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn snapshot() -> Snapshot {
+    let m = &*METRICS;
+    let last_success = m.last_success.load(Ordering::Relaxed);
+    Snapshot {
+        runs: m.runs.load(Ordering::Relaxed),
+        failed_runs: m.failed_runs.load(Ordering::Relaxed),
+        documents_found: m.documents_found.load(Ordering::Relaxed),
+        documents_fetched: m.documents_fetched.load(Ordering::Relaxed),
+        bytes_downloaded: m.bytes_downloaded.load(Ordering::Relaxed),
+        api_errors: m.api_errors.load(Ordering::Relaxed),
+        retries: m.retries.load(Ordering::Relaxed),
+        last_success: (last_success > 0).then(|| DateTime::from_timestamp(last_success, 0)).flatten(),
+    }
+}
+
+// The Prometheus text exposition format.
+pub fn prometheus() -> String {
+    let s = snapshot();
+    let metrics: [(&str, &str, &str, f64); 8] = [
+        ("exat_etax_runs_total", "counter", "Searches and downloads run", s.runs as f64),
+        ("exat_etax_run_failures_total", "counter", "Runs that failed", s.failed_runs as f64),
+        ("exat_etax_documents_found_total", "counter", "Documents found by searches", s.documents_found as f64),
+        ("exat_etax_documents_fetched_total", "counter", "Documents downloaded", s.documents_fetched as f64),
+        ("exat_etax_downloaded_bytes_total", "counter", "Bytes of ZIP files downloaded", s.bytes_downloaded as f64),
+        ("exat_etax_api_errors_total", "counter", "Failed requests to the EXAT backend", s.api_errors as f64),
+        ("exat_etax_api_retries_total", "counter", "Requests retried after a 429 response", s.retries as f64),
+        ("exat_etax_last_success_timestamp_seconds", "gauge", "Unix time of the last successful run, 0 if none", s.last_success.map(|t| t.timestamp() as f64).unwrap_or(0.0)),
+    ];
+    metrics
+        .iter()
+        .map(|(name, kind, help, value)| format!("# HELP {} {}.\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value))
+        .collect()
+}
+
+// `--summary-json`: the counters, and how the run ended.
+pub fn write_summary(path: &Path, error: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let summary = json!({
+        "ok": error.is_none(),
+        "error": error,
+        "finishedAt": Utc::now(),
+        "metrics": snapshot(),
+    });
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(&summary)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+// Answer `GET /metrics` on `listen` in the background, for watch mode.
+pub async fn listen(listen: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    info!("Serving metrics on http://{}/metrics", listener.local_addr()?);
+    let app = Router::new().route("/metrics", get(response));
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            warn!("Metrics server stopped: {}", e);
+        }
+    });
+    Ok(())
+}
+
+pub async fn response() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], prometheus())
+}
@@ -0,0 +1,293 @@
+use crate::api::{self, EtaxApi};
+use crate::dates::{self, DATE_FORMAT};
+use crate::import::{self, ImportOptions, Importer};
+use crate::query::Filter;
+use crate::store;
+use chrono::{Months, NaiveDate};
+use regex::Regex;
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+pub struct MigrateOptions {
+    // The store, index and state to migrate into; `dir` is the old archive.
+    pub import: ImportOptions,
+    // e.g. `{year}/{month}/{docNo}.pdf`, relative to `dir`.
+    pub layout: String,
+    // Delete each file once the store holds it.
+    pub remove_source: bool,
+    // Search the portal for the months the archive covers and report what it lacks.
+    pub check_portal: bool,
+    // Only list the file operations a run would do; see `Operation`.
+    pub dry_run: bool,
+}
+
+// A change to the disk a dry run reports instead of making, with the bytes involved.
+#[derive(Debug)]
+pub enum Operation {
+    Write(PathBuf, u64),
+    Remove(PathBuf, u64),
+    RemoveDir(PathBuf),
+    Update(PathBuf),
+}
+
+#[derive(Debug, Default)]
+pub struct MigrateSummary {
+    pub migrated: usize,
+    pub known: usize,
+    // Files the layout does not describe.
+    pub unmatched: Vec<PathBuf>,
+    pub removed: usize,
+    // (taxId, docNo, docDate) of documents the index or the portal has and the
+    // archive does not.
+    pub missing: Vec<(String, String, Option<NaiveDate>)>,
+    // Of a dry run, in the order a real run would do them.
+    pub operations: Vec<Operation>,
+}
+
+// A folder convention compiled to a regex over `/`-separated relative paths.
+pub struct Layout {
+    regex: Regex,
+    names: Vec<String>,
+}
+
+const PLACEHOLDERS: &[(&str, &str)] = &[
+    ("taxId", "[0-9]{13}"),
+    ("year", "[0-9]{4}"),
+    ("month", "[0-9]{1,2}"),
+    ("day", "[0-9]{1,2}"),
+    ("docNo", "[^/]+?"),
+    ("fileName", "[^/]+"),
+];
+
+impl Layout {
+    // `{name}` placeholders as in PLACEHOLDERS, and `*` for anything within a
+    // directory or file name. Everything else is literal.
+    pub fn parse(template: &str) -> Result<Layout, String> {
+        let mut pattern = String::from("^");
+        let mut names = Vec::new();
+        let mut rest = template.trim_start_matches('/');
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('{') {
+                let end = after.find('}').ok_or_else(|| format!("unclosed {{ in layout {:?}", template))?;
+                let name = &after[..end];
+                let (_, regex) = PLACEHOLDERS
+                    .iter()
+                    .find(|(known, _)| *known == name)
+                    .ok_or_else(|| format!("unknown placeholder {{{}}} in layout; use {}", name, PLACEHOLDERS.iter().map(|(n, _)| format!("{{{}}}", n)).collect::<Vec<_>>().join(", ")))?;
+                pattern.push_str(&format!("({})", regex));
+                names.push(name.to_string());
+                rest = &after[end + 1..];
+            } else if let Some(after) = rest.strip_prefix('*') {
+                pattern.push_str("[^/]*");
+                rest = after;
+            } else {
+                let end = rest.find(['{', '*']).unwrap_or(rest.len());
+                pattern.push_str(&regex::escape(&rest[..end]));
+                rest = &rest[end..];
+            }
+        }
+        pattern.push('$');
+        Ok(Layout { regex: Regex::new(&pattern).map_err(|e| e.to_string())?, names })
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.names.iter().any(|n| n == name)
+    }
+
+    // The placeholder values of a relative path, if the layout describes it.
+    fn capture(&self, relative: &str) -> Option<Vec<(&str, String)>> {
+        let captures = self.regex.captures(relative)?;
+        Some(self.names.iter().enumerate().map(|(i, name)| (name.as_str(), captures[i + 1].to_string())).collect())
+    }
+}
+
+// Folders named after Thai years are common; 2567 is 2024.
+fn gregorian_year(year: i32) -> i32 {
+    if year > 2400 {
+        year - 543
+    } else {
+        year
+    }
+}
+
+// Walk an archive kept by hand under some folder convention, record every file it
+// describes in the store, index and state, and report the documents of the months
+// it covers that it does not have.
+pub async fn migrate(api: &impl EtaxApi, opts: &MigrateOptions) -> Result<MigrateSummary, Box<dyn std::error::Error>> {
+    let layout = Layout::parse(&opts.layout)?;
+    if !layout.has("taxId") && opts.import.tax_id.is_none() {
+        return Err("The layout has no {taxId}; pass --tax-id".into());
+    }
+    let mut summary = MigrateSummary::default();
+    let mut importer = Importer::open(&opts.import, opts.dry_run).await?;
+    let mut files = Vec::new();
+    walk(&opts.import.dir, &mut files)?;
+
+    // The months the archive covers, per tax ID, and the docNos it holds.
+    let mut months: BTreeSet<(String, i32, u32)> = BTreeSet::new();
+    let mut held: HashSet<(String, String)> = HashSet::new();
+    let mut removable = Vec::new();
+    for path in files {
+        let relative = path.strip_prefix(&opts.import.dir).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+        let Some(values) = layout.capture(&relative) else {
+            debug!("{} does not match the layout", relative);
+            summary.unmatched.push(path);
+            continue;
+        };
+        let value = |name: &str| values.iter().find(|(n, _)| *n == name).map(|(_, v)| v.clone());
+        let tax_id = value("taxId").or_else(|| opts.import.tax_id.clone()).expect("checked above");
+        let year = value("year").and_then(|y| y.parse().ok()).map(gregorian_year);
+        let month = value("month").and_then(|m| m.parse().ok());
+        let day = value("day").and_then(|d| d.parse().ok());
+
+        let mut found = match import::read(&path, &tax_id) {
+            Ok(found) => found,
+            Err(e) => {
+                warn!("Skipping {}: {}", relative, e);
+                summary.unmatched.push(path);
+                continue;
+            }
+        };
+        // A ZIP's entries keep their own names; only a loose PDF is named by the layout.
+        let single = found.len() == 1 && !found[0].in_archive;
+        let mut stored_all = true;
+        for file in found.iter_mut().filter(|_| single) {
+            file.doc_no = value("docNo");
+            file.doc_date = year.zip(month).zip(day).and_then(|((y, m), d)| NaiveDate::from_ymd_opt(y, m, d));
+        }
+        for file in found {
+            let imported = importer.add(file)?;
+            stored_all &= imported.stored_path.is_some();
+            if imported.known {
+                summary.known += 1;
+            } else {
+                summary.migrated += 1;
+            }
+            held.insert((tax_id.clone(), imported.doc_no));
+        }
+        if let (Some(year), Some(month)) = (year, month) {
+            months.insert((tax_id.clone(), year, month));
+        }
+        // Only the store is a managed copy; without one, the files stay where they are.
+        if opts.remove_source && stored_all {
+            removable.push(path);
+        }
+    }
+
+    // Gaps: documents of the covered months the index or the portal knows about.
+    for (tax_id, year, month) in &months {
+        let Some(since) = NaiveDate::from_ymd_opt(*year, *month, 1) else {
+            continue;
+        };
+        let until = since.checked_add_months(Months::new(1)).and_then(|d| d.pred_opt()).unwrap_or(since);
+        let mut expected: Vec<(String, Option<NaiveDate>)> = Vec::new();
+        if let Some(index) = importer.index() {
+            let filter = Filter { tax_id: Some(tax_id.clone()), since: Some(since), until: Some(until), ..Filter::default() };
+            for document in index.query(&filter)? {
+                let date = api::doc_date(&document.item);
+                expected.push((document.doc_no, date));
+            }
+        }
+        if opts.check_portal {
+            let from = dates::day_bound(since, true).with_timezone(&dates::timezone()).format(DATE_FORMAT).to_string();
+            let to = dates::day_bound(until, false).with_timezone(&dates::timezone()).format(DATE_FORMAT).to_string();
+            info!("Searching the portal for {} {}-{:02}", tax_id, year, month);
+            for item in api::search(api, tax_id, &from, &to).await? {
+                expected.push((api::doc_no(&item), api::doc_date(&item)));
+            }
+        }
+        for (doc_no, date) in expected {
+            let recorded = importer.is_recorded(tax_id, &doc_no)?;
+            let key = (tax_id.clone(), doc_no);
+            if !recorded && !held.contains(&key) && !summary.missing.iter().any(|(t, d, _)| (t, d) == (&key.0, &key.1)) {
+                summary.missing.push((key.0, key.1, date));
+            }
+        }
+    }
+    summary.missing.sort_by(|a, b| (&a.0, a.2, &a.1).cmp(&(&b.0, b.2, &b.1)));
+
+    if opts.dry_run {
+        summary.operations = planned(opts, &importer, &removable, summary.migrated > 0)?;
+        summary.removed = removable.len();
+        return Ok(summary);
+    }
+    let imported = importer.finish()?;
+    debug!("{} document(s) matched to search results", imported.matched);
+    // Only once the store's own index is saved.
+    for path in removable {
+        std::fs::remove_file(&path)?;
+        summary.removed += 1;
+    }
+    if opts.remove_source {
+        remove_empty_dirs(&opts.import.dir);
+    }
+    Ok(summary)
+}
+
+fn planned(opts: &MigrateOptions, importer: &Importer, removable: &[PathBuf], changed: bool) -> std::io::Result<Vec<Operation>> {
+    let mut operations: Vec<Operation> = importer.planned().iter().map(|(path, size)| Operation::Write(path.clone(), *size)).collect();
+    if changed {
+        let updated = [opts.import.store.as_ref().map(|dir| dir.join(store::INDEX_FILE)), opts.import.index.clone(), opts.import.state.clone()];
+        operations.extend(updated.into_iter().flatten().map(Operation::Update));
+    }
+    for path in removable {
+        operations.push(Operation::Remove(path.clone(), std::fs::metadata(path)?.len()));
+    }
+    if opts.remove_source {
+        let removed: HashSet<&Path> = removable.iter().map(PathBuf::as_path).collect();
+        emptied_dirs(&opts.import.dir, &removed, &mut operations);
+    }
+    Ok(operations)
+}
+
+// The folders `remove_empty_dirs` would remove once `removed` are gone, deepest
+// first. Returns whether `dir` would be empty.
+fn emptied_dirs(dir: &Path, removed: &HashSet<&Path>, operations: &mut Vec<Operation>) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    let mut empty = true;
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            if emptied_dirs(&path, removed, operations) {
+                operations.push(Operation::RemoveDir(path));
+            } else {
+                empty = false;
+            }
+        } else if !removed.contains(path.as_path()) {
+            empty = false;
+        }
+    }
+    empty
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)?.map(|e| e.map(|e| e.path())).collect::<Result<_, _>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Folders emptied by --remove-source go too, but never `dir` itself.
+fn remove_empty_dirs(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            remove_empty_dirs(&path);
+            // Fails, harmlessly, unless the folder is empty.
+            let _ = std::fs::remove_dir(&path);
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+use crate::api;
+use crate::cost_center::BudgetAlert;
+
+const LINE_PUSH_URL: &str = "https://api.line.me/v2/bot/message/push";
+const TELEGRAM_API_URL: &str = "https://api.telegram.org";
+// Server-side limits on the message length of each channel.
+const LINE_MAX_CHARS: usize = 5000;
+const TELEGRAM_MAX_CHARS: usize = 4096;
+
+pub const DEFAULT_TEMPLATE: &str = "EXAT e-Tax: {count} new document(s) for {taxId}, total {total} THB\n{documents}";
+pub const DEFAULT_DOCUMENT_TEMPLATE: &str = "- {docDate} {docNo} {amount}";
+
+// Where to announce newly downloaded documents. The webhook and command receive the
+// JSON payload as-is (POSTed, or written to the command's stdin); chat channels get a
+// short message rendered from their own template.
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+    pub url: Option<String>,
+    pub cmd: Option<String>,
+    pub line: Option<Line>,
+    pub telegram: Option<Telegram>,
+}
+
+// A LINE Official Account pushing to a user, group or room through the Messaging
+// API (LINE Notify, which took a personal token, was shut down in March 2025).
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub channel_token: String,
+    // The user, group or room ID: `U...`, `C...` or `R...`.
+    pub to: String,
+    pub template: Template,
+}
+
+#[derive(Debug, Clone)]
+pub struct Telegram {
+    pub token: String,
+    pub chat_id: String,
+    pub template: Template,
+}
+
+// `message` may use {count}, {taxId}, {total}, {archive} and {documents}; the latter
+// expands to one `document` line per document, which may use {docNo}, {docDate},
+// {docType}, {fileName} and {amount}.
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub message: String,
+    pub document: String,
+}
+
+impl Template {
+    pub fn render(&self, payload: &Value) -> String {
+        let documents = payload["documents"].as_array().cloned().unwrap_or_default();
+        let total: f64 = documents.iter().filter_map(|d| d["amount"].as_f64()).sum();
+        let lines: Vec<String> = documents
+            .iter()
+            .map(|d| {
+                let amount = d["amount"].as_f64().map(|a| format!("{:.2}", a)).unwrap_or_default();
+                self.document
+                    .replace("{docNo}", &text(&d["docNo"]))
+                    .replace("{docDate}", &text(&d["docDate"]))
+                    .replace("{docType}", &text(&d["docType"]))
+                    .replace("{fileName}", &text(&d["fileName"]))
+                    .replace("{amount}", &amount)
+            })
+            .collect();
+
+        self.message
+            .replace("\\n", "\n")
+            .replace("{count}", &documents.len().to_string())
+            .replace("{taxId}", &text(&payload["taxId"]))
+            .replace("{total}", &format!("{:.2}", total))
+            .replace("{archive}", &text(&payload["archive"]))
+            .replace("{documents}", &lines.join("\n"))
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn truncate(message: String, max_chars: usize) -> String {
+    if message.chars().count() <= max_chars {
+        return message;
+    }
+    let mut truncated: String = message.chars().take(max_chars - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+// Sent instead of the document payload when a cost center goes over budget; chat
+// channels get one line per alert rather than their document template.
+fn budget_payload(tax_id: &str, alerts: &[BudgetAlert]) -> Value {
+    json!({
+        "event": "budgetExceeded",
+        "taxId": tax_id,
+        "alerts": alerts,
+    })
+}
+
+fn budget_message(alerts: &[BudgetAlert]) -> String {
+    let lines: Vec<String> = alerts
+        .iter()
+        .map(|a| format!("- {} {}: {:.2} of {:.2} THB", a.cost_center, a.month, a.total, a.budget))
+        .collect();
+    format!("EXAT e-Tax: {} cost center(s) over budget\n{}", alerts.len(), lines.join("\n"))
+}
+
+// A document's "path" is its PDF in the store; without --store there is none.
+pub fn payload(tax_id: &str, documents: &[Value], archive: &Path, stored: &HashMap<String, PathBuf>, reissued: &[String], uploaded: &[String], review: &[String]) -> Value {
+    json!({
+        "taxId": tax_id,
+        "archive": archive.display().to_string(),
+        "reissued": reissued,
+        "uploaded": uploaded,
+        "review": review,
+        "documents": documents.iter().map(|item| {
+            let mut document = json!({
+                "docNo": item["docNo"],
+                "docDate": item["docDate"],
+                "docType": item["docType"],
+                "fileName": item["fileName"],
+                "amount": api::amount(item),
+            });
+            if let Some(path) = stored.get(&api::doc_no(item)) {
+                document["path"] = json!(path.display().to_string());
+            }
+            document
+        }).collect::<Vec<_>>(),
+    })
+}
+
+impl Notifier {
+    pub fn is_empty(&self) -> bool {
+        self.url.is_none() && self.cmd.is_none() && self.line.is_none() && self.telegram.is_none()
+    }
+
+    // The documents are already safely on disk at this point, so a failing target is
+    // reported but never fails the run.
+    pub async fn notify(&self, payload: &Value) {
+        self.send(payload, |template| template.render(payload)).await;
+    }
+
+    pub async fn notify_budget(&self, tax_id: &str, alerts: &[BudgetAlert]) {
+        let message = budget_message(alerts);
+        self.send(&budget_payload(tax_id, alerts), |_| message.clone()).await;
+    }
+
+    async fn send(&self, payload: &Value, message: impl Fn(&Template) -> String) {
+        if let Some(url) = &self.url {
+            if let Err(e) = post_webhook(url, payload).await {
+                warn!("Webhook notification to {} failed: {}", url, e);
+            }
+        }
+        if let Some(cmd) = &self.cmd {
+            if let Err(e) = run_command(cmd, payload).await {
+                warn!("Notification command {:?} failed: {}", cmd, e);
+            }
+        }
+        if let Some(line) = &self.line {
+            if let Err(e) = send_line(line, message(&line.template)).await {
+                warn!("LINE message failed: {}", e);
+            }
+        }
+        if let Some(telegram) = &self.telegram {
+            if let Err(e) = send_telegram(telegram, message(&telegram.template)).await {
+                warn!("Telegram message failed: {}", e);
+            }
+        }
+    }
+}
+
+async fn send_line(line: &Line, message: String) -> Result<(), Box<dyn std::error::Error>> {
+    let message = truncate(message, LINE_MAX_CHARS);
+    let client = Client::builder().build()?;
+    let response = client
+        .post(LINE_PUSH_URL)
+        .bearer_auth(&line.channel_token)
+        .json(&json!({ "to": line.to, "messages": [{ "type": "text", "text": message }] }))
+        .send()
+        .await?;
+    debug!(status = %response.status(), "LINE message sent");
+    // An error body says why, e.g. `{"message":"The property, 'to', in the request body is invalid"}`.
+    let status = response.status();
+    if !status.is_success() {
+        let body: Value = response.json().await.unwrap_or_default();
+        return Err(match body["message"].as_str() {
+            Some(reason) => format!("LINE API returned {}: {}", status, reason),
+            None => format!("LINE API returned {}", status),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+// The bot token is part of the URL, so neither the URL nor reqwest's error (which
+// embeds it) is ever logged; only the status is surfaced.
+async fn send_telegram(telegram: &Telegram, message: String) -> Result<(), Box<dyn std::error::Error>> {
+    let message = truncate(message, TELEGRAM_MAX_CHARS);
+    let client = Client::builder().build()?;
+    let response = client
+        .post(format!("{}/bot{}/sendMessage", TELEGRAM_API_URL, telegram.token))
+        .json(&json!({ "chat_id": telegram.chat_id, "text": message }))
+        .send()
+        .await
+        .map_err(|e| e.without_url())?;
+    debug!(status = %response.status(), "Telegram message sent");
+    if !response.status().is_success() {
+        return Err(format!("Telegram API returned {}", response.status()).into());
+    }
+    Ok(())
+}
+
+async fn post_webhook(url: &str, payload: &Value) -> Result<(), Box<dyn std::error::Error>> {
+    let client = Client::builder().build()?;
+    let response = client.post(url).json(payload).send().await?;
+    debug!(status = %response.status(), "Webhook notified");
+    response.error_for_status()?;
+    Ok(())
+}
+
+async fn run_command(cmd: &str, payload: &Value) -> Result<(), Box<dyn std::error::Error>> {
+    let count = payload["documents"].as_array().map(Vec::len).unwrap_or(0);
+    let mut child = shell(cmd)
+        .env("EXAT_ETAX_NEW_DOCUMENTS", count.to_string())
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(serde_json::to_string(payload)?.as_bytes()).await?;
+    }
+
+    let status = child.wait().await?;
+    if !status.success() {
+        return Err(format!("exited with {}", status).into());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn shell(cmd: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(cmd);
+    command
+}
+
+#[cfg(not(windows))]
+pub fn shell(cmd: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(cmd);
+    command
+}
@@ -0,0 +1,130 @@
+use crate::config::Peak;
+use crate::dates;
+use crate::export::Record;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use serde_json::{json, Value};
+use sha1::Sha1;
+use std::path::Path;
+
+const PRODUCTION_URL: &str = "https://peakengineapi.peakaccount.com/api/v1";
+const SANDBOX_URL: &str = "https://peakengineapidev.azurewebsites.net/api/v1";
+
+const CONNECT_KEY_ENV: &str = "EXAT_ETAX_PEAK_CONNECT_KEY";
+const PASSWORD_ENV: &str = "EXAT_ETAX_PEAK_PASSWORD";
+const USER_TOKEN_ENV: &str = "EXAT_ETAX_PEAK_USER_TOKEN";
+
+// PEAK codes for prices that include VAT, and for product lines with 7% or no VAT.
+const VAT_INCLUSIVE: u8 = 1;
+const VAT_7: u8 = 3;
+const NO_VAT: u8 = 1;
+
+// A PEAK business, authorized for this run.
+pub struct Connection {
+    client: Client,
+    base: String,
+    connect_key: String,
+    client_token: String,
+    user_token: String,
+}
+
+fn secret(env: &str, configured: &Option<String>, name: &str) -> Result<String, String> {
+    std::env::var(env).ok().or_else(|| configured.clone()).ok_or(format!("PEAK {} missing; set {} or {}", name.replace('_', " "), env, name))
+}
+
+pub async fn connect(profile: &Peak) -> Result<Connection, Box<dyn std::error::Error>> {
+    let connect_key = secret(CONNECT_KEY_ENV, &profile.connect_key, "connect_key")?;
+    let password = secret(PASSWORD_ENV, &profile.password, "password")?;
+    let user_token = secret(USER_TOKEN_ENV, &profile.user_token, "user_token")?;
+    let base = match &profile.api_url {
+        Some(url) => url.trim_end_matches('/').to_string(),
+        None => if profile.sandbox { SANDBOX_URL } else { PRODUCTION_URL }.to_string(),
+    };
+
+    let mut connection = Connection { client: Client::builder().build()?, base, connect_key, client_token: String::new(), user_token };
+    let request = json!({ "PeakClientToken": { "connectId": profile.connect_id, "password": password } });
+    let response = connection.signed(connection.client.post(format!("{}/clienttoken", connection.base))).json(&request).send().await?;
+    let body = Connection::check(response, "PeakClientToken").await.map_err(|e| format!("PEAK authorization failed: {}", e))?;
+    connection.client_token = body["token"].as_str().ok_or("PEAK returned no client token")?.to_string();
+    Ok(connection)
+}
+
+// The body of the expense created for a document, dated the document date.
+fn expense(record: &Record, profile: &Peak) -> Result<Value, Box<dyn std::error::Error>> {
+    let total = record.total.ok_or("no amount")?;
+    let date = record.doc_date.ok_or("no document date")?.format(dates::ONLY_DATE_FORMAT).to_string();
+
+    let mut description = format!("EXAT {} {}", record.doc_type, record.doc_no);
+    if !record.plates.is_empty() {
+        description.push_str(&format!(" ({})", record.plates.join(", ")));
+    }
+    let mut remark = format!("taxId {}; file {}", record.tax_id, record.file_name);
+    if !record.cost_center.is_empty() {
+        remark.push_str(&format!("; cost center {}", record.cost_center));
+    }
+
+    Ok(json!({ "PeakExpenses": { "expenses": [{
+        "issuedDate": date,
+        "dueDate": date,
+        "contactCode": profile.contact_code,
+        "taxStatus": VAT_INCLUSIVE,
+        "reference": record.doc_no,
+        "remark": remark,
+        "products": [{
+            "accountCode": profile.account_code,
+            "description": description,
+            "quantity": 1,
+            "price": total,
+            "vatType": if record.vat == Some(0.0) { NO_VAT } else { VAT_7 },
+        }],
+    }] } }))
+}
+
+impl Connection {
+    // Every request carries a timestamp signed with the connect key, in Thai time
+    // whatever `--timezone` says, as PEAK checks it against its own clock.
+    fn signed(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let timestamp = Utc::now().with_timezone(&dates::DEFAULT_TIMEZONE).format("%Y%m%d%H%M%S").to_string();
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.connect_key.as_bytes()).expect("HMAC accepts keys of any size");
+        mac.update(timestamp.as_bytes());
+        let request = request.header("Time-Stamp", &timestamp).header("Time-Signature", format!("{:x}", mac.finalize().into_bytes()));
+        if self.client_token.is_empty() {
+            return request;
+        }
+        request.header("Client-Token", &self.client_token).header("User-Token", &self.user_token)
+    }
+
+    // PEAK answers 200 and reports the outcome in `resCode`/`resDesc` of the envelope.
+    async fn check(response: reqwest::Response, envelope: &str) -> Result<Value, Box<dyn std::error::Error>> {
+        let status = response.status();
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+        let body = &body[envelope];
+        if !status.is_success() || body["resCode"].as_str() != Some("200") {
+            return Err(format!("PEAK returned {}: {}", status, body["resDesc"].as_str().unwrap_or("no details")).into());
+        }
+        Ok(body.clone())
+    }
+
+    // Create the expense for a document and attach its PDF. Returns the expense's ID.
+    pub async fn push(&self, record: &Record, pdf: Option<&Path>, profile: &Peak) -> Result<String, Box<dyn std::error::Error>> {
+        let response = self.signed(self.client.post(format!("{}/expenses", self.base))).json(&expense(record, profile)?).send().await?;
+        let created = Self::check(response, "PeakExpenses").await?;
+        let created = &created["expenses"][0];
+        if created["resCode"].as_str().is_some_and(|code| code != "200") {
+            return Err(format!("PEAK rejected the expense: {}", created["resDesc"].as_str().unwrap_or("no details")).into());
+        }
+        let id = created["id"].as_str().ok_or("PEAK response has no expense ID")?.to_string();
+
+        if let Some(pdf) = pdf {
+            let file_name = match record.file_name.as_str() {
+                "" => format!("{}.pdf", record.doc_no),
+                name => name.to_string(),
+            };
+            let form = reqwest::multipart::Form::new().part("file", reqwest::multipart::Part::bytes(std::fs::read(pdf)?).file_name(file_name).mime_str("application/pdf")?);
+            let response = self.signed(self.client.post(format!("{}/expenses/{}/attachments", self.base, id))).multipart(form).send().await?;
+            Self::check(response, "PeakAttachments").await.map_err(|e| format!("Expense {} created, but attaching the PDF failed: {}", id, e))?;
+        }
+        Ok(id)
+    }
+}
@@ -0,0 +1,230 @@
+use crate::api;
+use crate::batch;
+use crate::dates;
+use crate::index::Index;
+use crate::query::Filter;
+use chrono::{Days, NaiveDate, NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Days of the index looked at to estimate a profile's volume.
+const HISTORY_DAYS: u64 = 30;
+
+// One tax ID to sync every night, read like a jobs file: CSV columns
+// taxId,documents,name. `documents` is how many a night's sync is expected to find;
+// without it the busiest day of the index over the last 30 days is assumed.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Profile {
+    #[serde(alias = "taxId", alias = "taxID")]
+    pub tax_id: String,
+    #[serde(default)]
+    pub documents: Option<usize>,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+// Start and end in local time (`--timezone`); a window ending before it starts runs past midnight.
+#[derive(Debug, Clone, Copy)]
+pub struct Window {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl Window {
+    // `01:00-05:00`, `22:30-04:00`
+    pub fn parse(spec: &str) -> Result<Window, String> {
+        let time = |s: &str| NaiveTime::parse_from_str(s.trim(), "%H:%M").map_err(|_| format!("invalid time {:?}; use HH:MM", s));
+        let (start, end) = spec.split_once('-').ok_or_else(|| format!("invalid window {:?}; use HH:MM-HH:MM", spec))?;
+        let window = Window { start: time(start)?, end: time(end)? };
+        if window.start == window.end {
+            return Err("the window is empty".to_string());
+        }
+        Ok(window)
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    // How long after `time` the window opens; zero when it is open.
+    pub fn until_open(&self, time: NaiveTime) -> std::time::Duration {
+        if self.contains(time) {
+            return std::time::Duration::ZERO;
+        }
+        let seconds = (self.start - time).num_seconds().rem_euclid(24 * 3600);
+        std::time::Duration::from_secs(seconds as u64)
+    }
+
+    fn minutes(&self) -> u32 {
+        let minutes = |t: NaiveTime| t.hour() * 60 + t.minute();
+        (minutes(self.end) + 24 * 60 - minutes(self.start)) % (24 * 60)
+    }
+
+    // `offset` minutes into the window.
+    fn at(&self, offset: u32) -> NaiveTime {
+        self.start.overflowing_add_signed(chrono::Duration::minutes(offset.into())).0
+    }
+}
+
+impl std::fmt::Display for Window {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start.format("%H:%M"), self.end.format("%H:%M"))
+    }
+}
+
+pub struct PlanOptions {
+    pub window: Window,
+    pub max_requests_per_minute: u32,
+    // At most this many requests over the whole window (default: as many as the rate
+    // allows).
+    pub budget: Option<u64>,
+    // Search results per page, as the portal returns them.
+    pub page_size: usize,
+    pub index: Option<std::path::PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Slot {
+    pub tax_id: String,
+    pub name: Option<String>,
+    pub documents: usize,
+    pub requests: u64,
+    // Local time, HH:MM; None for a profile that does not fit.
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub minutes: u32,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Plan {
+    pub window: String,
+    pub max_requests_per_minute: u32,
+    pub budget: u64,
+    pub requests: u64,
+    pub planned: Vec<Slot>,
+    pub unplanned: Vec<Slot>,
+}
+
+// Give each profile, in the order listed, the first free minutes of the window. The
+// rate limit is per process, so syncs must not overlap to stay within it: they run
+// one after another, each starting on a whole minute as cron does. A profile that
+// does not fit in what is left of the window or the budget is reported rather than
+// planned, and later, smaller ones may still fit.
+pub fn plan(profiles: &[Profile], opts: &PlanOptions) -> Result<Plan, Box<dyn std::error::Error>> {
+    let index = opts.index.as_deref().map(Index::open).transpose()?;
+    let window = opts.window.minutes();
+    let budget = opts.budget.unwrap_or(u64::from(window) * u64::from(opts.max_requests_per_minute));
+    let mut plan = Plan {
+        window: opts.window.to_string(),
+        max_requests_per_minute: opts.max_requests_per_minute,
+        budget,
+        requests: 0,
+        planned: Vec::new(),
+        unplanned: Vec::new(),
+    };
+    let mut used = 0;
+    for profile in profiles {
+        let documents = match (profile.documents, &index) {
+            (Some(documents), _) => documents,
+            (None, Some(index)) => busiest_day(index, &profile.tax_id)?,
+            (None, None) => return Err(format!("No expected documents for {}; add a documents column or pass --index", profile.tax_id).into()),
+        };
+        let requests = requests(documents, opts.page_size);
+        let minutes = requests.div_ceil(u64::from(opts.max_requests_per_minute)).max(1) as u32;
+        let mut slot = Slot { tax_id: profile.tax_id.clone(), name: profile.name.clone(), documents, requests, start: None, end: None, minutes, reason: None };
+        if used + minutes > window {
+            slot.reason = Some(format!("needs {} min, {} left in the window", minutes, window - used));
+        } else if plan.requests + requests > budget {
+            slot.reason = Some(format!("needs {} requests, {} left in the budget", requests, budget - plan.requests));
+        } else {
+            slot.start = Some(opts.window.at(used).format("%H:%M").to_string());
+            slot.end = Some(opts.window.at(used + minutes).format("%H:%M").to_string());
+            used += minutes;
+            plan.requests += requests;
+            plan.planned.push(slot);
+            continue;
+        }
+        plan.unplanned.push(slot);
+    }
+    Ok(plan)
+}
+
+// The search pages, at least one, and one download when anything is found.
+fn requests(documents: usize, page_size: usize) -> u64 {
+    (documents.div_ceil(page_size.max(1)).max(1) + usize::from(documents > 0)) as u64
+}
+
+fn busiest_day(index: &Index, tax_id: &str) -> Result<usize, Box<dyn std::error::Error>> {
+    let today = dates::today();
+    let filter = Filter { tax_id: Some(tax_id.to_string()), since: today.checked_sub_days(Days::new(HISTORY_DAYS)), until: Some(today), ..Filter::default() };
+    let mut days: BTreeMap<NaiveDate, usize> = BTreeMap::new();
+    for document in index.query(&filter)? {
+        if let Some(date) = api::doc_date(&document.item) {
+            *days.entry(date).or_default() += 1;
+        }
+    }
+    Ok(days.into_values().max().unwrap_or(0))
+}
+
+impl Plan {
+    // A crontab line per planned profile; `args` are passed to each sync.
+    pub fn crontab(&self, args: &[String]) -> String {
+        let timezone = dates::timezone();
+        let mut lines = format!("# exat-etax nightly syncs, {} {}, {} requests/min\nCRON_TZ={}\n", self.window, timezone.name(), self.max_requests_per_minute, timezone.name());
+        // The syncs must count their days in the same timezone as the schedule.
+        let global = if timezone == dates::DEFAULT_TIMEZONE { String::new() } else { format!(" --timezone {}", timezone.name()) };
+        let args: String = args.iter().map(|a| format!(" {}", shell_quote(a))).collect();
+        for slot in &self.planned {
+            let Some((hour, minute)) = slot.start.as_deref().and_then(|s| s.split_once(':')) else {
+                continue;
+            };
+            lines.push_str(&format!("{} {} * * * exat-etax{} --max-requests-per-minute {} sync {}{}\n", minute, hour, global, self.max_requests_per_minute, slot.tax_id, args));
+        }
+        lines
+    }
+}
+
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c)) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+pub fn read_profiles(path: &str) -> Result<Vec<Profile>, Box<dyn std::error::Error>> {
+    batch::read_records(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(s, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn windows_may_wrap_midnight_but_not_be_empty() {
+        let night = Window::parse("22:30-04:00").unwrap();
+        assert!(night.contains(time("23:59")) && night.contains(time("00:00")) && night.contains(time("03:59")));
+        assert!(!night.contains(time("04:00")) && !night.contains(time("12:00")));
+        assert_eq!(night.until_open(time("01:00")), std::time::Duration::ZERO);
+        assert_eq!(night.until_open(time("04:00")), std::time::Duration::from_secs((18 * 60 + 30) * 60));
+        assert_eq!(night.to_string(), "22:30-04:00");
+
+        let early = Window::parse("01:00-05:00").unwrap();
+        assert!(!early.contains(time("00:59")) && early.contains(time("01:00")));
+        assert_eq!(early.until_open(time("00:59")), std::time::Duration::from_secs(60));
+
+        assert_eq!(Window::parse("03:00-03:00").unwrap_err(), "the window is empty");
+        assert!(Window::parse("3am-5am").is_err());
+    }
+}
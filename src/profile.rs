@@ -0,0 +1,98 @@
+use crate::lang::{self, Lang};
+use keyring::Entry;
+use serde::{Deserialize, Serialize};
+
+// Profiles keep what should not be typed on a command line, starting with the tax ID,
+// in the OS keyring (Keychain, Windows Credential Manager, Secret Service), so that
+// `--profile work` stands in for it and it stays out of shell history and `ps`.
+const SERVICE: &str = "exat-etax";
+// The keyring cannot list its entries, so the names are kept in an entry of their own.
+const NAMES: &str = "profiles";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub tax_id: String,
+}
+
+fn entry(user: &str) -> Result<Entry, Box<dyn std::error::Error>> {
+    Entry::new(SERVICE, user).map_err(|e| keyring_error(e).into())
+}
+
+fn keyring_error(e: keyring::Error) -> String {
+    match e {
+        keyring::Error::PlatformFailure(e) | keyring::Error::NoStorageAccess(e) => format!("Cannot use the OS keyring: {}", e),
+        e => format!("OS keyring: {}", e),
+    }
+}
+
+fn read(user: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    match entry(user)?.get_password() {
+        Ok(secret) => Ok(Some(secret)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(keyring_error(e).into()),
+    }
+}
+
+pub fn validate_name(name: &str) -> Result<String, String> {
+    if !name.is_empty() && name != NAMES && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') {
+        Ok(name.to_string())
+    } else {
+        Err(format!("a profile name is letters, digits, '-', '_' and '.', and not {:?}", NAMES))
+    }
+}
+
+pub fn list() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    match read(NAMES)? {
+        Some(names) => Ok(serde_json::from_str(&names).map_err(|e| format!("The profile list in the OS keyring is damaged: {}", e))?),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn save_list(names: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    entry(NAMES)?.set_password(&serde_json::to_string(names)?).map_err(|e| keyring_error(e).into())
+}
+
+pub fn get(name: &str) -> Result<Profile, Box<dyn std::error::Error>> {
+    match read(&format!("profile:{}", name))? {
+        Some(secret) => Ok(serde_json::from_str(&secret).map_err(|e| format!("Profile {} in the OS keyring is damaged: {}", name, e))?),
+        None => Err(match lang::lang() {
+            Lang::En => format!("No profile {}; add it with `exat-etax profile add {}`", name, name),
+            Lang::Th => format!("ไม่พบโปรไฟล์ {} เพิ่มได้ด้วย `exat-etax profile add {}`", name, name),
+        }
+        .into()),
+    }
+}
+
+// Add or replace a profile.
+pub fn add(name: &str, profile: &Profile) -> Result<(), Box<dyn std::error::Error>> {
+    entry(&format!("profile:{}", name))?.set_password(&serde_json::to_string(profile)?).map_err(keyring_error)?;
+    let mut names = list()?;
+    if !names.iter().any(|n| n == name) {
+        names.push(name.to_string());
+        names.sort();
+        save_list(&names)?;
+    }
+    Ok(())
+}
+
+pub fn remove(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut names = list()?;
+    match entry(&format!("profile:{}", name))?.delete_credential() {
+        Ok(()) => {}
+        Err(keyring::Error::NoEntry) if names.iter().any(|n| n == name) => {}
+        Err(keyring::Error::NoEntry) => return Err(format!("No profile {}", name).into()),
+        Err(e) => return Err(keyring_error(e).into()),
+    }
+    names.retain(|n| n != name);
+    save_list(&names)
+}
+
+// The tax ID given on the command line, or that of `--profile`.
+pub fn tax_id(given: Option<&str>, profile: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+    match (given, profile) {
+        (Some(tax_id), _) => Ok(tax_id.to_string()),
+        (None, Some(name)) => Ok(get(name)?.tax_id),
+        (None, None) => Err(lang::lang().pick("A tax ID or --profile is required", "ต้องระบุเลขประจำตัวผู้เสียภาษีหรือ --profile").into()),
+    }
+}
@@ -0,0 +1,54 @@
+use crate::cli;
+use crate::dates;
+use chrono::Datelike;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+// Without any arguments in an interactive terminal, ask for what a search needs
+// instead of printing the usage. Returns the arguments to parse, or None when the
+// command line should be used as is (arguments given, or stdin is not a TTY).
+pub fn fallback_args() -> io::Result<Option<Vec<String>>> {
+    let mut args = std::env::args();
+    let program = args.next().unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string());
+    if args.next().is_some() || !io::stdin().is_terminal() {
+        return Ok(None);
+    }
+
+    let today = dates::today();
+    let month_start = today.with_day(1).expect("every month has a first day");
+
+    let mut input = io::stdin().lock();
+    let tax_id = ask(&mut input, "Tax ID", None, cli::parse_tax_id)?;
+    let since = ask(&mut input, "From (YYYY-MM-DD)", Some(month_start), cli::parse_date)?;
+    let until = ask(&mut input, "To (YYYY-MM-DD)", Some(today), |s| match cli::parse_date(s)? {
+        until if until < since => Err(format!("must not be before {}", since)),
+        until => Ok(until),
+    })?;
+
+    Ok(Some(vec![program, tax_id, "--since".to_string(), since.to_string(), "--until".to_string(), until.to_string()]))
+}
+
+// Ask until the answer validates; an empty answer takes the default if there is one.
+fn ask<T: std::fmt::Display + Clone>(input: &mut impl BufRead, label: &str, default: Option<T>, validate: impl Fn(&str) -> Result<T, String>) -> io::Result<T> {
+    loop {
+        match &default {
+            Some(default) => eprint!("{} [{}]: ", label, default),
+            None => eprint!("{}: ", label),
+        }
+        io::stderr().flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "no answer given"));
+        }
+        let answer = line.trim();
+        if answer.is_empty() {
+            if let Some(default) = &default {
+                return Ok(default.clone());
+            }
+        }
+        match validate(answer) {
+            Ok(value) => return Ok(value),
+            Err(e) => eprintln!("Invalid value: {}", e),
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use crate::api::{self, Reference};
+use crate::config::Config;
+use crate::cost_center;
+use crate::index::Index;
+use crate::state::State;
+use crate::store::{StoreIndex, StoredVersion};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Default, Clone)]
+pub struct Filter {
+    pub tax_id: Option<String>,
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+    pub doc_type: Option<String>,
+    pub min_amount: Option<f64>,
+    pub max_amount: Option<f64>,
+    pub as_of: Option<DateTime<Utc>>,
+}
+
+pub struct Row {
+    pub tax_id: String,
+    pub doc_no: String,
+    pub item: Value,
+    pub first_seen: DateTime<Utc>,
+    pub changed_at: Option<DateTime<Utc>>,
+    pub file_path: Option<String>,
+    pub sha256: Option<String>,
+    // The current stored version, for documents in the store; see `with_store`.
+    pub stored: Option<StoredVersion>,
+    // Documents this one refers to (e.g. the invoice of a credit note), and those
+    // referring to it.
+    pub references: Vec<Reference>,
+    pub referenced_by: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum GroupBy {
+    Plate,
+    Plaza,
+    Route,
+    CostCenter,
+}
+
+pub struct Group {
+    pub key: String,
+    pub documents: usize,
+    pub total: f64,
+}
+
+impl Filter {
+    fn matches(&self, tax_id: &str, item: &Value) -> bool {
+        let date = api::doc_date(item);
+        let amount = api::amount(item);
+        self.tax_id.as_deref().is_none_or(|t| t == tax_id)
+            && self.since.is_none_or(|since| date.is_some_and(|d| d >= since))
+            && self.until.is_none_or(|until| date.is_some_and(|d| d <= until))
+            && self.doc_type.as_deref().is_none_or(|t| api::text_field(item, "docType").as_deref() == Some(t))
+            && self.min_amount.is_none_or(|min| amount.is_some_and(|a| a >= min))
+            && self.max_amount.is_none_or(|max| amount.is_some_and(|a| a <= max))
+    }
+}
+
+// Documents as they were known at `as_of` (or now): anything first seen later is
+// left out and each document is shown in the version current at that moment.
+pub fn from_state(state: &State, filter: &Filter) -> Vec<Row> {
+    let at = filter.as_of.unwrap_or_else(Utc::now);
+    let mut rows = Vec::new();
+
+    // Referring documents may lie outside the filter, so every one is looked at.
+    let mut referenced_by: HashMap<(&str, String), Vec<String>> = HashMap::new();
+    for (id, entry) in &state.tax_ids {
+        for (doc_no, history) in &entry.documents {
+            for reference in history.as_of(at).map(|v| api::references(&v.item)).unwrap_or_default() {
+                referenced_by.entry((id.as_str(), reference.doc_no)).or_default().push(doc_no.clone());
+            }
+        }
+    }
+
+    for (id, entry) in &state.tax_ids {
+        for (doc_no, history) in &entry.documents {
+            let Some(version) = history.as_of(at) else {
+                continue;
+            };
+            if !filter.matches(id, &version.item) {
+                continue;
+            }
+            rows.push(Row {
+                tax_id: id.clone(),
+                doc_no: doc_no.clone(),
+                item: version.item.clone(),
+                first_seen: history.first_seen,
+                changed_at: (version.observed_at != history.first_seen).then_some(version.observed_at),
+                file_path: None,
+                sha256: None,
+                stored: None,
+                references: api::references(&version.item),
+                referenced_by: referenced_by.remove(&(id.as_str(), doc_no.clone())).unwrap_or_default(),
+            });
+        }
+    }
+
+    rows
+}
+
+pub fn from_index(index: &Index, filter: &Filter) -> Result<Vec<Row>, Box<dyn std::error::Error>> {
+    let mut rows = Vec::new();
+    for doc in index.query(filter)? {
+        rows.push(Row {
+            changed_at: (doc.observed_at != doc.first_seen_at).then_some(doc.observed_at),
+            references: index.references(&doc.tax_id, &doc.doc_no)?,
+            referenced_by: index.referenced_by(&doc.tax_id, &doc.doc_no)?,
+            tax_id: doc.tax_id,
+            doc_no: doc.doc_no,
+            item: doc.item,
+            first_seen: doc.first_seen_at,
+            file_path: doc.file_path.or(doc.archive_path),
+            sha256: doc.sha256,
+            stored: None,
+        });
+    }
+    Ok(rows)
+}
+
+// Attach what the store extracted (amounts, plates, routes, ...) to each row.
+pub fn with_store(rows: &mut [Row], store: &StoreIndex) {
+    for row in rows {
+        row.stored = store.documents.get(&format!("{}/{}", row.tax_id, row.doc_no)).and_then(|d| d.current()).cloned();
+        // The XML of the PDF may name references the search result didn't.
+        for reference in row.stored.iter().flat_map(|s| &s.references) {
+            if !row.references.iter().any(|r| r.doc_no == reference.doc_no) {
+                row.references.push(reference.clone());
+            }
+        }
+    }
+}
+
+// Cost center from the configured rules, matched against what the store extracted.
+pub fn cost_center<'a>(row: &Row, config: &'a Config) -> Option<&'a str> {
+    let fleet = row.stored.as_ref().and_then(|s| s.fleet.as_ref());
+    cost_center::assign(&config.cost_centers, config.default_cost_center.as_deref(), &row.tax_id, fleet)
+}
+
+// Document count and total amount per vehicle, plaza, route or cost center. A
+// document naming several (say two plates) gets a group of its own instead of being
+// counted twice.
+pub fn group(rows: &[Row], by: GroupBy, config: &Config) -> Vec<Group> {
+    let mut groups: BTreeMap<String, Group> = BTreeMap::new();
+    for row in rows {
+        let fleet = row.stored.as_ref().and_then(|s| s.fleet.as_ref());
+        let values = match by {
+            GroupBy::Plate => fleet.map(|f| f.plates.clone()),
+            GroupBy::Plaza => fleet.map(|f| f.plazas.clone()),
+            GroupBy::Route => fleet.map(|f| f.routes.clone()),
+            GroupBy::CostCenter => cost_center(row, config).map(|c| vec![c.to_string()]),
+        };
+        let key = match values {
+            Some(values) if !values.is_empty() => values.join(" + "),
+            _ => "(none)".to_string(),
+        };
+        let group = groups.entry(key.clone()).or_insert(Group { key, documents: 0, total: 0.0 });
+        group.documents += 1;
+        group.total += api::amount(&row.item).unwrap_or(0.0);
+    }
+    groups.into_values().collect()
+}
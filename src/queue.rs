@@ -0,0 +1,188 @@
+use crate::api::{self, EtaxApi};
+use crate::dates;
+use crate::interrupt::Interrupted;
+use crate::run::{self, RunOptions};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use tracing::{debug, info, warn};
+
+// Documents a search found but whose download failed, kept with the search result
+// item so that `flush-downloads` can ask for them again without searching.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Queue {
+    #[serde(default)]
+    pub documents: Vec<Pending>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Pending {
+    pub tax_id: String,
+    pub doc_no: String,
+    pub item: Value,
+    pub queued_at: DateTime<Utc>,
+    // Failed downloads, counting the one that queued it.
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct FlushSummary {
+    pub downloaded: usize,
+    // Still queued, as their download failed again.
+    pub remaining: usize,
+}
+
+impl Queue {
+    pub fn load(path: &Path) -> Result<Queue, Box<dyn std::error::Error>> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(serde_json::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Queue::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Like the state file, through a temp file so a crash never truncates it.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+// Queue `items` after a failed download; documents queued already keep their place
+// and count another attempt.
+pub fn defer(path: &Path, tax_id: &str, items: &[Value], error: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut queue = Queue::load(path)?;
+    let now = Utc::now();
+    for item in items {
+        let doc_no = api::doc_no(item);
+        match queue.documents.iter_mut().find(|p| p.tax_id == tax_id && p.doc_no == doc_no) {
+            Some(pending) => {
+                pending.item = item.clone();
+                pending.attempts += 1;
+                pending.last_error = Some(error.to_string());
+            }
+            None => queue.documents.push(Pending { tax_id: tax_id.to_string(), doc_no, item: item.clone(), queued_at: now, attempts: 1, last_error: Some(error.to_string()) }),
+        }
+    }
+    queue.save(path)
+}
+
+// Drop documents that have been downloaded, by a flush or any later run.
+pub fn remove(path: &Path, tax_id: &str, doc_nos: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut queue = Queue::load(path)?;
+    let before = queue.documents.len();
+    queue.documents.retain(|p| p.tax_id != tax_id || !doc_nos.contains(&p.doc_no));
+    if queue.documents.len() != before {
+        queue.save(path)?;
+    }
+    Ok(())
+}
+
+// Download everything queued (for `tax_id` only, if given), one ZIP per tax ID named
+// after the dates of its documents. `base` supplies where the documents go; a tax ID
+// whose download fails again stays queued and the others go ahead.
+pub async fn flush(api: &impl EtaxApi, path: &Path, base: &RunOptions, tax_id: Option<&str>) -> Result<FlushSummary, Box<dyn std::error::Error>> {
+    let queue = Queue::load(path)?;
+    let mut by_tax_id: BTreeMap<&str, Vec<Value>> = BTreeMap::new();
+    for pending in queue.documents.iter().filter(|p| tax_id.is_none_or(|t| t == p.tax_id)) {
+        by_tax_id.entry(&pending.tax_id).or_default().push(pending.item.clone());
+    }
+    let mut summary = FlushSummary::default();
+    if by_tax_id.is_empty() {
+        debug!("Nothing queued in {}", path.display());
+        return Ok(summary);
+    }
+
+    for (tax_id, items) in by_tax_id {
+        let dates: Vec<_> = items.iter().filter_map(api::doc_date).collect();
+        let mut opts = base.clone();
+        opts.tax_id = tax_id.to_string();
+        opts.since = dates::day_bound(dates.iter().min().copied().unwrap_or_else(dates::today), true);
+        opts.until = dates::day_bound(dates.iter().max().copied().unwrap_or_else(dates::today), false);
+        opts.queue = Some(path.to_path_buf());
+        // A failure leaves the documents queued, with one more attempt.
+        opts.metadata_only_fallback = true;
+        info!("Downloading {} queued document(s) of {}", items.len(), crate::logging::mask(tax_id));
+        let count = items.len();
+        let result = match run::download(api, &opts, items).await {
+            Ok(result) => result,
+            Err(e) if e.is::<Interrupted>() => return Err(e),
+            Err(e) => {
+                warn!("Downloading the queued documents of {} failed: {}", crate::logging::mask(tax_id), e);
+                summary.remaining += count;
+                continue;
+            }
+        };
+        summary.downloaded += result.downloaded.len();
+        summary.remaining += result.deferred.len();
+        if !result.deferred.is_empty() {
+            warn!("{} document(s) of {} are still queued", result.deferred.len(), crate::logging::mask(tax_id));
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::mock::MockApi;
+    use crate::config::Config;
+    use crate::notify::Notifier;
+    use crate::state::State;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn a_failing_tax_id_stays_queued_and_the_others_go_ahead() {
+        let dir = std::env::temp_dir().join(format!("exat-etax-queue-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("queue.json");
+        let item = |doc_no: &str| json!({ "docNo": doc_no, "docDate": "2026-10-01 10:00:00", "fileName": format!("{}.pdf", doc_no) });
+        defer(&path, "0105551234567", &[item("A1"), item("A2")], "timed out").unwrap();
+        defer(&path, "0105559876543", &[item("B1")], "timed out").unwrap();
+
+        let config = Config::default();
+        let base = RunOptions {
+            tax_id: String::new(),
+            since: dates::day_bound(dates::today(), true),
+            until: dates::day_bound(dates::today(), false),
+            download: true,
+            filename: None,
+            output_dir: Some(dir.clone()),
+            state: Some(dir.join("state.json")),
+            index: None,
+            embed_manifest: false,
+            manifest: None,
+            keep_partial: false,
+            metadata_only_fallback: true,
+            queue: None,
+            include_related: false,
+            store: None,
+            thai_segment: None,
+            amounts: config.extraction.amounts().unwrap(),
+            fleet: config.extraction.fleet().unwrap(),
+            allocation: config.allocation(),
+            hooks: Vec::new(),
+            upload: None,
+            merge: None,
+            notifier: Notifier::default(),
+            quiet: true,
+        };
+        // Tax IDs go in order: the download of A1 and A2 fails again, that of B1 works.
+        let api = MockApi::new(Vec::new()).with_downloads(vec![Err("Download failed: HTTP 500 Internal Server Error"), Ok(b"PK\x05\x06".to_vec())]);
+        let summary = flush(&api, &path, &base, None).await.unwrap();
+        assert_eq!((summary.downloaded, summary.remaining), (1, 2));
+        let queued: Vec<(String, String)> = Queue::load(&path).unwrap().documents.into_iter().map(|p| (p.tax_id, p.doc_no)).collect();
+        assert_eq!(queued, [("0105551234567".to_string(), "A1".to_string()), ("0105551234567".to_string(), "A2".to_string())]);
+        let mut state = State::load(&dir.join("state.json")).unwrap();
+        assert!(state.tax_id("0105551234567").downloaded.is_empty());
+        assert!(state.tax_id("0105559876543").downloaded.contains("B1"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
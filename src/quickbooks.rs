@@ -0,0 +1,158 @@
+use crate::config::{QuickBooks, QuickBooksEntry};
+use crate::export::Record;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::path::Path;
+use tracing::debug;
+
+const TOKEN_URL: &str = "https://oauth.platform.intuit.com/oauth2/v1/tokens/bearer";
+const PRODUCTION_URL: &str = "https://quickbooks.api.intuit.com";
+const SANDBOX_URL: &str = "https://sandbox-quickbooks.api.intuit.com";
+const MINOR_VERSION: &str = "73";
+
+// Secrets come from the environment first so the configuration file can be shared.
+const CLIENT_SECRET_ENV: &str = "EXAT_ETAX_QBO_CLIENT_SECRET";
+const REFRESH_TOKEN_ENV: &str = "EXAT_ETAX_QBO_REFRESH_TOKEN";
+
+// A QuickBooks Online company, authorized for this run.
+pub struct Connection {
+    client: Client,
+    base: String,
+    realm_id: String,
+    access_token: String,
+}
+
+// Refresh tokens rotate: every refresh hands out a new one and the old one soon stops
+// working, so it is written back to `refresh_token_file` straight away.
+pub async fn connect(profile: &QuickBooks) -> Result<Connection, Box<dyn std::error::Error>> {
+    let client_secret = std::env::var(CLIENT_SECRET_ENV)
+        .ok()
+        .or_else(|| profile.client_secret.clone())
+        .ok_or(format!("QuickBooks client secret missing; set {} or client_secret", CLIENT_SECRET_ENV))?;
+    let refresh_token = match std::env::var(REFRESH_TOKEN_ENV) {
+        Ok(token) => token,
+        Err(_) => {
+            let path = profile.refresh_token_file.as_ref().ok_or(format!("QuickBooks refresh token missing; set {} or refresh_token_file", REFRESH_TOKEN_ENV))?;
+            std::fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?.trim().to_string()
+        }
+    };
+
+    let client = Client::builder().build()?;
+    let response = client
+        .post(TOKEN_URL)
+        .basic_auth(&profile.client_id, Some(client_secret))
+        .header("Accept", "application/json")
+        .form(&[("grant_type", "refresh_token"), ("refresh_token", refresh_token.as_str())])
+        .send()
+        .await?;
+    let status = response.status();
+    // The body of a successful response holds the tokens, so only failures are shown.
+    let body: Value = response.json().await.unwrap_or(Value::Null);
+    if !status.is_success() {
+        return Err(format!("QuickBooks authorization failed ({}): {}", status, body["error"].as_str().unwrap_or("unknown error")).into());
+    }
+    let access_token = body["access_token"].as_str().ok_or("QuickBooks returned no access token")?.to_string();
+    if let (Some(new_token), Some(path)) = (body["refresh_token"].as_str(), &profile.refresh_token_file) {
+        if new_token != refresh_token {
+            std::fs::write(path, new_token)?;
+            debug!(path = %path.display(), "Stored the rotated QuickBooks refresh token");
+        }
+    }
+
+    Ok(Connection {
+        client,
+        base: if profile.sandbox { SANDBOX_URL } else { PRODUCTION_URL }.to_string(),
+        realm_id: profile.realm_id.clone(),
+        access_token,
+    })
+}
+
+// The body of the Purchase (expense) or Bill created for a document.
+fn entry(record: &Record, profile: &QuickBooks) -> Result<Value, Box<dyn std::error::Error>> {
+    let total = record.total.ok_or("no amount")?;
+    let date = record.doc_date.ok_or("no document date")?;
+
+    let mut detail = json!({ "AccountRef": { "value": profile.expense_account_id } });
+    if let Some(class) = profile.classes.get(&record.cost_center) {
+        detail["ClassRef"] = json!({ "value": class });
+    }
+    if let Some(tax_code) = &profile.tax_code_id {
+        detail["TaxCodeRef"] = json!({ "value": tax_code });
+    }
+    let mut description = format!("EXAT {} {}", record.doc_type, record.doc_no);
+    if !record.plates.is_empty() {
+        description.push_str(&format!(" ({})", record.plates.join(", ")));
+    }
+
+    let mut entry = json!({
+        "TxnDate": date.to_string(),
+        "DocNumber": record.doc_no,
+        "PrivateNote": format!("taxId {}; file {}", record.tax_id, record.file_name),
+        "Line": [{
+            "Amount": total,
+            "Description": description,
+            "DetailType": "AccountBasedExpenseLineDetail",
+            "AccountBasedExpenseLineDetail": detail,
+        }],
+    });
+    // Toll documents are VAT-inclusive; with a tax code QuickBooks splits the VAT out.
+    if profile.tax_code_id.is_some() {
+        entry["GlobalTaxCalculation"] = json!("TaxInclusive");
+    }
+    match profile.entry {
+        QuickBooksEntry::Expense => {
+            let account = profile.payment_account_id.as_ref().ok_or("payment_account_id is required for expense entries")?;
+            entry["PaymentType"] = json!("Cash");
+            entry["AccountRef"] = json!({ "value": account });
+            entry["EntityRef"] = json!({ "value": profile.vendor_id, "type": "Vendor" });
+        }
+        QuickBooksEntry::Bill => entry["VendorRef"] = json!({ "value": profile.vendor_id }),
+    }
+    Ok(entry)
+}
+
+impl Connection {
+    fn url(&self, resource: &str) -> String {
+        format!("{}/v3/company/{}/{}?minorversion={}", self.base, self.realm_id, resource, MINOR_VERSION)
+    }
+
+    async fn check(response: reqwest::Response) -> Result<Value, Box<dyn std::error::Error>> {
+        let status = response.status();
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+        if !status.is_success() {
+            let detail = body["Fault"]["Error"][0]["Detail"].as_str().or(body["Fault"]["Error"][0]["Message"].as_str()).unwrap_or("no details");
+            return Err(format!("QuickBooks returned {}: {}", status, detail).into());
+        }
+        Ok(body)
+    }
+
+    // Create the entry for a document and attach its PDF. Returns the entry's ID.
+    pub async fn push(&self, record: &Record, pdf: Option<&Path>, profile: &QuickBooks) -> Result<String, Box<dyn std::error::Error>> {
+        let (resource, entity) = match profile.entry {
+            QuickBooksEntry::Expense => ("purchase", "Purchase"),
+            QuickBooksEntry::Bill => ("bill", "Bill"),
+        };
+        let response = self.client.post(self.url(resource)).bearer_auth(&self.access_token).header("Accept", "application/json").json(&entry(record, profile)?).send().await?;
+        let created = Self::check(response).await?;
+        let id = created[entity]["Id"].as_str().ok_or("QuickBooks response has no entry ID")?.to_string();
+
+        if let Some(pdf) = pdf {
+            // The store prefixes file names with their hash; attach under the original name.
+            let file_name = match record.file_name.as_str() {
+                "" => pdf.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| format!("{}.pdf", record.doc_no)),
+                name => name.to_string(),
+            };
+            let metadata = json!({
+                "AttachableRef": [{ "EntityRef": { "type": entity, "value": id } }],
+                "FileName": file_name,
+                "ContentType": "application/pdf",
+            });
+            let form = reqwest::multipart::Form::new()
+                .part("file_metadata_01", reqwest::multipart::Part::text(metadata.to_string()).mime_str("application/json")?)
+                .part("file_content_01", reqwest::multipart::Part::bytes(std::fs::read(pdf)?).file_name(file_name).mime_str("application/pdf")?);
+            let response = self.client.post(self.url("upload")).bearer_auth(&self.access_token).header("Accept", "application/json").multipart(form).send().await?;
+            Self::check(response).await.map_err(|e| format!("{} {} created, but attaching the PDF failed: {}", entity, id, e))?;
+        }
+        Ok(id)
+    }
+}
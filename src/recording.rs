@@ -0,0 +1,48 @@
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use tracing::debug;
+
+// `--record <dir>` keeps every raw API response on disk; `--replay <dir>` answers
+// requests from such a directory instead of the network. Each response is stored as
+// `<kind>-<hash>.<ext>` next to `<kind>-<hash>.request.json` describing the request,
+// the hash being that of the request itself.
+pub enum Mode {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+static MODE: OnceLock<Mode> = OnceLock::new();
+
+pub fn set(mode: Mode) {
+    let _ = MODE.set(mode);
+}
+
+fn name(kind: &str, request: &Value) -> String {
+    let digest = Sha256::digest(request.to_string().as_bytes());
+    let hex: String = digest.iter().take(8).map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}", kind, hex)
+}
+
+// The recorded response when replaying, None otherwise.
+pub fn replay(kind: &str, request: &Value, ext: &str) -> Option<Result<Vec<u8>, String>> {
+    let Some(Mode::Replay(dir)) = MODE.get() else {
+        return None;
+    };
+    let path = dir.join(format!("{}.{}", name(kind, request), ext));
+    debug!(path = %path.display(), "Replaying recorded response");
+    Some(std::fs::read(&path).map_err(|e| format!("No recorded {} response {} ({}) for {}", kind, path.display(), e, request)))
+}
+
+pub fn record(kind: &str, request: &Value, ext: &str, body: &[u8]) -> std::io::Result<()> {
+    let Some(Mode::Record(dir)) = MODE.get() else {
+        return Ok(());
+    };
+    std::fs::create_dir_all(dir)?;
+    let name = name(kind, request);
+    std::fs::write(dir.join(format!("{}.request.json", name)), serde_json::to_string_pretty(request)?)?;
+    std::fs::write(dir.join(format!("{}.{}", name, ext)), body)?;
+    debug!(name, "Recorded response");
+    Ok(())
+}
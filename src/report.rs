@@ -0,0 +1,455 @@
+use crate::export::Record;
+use crate::lang::Lang;
+use crate::merge;
+use crate::ubl::escape;
+use chrono::{Datelike, NaiveDate};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    #[value(name = "md")]
+    Markdown,
+    Html,
+    Pdf,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Totals {
+    count: usize,
+    net: f64,
+    vat: f64,
+    total: f64,
+}
+
+impl Totals {
+    fn add(&mut self, record: &Record) {
+        self.count += 1;
+        self.net += record.net.unwrap_or(0.0);
+        self.vat += record.vat.unwrap_or(0.0);
+        self.total += record.total.unwrap_or(0.0);
+    }
+
+    fn row(&self, label: String) -> Vec<String> {
+        vec![label, self.count.to_string(), money(self.net), money(self.vat), money(self.total)]
+    }
+}
+
+// A titled table; columns from `numeric` on are right-aligned.
+struct Section {
+    title: &'static str,
+    header: Vec<&'static str>,
+    numeric: usize,
+    rows: Vec<Vec<String>>,
+    total: Option<Vec<String>>,
+}
+
+// The statement of one month: what a bookkeeper would otherwise build by hand from
+// an export. Amounts are VAT-inclusive totals split as in `export`.
+pub struct Statement {
+    lang: Lang,
+    title: String,
+    subtitle: String,
+    sections: Vec<Section>,
+}
+
+pub fn statement(records: &[Record], month: NaiveDate, lang: Lang) -> Statement {
+    let records: Vec<&Record> = records.iter().filter(|r| r.doc_date.is_some_and(|d| (d.year(), d.month()) == (month.year(), month.month()))).collect();
+
+    let mut overall = Totals::default();
+    let mut days: BTreeMap<NaiveDate, Totals> = BTreeMap::new();
+    let mut types: BTreeMap<String, Totals> = BTreeMap::new();
+    let mut rates: BTreeMap<&str, Totals> = BTreeMap::new();
+    for record in &records {
+        overall.add(record);
+        days.entry(record.doc_date.expect("filtered above")).or_default().add(record);
+        types.entry(if record.doc_type.is_empty() { lang.pick("(none)", "(ไม่ระบุ)").to_string() } else { record.doc_type.clone() }).or_default().add(record);
+        rates.entry(if record.vat == Some(0.0) { "0%" } else { "7%" }).or_default().add(record);
+    }
+    let unpriced = records.iter().filter(|r| r.total.is_none()).count();
+    let review = records.iter().filter(|r| r.needs_review).count();
+
+    let mut tax_ids: Vec<&str> = records.iter().map(|r| r.tax_id.as_str()).collect();
+    tax_ids.sort();
+    tax_ids.dedup();
+    let amounts = amount_headers(lang);
+    let total = Some(overall.row(lang.pick("Total", "รวม").to_string()));
+
+    let mut documents: Vec<&&Record> = records.iter().collect();
+    documents.sort_by(|a, b| (a.doc_date, &a.doc_no).cmp(&(b.doc_date, &b.doc_no)));
+
+    Statement {
+        lang,
+        title: format!("{} {}", lang.pick("EXAT e-Tax statement,", "รายงานใบกำกับภาษีอิเล็กทรอนิกส์ EXAT ประจำเดือน"), lang.month(month)),
+        subtitle: match tax_ids.as_slice() {
+            [] => lang.pick("No documents", "ไม่มีเอกสาร").to_string(),
+            [one] => format!("{} {}", lang.pick("Tax ID", "เลขประจำตัวผู้เสียภาษี"), one),
+            many => format!("{} {}", lang.pick("Tax IDs", "เลขประจำตัวผู้เสียภาษี"), many.join(", ")),
+        },
+        sections: vec![
+            Section {
+                title: lang.pick("Summary", "สรุป"),
+                header: vec!["", ""],
+                numeric: 1,
+                rows: vec![
+                    vec![amounts[0].to_string(), overall.count.to_string()],
+                    vec![amounts[1].to_string(), money(overall.net)],
+                    vec![amounts[2].to_string(), money(overall.vat)],
+                    vec![amounts[3].to_string(), money(overall.total)],
+                    vec![lang.pick("Without an amount", "ไม่มียอดเงิน").to_string(), unpriced.to_string()],
+                    vec![lang.pick("Amounts needing review", "ยอดเงินที่ต้องตรวจสอบ").to_string(), review.to_string()],
+                ],
+                total: None,
+            },
+            Section {
+                title: lang.pick("Per day", "รายวัน"),
+                header: [vec![lang.pick("Date", "วันที่")], amounts.clone()].concat(),
+                numeric: 1,
+                rows: days.iter().map(|(day, totals)| totals.row(lang.date(*day))).collect(),
+                total: total.clone(),
+            },
+            Section {
+                title: lang.pick("Per document type", "ตามประเภทเอกสาร"),
+                header: [vec![lang.pick("Type", "ประเภท")], amounts.clone()].concat(),
+                numeric: 1,
+                rows: types.iter().map(|(doc_type, totals)| totals.row(doc_type.clone())).collect(),
+                total: total.clone(),
+            },
+            Section {
+                title: lang.pick("VAT", "ภาษีมูลค่าเพิ่ม"),
+                header: vec![lang.pick("Rate", "อัตรา"), amounts[0], lang.pick("Tax base", "ฐานภาษี"), amounts[2], amounts[3]],
+                numeric: 1,
+                rows: rates.iter().map(|(rate, totals)| totals.row(rate.to_string())).collect(),
+                total,
+            },
+            Section {
+                title: lang.pick("Documents", "รายการเอกสาร"),
+                header: vec![lang.pick("Date", "วันที่"), lang.pick("docNo", "เลขที่เอกสาร"), lang.pick("Type", "ประเภท"), lang.pick("Tax ID", "เลขประจำตัวผู้เสียภาษี"), amounts[3]],
+                numeric: 4,
+                rows: documents
+                    .iter()
+                    .map(|r| {
+                        vec![
+                            r.doc_date.map(|d| lang.date(d)).unwrap_or_default(),
+                            r.doc_no.clone(),
+                            r.doc_type.clone(),
+                            r.tax_id.clone(),
+                            r.total.map(money).unwrap_or_default(),
+                        ]
+                    })
+                    .collect(),
+                total: None,
+            },
+        ],
+    }
+}
+
+// Documents, Net, VAT, Total
+fn amount_headers(lang: Lang) -> Vec<&'static str> {
+    vec![lang.pick("Documents", "จำนวนเอกสาร"), lang.pick("Net", "มูลค่าก่อนภาษี"), lang.pick("VAT", "ภาษีมูลค่าเพิ่ม"), lang.pick("Total", "ยอดรวม")]
+}
+
+// Every document so far comes from EXAT; the column is there for when others do.
+const PROVIDER: &str = "EXAT";
+
+// Every month of `records` by provider and company (tax ID), for comparing months
+// and companies at a glance rather than reconciling one month.
+pub fn combined(records: &[Record], lang: Lang) -> Statement {
+    let mut overall = Totals::default();
+    let mut months: BTreeMap<NaiveDate, Totals> = BTreeMap::new();
+    let mut by_company: BTreeMap<(NaiveDate, &str, &str), Totals> = BTreeMap::new();
+    let mut companies: BTreeMap<(&str, &str), Totals> = BTreeMap::new();
+    let mut undated = 0;
+    for record in records {
+        let Some(date) = record.doc_date else {
+            undated += 1;
+            continue;
+        };
+        let month = date.with_day(1).expect("every month has a first");
+        overall.add(record);
+        months.entry(month).or_default().add(record);
+        by_company.entry((month, PROVIDER, record.tax_id.as_str())).or_default().add(record);
+        companies.entry((PROVIDER, record.tax_id.as_str())).or_default().add(record);
+    }
+    let amounts = amount_headers(lang);
+    let total = Some(overall.row(lang.pick("Total", "รวม").to_string()));
+    let with_label = |labels: Vec<String>, totals: &Totals| [labels, totals.row(String::new())[1..].to_vec()].concat();
+    let count = match lang {
+        Lang::En => format!("{} compan{}", companies.len(), if companies.len() == 1 { "y" } else { "ies" }),
+        Lang::Th => format!("{} บริษัท", companies.len()),
+    };
+    let (month_header, provider_header, tax_id_header) = (lang.pick("Month", "เดือน"), lang.pick("Provider", "ผู้ให้บริการ"), lang.pick("Tax ID", "เลขประจำตัวผู้เสียภาษี"));
+
+    let mut statement = Statement {
+        lang,
+        title: lang.pick("EXAT e-Tax statement per month and company", "รายงานใบกำกับภาษีอิเล็กทรอนิกส์ EXAT รายเดือนและรายบริษัท").to_string(),
+        subtitle: match (months.keys().next(), months.keys().next_back()) {
+            (Some(first), Some(last)) if first != last => format!("{} {} {}, {}", lang.short_month(*first), lang.pick("to", "ถึง"), lang.short_month(*last), count),
+            (Some(only), _) => format!("{}, {}", lang.short_month(*only), count),
+            _ => lang.pick("No documents", "ไม่มีเอกสาร").to_string(),
+        },
+        sections: vec![
+            Section {
+                title: lang.pick("Per month", "รายเดือน"),
+                header: [vec![month_header], amounts.clone()].concat(),
+                numeric: 1,
+                rows: months.iter().map(|(month, totals)| totals.row(lang.short_month(*month))).collect(),
+                total: total.clone(),
+            },
+            Section {
+                title: lang.pick("Per month and company", "รายเดือนและรายบริษัท"),
+                header: [vec![month_header, provider_header, tax_id_header], amounts.clone()].concat(),
+                numeric: 3,
+                rows: by_company.iter().map(|((month, provider, tax_id), totals)| with_label(vec![lang.short_month(*month), provider.to_string(), tax_id.to_string()], totals)).collect(),
+                total: total.clone().map(|row| [vec![row[0].clone(), String::new(), String::new()], row[1..].to_vec()].concat()),
+            },
+            Section {
+                title: lang.pick("Per company", "รายบริษัท"),
+                header: [vec![provider_header, tax_id_header], amounts].concat(),
+                numeric: 2,
+                rows: companies.iter().map(|((provider, tax_id), totals)| with_label(vec![provider.to_string(), tax_id.to_string()], totals)).collect(),
+                total: total.map(|row| [vec![row[0].clone(), String::new()], row[1..].to_vec()].concat()),
+            },
+        ],
+    };
+    if undated > 0 {
+        statement.sections.push(Section {
+            title: lang.pick("Not included", "ไม่ได้รวมไว้"),
+            header: vec!["", ""],
+            numeric: 1,
+            rows: vec![vec![lang.pick("Documents without a date", "เอกสารที่ไม่มีวันที่").to_string(), undated.to_string()]],
+            total: None,
+        });
+    }
+    statement
+}
+
+// 1,234.50
+fn money(value: f64) -> String {
+    let formatted = format!("{:.2}", value.abs());
+    let (whole, cents) = formatted.split_once('.').expect("formatted with decimals");
+    let mut grouped = String::new();
+    for (i, digit) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    format!("{}{}.{}", if value < 0.0 { "-" } else { "" }, grouped, cents)
+}
+
+pub fn render(statement: &Statement, format: Format) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    Ok(match format {
+        Format::Markdown => markdown(statement).into_bytes(),
+        Format::Html => html(statement).into_bytes(),
+        Format::Pdf => merge::text_pdf(&plain(statement))?,
+    })
+}
+
+pub fn write(statement: &Statement, format: Format, output: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let content = render(statement, format)?;
+    match output {
+        Some(path) => std::fs::write(path, content)?,
+        None => std::io::stdout().lock().write_all(&content)?,
+    }
+    Ok(())
+}
+
+fn markdown(statement: &Statement) -> String {
+    let cell = |text: &str| text.replace('|', "\\|");
+    let mut out = format!("# {}\n\n{}\n", statement.title, statement.subtitle);
+    for section in &statement.sections {
+        out.push_str(&format!("\n## {}\n\n", section.title));
+        if section.rows.is_empty() {
+            out.push_str(&format!("{}\n", statement.lang.pick("None.", "ไม่มี")));
+            continue;
+        }
+        out.push_str(&format!("| {} |\n", section.header.iter().map(|h| cell(h)).collect::<Vec<_>>().join(" | ")));
+        out.push_str(&format!("|{}|\n", (0..section.header.len()).map(|i| if i < section.numeric { "---" } else { "---:" }).collect::<Vec<_>>().join("|")));
+        for row in &section.rows {
+            out.push_str(&format!("| {} |\n", row.iter().map(|c| cell(c)).collect::<Vec<_>>().join(" | ")));
+        }
+        if let Some(total) = &section.total {
+            out.push_str(&format!("| {} |\n", total.iter().map(|c| if c.is_empty() { String::new() } else { format!("**{}**", cell(c)) }).collect::<Vec<_>>().join(" | ")));
+        }
+    }
+    out
+}
+
+fn html(statement: &Statement) -> String {
+    let mut body = format!("<h1>{}</h1>\n<p>{}</p>\n", escape(&statement.title), escape(&statement.subtitle));
+    for section in &statement.sections {
+        body.push_str(&format!("<h2>{}</h2>\n", escape(section.title)));
+        if section.rows.is_empty() {
+            body.push_str(&format!("<p>{}</p>\n", statement.lang.pick("None.", "ไม่มี")));
+            continue;
+        }
+        let align = |i: usize| if i < section.numeric { "" } else { " class=\"n\"" };
+        body.push_str("<table>\n");
+        if section.header.iter().any(|h| !h.is_empty()) {
+            body.push_str("<thead><tr>");
+            for (i, header) in section.header.iter().enumerate() {
+                body.push_str(&format!("<th{}>{}</th>", align(i), escape(header)));
+            }
+            body.push_str("</tr></thead>\n");
+        }
+        body.push_str("<tbody>\n");
+        for row in &section.rows {
+            body.push_str("<tr>");
+            for (i, value) in row.iter().enumerate() {
+                body.push_str(&format!("<td{}>{}</td>", align(i), escape(value)));
+            }
+            body.push_str("</tr>\n");
+        }
+        body.push_str("</tbody>\n");
+        if let Some(total) = &section.total {
+            body.push_str("<tfoot><tr>");
+            for (i, value) in total.iter().enumerate() {
+                body.push_str(&format!("<th{}>{}</th>", align(i), escape(value)));
+            }
+            body.push_str("</tr></tfoot>\n");
+        }
+        body.push_str("</table>\n");
+    }
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"{}\"><head><meta charset=\"utf-8\"><title>{}</title><style>\
+         body{{font-family:sans-serif;margin:2em}}table{{border-collapse:collapse;margin-bottom:1em}}\
+         th,td{{padding:4px 10px;border-bottom:1px solid #ddd;text-align:left}}.n{{text-align:right}}</style></head><body>\n{}</body></html>\n",
+        statement.lang.pick("en", "th"),
+        escape(&statement.title),
+        body
+    )
+}
+
+// Fixed-width lines for the PDF; its fonts only cover ASCII, so the PDF is always in
+// English.
+fn plain(statement: &Statement) -> Vec<String> {
+    let ascii = |text: &str| text.chars().map(|c| if c.is_ascii() { c } else { '?' }).collect::<String>();
+    let mut lines = vec![ascii(&statement.title), ascii(&statement.subtitle)];
+    for section in &statement.sections {
+        lines.push(String::new());
+        lines.push(section.title.to_string());
+        if section.rows.is_empty() {
+            lines.push("None.".to_string());
+            continue;
+        }
+        let mut widths: Vec<usize> = section.header.iter().map(|h| h.len()).collect();
+        for row in section.rows.iter().chain(&section.total) {
+            for (width, value) in widths.iter_mut().zip(row) {
+                *width = (*width).max(value.chars().count());
+            }
+        }
+        let format_row = |row: &[String]| {
+            row.iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(i, (value, width))| if i < section.numeric { format!("{:<width$}", ascii(value), width = width) } else { format!("{:>width$}", ascii(value), width = width) })
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        };
+        let rule = "-".repeat(widths.iter().sum::<usize>() + 2 * (widths.len() - 1));
+        if section.header.iter().any(|h| !h.is_empty()) {
+            lines.push(format_row(&section.header.iter().map(|h| h.to_string()).collect::<Vec<_>>()));
+            lines.push(rule.clone());
+        }
+        lines.extend(section.rows.iter().map(|row| format_row(row)));
+        if let Some(total) = &section.total {
+            lines.push(rule);
+            lines.push(format_row(total));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::export::record;
+    use crate::query::Row;
+    use chrono::Utc;
+    use serde_json::Value;
+
+    // Two companies over September and October: A4 is zero-rated with amounts read
+    // from the PDF, B1 has no amount and B2 no date.
+    fn records() -> Vec<Record> {
+        let allocation = Config::default().allocation();
+        let documents: Vec<Value> = serde_json::from_str(include_str!("../testdata/report/documents.json")).unwrap();
+        documents
+            .into_iter()
+            .map(|document| Row {
+                tax_id: document["taxId"].as_str().unwrap().to_string(),
+                doc_no: crate::api::doc_no(&document["item"]),
+                item: document["item"].clone(),
+                first_seen: Utc::now(),
+                changed_at: None,
+                file_path: None,
+                sha256: None,
+                stored: serde_json::from_value(document["stored"].clone()).unwrap(),
+                references: Vec::new(),
+                referenced_by: Vec::new(),
+            })
+            .map(|row| record(&row, &allocation))
+            .collect()
+    }
+
+    fn october() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, 1).unwrap()
+    }
+
+    #[test]
+    fn a_month_is_summed_per_day_type_and_rate() {
+        let markdown = String::from_utf8(render(&statement(&records(), october(), Lang::En), Format::Markdown).unwrap()).unwrap();
+        assert_eq!(markdown, include_str!("../testdata/report/october.md"));
+    }
+
+    #[test]
+    fn every_month_is_summed_per_company() {
+        let markdown = String::from_utf8(render(&combined(&records(), Lang::En), Format::Markdown).unwrap()).unwrap();
+        assert_eq!(markdown, include_str!("../testdata/report/combined.md"));
+    }
+
+    #[test]
+    fn html_is_escaped_and_amounts_right_aligned() {
+        let html = String::from_utf8(render(&statement(&records(), october(), Lang::Th), Format::Html).unwrap()).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>\n<html lang=\"th\">"));
+        assert!(html.contains("<h2>รายวัน</h2>"));
+        assert!(html.contains("<tr><td>Receipt | tax invoice</td><td class=\"n\">1</td><td class=\"n\">100.00</td><td class=\"n\">7.00</td><td class=\"n\">107.00</td></tr>"));
+        assert!(html.contains("<tfoot><tr><th>รวม</th><th class=\"n\">4</th><th class=\"n\">1,300.00</th><th class=\"n\">77.00</th><th class=\"n\">1,377.00</th></tr></tfoot>"));
+    }
+
+    #[test]
+    fn the_pdf_text_is_in_aligned_columns() {
+        let lines = plain(&statement(&records(), october(), Lang::En));
+        let start = lines.iter().position(|line| line == "Per day").unwrap();
+        assert_eq!(
+            lines[start..start + 8],
+            [
+                "Per day",
+                "Date        Documents       Net    VAT     Total",
+                "------------------------------------------------",
+                "2026-10-01          2  1,100.00  77.00  1,177.00",
+                "2026-10-15          1    200.00   0.00    200.00",
+                "2026-10-20          1      0.00   0.00      0.00",
+                "------------------------------------------------",
+                "Total               4  1,300.00  77.00  1,377.00",
+            ]
+        );
+        assert_eq!(lines.last().unwrap(), "2026-10-20  B1                            0105559876543");
+    }
+
+    #[test]
+    fn a_month_without_documents_says_so() {
+        let markdown = markdown(&statement(&records(), NaiveDate::from_ymd_opt(2026, 8, 1).unwrap(), Lang::En));
+        assert!(markdown.starts_with("# EXAT e-Tax statement, August 2026\n\nNo documents\n"));
+        assert!(markdown.contains("## Per day\n\nNone.\n"));
+    }
+
+    #[test]
+    fn money_is_grouped_in_thousands() {
+        assert_eq!([money(0.0), money(999.999), money(1234567.5), money(-1070.0)], ["0.00", "1,000.00", "1,234,567.50", "-1,070.00"]);
+    }
+}
@@ -0,0 +1,755 @@
+use crate::amounts;
+use crate::api::{self, EtaxApi};
+use crate::archive;
+use crate::cost_center::{Allocation, BudgetAlert};
+use crate::dates::{self, DATE_FORMAT, ONLY_DATE_FORMAT};
+use crate::export;
+use crate::fleet;
+use crate::hooks::{self, Step};
+use crate::index::{Fetched, Index};
+use crate::interrupt::{self, Interrupted};
+use crate::lock;
+use crate::manifest::{Manifest, ManifestEntry, Recorded};
+use crate::query::Row;
+use crate::merge::{self, Part};
+use crate::metrics;
+use crate::notify::{self, Notifier};
+use crate::queue;
+use crate::s3::{S3Client, S3Target};
+use crate::state::{Observation, State};
+use crate::store::{self, Store, StoreOutcome, StoredVersion};
+use crate::thai::Segmenter;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+#[derive(Clone)]
+pub struct RunOptions {
+    pub tax_id: String,
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub download: bool,
+    pub filename: Option<String>,
+    pub output_dir: Option<PathBuf>,
+    pub state: Option<PathBuf>,
+    pub index: Option<PathBuf>,
+    pub embed_manifest: bool,
+    // Record the checksum of every downloaded PDF here, across runs; see `Manifest`.
+    pub manifest: Option<PathBuf>,
+    // Keep the `.part` file of an interrupted or failed download.
+    pub keep_partial: bool,
+    // A failed download leaves the run with what the search found (see `RunSummary::deferred`)
+    // instead of failing it.
+    pub metadata_only_fallback: bool,
+    // Where the deferred documents are queued for `flush-downloads`.
+    pub queue: Option<PathBuf>,
+    // Also fetch the documents those found refer to, whatever their date.
+    pub include_related: bool,
+    pub store: Option<PathBuf>,
+    pub thai_segment: Option<Vec<String>>,
+    pub amounts: amounts::Extractor,
+    pub fleet: fleet::Extractor,
+    // Cost-center rules; with an index, downloads are booked against their budgets.
+    pub allocation: Allocation,
+    // Run on each downloaded document; see `hooks`.
+    pub hooks: Vec<Step>,
+    pub upload: Option<Upload>,
+    pub merge: Option<Merge>,
+    pub notifier: Notifier,
+    pub quiet: bool,
+}
+
+// Write the downloaded PDFs, sorted by document date and number, as one PDF.
+#[derive(Debug, Clone)]
+pub struct Merge {
+    pub path: PathBuf,
+    // Start with pages listing the merged documents.
+    pub cover: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Upload {
+    pub target: S3Target,
+    // Upload each PDF in the archive instead of the ZIP itself.
+    pub pdfs: bool,
+    // Remove the local ZIP once everything was uploaded.
+    pub delete_local: bool,
+}
+
+pub struct RunSummary {
+    pub found: usize,
+    pub downloaded: Vec<Value>,
+    pub archive: Option<PathBuf>,
+    pub reissued: Vec<String>,
+    // Found but not downloaded, as the download failed; neither the state nor the index
+    // marks them as fetched, so the next run asks for them again.
+    pub deferred: Vec<String>,
+    pub uploaded: Vec<String>,
+    // Stored documents whose extracted amounts need a manual check.
+    pub review: Vec<String>,
+    pub over_budget: Vec<BudgetAlert>,
+    // Where each document extracted into the store is, by docNo.
+    pub stored: HashMap<String, PathBuf>,
+}
+
+impl RunSummary {
+    fn new(found: usize) -> RunSummary {
+        RunSummary { found, downloaded: Vec::new(), archive: None, reissued: Vec::new(), deferred: Vec::new(), uploaded: Vec::new(), review: Vec::new(), over_budget: Vec::new(), stored: HashMap::new() }
+    }
+}
+
+// Search, print the results and (optionally) download them. With a state file or
+// index only documents not downloaded by a previous run, or whose details changed
+// since, are requested.
+pub async fn run(api: &impl EtaxApi, opts: &RunOptions) -> Result<RunSummary, Box<dyn std::error::Error>> {
+    let _lock = lock(opts).await?;
+    let result = search_and_download(api, opts).await;
+    match &result {
+        Ok(summary) => metrics::record_run(summary.downloaded.len(), true),
+        Err(_) => metrics::record_run(0, false),
+    }
+    result
+}
+
+// Download search result items found before, as `flush-downloads` does, skipping those
+// fetched since.
+pub async fn download(api: &impl EtaxApi, opts: &RunOptions, items: Vec<Value>) -> Result<RunSummary, Box<dyn std::error::Error>> {
+    let _lock = lock(opts).await?;
+    let result = fetch_items(api, opts, items).await;
+    match &result {
+        Ok(summary) => metrics::record_run(summary.downloaded.len(), true),
+        Err(_) => metrics::record_run(0, false),
+    }
+    result
+}
+
+// Everything a run writes to: its state, index, manifest, queue and store, and the
+// directory the ZIP goes to.
+async fn lock(opts: &RunOptions) -> Result<lock::Guard, Box<dyn std::error::Error>> {
+    let mut targets: Vec<lock::Target> = [&opts.state, &opts.index, &opts.manifest, &opts.queue].into_iter().flatten().map(|p| lock::Target::File(p)).collect();
+    if let Some(dir) = &opts.store {
+        targets.push(lock::Target::Dir(dir));
+    }
+    if opts.download {
+        targets.push(lock::Target::Dir(opts.output_dir.as_deref().unwrap_or(Path::new("."))));
+    }
+    lock::acquire(&targets).await
+}
+
+async fn search_and_download(api: &impl EtaxApi, opts: &RunOptions) -> Result<RunSummary, Box<dyn std::error::Error>> {
+    let timezone = dates::timezone();
+    let doc_date_from = opts.since.with_timezone(&timezone).format(DATE_FORMAT).to_string();
+    let doc_date_to = opts.until.with_timezone(&timezone).format(DATE_FORMAT).to_string();
+
+    // Fetch tax document data
+    info!("Searching documents from {} to {}", doc_date_from, doc_date_to);
+    let mut items = tokio::select! {
+        items = api::search(api, &opts.tax_id, &doc_date_from, &doc_date_to) => items?,
+        _ = interrupt::requested() => return Err(Interrupted.into()),
+    };
+    info!("Found {} document(s)", items.len());
+
+    let mut state = match &opts.state {
+        Some(path) => Some(State::load(path)?),
+        None => None,
+    };
+
+    let index = match &opts.index {
+        Some(path) => Some(Index::open(path)?),
+        None => None,
+    };
+
+    if opts.include_related {
+        let related = tokio::select! {
+            related = related_originals(api, opts, &items, &mut state, index.as_ref()) => related?,
+            _ = interrupt::requested() => return Err(Interrupted.into()),
+        };
+        items.extend(related);
+    }
+    metrics::add_found(items.len());
+    if !opts.quiet {
+        for item in &items {
+            let references = api::references(item);
+            if references.is_empty() {
+                println!("docDate: {}, docNo: {}, fileName: {}", item["docDate"], item["docNo"], item["fileName"]);
+            } else {
+                let references: Vec<&str> = references.iter().map(|r| r.doc_no.as_str()).collect();
+                println!("docDate: {}, docNo: {}, fileName: {}, references: {}", item["docDate"], item["docNo"], item["fileName"], references.join(" "));
+            }
+        }
+    }
+
+    // Record every item in the state/index history and note which ones changed
+    // server-side since they were first seen; those are downloaded again.
+    let now = Utc::now();
+    let mut changed: Vec<String> = Vec::new();
+    if let Some(state) = &mut state {
+        let entry = state.tax_id(&opts.tax_id);
+        for item in &items {
+            if entry.observe(api::doc_no(item), item, now) == Observation::Changed {
+                changed.push(api::doc_no(item));
+            }
+        }
+    }
+    if let Some(index) = &index {
+        for item in &items {
+            if index.observe(&opts.tax_id, item, now)? && !changed.contains(&api::doc_no(item)) {
+                changed.push(api::doc_no(item));
+            }
+        }
+    }
+    for doc_no in &changed {
+        warn!("Document {} changed since it was first seen", doc_no);
+    }
+
+    let mut new_items = Vec::new();
+    for item in &items {
+        let doc_no = api::doc_no(item);
+        if !already_fetched(&mut state, index.as_ref(), &opts.tax_id, &doc_no)? || changed.contains(&doc_no) {
+            new_items.push(item.clone());
+        }
+    }
+    if state.is_some() || index.is_some() {
+        info!("{} new document(s) since the last run", new_items.len());
+    }
+
+    let summary = RunSummary::new(items.len());
+
+    // Download ZIP file based on flag
+    if !opts.download {
+        if let (Some(state), Some(state_path)) = (&state, &opts.state) {
+            state.save(state_path)?;
+        }
+        return Ok(summary);
+    }
+    fetch(api, opts, new_items, state, index, summary, true).await
+}
+
+// What the documents of `items` refer to but the search didn't return, e.g. the
+// September invoice an October credit note corrects. Each is searched for on its
+// date, as given with the reference or known to the index; documents fetched before
+// are left to the state and index as usual.
+async fn related_originals(api: &impl EtaxApi, opts: &RunOptions, items: &[Value], state: &mut Option<State>, index: Option<&Index>) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let found: BTreeSet<String> = items.iter().map(api::doc_no).collect();
+    let mut wanted: BTreeMap<NaiveDate, Vec<(String, String)>> = BTreeMap::new();
+    let mut seen = BTreeSet::new();
+    for item in items {
+        let doc_no = api::doc_no(item);
+        let mut references = api::references(item);
+        if let Some(index) = index {
+            for reference in index.references(&opts.tax_id, &doc_no)? {
+                if !references.iter().any(|r| r.doc_no == reference.doc_no) {
+                    references.push(reference);
+                }
+            }
+        }
+        for reference in references {
+            if found.contains(&reference.doc_no) || !seen.insert(reference.doc_no.clone()) {
+                continue;
+            }
+            if already_fetched(state, index, &opts.tax_id, &reference.doc_no)? {
+                debug!("{}, referred to by {}, was downloaded before", reference.doc_no, doc_no);
+                continue;
+            }
+            match reference.doc_date {
+                Some(date) => wanted.entry(date).or_default().push((reference.doc_no, doc_no.clone())),
+                None => warn!("{} refers to {}, whose date is unknown; it is left out", doc_no, reference.doc_no),
+            }
+        }
+    }
+
+    let timezone = dates::timezone();
+    let mut related = Vec::new();
+    for (date, documents) in wanted {
+        let from = dates::day_bound(date, true).with_timezone(&timezone).format(DATE_FORMAT).to_string();
+        let to = dates::day_bound(date, false).with_timezone(&timezone).format(DATE_FORMAT).to_string();
+        let results = api::search(api, &opts.tax_id, &from, &to).await?;
+        for (doc_no, referrer) in documents {
+            match results.iter().find(|item| api::doc_no(item) == doc_no) {
+                Some(item) => {
+                    info!("Including {} of {}, referred to by {}", doc_no, date, referrer);
+                    related.push(item.clone());
+                }
+                None => warn!("{} refers to {}, which is not among the documents of {}", referrer, doc_no, date),
+            }
+        }
+    }
+    Ok(related)
+}
+
+async fn fetch_items(api: &impl EtaxApi, opts: &RunOptions, items: Vec<Value>) -> Result<RunSummary, Box<dyn std::error::Error>> {
+    let mut state = opts.state.as_deref().map(State::load).transpose()?;
+    let index = opts.index.as_deref().map(Index::open).transpose()?;
+    let mut new_items = Vec::new();
+    let mut fetched = Vec::new();
+    for item in items {
+        let doc_no = api::doc_no(&item);
+        if already_fetched(&mut state, index.as_ref(), &opts.tax_id, &doc_no)? {
+            debug!("{} was downloaded since it was queued", doc_no);
+            fetched.push(doc_no);
+        } else {
+            new_items.push(item);
+        }
+    }
+    if let Some(path) = &opts.queue {
+        queue::remove(path, &opts.tax_id, &fetched)?;
+    }
+    let summary = RunSummary::new(new_items.len());
+    fetch(api, opts, new_items, state, index, summary, false).await
+}
+
+// A document is only skipped when every configured dedup store already has it.
+fn already_fetched(state: &mut Option<State>, index: Option<&Index>, tax_id: &str, doc_no: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    if state.is_none() && index.is_none() {
+        return Ok(false);
+    }
+    let in_state = match state {
+        Some(state) => state.tax_id(tax_id).downloaded.contains(doc_no),
+        None => true,
+    };
+    let in_index = match index {
+        Some(index) => index.is_fetched(tax_id, doc_no)?,
+        None => true,
+    };
+    Ok(in_state && in_index)
+}
+
+// Download `new_items` and hand them to the store, the manifest, the index and the
+// rest. `advance` moves the state's last day searched to `opts.until`, for runs that
+// searched up to it.
+async fn fetch(api: &impl EtaxApi, opts: &RunOptions, new_items: Vec<Value>, mut state: Option<State>, index: Option<Index>, mut summary: RunSummary, advance: bool) -> Result<RunSummary, Box<dyn std::error::Error>> {
+    let timezone = dates::timezone();
+    let doc_only_date_from = opts.since.with_timezone(&timezone).format(ONLY_DATE_FORMAT).to_string();
+    let doc_only_date_to = opts.until.with_timezone(&timezone).format(ONLY_DATE_FORMAT).to_string();
+
+    if !new_items.is_empty() {
+        let invoice_data = api::build_listfile(&new_items)?;
+        let (path, placeholder) = archive::reserve_archive(&opts.tax_id, &doc_only_date_from, &doc_only_date_to, opts.filename.as_deref(), opts.output_dir.as_deref())?;
+        let part = archive::part_path(&path);
+        let downloaded = tokio::select! {
+            content = api::download_zip(api, &invoice_data, Some(&part)) => content,
+            _ = interrupt::requested() => Err(Interrupted.into()),
+        };
+        let mut content = match downloaded {
+            Ok(content) => content,
+            Err(e) => {
+                // Nothing of this download is usable, but what the search recorded is.
+                if let Err(cleanup) = archive::abandon_archive(&path, placeholder, opts.keep_partial) {
+                    warn!("Cannot clean up {}: {}", path.display(), cleanup);
+                }
+                if opts.keep_partial && part.exists() {
+                    warn!("Partial download kept at {}", part.display());
+                }
+                if let (Some(state), Some(state_path)) = (&state, &opts.state) {
+                    state.save(state_path)?;
+                }
+                // The search results are recorded already, so reports can go ahead.
+                // `last_until` stays, so that a sync searches these days again.
+                if opts.metadata_only_fallback && !e.is::<Interrupted>() {
+                    warn!("Download failed, continuing with metadata only: {}", e);
+                    summary.deferred = new_items.iter().map(api::doc_no).collect();
+                    if let Some(path) = &opts.queue {
+                        queue::defer(path, &opts.tax_id, &new_items, &e.to_string())?;
+                        info!("Queued {} document(s) in {} for flush-downloads", new_items.len(), path.display());
+                    }
+                    if !opts.quiet {
+                        for doc_no in &summary.deferred {
+                            println!("DEFERRED {}", doc_no);
+                        }
+                    }
+                    return Ok(summary);
+                }
+                return Err(e);
+            }
+        };
+        if opts.embed_manifest {
+            let run_summary = json!({
+                "generator": concat!("exat-etax ", env!("CARGO_PKG_VERSION")),
+                "taxId": opts.tax_id,
+                "docDateFrom": opts.since.with_timezone(&timezone).format(DATE_FORMAT).to_string(),
+                "docDateTo": opts.until.with_timezone(&timezone).format(DATE_FORMAT).to_string(),
+                "generatedAt": Utc::now().to_rfc3339(),
+                "documentsFound": summary.found,
+                "documentsDownloaded": new_items.len(),
+                "downloadBytes": content.len(),
+            });
+            content = archive::embed_manifest(content, &new_items, run_summary)?;
+            std::fs::write(&part, &content)?;
+        }
+        archive::finish_archive(&path)?;
+        info!("Zip file downloaded successfully to {}", path.display());
+        if !opts.quiet {
+            println!("{}", path.display());
+        }
+
+        let stored = match &opts.store {
+            Some(store_dir) => store_documents(opts, store_dir, &content, &new_items, &path, &mut summary)?,
+            None => HashMap::new(),
+        };
+        if let Some(manifest) = &opts.manifest {
+            record_manifest(manifest, opts, &content, &new_items, &path, &mut summary)?;
+        }
+        if let Some(index) = &index {
+            record_fetches(index, &opts.tax_id, &content, &new_items, &path, opts.store.as_deref(), &stored)?;
+            summary.over_budget = charge_cost_centers(index, opts, &new_items, &stored)?;
+        } else if opts.allocation.has_budgets() {
+            warn!("Cost-center budgets are only tracked with --index");
+        }
+        for alert in &summary.over_budget {
+            warn!("Cost center {} is over its {} budget: {:.2} of {:.2} THB", alert.cost_center, alert.month, alert.total, alert.budget);
+            if !opts.quiet {
+                println!("OVER BUDGET {} {} ({:.2} of {:.2} THB)", alert.cost_center, alert.month, alert.total, alert.budget);
+            }
+        }
+
+        if let Some(merge) = &opts.merge {
+            merge_pdfs(merge, &opts.tax_id, opts.since.with_timezone(&timezone).date_naive(), opts.until.with_timezone(&timezone).date_naive(), &content, &new_items)?;
+        }
+
+        if !opts.hooks.is_empty() {
+            run_hooks(opts, &content, &new_items, &path, &stored).await?;
+        }
+        if let Some(store_dir) = &opts.store {
+            summary.stored = stored.iter().map(|(doc_no, version)| (doc_no.clone(), store_dir.join(&version.path))).collect();
+        }
+
+        if let Some(upload) = &opts.upload {
+            summary.uploaded = upload_archive(upload, &opts.tax_id, &path, &content).await?;
+        }
+        summary.archive = Some(path);
+    }
+
+    if let (Some(state), Some(state_path)) = (&mut state, &opts.state) {
+        let entry = state.tax_id(&opts.tax_id);
+        entry.downloaded.extend(new_items.iter().map(api::doc_no));
+        if advance {
+            entry.last_until = Some(opts.until.with_timezone(&timezone).date_naive());
+        }
+        state.save(state_path)?;
+    }
+    if let Some(path) = &opts.queue {
+        queue::remove(path, &opts.tax_id, &new_items.iter().map(api::doc_no).collect::<Vec<_>>())?;
+    }
+
+    if let Some(path) = &summary.archive {
+        if !opts.notifier.is_empty() {
+            opts.notifier.notify(&notify::payload(&opts.tax_id, &new_items, path, &summary.stored, &summary.reissued, &summary.uploaded, &summary.review)).await;
+            if !summary.over_budget.is_empty() {
+                opts.notifier.notify_budget(&opts.tax_id, &summary.over_budget).await;
+            }
+        }
+        if opts.upload.as_ref().is_some_and(|u| u.delete_local) {
+            std::fs::remove_file(path)?;
+            info!("Removed local copy {}", path.display());
+        }
+    }
+
+    summary.downloaded = new_items;
+    Ok(summary)
+}
+
+// Extract the archive into the store, reporting re-issues. Returns the current
+// version of each document.
+fn store_documents(opts: &RunOptions, store_dir: &Path, content: &[u8], items: &[Value], archive_path: &Path, summary: &mut RunSummary) -> Result<HashMap<String, StoredVersion>, Box<dyn std::error::Error>> {
+    let mut store = Store::open(store_dir)?;
+    if let Some(words) = &opts.thai_segment {
+        store.text.segmenter = Some(Segmenter::new(words));
+    }
+    store.amounts = opts.amounts.clone();
+    store.fleet = opts.fleet.clone();
+    for outcome in store.add_archive(&opts.tax_id, content, items, Some(archive_path))? {
+        match outcome {
+            StoreOutcome::Stored { doc_no } => debug!("Stored {}", doc_no),
+            StoreOutcome::Duplicate { doc_no } => debug!("{} already stored with identical content", doc_no),
+            StoreOutcome::Reissued { doc_no, previous, current } => {
+                warn!("Document {} was re-issued: {} superseded by {}", doc_no, store::short_hash(&previous), store::short_hash(&current));
+                if !opts.quiet {
+                    println!("REISSUED {} (previous version kept in {})", doc_no, store_dir.display());
+                }
+                summary.reissued.push(doc_no);
+            }
+        }
+    }
+    store.save()?;
+
+    let mut stored = HashMap::new();
+    for item in items {
+        let doc_no = api::doc_no(item);
+        let Some(current) = store.index.documents.get(&format!("{}/{}", opts.tax_id, doc_no)).and_then(|d| d.current()) else {
+            continue;
+        };
+        // Low-confidence amounts are flagged rather than silently trusted downstream.
+        if let Some(amounts) = current.amounts.as_ref().filter(|a| a.needs_review) {
+            let reasons = amounts.total.as_ref().map(|t| t.reasons.join(", ")).unwrap_or_else(|| "no total found".to_string());
+            warn!("Amounts of {} need review (confidence {:.2}): {}", doc_no, amounts.confidence(), reasons);
+            if !opts.quiet {
+                println!("REVIEW {} (amount confidence {:.2})", doc_no, amounts.confidence());
+            }
+            summary.review.push(doc_no.clone());
+        }
+        stored.insert(doc_no, current.clone());
+    }
+    Ok(stored)
+}
+
+// Warn about documents whose PDF differs from the one an earlier archive had. The
+// store reports the same for the documents it holds, so each is only listed once.
+fn record_manifest(path: &Path, opts: &RunOptions, content: &[u8], items: &[Value], archive_path: &Path, summary: &mut RunSummary) -> Result<(), Box<dyn std::error::Error>> {
+    let mut manifest = Manifest::load(path)?;
+    let checksums = archive::checksum_entries(content)?;
+    let now = Utc::now();
+    for item in items {
+        let doc_no = api::doc_no(item);
+        let Some(checksum) = checksums.iter().find(|c| Path::new(&c.name).file_name().map(|n| n.to_string_lossy().to_string()).as_deref() == item["fileName"].as_str()) else {
+            debug!("{} is not in {}", doc_no, archive_path.display());
+            continue;
+        };
+        let entry = ManifestEntry {
+            tax_id: opts.tax_id.clone(),
+            doc_no: doc_no.clone(),
+            doc_date: api::text_field(item, "docDate"),
+            file_name: checksum.name.clone(),
+            size: checksum.size,
+            sha256: checksum.sha256.clone(),
+            archive: std::path::absolute(archive_path).unwrap_or_else(|_| archive_path.to_path_buf()),
+            recorded_at: now,
+        };
+        if let Recorded::Changed { previous } = manifest.record(entry) {
+            warn!("Document {} differs from the copy in {}: {} is now {}", doc_no, previous.archive.display(), store::short_hash(&previous.sha256), store::short_hash(&checksum.sha256));
+            if !summary.reissued.contains(&doc_no) {
+                if !opts.quiet {
+                    println!("CHANGED {} (differs from the copy in {})", doc_no, previous.archive.display());
+                }
+                summary.reissued.push(doc_no);
+            }
+        }
+    }
+    manifest.save(path)?;
+    Ok(())
+}
+
+fn record_fetches(index: &Index, tax_id: &str, content: &[u8], items: &[Value], archive_path: &Path, store_dir: Option<&Path>, stored: &HashMap<String, StoredVersion>) -> Result<(), Box<dyn std::error::Error>> {
+    let checksums = archive::checksum_entries(content)?;
+    for item in items {
+        let doc_no = api::doc_no(item);
+        let checksum = checksums.iter().find(|c| {
+            Path::new(&c.name).file_name().map(|n| n.to_string_lossy().to_string()).as_deref() == item["fileName"].as_str()
+        });
+        let file_path = store_dir.zip(stored.get(&doc_no)).map(|(dir, version)| dir.join(&version.path));
+        if let Some(version) = stored.get(&doc_no) {
+            index.record_references(tax_id, &doc_no, &version.references)?;
+        }
+        index.record_fetch(tax_id, &doc_no, &Fetched {
+            archive_path,
+            file_path: file_path.as_deref(),
+            sha256: checksum.map(|c| c.sha256.as_str()),
+            size: checksum.map(|c| c.size),
+        })?;
+    }
+    Ok(())
+}
+
+// Book downloaded documents to their cost center and month (by document date) and
+// return the cost centers whose month-to-date total is now over budget. Each overrun
+// is reported once per month and budget.
+fn charge_cost_centers(index: &Index, opts: &RunOptions, items: &[Value], stored: &HashMap<String, StoredVersion>) -> Result<Vec<BudgetAlert>, Box<dyn std::error::Error>> {
+    let this_month = dates::today().format("%Y-%m").to_string();
+    let mut touched = BTreeSet::new();
+    for item in items {
+        let doc_no = api::doc_no(item);
+        let version = stored.get(&doc_no);
+        let Some(cost_center) = opts.allocation.assign(&opts.tax_id, version.and_then(|v| v.fleet.as_ref())) else {
+            continue;
+        };
+        let month = api::doc_date(item).map(|d| d.format("%Y-%m").to_string()).unwrap_or_else(|| this_month.clone());
+        let extracted = version.and_then(|v| v.amounts.as_ref()).and_then(|a| a.total.as_ref()).map(|t| t.value);
+        index.charge(&opts.tax_id, &doc_no, cost_center, &month, api::amount(item).or(extracted).unwrap_or(0.0))?;
+        touched.insert((cost_center.to_string(), month));
+    }
+
+    let mut alerts = Vec::new();
+    for (cost_center, month) in touched {
+        let Some(budget) = opts.allocation.budget(&cost_center) else {
+            continue;
+        };
+        let total = index.month_total(&cost_center, &month)?;
+        if total > budget && index.record_alert(&cost_center, &month, budget, total)? {
+            alerts.push(BudgetAlert { cost_center, month, budget, total });
+        }
+    }
+    Ok(alerts)
+}
+
+// Run the hooks on each document of the archive. A document whose hook fails is
+// reported and the others go ahead; the download itself has succeeded.
+async fn run_hooks(opts: &RunOptions, content: &[u8], items: &[Value], archive_path: &Path, stored: &HashMap<String, StoredVersion>) -> Result<(), Box<dyn std::error::Error>> {
+    let entries = archive::entries(content)?;
+    let dir = archive_path.parent().unwrap_or(Path::new(""));
+    let now = Utc::now();
+    for item in items {
+        if interrupt::is_requested() {
+            warn!("Interrupted; hooks were not run for the remaining documents");
+            break;
+        }
+        let doc_no = api::doc_no(item);
+        let Some(entry) = entries.iter().find(|e| Path::new(&e.name).file_name().map(|n| n.to_string_lossy().to_string()).as_deref() == item["fileName"].as_str()) else {
+            debug!("{} is not in {}; no hooks run", doc_no, archive_path.display());
+            continue;
+        };
+        let version = stored.get(&doc_no);
+        let row = Row {
+            tax_id: opts.tax_id.clone(),
+            doc_no: doc_no.clone(),
+            item: item.clone(),
+            first_seen: now,
+            changed_at: None,
+            file_path: opts.store.as_deref().zip(version).map(|(dir, v)| dir.join(&v.path).display().to_string()),
+            sha256: version.map(|v| v.sha256.clone()),
+            stored: version.cloned(),
+            references: version.map(|v| v.references.clone()).unwrap_or_default(),
+            referenced_by: Vec::new(),
+        };
+        match hooks::run(&opts.hooks, dir, &export::record(&row, &opts.allocation), item, archive_path, &entry.data).await {
+            Ok(path) => info!("Hooks done for {}: {}", doc_no, path.display()),
+            Err(e) => {
+                warn!("Hooks failed for {}: {}", doc_no, e);
+                if !opts.quiet {
+                    println!("HOOK FAILED {} ({})", doc_no, e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// The PDFs of the archive joined into one file, in document order.
+fn merge_pdfs(merge: &Merge, tax_id: &str, since: NaiveDate, until: NaiveDate, content: &[u8], items: &[Value]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut entries: Vec<archive::Entry> = archive::entries(content)?.into_iter().filter(|e| e.name.to_lowercase().ends_with(".pdf")).collect();
+    let mut items: Vec<&Value> = items.iter().collect();
+    items.sort_by(|a, b| api::doc_date(a).cmp(&api::doc_date(b)).then_with(|| api::doc_no(a).cmp(&api::doc_no(b))));
+
+    let mut parts = Vec::new();
+    let mut lines = vec!["EXAT e-Tax documents".to_string(), format!("Tax ID {}, {} to {}", tax_id, since, until), String::new()];
+    let mut total = 0.0;
+    let mut add = |title: String, line: String, pdf: &[u8]| match Part::new(title.clone(), pdf) {
+        Ok(part) => {
+            parts.push(part);
+            lines.push(line);
+            true
+        }
+        Err(e) => {
+            warn!("{} is not a readable PDF ({}); left out of {}", title, e, merge.path.display());
+            false
+        }
+    };
+    for item in items {
+        let Some(i) = entries.iter().position(|e| Path::new(&e.name).file_name().map(|n| n.to_string_lossy().to_string()).as_deref() == item["fileName"].as_str()) else {
+            continue;
+        };
+        let date = api::doc_date(item).map(|d| d.to_string()).unwrap_or_default();
+        let doc_no = api::doc_no(item);
+        let amount = api::amount(item);
+        let line = format!("{}   {}   {}   {}", date, doc_no, api::text_field(item, "docType").unwrap_or_default(), amount.map(|a| format!("{:.2}", a)).unwrap_or_default());
+        if add(format!("{} {}", date, doc_no), line, &entries.remove(i).data) {
+            total += amount.unwrap_or(0.0);
+        }
+    }
+    // PDFs the search didn't list go last, by name.
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    for entry in entries {
+        add(entry.name.clone(), entry.name.clone(), &entry.data);
+    }
+    lines.push(String::new());
+    lines.push(format!("{} document(s), {:.2} THB", parts.len(), total));
+
+    let count = parts.len();
+    std::fs::write(&merge.path, merge::merge(parts, merge.cover.then_some(lines.as_slice()))?)?;
+    info!("Merged {} PDF(s) into {}", count, merge.path.display());
+    Ok(())
+}
+
+// Returns the s3:// URLs of the uploaded objects.
+async fn upload_archive(upload: &Upload, tax_id: &str, path: &Path, content: &[u8]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let client = S3Client::from_env()?;
+    let mut uploaded = Vec::new();
+
+    if upload.pdfs {
+        for entry in archive::entries(content)? {
+            let key = upload.target.key(&format!("{}/{}", tax_id, entry.name));
+            client.put(&upload.target, &key, &entry.data).await?;
+            uploaded.push(format!("s3://{}/{}", upload.target.bucket, key));
+        }
+    } else {
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let key = upload.target.key(&name);
+        client.put(&upload.target, &key, content).await?;
+        uploaded.push(format!("s3://{}/{}", upload.target.bucket, key));
+    }
+
+    Ok(uploaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::mock::MockApi;
+    use crate::config::Config;
+    use crate::queue::Queue;
+
+    // The search is recorded and the documents queued when the download fails.
+    #[tokio::test]
+    async fn a_failed_download_leaves_the_search_recorded_and_the_documents_queued() {
+        let dir = std::env::temp_dir().join(format!("exat-etax-run-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let api = MockApi::new(vec![Ok(r#"{"reprintList":[
+            {"docNo":"A1","docDate":"2026-10-01 10:00:00","fileName":"A1.pdf"},
+            {"docNo":"A2","docDate":"2026-10-02 10:00:00","fileName":"A2.pdf"}
+        ],"totalCount":2,"pageNo":1,"pageSize":50}"#)])
+        .with_downloads(vec![Err("Download failed: HTTP 500 Internal Server Error")]);
+
+        let config = Config::default();
+        let opts = RunOptions {
+            tax_id: "0105551234567".to_string(),
+            since: dates::day_bound(NaiveDate::from_ymd_opt(2026, 10, 1).unwrap(), true),
+            until: dates::day_bound(NaiveDate::from_ymd_opt(2026, 10, 31).unwrap(), false),
+            download: true,
+            filename: None,
+            output_dir: Some(dir.clone()),
+            state: Some(dir.join("state.json")),
+            index: None,
+            embed_manifest: false,
+            manifest: None,
+            keep_partial: false,
+            metadata_only_fallback: true,
+            queue: Some(dir.join("queue.json")),
+            include_related: false,
+            store: None,
+            thai_segment: None,
+            amounts: config.extraction.amounts().unwrap(),
+            fleet: config.extraction.fleet().unwrap(),
+            allocation: config.allocation(),
+            hooks: Vec::new(),
+            upload: None,
+            merge: None,
+            notifier: Notifier::default(),
+            quiet: true,
+        };
+        let summary = run(&api, &opts).await.unwrap();
+        assert_eq!(summary.found, 2);
+        assert!(summary.downloaded.is_empty());
+        assert_eq!(summary.deferred, ["A1", "A2"]);
+
+        let queued: Vec<(String, String)> = Queue::load(&dir.join("queue.json")).unwrap().documents.into_iter().map(|p| (p.tax_id, p.doc_no)).collect();
+        assert_eq!(queued, [("0105551234567".to_string(), "A1".to_string()), ("0105551234567".to_string(), "A2".to_string())]);
+        let mut state = State::load(&dir.join("state.json")).unwrap();
+        let entry = state.tax_id("0105551234567");
+        assert_eq!(entry.documents.keys().collect::<Vec<_>>(), ["A1", "A2"]);
+        assert!(entry.downloaded.is_empty());
+        assert_eq!(entry.last_until, None);
+        // Neither an archive nor its part file is left behind.
+        assert!(!std::fs::read_dir(&dir).unwrap().any(|e| e.unwrap().path().extension().is_some_and(|x| x == "zip" || x == "part")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,270 @@
+use crate::api;
+use crate::dates::{self, DATE_FORMAT};
+use crate::index::{Fetched, Index};
+use crate::merge;
+use crate::state::State;
+use crate::store::{self, Store};
+use chrono::{Datelike, Months, NaiveDate, NaiveTime, TimeZone, Utc};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use tracing::debug;
+
+// Sample documents are laid out like real ones so that every command works on them,
+// but nothing in them is real: the tax IDs are made up (with a valid check digit),
+// and every PDF says it is a sample.
+pub struct SampleOptions {
+    pub store: PathBuf,
+    pub index: Option<PathBuf>,
+    pub state: Option<PathBuf>,
+    // The last month generated and how many months up to it; the current month
+    // stops at today.
+    pub until: NaiveDate,
+    pub months: u32,
+    pub companies: u32,
+    // Per company and month.
+    pub documents: u32,
+    // The same seed and options generate the same documents.
+    pub seed: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct SampleSummary {
+    pub tax_ids: Vec<String>,
+    pub documents: usize,
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+const COMPANIES: &[&str] = &[
+    "Siam Freight Sample Co., Ltd.",
+    "Chao Phraya Logistics Sample Co., Ltd.",
+    "Rattanakosin Trading Sample Co., Ltd.",
+    "Lanna Delivery Sample Co., Ltd.",
+    "Andaman Transport Sample Co., Ltd.",
+    "Isan Express Sample Co., Ltd.",
+];
+
+// (route, plazas), in names the default fleet patterns recognize.
+const ROUTES: &[(&str, &[&str])] = &[
+    ("Chaloem Maha Nakhon", &["Din Daeng", "Bang Na", "Dao Khanong", "Sukhumvit 62", "Tha Ruea"]),
+    ("Si Rat", &["Phaya Thai", "Rama IX", "Ngam Wong Wan", "Pracha Chuen"]),
+    ("Chalong Rat", &["Ram Inthra", "Sukhaphiban 1", "Chatuchot"]),
+    ("Burapha Withi", &["Bang Phli", "Bang Pakong", "Chon Buri"]),
+    ("Udon Ratthaya", &["Bang Phun", "Chaeng Watthana", "Bang Pa In"]),
+];
+
+// Toll fees including VAT, in baht.
+const FEES: &[u32] = &[25, 30, 40, 45, 50, 60, 65, 75, 105];
+
+pub fn generate(opts: &SampleOptions) -> Result<SampleSummary, Box<dyn std::error::Error>> {
+    if opts.months == 0 || opts.companies == 0 || opts.documents == 0 {
+        return Err("Nothing to generate; --months, --companies and --documents must be at least 1".into());
+    }
+    if opts.companies as usize > COMPANIES.len() {
+        return Err(format!("At most {} sample companies", COMPANIES.len()).into());
+    }
+    // Samples never mix with real documents.
+    let mut store = Store::open(&opts.store)?;
+    if !store.index.documents.is_empty() {
+        return Err(format!("Store {} already holds documents; generate samples into a new one", opts.store.display()).into());
+    }
+    for path in opts.index.iter().chain(&opts.state) {
+        if path.exists() {
+            return Err(format!("{} already exists; generate samples into a new file", path.display()).into());
+        }
+    }
+    let index = opts.index.as_deref().map(Index::open).transpose()?;
+    let mut state = opts.state.as_ref().map(|_| State::default());
+
+    let mut rng = Rng::new(opts.seed);
+    let companies: Vec<Company> = COMPANIES[..opts.companies as usize].iter().map(|name| Company::new(name, &mut rng)).collect();
+    let first = opts.until.with_day(1).unwrap_or(opts.until).checked_sub_months(Months::new(opts.months - 1)).ok_or("--months goes back too far")?;
+    let today = dates::today();
+    let mut summary = SampleSummary { tax_ids: companies.iter().map(|c| c.tax_id.clone()).collect(), ..SampleSummary::default() };
+    let mut number = 0;
+
+    for month in 0..opts.months {
+        let start = first.checked_add_months(Months::new(month)).expect("within the range checked above");
+        let end = start.checked_add_months(Months::new(1)).and_then(|d| d.pred_opt()).unwrap_or(start);
+        // Nothing after today.
+        let last = end.min(today);
+        if last < start {
+            break;
+        }
+        for company in &companies {
+            let mut documents: Vec<(NaiveDate, NaiveTime)> = (0..opts.documents)
+                .map(|_| {
+                    let day = start + chrono::Days::new(rng.below((last - start).num_days() as u64 + 1));
+                    let time = NaiveTime::from_hms_opt(6 + rng.below(16) as u32, rng.below(60) as u32, rng.below(60) as u32).expect("valid time");
+                    (day, time)
+                })
+                .collect();
+            documents.sort();
+            for (day, time) in documents {
+                number += 1;
+                let doc_no = format!("SMP{:02}{:02}{:06}", day.year() % 100, day.month(), number);
+                let document = Document::new(company, &doc_no, day.and_time(time), &mut rng);
+                let item = document.item();
+                let pdf = merge::text_pdf(&document.lines())?;
+                let file_name = format!("{}.pdf", doc_no);
+                store.add(&company.tax_id, &doc_no, &file_name, &pdf, api::amount(&item), None)?;
+
+                let seen = dates::timezone().from_local_datetime(&document.issued).single().map(|d| d.with_timezone(&Utc)).unwrap_or_else(Utc::now);
+                if let Some(index) = &index {
+                    let stored = store.index.documents.get(&format!("{}/{}", company.tax_id, doc_no)).and_then(store::StoredDocument::current).map(|v| opts.store.join(&v.path));
+                    let path = stored.unwrap_or_else(|| opts.store.join(&file_name));
+                    index.observe(&company.tax_id, &item, seen)?;
+                    index.record_fetch(&company.tax_id, &doc_no, &Fetched {
+                        archive_path: &path,
+                        file_path: Some(&path),
+                        sha256: Some(&format!("{:x}", Sha256::digest(&pdf))),
+                        size: Some(pdf.len() as u64),
+                    })?;
+                }
+                if let Some(state) = &mut state {
+                    let state = state.tax_id(&company.tax_id);
+                    state.observe(doc_no.clone(), &item, seen);
+                    state.downloaded.insert(doc_no.clone());
+                }
+                debug!("Generated {} {} for {}", company.tax_id, doc_no, day);
+                summary.documents += 1;
+                summary.since = Some(summary.since.map_or(day, |d| d.min(day)));
+                summary.until = Some(summary.until.map_or(day, |d| d.max(day)));
+            }
+        }
+    }
+    store.save()?;
+    if let (Some(state), Some(path)) = (&state, &opts.state) {
+        state.save(path)?;
+    }
+    Ok(summary)
+}
+
+struct Company {
+    name: &'static str,
+    tax_id: String,
+    // Easy Pass cards, 12 digits each.
+    cards: Vec<String>,
+}
+
+impl Company {
+    fn new(name: &'static str, rng: &mut Rng) -> Company {
+        // Juristic persons' IDs start with 0.
+        let mut digits: Vec<u32> = std::iter::once(0).chain((0..11).map(|_| rng.below(10) as u32)).collect();
+        digits.push(check_digit(&digits));
+        let tax_id = digits.iter().map(|d| char::from_digit(*d, 10).expect("a digit")).collect();
+        let cards = (0..1 + rng.below(3)).map(|_| (0..12).map(|_| char::from_digit(rng.below(10) as u32, 10).expect("a digit")).collect()).collect();
+        Company { name, tax_id, cards }
+    }
+}
+
+// The 13th digit of a Thai tax ID, from the first 12.
+fn check_digit(digits: &[u32]) -> u32 {
+    let sum: u32 = digits.iter().enumerate().map(|(i, d)| d * (13 - i as u32)).sum();
+    (11 - sum % 11) % 10
+}
+
+struct Trip {
+    route: &'static str,
+    plaza: &'static str,
+    fee: u32,
+}
+
+struct Document<'a> {
+    company: &'a Company,
+    doc_no: String,
+    issued: chrono::NaiveDateTime,
+    card: String,
+    trips: Vec<Trip>,
+}
+
+impl<'a> Document<'a> {
+    fn new(company: &'a Company, doc_no: &str, issued: chrono::NaiveDateTime, rng: &mut Rng) -> Document<'a> {
+        let card = rng.pick(&company.cards).clone();
+        let trips = (0..1 + rng.below(12))
+            .map(|_| {
+                let &(route, plazas) = rng.pick(ROUTES);
+                let plaza = *rng.pick(plazas);
+                Trip { route, plaza, fee: *rng.pick(FEES) }
+            })
+            .collect();
+        Document { company, doc_no: doc_no.to_string(), issued, card, trips }
+    }
+
+    // Satang, so the VAT split adds up exactly.
+    fn total(&self) -> u64 {
+        self.trips.iter().map(|t| t.fee as u64 * 100).sum()
+    }
+
+    fn net(&self) -> u64 {
+        (self.total() as f64 / 1.07).round() as u64
+    }
+
+    fn item(&self) -> Value {
+        json!({
+            "docNo": self.doc_no,
+            "docDate": self.issued.format(DATE_FORMAT).to_string(),
+            "fileName": format!("{}.pdf", self.doc_no),
+            "totalAmount": baht(self.total()),
+            "docType": "T03",
+        })
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            "SAMPLE - NOT A REAL TAX DOCUMENT".to_string(),
+            String::new(),
+            "Expressway Authority of Thailand".to_string(),
+            "Tax invoice / receipt".to_string(),
+            String::new(),
+            format!("Document no.: {}", self.doc_no),
+            format!("Date: {}", self.issued.format(DATE_FORMAT)),
+            format!("Customer: {}", self.company.name),
+            format!("Customer tax ID: {}", self.company.tax_id),
+            format!("Easy Pass no.: {}", self.card),
+            String::new(),
+        ];
+        for trip in &self.trips {
+            // The fee first: the plaza pattern runs to the end of the line.
+            lines.push(format!("{:>8}  Route: {}", baht(trip.fee as u64 * 100), trip.route));
+            lines.push(format!("{:>8}  Plaza: {}", "", trip.plaza));
+        }
+        lines.extend([
+            String::new(),
+            format!("Sub total: {:>10}", baht(self.net())),
+            format!("VAT 7%: {:>10}", baht(self.total() - self.net())),
+            format!("Grand total: {:>10}", baht(self.total())),
+        ]);
+        lines
+    }
+}
+
+fn baht(satang: u64) -> String {
+    format!("{}.{:02}", satang / 100, satang % 100)
+}
+
+// SplitMix64: small, fast and the same everywhere, which is all samples need.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+}
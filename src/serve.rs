@@ -0,0 +1,244 @@
+use crate::api::{self, HttpApi};
+use crate::cli;
+use crate::dates::{self, DATE_FORMAT};
+use crate::index::{Index, IndexedDocument};
+use crate::interrupt;
+use crate::metrics;
+use crate::viewer;
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{Query, Request, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::Engine;
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tracing::{info, warn};
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub listen: SocketAddr,
+    // Without one, only the endpoints that go to the portal are served.
+    pub index: Option<PathBuf>,
+    // Required from clients when set; see `authorize`.
+    pub api_key: Option<String>,
+}
+
+pub struct AppState {
+    api: HttpApi,
+    index: Option<Mutex<Index>>,
+    pub api_key: Option<String>,
+}
+
+impl AppState {
+    pub fn index(&self) -> Result<MutexGuard<'_, Index>, ApiError> {
+        let index = self.index.as_ref().ok_or_else(|| ApiError(StatusCode::NOT_FOUND, "this server has no index; start it with --index".to_string()))?;
+        Ok(index.lock().expect("index lock"))
+    }
+}
+
+// An error answered as `{"error": ...}` with its status.
+pub struct ApiError(pub StatusCode, pub String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.0, Json(json!({ "error": self.1 }))).into_response();
+        // Lets a browser ask for the key; any user name goes.
+        if self.0 == StatusCode::UNAUTHORIZED {
+            response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Basic realm=\"exat-etax\""));
+        }
+        response
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(e: QueryRejection) -> Self {
+        ApiError(StatusCode::BAD_REQUEST, e.body_text())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(e: JsonRejection) -> Self {
+        ApiError(StatusCode::BAD_REQUEST, e.body_text())
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(e: rusqlite::Error) -> Self {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+}
+
+// Serve until Ctrl-C, going to the portal through `api`.
+pub async fn serve(api: HttpApi, opts: ServeOptions) -> Result<(), Box<dyn std::error::Error>> {
+    if opts.api_key.is_none() && !opts.listen.ip().is_loopback() {
+        warn!("Listening on {} without --api-key; anyone who can reach it can search the portal and read the index", opts.listen);
+    }
+    let index = opts.index.as_deref().map(Index::open).transpose()?;
+    let state = Arc::new(AppState { api, index: index.map(Mutex::new), api_key: opts.api_key });
+    let app = Router::new()
+        .route("/documents", get(documents))
+        .route("/download", post(download))
+        .route("/metrics", get(metrics::response))
+        .route("/zapier/me", get(|| async { Json(json!({ "ok": true })) }))
+        .route("/zapier/new-documents", get(new_documents))
+        .merge(viewer::routes())
+        .layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(opts.listen).await?;
+    info!("Listening on http://{}", listener.local_addr()?);
+    axum::serve(listener, app).with_graceful_shutdown(interrupt::requested()).await?;
+    Ok(())
+}
+
+// The key is accepted as `X-API-Key`, a bearer token or an `api_key` query parameter,
+// whichever the no-code tool makes easiest to configure, and as the password of
+// HTTP basic authentication for browsers.
+async fn authorize(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Result<Response, ApiError> {
+    let Some(expected) = &state.api_key else {
+        return Ok(next.run(request).await);
+    };
+    let headers = request.headers();
+    let given = headers
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| headers.get("authorization").and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("Bearer ")))
+        .map(str::to_string)
+        .or_else(|| {
+            let encoded = headers.get("authorization")?.to_str().ok()?.strip_prefix("Basic ")?;
+            let decoded = String::from_utf8(base64::engine::general_purpose::STANDARD.decode(encoded).ok()?).ok()?;
+            decoded.split_once(':').map(|(_, password)| password.to_string())
+        })
+        .or_else(|| {
+            request.uri().query().and_then(|query| {
+                query.split('&').find_map(|pair| pair.strip_prefix("api_key=")).map(|key| key.to_string())
+            })
+        });
+    if given.as_deref() != Some(expected.as_str()) {
+        return Err(ApiError(StatusCode::UNAUTHORIZED, "missing or wrong API key".to_string()));
+    }
+    Ok(next.run(request).await)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NewDocumentsQuery {
+    cursor: Option<i64>,
+    tax_id: Option<String>,
+    limit: Option<usize>,
+}
+
+// A polling trigger: documents first seen after `cursor`, newest first, each with an
+// `id` to deduplicate on and its own `cursor`. Zapier only needs the `id`s and can
+// poll without a cursor; Make passes the highest cursor it has seen.
+async fn new_documents(State(state): State<Arc<AppState>>, query: Result<Query<NewDocumentsQuery>, QueryRejection>) -> Result<Json<Vec<Value>>, ApiError> {
+    let Query(query) = query?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let index = state.index()?;
+    let mut documents = Vec::new();
+    for (cursor, document) in index.new_since(query.cursor, query.tax_id.as_deref(), limit)?.into_iter().rev() {
+        documents.push(document_json(cursor, &document, &index)?);
+    }
+    Ok(Json(documents))
+}
+
+fn document_json(cursor: i64, document: &IndexedDocument, index: &Index) -> Result<Value, rusqlite::Error> {
+    Ok(json!({
+        "id": format!("{}/{}", document.tax_id, document.doc_no),
+        "cursor": cursor,
+        "taxId": document.tax_id,
+        "docNo": document.doc_no,
+        "docDate": api::doc_date(&document.item),
+        "docType": api::text_field(&document.item, "docType"),
+        "amount": api::amount(&document.item),
+        "fileName": api::text_field(&document.item, "fileName"),
+        "firstSeenAt": document.first_seen_at,
+        "fetchedAt": document.fetched_at,
+        "sha256": document.sha256,
+        "references": index.references(&document.tax_id, &document.doc_no)?,
+        "referencedBy": index.referenced_by(&document.tax_id, &document.doc_no)?,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchQuery {
+    tax_id: String,
+    // YYYY-MM-DD, today by default.
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    // Only these documents, for a download.
+    #[serde(default)]
+    doc_nos: Option<Vec<String>>,
+}
+
+// Search the portal as the search command does, through the same cache, rate limit
+// and retries. Results are recorded in the index when there is one.
+async fn search(state: &AppState, query: &SearchQuery) -> Result<Vec<Value>, ApiError> {
+    cli::parse_tax_id(&query.tax_id).map_err(|e| ApiError(StatusCode::BAD_REQUEST, format!("invalid taxId: {}", e)))?;
+    let today = dates::today();
+    let (since, until) = (query.from.unwrap_or(today), query.to.unwrap_or(today));
+    if until < since {
+        return Err(ApiError(StatusCode::BAD_REQUEST, "to is before from".to_string()));
+    }
+    let from = dates::day_bound(since, true).with_timezone(&dates::timezone()).format(DATE_FORMAT).to_string();
+    let to = dates::day_bound(until, false).with_timezone(&dates::timezone()).format(DATE_FORMAT).to_string();
+    let items = api::search(&state.api, &query.tax_id, &from, &to).await.map_err(|e| ApiError(StatusCode::BAD_GATEWAY, format!("EXAT search failed: {}", e)))?;
+    if state.index.is_some() {
+        let index = state.index()?;
+        let now = Utc::now();
+        for item in &items {
+            index.observe(&query.tax_id, item, now)?;
+        }
+    }
+    Ok(match &query.doc_nos {
+        Some(doc_nos) => items.into_iter().filter(|item| doc_nos.contains(&api::doc_no(item))).collect(),
+        None => items,
+    })
+}
+
+// `GET /documents?taxId=...&from=...&to=...`: the search result items as the portal
+// returns them.
+async fn documents(State(state): State<Arc<AppState>>, query: Result<Query<SearchQuery>, QueryRejection>) -> Result<Json<Vec<Value>>, ApiError> {
+    let Query(query) = query?;
+    Ok(Json(search(&state, &query).await?))
+}
+
+// `POST /download` with `{"taxId": ..., "from": ..., "to": ..., "docNos": [...]}`:
+// the ZIP of the documents found, or of just `docNos`.
+async fn download(State(state): State<Arc<AppState>>, body: Result<Json<SearchQuery>, JsonRejection>) -> Result<Response, ApiError> {
+    let Json(query) = body?;
+    let items = search(&state, &query).await?;
+    metrics::add_found(items.len());
+    if items.is_empty() {
+        return Err(ApiError(StatusCode::NOT_FOUND, "no documents found".to_string()));
+    }
+    let listfile = api::build_listfile(&items).map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    // A download here is a run, as for `watch`.
+    let content = match api::download_zip(&state.api, &listfile, None).await {
+        Ok(content) => {
+            metrics::record_run(items.len(), true);
+            content
+        }
+        Err(e) => {
+            metrics::record_run(0, false);
+            return Err(ApiError(StatusCode::BAD_GATEWAY, format!("EXAT download failed: {}", e)));
+        }
+    };
+    let name = format!("TaxDocuments_{}_{}_{}.zip", query.tax_id, query.from.unwrap_or_else(dates::today).format("%Y%m%d"), query.to.unwrap_or_else(dates::today).format("%Y%m%d"));
+    Ok((
+        [(header::CONTENT_TYPE, "application/zip".to_string()), (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", name))],
+        content,
+    )
+        .into_response())
+}
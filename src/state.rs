@@ -0,0 +1,100 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+// Incremental state shared by one-shot runs (`--state`) and watch mode: which
+// documents have already been downloaded, how far each tax ID has been searched, and
+// the history of every document seen, which doubles as a small local index.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub tax_ids: BTreeMap<String, TaxIdState>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TaxIdState {
+    // Last day (in local time, see `dates::timezone`) covered by a successful search + download.
+    pub last_until: Option<NaiveDate>,
+    #[serde(default)]
+    pub downloaded: BTreeSet<String>,
+    #[serde(default)]
+    pub documents: BTreeMap<String, DocumentHistory>,
+}
+
+// Every distinct form a document has been returned in by the search API, oldest
+// first, so `query --as-of` can answer what was known at a given moment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentHistory {
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub versions: Vec<DocumentVersion>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentVersion {
+    pub observed_at: DateTime<Utc>,
+    pub item: Value,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Observation {
+    New,
+    Changed,
+    Unchanged,
+}
+
+impl TaxIdState {
+    // Record a search result item; a new version is only added when the item differs
+    // from the latest one recorded.
+    pub fn observe(&mut self, doc_no: String, item: &Value, at: DateTime<Utc>) -> Observation {
+        let version = DocumentVersion { observed_at: at, item: item.clone() };
+        match self.documents.get_mut(&doc_no) {
+            None => {
+                self.documents.insert(doc_no, DocumentHistory { first_seen: at, last_seen: at, versions: vec![version] });
+                Observation::New
+            }
+            Some(history) => {
+                history.last_seen = at;
+                if history.versions.last().map(|v| &v.item) == Some(item) {
+                    Observation::Unchanged
+                } else {
+                    history.versions.push(version);
+                    Observation::Changed
+                }
+            }
+        }
+    }
+}
+
+impl DocumentHistory {
+    // The version current at `at`, or None if the document hadn't appeared yet.
+    pub fn as_of(&self, at: DateTime<Utc>) -> Option<&DocumentVersion> {
+        self.versions.iter().take_while(|v| v.observed_at <= at).last()
+    }
+}
+
+impl State {
+    pub fn load(path: &Path) -> Result<State, Box<dyn std::error::Error>> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Write to a sibling temp file and rename over the original so a crash mid-write
+    // never leaves a truncated state file behind.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn tax_id(&mut self, tax_id: &str) -> &mut TaxIdState {
+        self.tax_ids.entry(tax_id.to_string()).or_default()
+    }
+}
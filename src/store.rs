@@ -0,0 +1,343 @@
+use crate::amounts::{Extraction, Extractor};
+use crate::api::{self, Reference};
+use crate::fleet::{self, Fleet};
+use crate::archive;
+use crate::etda;
+use crate::text::{self, TextPipeline};
+use crate::upgrade;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+pub const INDEX_FILE: &str = "store.json";
+
+// Long-term document store: every PDF from every downloaded archive, one directory
+// per tax ID and docNo. Files are content-addressed and never overwritten, so when
+// EXAT re-issues a docNo with different content the original evidence is kept next
+// to the new version and the two are linked through supersedes/superseded_by.
+pub struct Store {
+    root: PathBuf,
+    pub index: StoreIndex,
+    // Extracted text is written next to each PDF as `<file>.txt`.
+    pub text: TextPipeline,
+    // Amounts read from the extracted text, scored for manual review.
+    pub amounts: Extractor,
+    // Plates, plazas and routes read from the extracted text.
+    pub fleet: fleet::Extractor,
+    // Files `add` would have written (path, bytes) when opened with `open_dry_run`.
+    pub planned: Option<Vec<(PathBuf, u64)>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StoreIndex {
+    // How many of MIGRATIONS the store has been through; 0 before versioning.
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
+    pub documents: BTreeMap<String, StoredDocument>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredDocument {
+    pub tax_id: String,
+    pub doc_no: String,
+    pub versions: Vec<StoredVersion>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredVersion {
+    pub sha256: String,
+    pub size: u64,
+    // Relative to the store root.
+    pub path: PathBuf,
+    pub file_name: String,
+    pub stored_at: DateTime<Utc>,
+    pub source_archive: Option<PathBuf>,
+    #[serde(default)]
+    pub text_path: Option<PathBuf>,
+    #[serde(default)]
+    pub amounts: Option<Extraction>,
+    #[serde(default)]
+    pub fleet: Option<Fleet>,
+    // Documents the e-Tax XML attached to the PDF refers to, e.g. the invoice a credit
+    // note corrects.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub references: Vec<Reference>,
+    pub supersedes: Option<String>,
+    pub superseded_by: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum StoreOutcome {
+    Stored { doc_no: String },
+    Duplicate { doc_no: String },
+    Reissued { doc_no: String, previous: String, current: String },
+}
+
+// Layout changes are appended here and never edited, so a store of any age can be
+// brought up to date. Each may move files and rewrite their paths in the index, but
+// must not delete anything. A store from before versioning already has the layout
+// of version 1.
+type Migration = fn(&Path, &mut StoreIndex) -> Result<(), Box<dyn std::error::Error>>;
+const MIGRATIONS: &[Migration] = &[|_, _| Ok(())];
+const LAYOUT_VERSION: u32 = MIGRATIONS.len() as u32;
+
+impl StoredDocument {
+    pub fn current(&self) -> Option<&StoredVersion> {
+        self.versions.iter().find(|v| v.superseded_by.is_none())
+    }
+}
+
+impl Store {
+    pub fn open(root: &Path) -> Result<Store, Box<dyn std::error::Error>> {
+        fs::create_dir_all(root)?;
+        let mut store = Store::load(root)?;
+        if store.index.version < LAYOUT_VERSION {
+            store.upgrade(&root.join(INDEX_FILE))?;
+        }
+        Ok(store)
+    }
+
+    // The store as it is, touching nothing: `add` only records what it would write
+    // in `planned`, and an outstanding upgrade is left for the real run. Upgrades so
+    // far keep store.json readable as it was.
+    pub fn open_dry_run(root: &Path) -> Result<Store, Box<dyn std::error::Error>> {
+        let mut store = Store::load(root)?;
+        if store.index.version < LAYOUT_VERSION {
+            info!("Store {} would be upgraded from layout version {} to {} first", root.display(), store.index.version, LAYOUT_VERSION);
+        }
+        store.planned = Some(Vec::new());
+        Ok(store)
+    }
+
+    fn load(root: &Path) -> Result<Store, Box<dyn std::error::Error>> {
+        let index: StoreIndex = match fs::read_to_string(root.join(INDEX_FILE)) {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StoreIndex { version: LAYOUT_VERSION, ..StoreIndex::default() },
+            Err(e) => return Err(e.into()),
+        };
+        if index.version > LAYOUT_VERSION {
+            return Err(upgrade::too_new("Store", root, index.version, LAYOUT_VERSION));
+        }
+        Ok(Store {
+            root: root.to_path_buf(),
+            index,
+            text: TextPipeline::default(),
+            amounts: Extractor::default(),
+            fleet: fleet::Extractor::default(),
+            planned: None,
+        })
+    }
+
+    // Keep a copy of store.json (the files themselves are never deleted), then run
+    // the outstanding migrations and save.
+    fn upgrade(&mut self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let from = self.index.version;
+        let backup = upgrade::backup_path(path, from);
+        fs::copy(path, &backup)?;
+        info!("Upgrading store {} from layout version {} to {}; the old index is kept as {}", self.root.display(), from, LAYOUT_VERSION, backup.display());
+        for migration in &MIGRATIONS[from as usize..] {
+            migration(&self.root, &mut self.index)?;
+        }
+        self.index.version = LAYOUT_VERSION;
+        self.save()
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.root.join(INDEX_FILE);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.index)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    // Store every document of a downloaded ZIP. Entries are matched to search items by
+    // file name; entries without a match are keyed by their file stem.
+    pub fn add_archive(&mut self, tax_id: &str, content: &[u8], items: &[Value], source: Option<&Path>) -> Result<Vec<StoreOutcome>, Box<dyn std::error::Error>> {
+        let mut outcomes = Vec::new();
+
+        for entry in archive::entries(content)? {
+            let file_name = Path::new(&entry.name)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let item = items.iter().find(|item| item["fileName"].as_str() == Some(file_name.as_str()));
+            let doc_no = item.map(api::doc_no).unwrap_or_else(|| file_stem(&file_name));
+            let reference = item.and_then(api::amount);
+
+            outcomes.push(self.add(tax_id, &doc_no, &file_name, &entry.data, reference, source)?);
+        }
+
+        Ok(outcomes)
+    }
+
+    // `reference` is the amount the search API reported, to cross-check the amounts
+    // read from the PDF against.
+    pub fn add(&mut self, tax_id: &str, doc_no: &str, file_name: &str, data: &[u8], reference: Option<f64>, source: Option<&Path>) -> Result<StoreOutcome, Box<dyn std::error::Error>> {
+        let sha256 = format!("{:x}", Sha256::digest(data));
+        let key = format!("{}/{}", tax_id, doc_no);
+        let document = self.index.documents.entry(key).or_insert_with(|| StoredDocument {
+            tax_id: tax_id.to_string(),
+            doc_no: doc_no.to_string(),
+            versions: Vec::new(),
+        });
+
+        // Only the current version counts: content matching an older one is the
+        // document going back to it (A, B, A), a re-issue like any other.
+        let current = document.versions.iter().position(|v| v.superseded_by.is_none());
+        if current.is_some_and(|i| document.versions[i].sha256 == sha256) {
+            return Ok(StoreOutcome::Duplicate { doc_no: doc_no.to_string() });
+        }
+
+        let relative = PathBuf::from(sanitize(tax_id))
+            .join(sanitize(doc_no))
+            .join(format!("{}_{}", &sha256[..16], sanitize(file_name)));
+        let path = self.root.join(&relative);
+        let text = extract_text(&self.text, &relative, data);
+        let text_path = match &mut self.planned {
+            Some(planned) => {
+                planned.push((path, data.len() as u64));
+                text.as_deref().map(|text| {
+                    let text_relative = text_relative(&relative);
+                    planned.push((self.root.join(&text_relative), text.len() as u64));
+                    text_relative
+                })
+            }
+            None => {
+                fs::create_dir_all(path.parent().unwrap())?;
+                fs::write(&path, data)?;
+                text.as_deref().and_then(|text| write_text(&self.root, &relative, text))
+            }
+        };
+        let amounts = text.as_deref().map(|text| self.amounts.extract(text, reference));
+        let fleet = text.as_deref().map(|text| self.fleet.extract(text));
+        let references = xml_references(doc_no, data);
+
+        let previous = current.map(|i| {
+            let version = &mut document.versions[i];
+            version.superseded_by = Some(sha256.clone());
+            version.sha256.clone()
+        });
+        document.versions.push(StoredVersion {
+            sha256: sha256.clone(),
+            size: data.len() as u64,
+            path: relative,
+            file_name: file_name.to_string(),
+            stored_at: Utc::now(),
+            source_archive: source.map(Path::to_path_buf),
+            text_path,
+            amounts,
+            fleet,
+            references,
+            supersedes: previous.clone(),
+            superseded_by: None,
+        });
+
+        Ok(match previous {
+            Some(previous) => StoreOutcome::Reissued { doc_no: doc_no.to_string(), previous, current: sha256 },
+            None => StoreOutcome::Stored { doc_no: doc_no.to_string() },
+        })
+    }
+}
+
+// From the ETDA XML a PDF/A-3 e-Tax invoice carries, if it has one.
+fn xml_references(doc_no: &str, data: &[u8]) -> Vec<Reference> {
+    let mut references: Vec<Reference> = Vec::new();
+    for xml in etda::pdf_attachments(data) {
+        let text = String::from_utf8_lossy(&xml.data);
+        let Ok(invoice) = etda::parse(text.trim_start_matches('\u{feff}'), &xml.name) else {
+            continue;
+        };
+        for reference in invoice.references {
+            if !reference.id.is_empty() && reference.id != doc_no && !references.iter().any(|r| r.doc_no == reference.id) {
+                let doc_date = reference.issue_date.get(..10).and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok());
+                references.push(Reference { doc_no: reference.id, doc_date });
+            }
+        }
+    }
+    references
+}
+
+// Text extraction is best effort: a PDF that can't be read is still stored.
+fn extract_text(pipeline: &TextPipeline, relative: &Path, data: &[u8]) -> Option<String> {
+    if !text::is_pdf(data) {
+        return None;
+    }
+    match pipeline.extract(data) {
+        Ok(text) => Some(text),
+        Err(e) => {
+            warn!("Cannot extract text from {}: {}", relative.display(), e);
+            None
+        }
+    }
+}
+
+fn text_relative(relative: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.txt", relative.display()))
+}
+
+fn write_text(root: &Path, relative: &Path, text: &str) -> Option<PathBuf> {
+    let text_relative = text_relative(relative);
+    match fs::write(root.join(&text_relative), text) {
+        Ok(()) => Some(text_relative),
+        Err(e) => {
+            warn!("Cannot write extracted text for {}: {}", relative.display(), e);
+            None
+        }
+    }
+}
+
+fn file_stem(file_name: &str) -> String {
+    Path::new(file_name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| file_name.to_string())
+}
+
+// The start of a SHA-256 for messages. Hashes read back from store.json or a
+// manifest may have been edited by hand, so a short or non-ASCII one is shown whole.
+pub fn short_hash(sha256: &str) -> &str {
+    sha256.get(..16).unwrap_or(sha256)
+}
+
+// docNos and file names come from the server; keep them from escaping the store.
+pub fn sanitize(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+        .collect();
+    match cleaned.as_str() {
+        "" | "." | ".." => "_".to_string(),
+        _ => cleaned,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn going_back_to_an_older_version_is_a_reissue() {
+        let dir = std::env::temp_dir().join(format!("exat-etax-store-{}", std::process::id()));
+        let mut store = Store::open(&dir).unwrap();
+        let hash = |data: &[u8]| format!("{:x}", Sha256::digest(data));
+        let (a, b) = (hash(b"version A"), hash(b"version B"));
+        let mut add = |data: &[u8]| store.add("0105551234567", "INV-1", "INV-1.pdf", data, None, None).unwrap();
+
+        assert!(matches!(add(b"version A"), StoreOutcome::Stored { .. }));
+        assert!(matches!(add(b"version B"), StoreOutcome::Reissued { previous, current, .. } if previous == a && current == b));
+        assert!(matches!(add(b"version A"), StoreOutcome::Reissued { previous, current, .. } if previous == b && current == a));
+        assert!(matches!(add(b"version A"), StoreOutcome::Duplicate { .. }));
+
+        let versions = &store.index.documents["0105551234567/INV-1"].versions;
+        let links: Vec<(Option<&str>, Option<&str>)> = versions.iter().map(|v| (v.supersedes.as_deref(), v.superseded_by.as_deref())).collect();
+        assert_eq!(links, [(None, Some(b.as_str())), (Some(a.as_str()), Some(a.as_str())), (Some(b.as_str()), None)]);
+        assert_eq!(store.index.documents["0105551234567/INV-1"].current().unwrap().sha256, a);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,36 @@
+use crate::thai::{self, Segmenter};
+use std::panic::{self, AssertUnwindSafe};
+
+// Text extraction pipeline: PDF -> raw text -> Thai normalization -> optional word
+// segmentation. Everything downstream (amount extraction, searching the store)
+// works on this output rather than on the raw extractor text.
+#[derive(Default)]
+pub struct TextPipeline {
+    pub segmenter: Option<Segmenter>,
+}
+
+impl TextPipeline {
+    pub fn extract(&self, pdf: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+        let raw = extract_raw(pdf)?;
+        let text = thai::normalize(&raw);
+        Ok(match &self.segmenter {
+            Some(segmenter) => segmenter.segment(&text),
+            None => text,
+        })
+    }
+}
+
+pub fn is_pdf(data: &[u8]) -> bool {
+    data.starts_with(b"%PDF")
+}
+
+// pdf-extract panics on some malformed inputs; one bad document must not abort a run
+// that is storing hundreds.
+fn extract_raw(pdf: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| pdf_extract::extract_text_from_mem(pdf)));
+    match result {
+        Ok(Ok(text)) => Ok(text),
+        Ok(Err(e)) => Err(e.into()),
+        Err(_) => Err("PDF text extractor crashed on this document".into()),
+    }
+}
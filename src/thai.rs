@@ -0,0 +1,268 @@
+// Thai text clean-up for PDF text extraction.
+//
+// PDFs position Thai glyphs individually, so extracted text commonly contains
+// presentation-form glyphs from legacy fonts (the U+F700 private-use block), SARA AM
+// split into NIKHAHIT + SARA AA, tone marks emitted before the vowel they sit on, and
+// stray spaces between a consonant and its marks. `normalize` repairs all of these so
+// the text matches what was typed; `segment` optionally marks word boundaries, which
+// Thai doesn't write, with ZERO WIDTH SPACE.
+
+use std::collections::HashSet;
+
+const NIKHAHIT: char = '\u{0E4D}';
+const SARA_AA: char = '\u{0E32}';
+const SARA_AM: char = '\u{0E33}';
+pub const WORD_BREAK: char = '\u{200B}';
+
+// Words the segmenter knows out of the box: what shows up on EXAT toll receipts and
+// tax invoices, plus common function words. Extra words can be supplied at runtime.
+const DICTIONARY: &[&str] = &[
+    "ใบกำกับภาษี", "ใบเสร็จรับเงิน", "ใบกำกับ", "ใบเสร็จ", "ภาษี", "มูลค่า", "เพิ่ม", "ภาษีมูลค่าเพิ่ม",
+    "อิเล็กทรอนิกส์", "เต็มรูป", "อย่างย่อ", "ต้นฉบับ", "สำเนา", "เลขที่", "เลข", "ที่", "วันที่", "วัน",
+    "เดือน", "ปี", "เวลา", "จำนวน", "จำนวนเงิน", "เงิน", "รวม", "ทั้งสิ้น", "ยอด", "ยอดรวม", "สุทธิ", "บาท",
+    "สตางค์", "ราคา", "ค่า", "ค่าผ่านทาง", "ผ่าน", "ทาง", "ทางพิเศษ", "พิเศษ", "การทางพิเศษแห่งประเทศไทย",
+    "การ", "แห่ง", "ประเทศ", "ไทย", "ประเทศไทย", "ด่าน", "ด่านเก็บเงิน", "เก็บ", "ทะเบียน", "ทะเบียนรถ",
+    "รถ", "รถยนต์", "ยนต์", "ประเภท", "ผู้", "ผู้ซื้อ", "ผู้ขาย", "ผู้เสียภาษี", "ซื้อ", "ขาย", "เสีย",
+    "ประจำตัว", "เลขประจำตัวผู้เสียภาษี", "ชื่อ", "ที่อยู่", "อยู่", "สำนักงาน", "สำนักงานใหญ่", "ใหญ่",
+    "สาขา", "บริษัท", "จำกัด", "มหาชน", "ห้างหุ้นส่วน", "บัตร", "บัตรสมาร์ทการ์ด", "สมาร์ทการ์ด", "เติม",
+    "เติมเงิน", "ชำระ", "ชำระเงิน", "รายการ", "หน่วย", "ส่วนลด", "อัตรา", "ร้อยละ", "และ", "หรือ", "ของ",
+    "ใน", "จาก", "ถึง", "โดย", "เป็น", "ไม่", "มี", "ได้", "แล้ว", "ตาม", "กับ", "นี้", "ดังกล่าว", "หมายเหตุ",
+    "ระบบ", "ลูกค้า", "เอกสาร", "อ้างอิง", "ใบลดหนี้", "ใบเพิ่มหนี้", "ลดหนี้", "เพิ่มหนี้", "หนี้", "แก้ไข",
+    "ยกเลิก", "แทน", "ฉบับ", "ออก", "ออกให้",
+];
+
+pub fn normalize(text: &str) -> String {
+    let mapped: String = text.chars().map(map_presentation_form).collect();
+    let joined = remove_spaces_before_marks(&mapped);
+    let reordered = reorder_marks(&joined);
+    compose_sara_am(&reordered)
+}
+
+// Legacy Thai fonts (and the PDFs made from them) use private-use glyphs for marks
+// shifted left/down to avoid collisions, and for consonants without their descender.
+fn map_presentation_form(c: char) -> char {
+    match c {
+        '\u{F700}' => '\u{0E10}',
+        '\u{F701}' => '\u{0E34}',
+        '\u{F702}' => '\u{0E35}',
+        '\u{F703}' => '\u{0E36}',
+        '\u{F704}' => '\u{0E37}',
+        '\u{F705}' | '\u{F70A}' | '\u{F713}' => '\u{0E48}',
+        '\u{F706}' | '\u{F70B}' | '\u{F714}' => '\u{0E49}',
+        '\u{F707}' | '\u{F70C}' | '\u{F715}' => '\u{0E4A}',
+        '\u{F708}' | '\u{F70D}' | '\u{F716}' => '\u{0E4B}',
+        '\u{F709}' | '\u{F70E}' | '\u{F717}' => '\u{0E4C}',
+        '\u{F70F}' => '\u{0E0D}',
+        '\u{F710}' => '\u{0E31}',
+        '\u{F711}' => '\u{0E4D}',
+        '\u{F712}' => '\u{0E47}',
+        '\u{F718}' => '\u{0E38}',
+        '\u{F719}' => '\u{0E39}',
+        '\u{F71A}' => '\u{0E3A}',
+        _ => c,
+    }
+}
+
+// Marks that combine with the preceding consonant (above/below vowels, tone marks
+// and diacritics).
+pub fn is_combining(c: char) -> bool {
+    matches!(c, '\u{0E31}' | '\u{0E34}'..='\u{0E3A}' | '\u{0E47}'..='\u{0E4E}')
+}
+
+fn is_thai(c: char) -> bool {
+    ('\u{0E01}'..='\u{0E5B}').contains(&c)
+}
+
+fn remove_spaces_before_marks(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == ' ' {
+            let mut j = i;
+            while j < chars.len() && chars[j] == ' ' {
+                j += 1;
+            }
+            let prev_thai = out.chars().last().is_some_and(is_thai);
+            let next_mark = j < chars.len() && (is_combining(chars[j]) || chars[j] == SARA_AA && out.ends_with(NIKHAHIT));
+            if prev_thai && next_mark {
+                i = j;
+                continue;
+            }
+        }
+        out.push(chars[i]);
+        i += 1;
+    }
+    out
+}
+
+// Rank of a combining mark in logical order: vowel first, then tone mark, then the
+// remaining diacritics. NIKHAHIT stays last so it can be recomposed into SARA AM.
+fn mark_rank(c: char) -> u8 {
+    match c {
+        '\u{0E31}' | '\u{0E34}'..='\u{0E3A}' | '\u{0E47}' => 0,
+        '\u{0E48}'..='\u{0E4B}' => 1,
+        NIKHAHIT => 3,
+        _ => 2,
+    }
+}
+
+fn reorder_marks(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut marks: Vec<char> = Vec::new();
+    let flush = |marks: &mut Vec<char>, out: &mut String| {
+        marks.sort_by_key(|c| mark_rank(*c));
+        marks.dedup();
+        out.extend(marks.drain(..));
+    };
+
+    for c in text.chars() {
+        if is_combining(c) {
+            marks.push(c);
+        } else {
+            flush(&mut marks, &mut out);
+            out.push(c);
+        }
+    }
+    flush(&mut marks, &mut out);
+    out
+}
+
+// NIKHAHIT (+ tone mark) + SARA AA -> (tone mark +) SARA AM.
+fn compose_sara_am(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == NIKHAHIT {
+            if chars.get(i + 1) == Some(&SARA_AA) {
+                out.push(SARA_AM);
+                i += 2;
+                continue;
+            }
+            if chars.get(i + 1).is_some_and(|c| mark_rank(*c) == 1) && chars.get(i + 2) == Some(&SARA_AA) {
+                out.push(chars[i + 1]);
+                out.push(SARA_AM);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(chars[i]);
+        i += 1;
+    }
+    out
+}
+
+pub struct Segmenter {
+    words: HashSet<String>,
+    longest: usize,
+}
+
+impl Segmenter {
+    pub fn new(extra_words: &[String]) -> Segmenter {
+        let words: HashSet<String> = DICTIONARY
+            .iter()
+            .map(|w| w.to_string())
+            .chain(extra_words.iter().map(|w| normalize(w.trim())))
+            .filter(|w| !w.is_empty())
+            .collect();
+        let longest = words.iter().map(|w| w.chars().count()).max().unwrap_or(1);
+        Segmenter { words, longest }
+    }
+
+    // Insert WORD_BREAK between the words of every run of Thai text.
+    pub fn segment(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut run = String::new();
+        for c in text.chars() {
+            if is_thai(c) {
+                run.push(c);
+            } else {
+                if !run.is_empty() {
+                    out.push_str(&self.segment_run(&run).join(&WORD_BREAK.to_string()));
+                    run.clear();
+                }
+                out.push(c);
+            }
+        }
+        if !run.is_empty() {
+            out.push_str(&self.segment_run(&run).join(&WORD_BREAK.to_string()));
+        }
+        out
+    }
+
+    // Maximal matching over character clusters: pick the split with the fewest
+    // unknown clusters, then the fewest words. Unknown clusters next to each other
+    // are kept together as one token.
+    fn segment_run(&self, run: &str) -> Vec<String> {
+        let clusters = clusters(run);
+        let n = clusters.len();
+        // best[i] = (unknown clusters, words, previous boundary, token is known)
+        let mut best: Vec<Option<(usize, usize, usize, bool)>> = vec![None; n + 1];
+        best[0] = Some((0, 0, 0, true));
+
+        for start in 0..n {
+            let Some((unknown, words, _, _)) = best[start] else {
+                continue;
+            };
+            let mut candidate = String::new();
+            for end in start + 1..=n {
+                candidate.push_str(&clusters[end - 1]);
+                if candidate.chars().count() > self.longest {
+                    break;
+                }
+                if self.words.contains(&candidate) {
+                    let score = (unknown, words + 1, start, true);
+                    if better(score, best[end]) {
+                        best[end] = Some(score);
+                    }
+                }
+            }
+            let score = (unknown + 1, words + 1, start, false);
+            if better(score, best[start + 1]) {
+                best[start + 1] = Some(score);
+            }
+        }
+
+        let mut tokens: Vec<(String, bool)> = Vec::new();
+        let mut end = n;
+        while end > 0 {
+            let (_, _, start, known) = best[end].expect("every cluster boundary is reachable");
+            tokens.push((clusters[start..end].concat(), known));
+            end = start;
+        }
+        tokens.reverse();
+
+        let mut merged: Vec<(String, bool)> = Vec::new();
+        for (token, known) in tokens {
+            match merged.last_mut() {
+                Some((last, false)) if !known => last.push_str(&token),
+                _ => merged.push((token, known)),
+            }
+        }
+        merged.into_iter().map(|(token, _)| token).collect()
+    }
+}
+
+fn better(score: (usize, usize, usize, bool), current: Option<(usize, usize, usize, bool)>) -> bool {
+    match current {
+        None => true,
+        Some(current) => (score.0, score.1) < (current.0, current.1),
+    }
+}
+
+// Split into units that must never be broken: a leading vowel with its consonant, and
+// a consonant with its marks and trailing SARA A/AA/AM.
+fn clusters(run: &str) -> Vec<String> {
+    let mut clusters: Vec<String> = Vec::new();
+    let mut pending_leading = false;
+    for c in run.chars() {
+        let attaches = is_combining(c) || matches!(c, '\u{0E30}' | SARA_AA | SARA_AM | '\u{0E45}');
+        match clusters.last_mut() {
+            Some(last) if attaches || pending_leading => last.push(c),
+            _ => clusters.push(c.to_string()),
+        }
+        pending_leading = matches!(c, '\u{0E40}'..='\u{0E44}');
+    }
+    clusters
+}
@@ -0,0 +1,101 @@
+use crate::metrics;
+use chrono::{DateTime, Utc};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+// A 429 is retried this many times before it is returned as an error.
+const MAX_RETRIES: u32 = 5;
+// A server asking to wait longer than this gets the error instead.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+// Pacing of requests to the EXAT backend (`--max-requests-per-minute`). One limiter
+// serves the whole process, so search pages, downloads and concurrent batch jobs
+// all share the same budget.
+static LIMITER: OnceLock<Limiter> = OnceLock::new();
+
+struct Limiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+pub fn set_max_per_minute(requests: u32) {
+    let interval = Duration::from_secs(60) / requests.max(1);
+    let _ = LIMITER.set(Limiter { interval, next: Mutex::new(Instant::now()) });
+}
+
+// `--limit-rate`: downloads are read no faster than this, all of them together, so
+// that a long backfill leaves room on the office link.
+static BANDWIDTH: OnceLock<Bandwidth> = OnceLock::new();
+
+struct Bandwidth {
+    bytes_per_second: u64,
+    next: Mutex<Instant>,
+}
+
+pub fn set_limit_rate(bytes_per_second: u64) {
+    let _ = BANDWIDTH.set(Bandwidth { bytes_per_second: bytes_per_second.max(1), next: Mutex::new(Instant::now()) });
+}
+
+// Count `bytes` just received, and wait as long as they should have taken. Reading
+// the response more slowly makes the server send it more slowly.
+pub async fn received(bytes: usize) {
+    let Some(bandwidth) = BANDWIDTH.get() else {
+        return;
+    };
+    let mut next = bandwidth.next.lock().await;
+    let now = Instant::now();
+    *next = (*next).max(now) + Duration::from_secs_f64(bytes as f64 / bandwidth.bytes_per_second as f64);
+    tokio::time::sleep_until(*next).await;
+}
+
+// Wait until the next request slot; requests are spread evenly over the minute
+// rather than sent in bursts.
+async fn wait_turn() {
+    let Some(limiter) = LIMITER.get() else {
+        return;
+    };
+    let mut next = limiter.next.lock().await;
+    let now = Instant::now();
+    if *next > now {
+        debug!(wait_ms = (*next - now).as_millis() as u64, "Waiting for a request slot");
+        tokio::time::sleep_until(*next).await;
+    }
+    *next = (*next).max(now) + limiter.interval;
+}
+
+// Send a request built by `request` (called again for every attempt, since a body
+// such as a multipart form can only be sent once), honouring the rate limit and
+// retrying 429 responses after their Retry-After delay.
+pub async fn send(request: impl Fn() -> RequestBuilder) -> reqwest::Result<Response> {
+    let mut attempt = 0;
+    loop {
+        wait_turn().await;
+        let response = request().send().await?;
+        if response.status() != StatusCode::TOO_MANY_REQUESTS {
+            return Ok(response);
+        }
+        attempt += 1;
+        let delay = retry_after(&response).unwrap_or_else(|| Duration::from_secs(2u64.pow(attempt)));
+        if attempt > MAX_RETRIES || delay > MAX_RETRY_AFTER {
+            return response.error_for_status();
+        }
+        metrics::retry();
+        warn!("Rate limited by the server; retrying in {} (attempt {}/{})", humantime::format_duration(delay), attempt, MAX_RETRIES);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// Retry-After is either a number of seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((at.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default())
+}
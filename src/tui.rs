@@ -0,0 +1,169 @@
+use crate::api;
+use crate::lang::{self, Lang};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+const HELP: &str = "↑/↓ move  space mark  a mark all shown  / filter  enter download marked  q quit";
+const HELP_TH: &str = "↑/↓ เลื่อน  space เลือก  a เลือกทั้งหมดที่แสดง  / กรอง  enter ดาวน์โหลดที่เลือก  q ออก";
+
+// Interactive document picker over a search result. Returns the marked documents,
+// or None when the user quit without downloading.
+pub fn select(items: &[Value], title: &str) -> Result<Option<Vec<Value>>, Box<dyn std::error::Error>> {
+    let mut terminal = ratatui::init();
+    let result = Picker::new(items, title).run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+struct Picker<'a> {
+    items: &'a [Value],
+    title: &'a str,
+    marked: BTreeSet<usize>,
+    filter: String,
+    editing_filter: bool,
+    // Indexes into `items` of the rows matching the filter.
+    visible: Vec<usize>,
+    table: TableState,
+}
+
+impl<'a> Picker<'a> {
+    fn new(items: &'a [Value], title: &'a str) -> Picker<'a> {
+        let mut picker = Picker {
+            items,
+            title,
+            marked: BTreeSet::new(),
+            filter: String::new(),
+            editing_filter: false,
+            visible: Vec::new(),
+            table: TableState::default(),
+        };
+        picker.apply_filter();
+        picker
+    }
+
+    fn run(mut self, terminal: &mut DefaultTerminal) -> Result<Option<Vec<Value>>, Box<dyn std::error::Error>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            if self.editing_filter {
+                match key.code {
+                    KeyCode::Enter | KeyCode::Esc => self.editing_filter = false,
+                    KeyCode::Backspace => {
+                        self.filter.pop();
+                        self.apply_filter();
+                    }
+                    KeyCode::Char(c) => {
+                        self.filter.push(c);
+                        self.apply_filter();
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+                KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+                KeyCode::PageDown => self.table.scroll_down_by(10),
+                KeyCode::PageUp => self.table.scroll_up_by(10),
+                KeyCode::Home | KeyCode::Char('g') => self.table.select_first(),
+                KeyCode::End | KeyCode::Char('G') => self.table.select_last(),
+                KeyCode::Char(' ') => {
+                    if let Some(&index) = self.table.selected().and_then(|row| self.visible.get(row)) {
+                        if !self.marked.remove(&index) {
+                            self.marked.insert(index);
+                        }
+                        self.table.select_next();
+                    }
+                }
+                KeyCode::Char('a') => {
+                    // Toggle: unmark everything shown when it's all marked already.
+                    if self.visible.iter().all(|i| self.marked.contains(i)) {
+                        for i in &self.visible {
+                            self.marked.remove(i);
+                        }
+                    } else {
+                        self.marked.extend(self.visible.iter().copied());
+                    }
+                }
+                KeyCode::Char('/') => self.editing_filter = true,
+                KeyCode::Enter if !self.marked.is_empty() => {
+                    return Ok(Some(self.marked.iter().map(|&i| self.items[i].clone()).collect()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Case-insensitive substring match against every column.
+    fn apply_filter(&mut self) {
+        let needle = self.filter.to_lowercase();
+        self.visible = (0..self.items.len())
+            .filter(|&i| needle.is_empty() || columns(&self.items[i]).iter().any(|c| c.to_lowercase().contains(&needle)))
+            .collect();
+        let selected = self.table.selected().unwrap_or(0).min(self.visible.len().saturating_sub(1));
+        self.table.select(if self.visible.is_empty() { None } else { Some(selected) });
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, body, footer] = Layout::vertical([Constraint::Length(1), Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
+        let lang = lang::lang();
+        let marked_total: f64 = self.marked.iter().filter_map(|&i| api::amount(&self.items[i])).sum();
+        frame.render_widget(
+            Paragraph::new(Line::from(vec![
+                self.title.bold(),
+                match lang {
+                    Lang::En => format!("  {} shown, {} marked ({:.2})", self.visible.len(), self.marked.len(), marked_total),
+                    Lang::Th => format!("  แสดง {} รายการ, เลือก {} รายการ ({:.2})", self.visible.len(), self.marked.len(), marked_total),
+                }
+                .into(),
+            ])),
+            header,
+        );
+
+        let rows = self.visible.iter().map(|&i| {
+            let mark = if self.marked.contains(&i) { "[x]" } else { "[ ]" };
+            let cells = std::iter::once(mark.to_string()).chain(columns(&self.items[i])).map(Cell::from);
+            Row::new(cells)
+        });
+        let table = Table::new(rows, [Constraint::Length(3), Constraint::Length(19), Constraint::Length(20), Constraint::Length(14), Constraint::Length(10), Constraint::Fill(1)])
+            .header(Row::new(match lang {
+                Lang::En => ["", "docDate", "docNo", "docType", "amount", "fileName"],
+                Lang::Th => ["", "วันที่", "เลขที่เอกสาร", "ประเภท", "ยอดเงิน", "ชื่อไฟล์"],
+            }).bold())
+            .block(Block::bordered())
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, body, &mut self.table);
+
+        let footer_text = if self.editing_filter || !self.filter.is_empty() {
+            format!("{}: {}{}", lang.pick("filter", "กรอง"), self.filter, if self.editing_filter { "▏" } else { "" })
+        } else {
+            lang.pick(HELP, HELP_TH).to_string()
+        };
+        frame.render_widget(Paragraph::new(footer_text), footer);
+    }
+}
+
+fn columns(item: &Value) -> Vec<String> {
+    vec![
+        api::text_field(item, "docDate").unwrap_or_default(),
+        api::doc_no(item),
+        api::text_field(item, "docType").unwrap_or_default(),
+        api::amount(item).map(|a| format!("{:.2}", a)).unwrap_or_default(),
+        api::text_field(item, "fileName").unwrap_or_default(),
+    ]
+}
@@ -0,0 +1,114 @@
+use crate::config::Ubl;
+use crate::export::Record;
+use std::fmt::Write;
+
+const VAT_PERCENT: f64 = 7.0;
+
+// One document as a UBL 2.1 invoice, for AP systems that ingest standard XML.
+// Amounts are those of the export record, so net and VAT are either read from the
+// PDF or derived from the total; cost center and vehicle details travel along as
+// accounting cost and notes. Returns None without a date or amount.
+pub fn invoice(record: &Record, ubl: &Ubl) -> Option<String> {
+    let (date, total, net, vat) = (record.doc_date?, record.total?, record.net?, record.vat?);
+    let category = if vat == 0.0 { ("Z", 0.0) } else { ("S", VAT_PERCENT) };
+    let mut xml = String::new();
+
+    // Writing to a String can't fail.
+    let mut line = |indent: usize, text: &str| {
+        let _ = writeln!(xml, "{:width$}{}", "", text, width = indent * 2);
+    };
+    let amount = |value: f64| format!(r#"currencyID="THB">{:.2}"#, value);
+
+    line(0, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    line(0, r#"<Invoice xmlns="urn:oasis:names:specification:ubl:schema:xsd:Invoice-2" xmlns:cac="urn:oasis:names:specification:ubl:schema:xsd:CommonAggregateComponents-2" xmlns:cbc="urn:oasis:names:specification:ubl:schema:xsd:CommonBasicComponents-2">"#);
+    line(1, "<cbc:UBLVersionID>2.1</cbc:UBLVersionID>");
+    line(1, &format!("<cbc:ID>{}</cbc:ID>", escape(&record.doc_no)));
+    line(1, &format!("<cbc:IssueDate>{}</cbc:IssueDate>", date));
+    line(1, "<cbc:InvoiceTypeCode>380</cbc:InvoiceTypeCode>");
+    for (label, values) in [("Plates", &record.plates), ("Plazas", &record.plazas), ("Routes", &record.routes), ("Cards", &record.cards)] {
+        if !values.is_empty() {
+            line(1, &format!("<cbc:Note>{}: {}</cbc:Note>", label, escape(&values.join(", "))));
+        }
+    }
+    line(1, "<cbc:DocumentCurrencyCode>THB</cbc:DocumentCurrencyCode>");
+    if !record.cost_center.is_empty() {
+        line(1, &format!("<cbc:AccountingCost>{}</cbc:AccountingCost>", escape(&record.cost_center)));
+    }
+    if !record.file_name.is_empty() {
+        line(1, "<cac:AdditionalDocumentReference>");
+        line(2, &format!("<cbc:ID>{}</cbc:ID>", escape(&record.file_name)));
+        line(2, &format!("<cbc:DocumentType>{}</cbc:DocumentType>", escape(&record.doc_type)));
+        line(1, "</cac:AdditionalDocumentReference>");
+    }
+
+    for (role, name, tax_id) in [
+        ("AccountingSupplierParty", Some(ubl.supplier_name.as_str()), ubl.supplier_tax_id.as_deref()),
+        ("AccountingCustomerParty", ubl.customer_name.as_deref(), Some(record.tax_id.as_str())),
+    ] {
+        line(1, &format!("<cac:{}>", role));
+        line(2, "<cac:Party>");
+        if let Some(tax_id) = tax_id {
+            line(3, "<cac:PartyTaxScheme>");
+            line(4, &format!("<cbc:CompanyID>{}</cbc:CompanyID>", escape(tax_id)));
+            line(4, "<cac:TaxScheme><cbc:ID>VAT</cbc:ID></cac:TaxScheme>");
+            line(3, "</cac:PartyTaxScheme>");
+        }
+        if let Some(name) = name {
+            line(3, "<cac:PartyLegalEntity>");
+            line(4, &format!("<cbc:RegistrationName>{}</cbc:RegistrationName>", escape(name)));
+            line(3, "</cac:PartyLegalEntity>");
+        }
+        line(2, "</cac:Party>");
+        line(1, &format!("</cac:{}>", role));
+    }
+
+    let tax_category = format!(
+        "<cbc:ID>{}</cbc:ID><cbc:Percent>{:.0}</cbc:Percent><cac:TaxScheme><cbc:ID>VAT</cbc:ID></cac:TaxScheme>",
+        category.0, category.1
+    );
+    line(1, "<cac:TaxTotal>");
+    line(2, &format!("<cbc:TaxAmount {}</cbc:TaxAmount>", amount(vat)));
+    line(2, "<cac:TaxSubtotal>");
+    line(3, &format!("<cbc:TaxableAmount {}</cbc:TaxableAmount>", amount(net)));
+    line(3, &format!("<cbc:TaxAmount {}</cbc:TaxAmount>", amount(vat)));
+    line(3, &format!("<cac:TaxCategory>{}</cac:TaxCategory>", tax_category));
+    line(2, "</cac:TaxSubtotal>");
+    line(1, "</cac:TaxTotal>");
+
+    line(1, "<cac:LegalMonetaryTotal>");
+    line(2, &format!("<cbc:LineExtensionAmount {}</cbc:LineExtensionAmount>", amount(net)));
+    line(2, &format!("<cbc:TaxExclusiveAmount {}</cbc:TaxExclusiveAmount>", amount(net)));
+    line(2, &format!("<cbc:TaxInclusiveAmount {}</cbc:TaxInclusiveAmount>", amount(total)));
+    line(2, &format!("<cbc:PayableAmount {}</cbc:PayableAmount>", amount(total)));
+    line(1, "</cac:LegalMonetaryTotal>");
+
+    line(1, "<cac:InvoiceLine>");
+    line(2, "<cbc:ID>1</cbc:ID>");
+    line(2, r#"<cbc:InvoicedQuantity unitCode="C62">1</cbc:InvoicedQuantity>"#);
+    line(2, &format!("<cbc:LineExtensionAmount {}</cbc:LineExtensionAmount>", amount(net)));
+    line(2, "<cac:Item>");
+    if !record.routes.is_empty() {
+        line(3, &format!("<cbc:Description>{}</cbc:Description>", escape(&record.routes.join(", "))));
+    }
+    line(3, "<cbc:Name>Expressway toll</cbc:Name>");
+    line(3, &format!("<cac:ClassifiedTaxCategory>{}</cac:ClassifiedTaxCategory>", tax_category));
+    line(2, "</cac:Item>");
+    line(2, &format!("<cac:Price><cbc:PriceAmount {}</cbc:PriceAmount></cac:Price>", amount(net)));
+    line(1, "</cac:InvoiceLine>");
+    line(0, "</Invoice>");
+
+    Some(xml)
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// `<taxId>_<docNo>.xml`, with anything unsafe in a file name replaced.
+pub fn file_name(record: &Record) -> String {
+    let name: String = format!("{}_{}", record.tax_id, record.doc_no)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    format!("{}.xml", name)
+}
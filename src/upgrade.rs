@@ -0,0 +1,17 @@
+use std::path::{Path, PathBuf};
+
+// Where to copy a file before upgrading it from `version`: `<file>.v<version>.bak`,
+// numbered when a copy from an earlier attempt is still there.
+pub fn backup_path(path: &Path, version: u32) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    (0..)
+        .map(|n| if n == 0 { format!("{}.v{}.bak", name, version) } else { format!("{}.v{}.{}.bak", name, version, n) })
+        .map(|backup| path.with_file_name(backup))
+        .find(|backup| !backup.exists())
+        .expect("some backup name is free")
+}
+
+// Written by a newer exat-etax; opening it here could lose what that version added.
+pub fn too_new(what: &str, path: &Path, version: u32, supported: u32) -> Box<dyn std::error::Error> {
+    format!("{} {} has version {}, but this exat-etax only knows up to {}; upgrade exat-etax", what, path.display(), version, supported).into()
+}
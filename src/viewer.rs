@@ -0,0 +1,148 @@
+use crate::api;
+use crate::index::IndexedDocument;
+use crate::query::Filter;
+use crate::serve::{ApiError, AppState};
+use crate::ubl::escape;
+use axum::extract::{Path as UrlPath, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use chrono::NaiveDate;
+use serde::Deserialize;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+// More than a page of results is a sign the filters need narrowing.
+const MAX_ROWS: usize = 500;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:0;display:flex;height:100vh}\
+#list{width:55%;overflow:auto;padding:1em;box-sizing:border-box}\
+#viewer{flex:1;border:0;border-left:1px solid #ccc}\
+table{border-collapse:collapse;width:100%;font-size:14px}\
+th,td{text-align:left;padding:4px 8px;border-bottom:1px solid #eee}\
+td.amount{text-align:right}form{margin-bottom:1em}input{margin-right:.5em}";
+
+// The staff-facing pages: a filterable list of documents and their PDFs.
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().route("/", get(list)).route("/documents/{tax_id}/{doc_no}/pdf", get(pdf))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ListQuery {
+    q: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+// Date inputs submit an empty string when left blank.
+fn form_date(value: &Option<String>) -> Result<Option<NaiveDate>, ApiError> {
+    match value.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(value) => NaiveDate::parse_from_str(value, "%Y-%m-%d").map(Some).map_err(|e| ApiError(StatusCode::BAD_REQUEST, format!("Invalid date {:?}: {}", value, e))),
+    }
+}
+
+fn matches_text(document: &IndexedDocument, text: &str) -> bool {
+    let text = text.to_lowercase();
+    [Some(document.tax_id.clone()), Some(document.doc_no.clone()), api::text_field(&document.item, "fileName"), api::text_field(&document.item, "docType")]
+        .into_iter()
+        .flatten()
+        .any(|field| field.to_lowercase().contains(&text))
+}
+
+async fn list(State(state): State<Arc<AppState>>, Query(query): Query<ListQuery>) -> Result<Html<String>, ApiError> {
+    let filter = Filter { since: form_date(&query.from)?, until: form_date(&query.to)?, ..Filter::default() };
+    let text = query.q.as_deref().map(str::trim).unwrap_or("");
+    let documents = state.index()?.query(&filter).map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    // Newest first.
+    let documents: Vec<&IndexedDocument> = documents.iter().rev().filter(|d| text.is_empty() || matches_text(d, text)).collect();
+
+    let mut rows = String::new();
+    for document in documents.iter().take(MAX_ROWS) {
+        let link = match document.fetched_at {
+            Some(_) => format!(
+                "<a href=\"/documents/{}/{}/pdf\" target=\"viewer\">{}</a>",
+                escape(&document.tax_id),
+                escape(&urlencode(&document.doc_no)),
+                escape(&document.doc_no)
+            ),
+            None => escape(&document.doc_no),
+        };
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"amount\">{}</td></tr>\n",
+            api::doc_date(&document.item).map(|d| d.to_string()).unwrap_or_default(),
+            link,
+            escape(&document.tax_id),
+            escape(&api::text_field(&document.item, "docType").unwrap_or_default()),
+            api::amount(&document.item).map(|a| format!("{:.2}", a)).unwrap_or_default(),
+        ));
+    }
+    let shown = if documents.len() > MAX_ROWS { format!("First {} of {} documents; narrow the search", MAX_ROWS, documents.len()) } else { format!("{} document(s)", documents.len()) };
+
+    Ok(Html(format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>EXAT e-Tax documents</title><style>{style}</style></head><body>\n\
+         <div id=\"list\"><form method=\"get\" action=\"/\">\
+         <input type=\"search\" name=\"q\" placeholder=\"docNo, tax ID, file\" value=\"{q}\">\
+         <input type=\"date\" name=\"from\" value=\"{from}\"><input type=\"date\" name=\"to\" value=\"{to}\">\
+         <button type=\"submit\">Search</button></form>\n<p>{shown}</p>\n\
+         <table><thead><tr><th>Date</th><th>docNo</th><th>Tax ID</th><th>Type</th><th>Amount</th></tr></thead><tbody>\n{rows}</tbody></table></div>\n\
+         <iframe id=\"viewer\" name=\"viewer\" title=\"Document\"></iframe>\n</body></html>\n",
+        style = STYLE,
+        q = escape(text),
+        from = filter.since.map(|d| d.to_string()).unwrap_or_default(),
+        to = filter.until.map(|d| d.to_string()).unwrap_or_default(),
+        shown = shown,
+        rows = rows,
+    )))
+}
+
+// Document numbers go into a URL path segment.
+fn urlencode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// The stored PDF, or the document's entry in the archive it was downloaded in.
+async fn pdf(State(state): State<Arc<AppState>>, UrlPath((tax_id, doc_no)): UrlPath<(String, String)>) -> Result<Response, ApiError> {
+    let not_found = || ApiError(StatusCode::NOT_FOUND, format!("{} {} was not downloaded", tax_id, doc_no));
+    let filter = Filter { tax_id: Some(tax_id.clone()), ..Filter::default() };
+    let document = state
+        .index()?
+        .query(&filter)
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .into_iter()
+        .find(|d| d.doc_no == doc_no)
+        .ok_or_else(not_found)?;
+
+    let file_name = api::text_field(&document.item, "fileName").unwrap_or_else(|| format!("{}.pdf", doc_no));
+    let data = match (&document.file_path, &document.archive_path) {
+        (Some(path), _) if path.to_lowercase().ends_with(".pdf") => std::fs::read(path).ok(),
+        (_, Some(archive)) => archive_entry(Path::new(archive), &file_name),
+        _ => None,
+    }
+    .ok_or_else(not_found)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (header::CONTENT_DISPOSITION, format!("inline; filename=\"{}\"", file_name.replace('"', ""))),
+        ],
+        data,
+    )
+        .into_response())
+}
+
+fn archive_entry(archive: &Path, file_name: &str) -> Option<Vec<u8>> {
+    let mut zip = zip::ZipArchive::new(std::fs::File::open(archive).ok()?).ok()?;
+    let name = zip.file_names().find(|name| Path::new(name).file_name().is_some_and(|n| n.to_string_lossy() == file_name))?.to_string();
+    let mut data = Vec::new();
+    zip.by_name(&name).ok()?.read_to_end(&mut data).ok()?;
+    Some(data)
+}
@@ -0,0 +1,150 @@
+use crate::api::EtaxApi;
+use crate::dates;
+use crate::interrupt::{self, Interrupted};
+use crate::metrics;
+use crate::plan::Window;
+use crate::queue;
+use crate::run::{self, RunOptions};
+use crate::state::State;
+use chrono::{NaiveDate, Utc};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+#[derive(Clone)]
+pub enum Schedule {
+    Every(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Default for Schedule {
+    fn default() -> Schedule {
+        Schedule::Every(Duration::from_secs(24 * 3600))
+    }
+}
+
+impl Schedule {
+    // `--every`, e.g. 30m or 24h.
+    pub fn parse_every(every: &str) -> Result<Schedule, String> {
+        let every = humantime::parse_duration(every).map_err(|e| e.to_string())?;
+        if every.is_zero() {
+            return Err("must be greater than zero".to_string());
+        }
+        Ok(Schedule::Every(every))
+    }
+
+    // `--cron` accepts the usual five-field crontab syntax (minute hour dom month dow)
+    // as well as the six/seven-field form with seconds understood by the cron crate.
+    pub fn parse_cron(expr: &str) -> Result<Schedule, String> {
+        let expr = if expr.split_whitespace().count() == 5 {
+            format!("0 {}", expr)
+        } else {
+            expr.to_string()
+        };
+        Ok(Schedule::Cron(Box::new(cron::Schedule::from_str(&expr).map_err(|e| e.to_string())?)))
+    }
+
+    fn delay_until_next(&self) -> Option<Duration> {
+        match self {
+            Schedule::Every(every) => Some(*every),
+            Schedule::Cron(schedule) => {
+                let now = Utc::now().with_timezone(&dates::timezone());
+                let next = schedule.after(&now).next()?;
+                Some((next - now).to_std().unwrap_or_default())
+            }
+        }
+    }
+}
+
+pub struct WatchOptions {
+    // Everything but the dates and the state, which each sync sets from the state.
+    // Its queue of deferred downloads is flushed by `watch` before every cycle.
+    pub run: RunOptions,
+    pub since: Option<NaiveDate>,
+    pub state: PathBuf,
+    // Written by `watch` after every cycle; a single sync leaves it to its caller.
+    pub summary_json: Option<PathBuf>,
+}
+
+// Run forever: interval schedules fire immediately and then every interval, cron
+// schedules wait for their first matching time. A failed cycle is logged and retried
+// on the next tick rather than ending the watch; Ctrl-C ends it. Each cycle first
+// retries the downloads queued by earlier ones. With `only_between`, a cycle due
+// outside the window waits for it to open; one still running when it closes finishes.
+pub async fn watch(api: &impl EtaxApi, opts: WatchOptions, schedule: Schedule, only_between: Option<Window>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut first = matches!(schedule, Schedule::Every(_));
+    loop {
+        if !first {
+            let delay = schedule.delay_until_next().ok_or("Cron expression has no upcoming run")?;
+            info!("Next cycle in {}", humantime::format_duration(Duration::from_secs(delay.as_secs())));
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = interrupt::requested() => return Err(Interrupted.into()),
+            }
+        }
+        first = false;
+        if let Some(window) = &only_between {
+            let delay = window.until_open(Utc::now().with_timezone(&dates::timezone()).time());
+            if !delay.is_zero() {
+                info!("Outside {}; next cycle in {}", window, humantime::format_duration(Duration::from_secs(delay.as_secs())));
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = interrupt::requested() => return Err(Interrupted.into()),
+                }
+            }
+        }
+
+        if let Some(path) = &opts.run.queue {
+            match queue::flush(api, path, &run_options(&opts, dates::today()), None).await {
+                Ok(flushed) if flushed.downloaded + flushed.remaining > 0 => info!(downloaded = flushed.downloaded, remaining = flushed.remaining, "Queue flushed"),
+                Ok(_) => {}
+                Err(e) if e.is::<Interrupted>() => return Err(e),
+                Err(e) => error!("Flushing {} failed: {}", path.display(), e),
+            }
+        }
+        let result = sync(api, &opts).await;
+        if let Some(path) = &opts.summary_json {
+            let error = result.as_ref().err().map(|e| e.to_string());
+            if let Err(e) = metrics::write_summary(path, error.as_deref()) {
+                warn!("Cannot write {}: {}", path.display(), e);
+            }
+        }
+        match result {
+            Ok(summary) => info!(
+                found = summary.found,
+                downloaded = summary.downloaded.len(),
+                archive = summary.archive.as_ref().map(|p| p.display().to_string()).unwrap_or_default(),
+                reissued = summary.reissued.len(),
+                deferred = summary.deferred.len(),
+                review = summary.review.len(),
+                over_budget = summary.over_budget.len(),
+                "Cycle complete"
+            ),
+            Err(e) if e.is::<Interrupted>() => return Err(e),
+            Err(e) => error!("Cycle failed: {}", e),
+        }
+    }
+}
+
+// Each sync (and watch cycle) searches from the last day covered by the state (so documents issued
+// later that same day aren't missed) up to today; the state filters out anything
+// already downloaded.
+pub async fn sync(api: &impl EtaxApi, opts: &WatchOptions) -> Result<run::RunSummary, Box<dyn std::error::Error>> {
+    let mut state = State::load(&opts.state)?;
+    let since = state
+        .tax_id(&opts.run.tax_id)
+        .last_until
+        .or(opts.since)
+        .unwrap_or_else(dates::today);
+    run::run(api, &run_options(opts, since)).await
+}
+
+fn run_options(opts: &WatchOptions, since: NaiveDate) -> RunOptions {
+    RunOptions {
+        since: dates::day_bound(since, true),
+        until: dates::day_bound(dates::today(), false),
+        state: Some(opts.state.clone()),
+        ..opts.run.clone()
+    }
+}
@@ -0,0 +1,663 @@
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use roxmltree::{Document, Node, NodeId};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::collections::BTreeMap;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::Certificate;
+
+const DSIG: &str = "http://www.w3.org/2000/09/xmldsig#";
+const ENVELOPED: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+const C14N: &str = "http://www.w3.org/TR/2001/REC-xml-c14n-20010315";
+const C14N_COMMENTS: &str = "http://www.w3.org/TR/2001/REC-xml-c14n-20010315#WithComments";
+const C14N_11: &str = "http://www.w3.org/2006/12/xml-c14n11";
+const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+const EXC_C14N_COMMENTS: &str = "http://www.w3.org/2001/10/xml-exc-c14n#WithComments";
+
+// Longer chains than this are a loop or not worth following.
+const MAX_CHAIN: usize = 8;
+
+// The outcome of verifying one signed XML document. `valid` only when every check
+// passed.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub source: String,
+    pub valid: bool,
+    pub signer: Option<String>,
+    pub issuer: Option<String>,
+    pub signing_time: Option<String>,
+    pub checks: Vec<Check>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Report {
+    fn check(&mut self, name: &'static str, result: Result<String, String>) -> bool {
+        let ok = result.is_ok();
+        let detail = result.unwrap_or_else(|e| e);
+        self.checks.push(Check { name, ok, detail });
+        ok
+    }
+
+    // The failed checks, for a one-line summary.
+    pub fn failures(&self) -> Vec<String> {
+        self.checks.iter().filter(|c| !c.ok).map(|c| format!("{}: {}", c.name, c.detail)).collect()
+    }
+}
+
+// CA certificates to build signer chains to, from PEM bundles or single DER files.
+pub fn load_trusted(data: &[u8]) -> Result<Vec<Certificate>, Box<dyn std::error::Error>> {
+    if data.starts_with(b"-----") || data.windows(11).any(|w| w == b"-----BEGIN ") {
+        return Ok(Certificate::load_pem_chain(data)?);
+    }
+    Ok(vec![Certificate::from_der(data)?])
+}
+
+// Verify the XML-DSig/XAdES signature of an e-Tax document: the digest of every
+// signed reference, that one of them is the whole document, the signature over SignedInfo, that the XAdES properties name the
+// certificate that signed, the certificate's validity at the signing time and its
+// chain up to one of `trusted`. Revocation is not checked.
+pub fn verify(xml: &str, source: &str, trusted: &[Certificate]) -> Report {
+    let mut report = Report { source: source.to_string(), valid: false, signer: None, issuer: None, signing_time: None, checks: Vec::new() };
+    let doc = match Document::parse(xml) {
+        Ok(doc) => doc,
+        Err(e) => {
+            report.check("document", Err(e.to_string()));
+            return report;
+        }
+    };
+    let Some(signature) = doc.descendants().find(|n| is_dsig(n, "Signature")) else {
+        report.check("signature", Err("no ds:Signature element".to_string()));
+        return report;
+    };
+    let Some(signed_info) = child(signature, "SignedInfo") else {
+        report.check("signature", Err("no ds:SignedInfo".to_string()));
+        return report;
+    };
+
+    let mut ok = true;
+    let mut covered = false;
+    for reference in signed_info.children().filter(|n| is_dsig(n, "Reference")) {
+        ok &= report.check("reference", check_reference(&doc, signature, reference, xml));
+        covered |= covers_document(&doc, reference);
+    }
+    // Otherwise only, say, SignedProperties is signed and the invoice can be anything.
+    ok &= report.check(
+        "document",
+        if covered { Ok("the whole document is signed".to_string()) } else { Err("no enveloped reference covers the document; its content is not signed".to_string()) },
+    );
+
+    // KeyInfo may carry the whole chain; the signer is the certificate whose key
+    // verifies SignedInfo.
+    let certificates: Vec<Certificate> = signature
+        .descendants()
+        .filter(|n| is_dsig(n, "X509Certificate"))
+        .filter_map(|n| Certificate::from_der(&decode_base64(n.text().unwrap_or_default()).ok()?).ok())
+        .collect();
+    let signer = match check_signature_value(signed_info, signature, xml, &certificates) {
+        Ok(signer) => {
+            report.check("signatureValue", Ok(format!("verified with {}", signer.tbs_certificate.subject)));
+            Some(signer)
+        }
+        Err(e) => {
+            report.check("signatureValue", Err(e));
+            None
+        }
+    };
+    ok &= signer.is_some();
+
+    let properties = signature.descendants().find(|n| n.is_element() && n.tag_name().name() == "SignedProperties");
+    let signing_time = properties
+        .and_then(|p| p.descendants().find(|n| n.is_element() && n.tag_name().name() == "SigningTime"))
+        .and_then(|n| n.text())
+        .map(|t| t.trim().to_string());
+    report.signing_time = signing_time.clone();
+    let at = match signing_time.as_deref().map(DateTime::parse_from_rfc3339) {
+        Some(Ok(time)) => time.with_timezone(&Utc),
+        _ => Utc::now(),
+    };
+
+    ok &= report.check("signedProperties", check_signed_properties(properties, signed_info, signer));
+    if let Some(signer) = signer {
+        report.signer = Some(signer.tbs_certificate.subject.to_string());
+        report.issuer = Some(signer.tbs_certificate.issuer.to_string());
+        ok &= report.check("validity", check_validity(signer, at));
+        ok &= report.check("chain", check_chain(signer, &certificates, trusted, at));
+    }
+    report.valid = ok;
+    report
+}
+
+fn is_dsig(node: &Node<'_, '_>, name: &str) -> bool {
+    node.is_element() && node.tag_name().name() == name && node.tag_name().namespace() == Some(DSIG)
+}
+
+fn child<'a, 'i>(node: Node<'a, 'i>, name: &str) -> Option<Node<'a, 'i>> {
+    node.children().find(|n| is_dsig(n, name))
+}
+
+fn algorithm<'a>(node: Option<Node<'a, '_>>) -> &'a str {
+    node.and_then(|n| n.attribute("Algorithm")).unwrap_or_default()
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    base64::engine::general_purpose::STANDARD.decode(text).map_err(|e| format!("invalid base64: {}", e))
+}
+
+// Digest algorithms are named by URIs ending in `#sha256` and the like.
+fn digest(algorithm: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+    hash(algorithm.rsplit('#').next().unwrap_or_default(), data).ok_or_else(|| format!("unsupported digest algorithm {}", algorithm))
+}
+
+fn hash(name: &str, data: &[u8]) -> Option<Vec<u8>> {
+    Some(match name {
+        "sha1" => Sha1::digest(data).to_vec(),
+        "sha256" => Sha256::digest(data).to_vec(),
+        "sha384" => Sha384::digest(data).to_vec(),
+        "sha512" => Sha512::digest(data).to_vec(),
+        _ => return None,
+    })
+}
+
+// What a Reference URI points at. An Id carried by more than one element is refused:
+// a verifier and an application picking different ones is how signatures get wrapped.
+fn resolve<'a, 'i>(doc: &'a Document<'i>, uri: &str) -> Result<Node<'a, 'i>, String> {
+    if uri.is_empty() || uri == "#xpointer(/)" {
+        return Ok(doc.root());
+    }
+    let id = uri.strip_prefix('#').ok_or_else(|| format!("external reference {} not supported", uri))?;
+    let mut found = doc.descendants().filter(|n| n.is_element() && ["Id", "ID", "id"].iter().any(|name| n.attribute(*name) == Some(id)));
+    match (found.next(), found.next()) {
+        (Some(target), None) => Ok(target),
+        (Some(_), Some(_)) => Err(format!("{} is the Id of more than one element", uri)),
+        (None, _) => Err(format!("{} not found in the document", uri)),
+    }
+}
+
+// A reference to the document (or its root element) with the signature left out.
+fn covers_document(doc: &Document<'_>, reference: Node<'_, '_>) -> bool {
+    let whole = resolve(doc, reference.attribute("URI").unwrap_or_default()).is_ok_and(|target| target == doc.root() || target == doc.root_element());
+    whole && child(reference, "Transforms").is_some_and(|t| t.children().any(|n| is_dsig(&n, "Transform") && algorithm(Some(n)) == ENVELOPED))
+}
+
+fn check_reference(doc: &Document<'_>, signature: Node<'_, '_>, reference: Node<'_, '_>, input: &str) -> Result<String, String> {
+    let uri = reference.attribute("URI").unwrap_or_default();
+    let actual = reference_digest(doc, signature, reference, input)?;
+    let expected = decode_base64(child(reference, "DigestValue").and_then(|n| n.text()).unwrap_or_default())?;
+    let name = if uri.is_empty() { "the document".to_string() } else { uri.to_string() };
+    if actual != expected {
+        return Err(format!("digest of {} does not match; the content was changed after signing", name));
+    }
+    Ok(format!("digest of {} matches", name))
+}
+
+// The digest of what a reference points at, after its transforms.
+fn reference_digest(doc: &Document<'_>, signature: Node<'_, '_>, reference: Node<'_, '_>, input: &str) -> Result<Vec<u8>, String> {
+    let target = resolve(doc, reference.attribute("URI").unwrap_or_default())?;
+    let mut c14n = Canonicalizer { method: Method::Inclusive, prefixes: Vec::new(), exclude: None, input };
+    for transform in child(reference, "Transforms").into_iter().flat_map(|t| t.children().filter(|n| is_dsig(n, "Transform"))) {
+        match algorithm(Some(transform)) {
+            ENVELOPED => c14n.exclude = Some(signature.id()),
+            other => c14n.set_method(other, transform)?,
+        }
+    }
+    digest(algorithm(child(reference, "DigestMethod")), c14n.canonicalize(target).as_bytes())
+}
+
+// SignedInfo as its CanonicalizationMethod says it was signed.
+fn canonical_signed_info(signed_info: Node<'_, '_>, input: &str) -> Result<String, String> {
+    let mut c14n = Canonicalizer { method: Method::Inclusive, prefixes: Vec::new(), exclude: None, input };
+    let method = child(signed_info, "CanonicalizationMethod").ok_or("no CanonicalizationMethod")?;
+    c14n.set_method(algorithm(Some(method)), method)?;
+    Ok(c14n.canonicalize(signed_info))
+}
+
+fn check_signature_value<'c>(signed_info: Node<'_, '_>, signature: Node<'_, '_>, input: &str, certificates: &'c [Certificate]) -> Result<&'c Certificate, String> {
+    let canonical = canonical_signed_info(signed_info, input)?;
+    let value = decode_base64(child(signature, "SignatureValue").and_then(|n| n.text()).unwrap_or_default())?;
+    // `...#rsa-sha256`
+    let signature_method = algorithm(child(signed_info, "SignatureMethod"));
+    let hash_name = signature_method.rsplit_once("#rsa-").map(|(_, name)| name).unwrap_or_default();
+    let hashed = hash(hash_name, canonical.as_bytes()).ok_or_else(|| format!("unsupported signature algorithm {}", signature_method))?;
+    if certificates.is_empty() {
+        return Err("no X509Certificate in KeyInfo".to_string());
+    }
+    certificates
+        .iter()
+        .find(|certificate| rsa_verify(certificate, hash_name, &hashed, &value).is_ok())
+        .ok_or_else(|| "does not verify with the certificate in KeyInfo".to_string())
+}
+
+fn rsa_verify(certificate: &Certificate, hash: &str, hashed: &[u8], signature: &[u8]) -> Result<(), String> {
+    let spki = certificate.tbs_certificate.subject_public_key_info.to_der().map_err(|e| e.to_string())?;
+    let key = RsaPublicKey::from_public_key_der(&spki).map_err(|e| format!("not an RSA key: {}", e))?;
+    let scheme = match hash {
+        "sha1" => Pkcs1v15Sign::new::<Sha1>(),
+        "sha256" => Pkcs1v15Sign::new::<Sha256>(),
+        "sha384" => Pkcs1v15Sign::new::<Sha384>(),
+        "sha512" => Pkcs1v15Sign::new::<Sha512>(),
+        _ => return Err(format!("unsupported hash {}", hash)),
+    };
+    key.verify(scheme, hashed, signature).map_err(|e| e.to_string())
+}
+
+// XAdES binds the signing certificate into the signed data: SignedProperties must be
+// a signed reference, and its SigningCertificate digest must match the signer's.
+fn check_signed_properties(properties: Option<Node<'_, '_>>, signed_info: Node<'_, '_>, signer: Option<&Certificate>) -> Result<String, String> {
+    let properties = properties.ok_or("no XAdES SignedProperties; not a XAdES signature")?;
+    let id = properties.attribute("Id").or(properties.attribute("ID")).unwrap_or_default();
+    let covered = signed_info.children().filter(|n| is_dsig(n, "Reference")).any(|r| !id.is_empty() && r.attribute("URI") == Some(&format!("#{}", id)));
+    if !covered {
+        return Err("SignedProperties is not covered by the signature".to_string());
+    }
+    let signer = signer.ok_or("no verified signer to compare with")?;
+    let der = signer.to_der().map_err(|e| e.to_string())?;
+    let digests: Vec<Node> = properties.descendants().filter(|n| n.is_element() && n.tag_name().name() == "CertDigest").collect();
+    if digests.is_empty() {
+        return Err("no SigningCertificate digest".to_string());
+    }
+    for cert_digest in digests {
+        let method = cert_digest.children().find(|n| n.is_element() && n.tag_name().name() == "DigestMethod");
+        let value = cert_digest.children().find(|n| n.is_element() && n.tag_name().name() == "DigestValue").and_then(|n| n.text()).unwrap_or_default();
+        if digest(algorithm(method), &der)? == decode_base64(value)? {
+            return Ok("SigningCertificate matches the signer".to_string());
+        }
+    }
+    Err("SigningCertificate does not match the certificate that signed".to_string())
+}
+
+fn check_validity(certificate: &Certificate, at: DateTime<Utc>) -> Result<String, String> {
+    let validity = &certificate.tbs_certificate.validity;
+    let to_utc = |time: &x509_cert::time::Time| DateTime::from_timestamp(time.to_unix_duration().as_secs() as i64, 0).unwrap_or_default();
+    let (from, until) = (to_utc(&validity.not_before), to_utc(&validity.not_after));
+    if at < from || at > until {
+        return Err(format!("certificate valid {} to {}, not at {}", from.to_rfc3339(), until.to_rfc3339(), at.to_rfc3339()));
+    }
+    Ok(format!("certificate valid at {}", at.to_rfc3339()))
+}
+
+// Follow issuers through the KeyInfo certificates and `trusted` until a trusted
+// certificate vouches for the chain.
+fn check_chain(signer: &Certificate, certificates: &[Certificate], trusted: &[Certificate], at: DateTime<Utc>) -> Result<String, String> {
+    if trusted.is_empty() {
+        return Err("no trusted CA certificates given".to_string());
+    }
+    let mut current = signer;
+    let mut path = vec![signer.tbs_certificate.subject.to_string()];
+    for _ in 0..MAX_CHAIN {
+        if trusted.contains(current) {
+            return Ok(format!("trusted: {}", path.join(" <- ")));
+        }
+        let tbs = current.tbs_certificate.to_der().map_err(|e| e.to_string())?;
+        let hash_name = match current.signature_algorithm.oid.to_string().as_str() {
+            "1.2.840.113549.1.1.5" => "sha1",
+            "1.2.840.113549.1.1.11" => "sha256",
+            "1.2.840.113549.1.1.12" => "sha384",
+            "1.2.840.113549.1.1.13" => "sha512",
+            oid => return Err(format!("unsupported certificate signature algorithm {}", oid)),
+        };
+        let hashed = hash(hash_name, &tbs).expect("named above");
+        let signature = current.signature.raw_bytes();
+        let issuer = trusted
+            .iter()
+            .chain(certificates)
+            .filter(|c| c.tbs_certificate.subject == current.tbs_certificate.issuer)
+            .find(|c| rsa_verify(c, hash_name, &hashed, signature).is_ok())
+            .ok_or_else(|| format!("issuer {} of {} not found or its signature does not verify", current.tbs_certificate.issuer, current.tbs_certificate.subject))?;
+        if trusted.contains(issuer) {
+            path.push(issuer.tbs_certificate.subject.to_string());
+            return Ok(format!("trusted: {}", path.join(" <- ")));
+        }
+        if issuer == current {
+            return Err(format!("untrusted root {}", issuer.tbs_certificate.subject));
+        }
+        check_validity(issuer, at).map_err(|e| format!("{}: {}", issuer.tbs_certificate.subject, e))?;
+        path.push(issuer.tbs_certificate.subject.to_string());
+        current = issuer;
+    }
+    Err("certificate chain too long".to_string())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Method {
+    Inclusive,
+    Exclusive,
+}
+
+// Canonical XML 1.0 (and 1.1, which differs only in xml:* attributes) and Exclusive
+// Canonical XML, without comments, of a document or element subtree.
+struct Canonicalizer<'i> {
+    method: Method,
+    // InclusiveNamespaces PrefixList of exclusive canonicalization.
+    prefixes: Vec<String>,
+    // The signature itself, under the enveloped-signature transform.
+    exclude: Option<NodeId>,
+    input: &'i str,
+}
+
+impl Canonicalizer<'_> {
+    fn set_method(&mut self, algorithm: &str, node: Node<'_, '_>) -> Result<(), String> {
+        self.method = match algorithm {
+            C14N | C14N_COMMENTS | C14N_11 => Method::Inclusive,
+            EXC_C14N | EXC_C14N_COMMENTS => Method::Exclusive,
+            other => return Err(format!("unsupported transform {}", other)),
+        };
+        self.prefixes = node
+            .children()
+            .find(|n| n.is_element() && n.tag_name().name() == "InclusiveNamespaces")
+            .and_then(|n| n.attribute("PrefixList"))
+            .map(|list| list.split_whitespace().map(|p| if p == "#default" { String::new() } else { p.to_string() }).collect())
+            .unwrap_or_default();
+        Ok(())
+    }
+
+    fn canonicalize(&self, node: Node<'_, '_>) -> String {
+        let mut out = String::new();
+        if !node.is_root() {
+            self.element(node, &BTreeMap::new(), &mut out);
+            return out;
+        }
+        // Processing instructions around the document element go on their own lines.
+        let mut before_root = true;
+        for child in node.children() {
+            if child.is_element() {
+                self.element(child, &BTreeMap::new(), &mut out);
+                before_root = false;
+            } else if child.is_pi() {
+                if !before_root {
+                    out.push('\n');
+                }
+                push_pi(child, &mut out);
+                if before_root {
+                    out.push('\n');
+                }
+            }
+        }
+        out
+    }
+
+    // `rendered` is the namespaces already declared by output ancestors.
+    fn element(&self, node: Node<'_, '_>, rendered: &BTreeMap<String, String>, out: &mut String) {
+        if Some(node.id()) == self.exclude {
+            return;
+        }
+        let qname = element_qname(node, self.input);
+        let in_scope: BTreeMap<String, String> =
+            node.namespaces().filter(|ns| ns.name() != Some("xml")).map(|ns| (ns.name().unwrap_or_default().to_string(), ns.uri().to_string())).collect();
+
+        let candidates: Vec<String> = match self.method {
+            Method::Inclusive => in_scope.keys().cloned().chain(std::iter::once(String::new())).collect(),
+            Method::Exclusive => {
+                let mut used = vec![prefix(qname).to_string()];
+                for attribute in node.attributes() {
+                    let attribute_prefix = prefix(&self.input[attribute.range_qname()]);
+                    if !attribute_prefix.is_empty() && attribute_prefix != "xml" {
+                        used.push(attribute_prefix.to_string());
+                    }
+                }
+                used.extend(self.prefixes.iter().filter(|p| p.is_empty() || in_scope.contains_key(*p)).cloned());
+                used
+            }
+        };
+        let mut declarations = BTreeMap::new();
+        for prefix in candidates {
+            let uri = in_scope.get(&prefix).cloned().unwrap_or_default();
+            let declared = rendered.get(&prefix).cloned().unwrap_or_default();
+            // An empty default namespace only needs saying when an ancestor set one.
+            if uri != declared {
+                declarations.insert(prefix, uri);
+            }
+        }
+        let mut scope = rendered.clone();
+        scope.extend(declarations.iter().map(|(p, u)| (p.clone(), u.clone())));
+
+        out.push('<');
+        out.push_str(qname);
+        for (prefix, uri) in &declarations {
+            match prefix.as_str() {
+                "" => out.push_str(" xmlns=\""),
+                _ => {
+                    out.push_str(" xmlns:");
+                    out.push_str(prefix);
+                    out.push_str("=\"");
+                }
+            }
+            push_escaped(uri, true, out);
+            out.push('"');
+        }
+        let mut attributes: Vec<_> = node.attributes().collect();
+        attributes.sort_by(|a, b| (a.namespace().unwrap_or_default(), a.name()).cmp(&(b.namespace().unwrap_or_default(), b.name())));
+        for attribute in attributes {
+            out.push(' ');
+            out.push_str(&self.input[attribute.range_qname()]);
+            out.push_str("=\"");
+            push_escaped(attribute.value(), true, out);
+            out.push('"');
+        }
+        out.push('>');
+        for child in node.children() {
+            if child.is_element() {
+                self.element(child, &scope, out);
+            } else if child.is_text() {
+                push_escaped(child.text().unwrap_or_default(), false, out);
+            } else if child.is_pi() {
+                push_pi(child, out);
+            }
+        }
+        out.push_str("</");
+        out.push_str(qname);
+        out.push('>');
+    }
+}
+
+// roxmltree resolves names to namespaces; canonical XML keeps the prefixes as
+// written, so they are read back from the source.
+fn element_qname<'i>(node: Node<'_, '_>, input: &'i str) -> &'i str {
+    let start = node.range().start + 1;
+    let rest = &input[start..];
+    let end = rest.find(|c: char| c.is_whitespace() || c == '/' || c == '>').unwrap_or(rest.len());
+    &rest[..end]
+}
+
+fn prefix(qname: &str) -> &str {
+    qname.split_once(':').map(|(prefix, _)| prefix).unwrap_or_default()
+}
+
+fn push_pi(node: Node<'_, '_>, out: &mut String) {
+    if let Some(pi) = node.pi() {
+        out.push_str("<?");
+        out.push_str(pi.target);
+        if let Some(value) = pi.value.filter(|v| !v.is_empty()) {
+            out.push(' ');
+            out.push_str(value);
+        }
+        out.push_str("?>");
+    }
+}
+
+fn push_escaped(text: &str, attribute: bool, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' if !attribute => out.push_str("&gt;"),
+            '"' if attribute => out.push_str("&quot;"),
+            '\t' if attribute => out.push_str("&#x9;"),
+            '\n' if attribute => out.push_str("&#xA;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs8::DecodePrivateKey;
+    use rsa::RsaPrivateKey;
+    use x509_cert::der::DecodePem;
+
+    const KEY: &str = include_str!("../testdata/xades/signer.key");
+    const CERTIFICATE: &str = include_str!("../testdata/xades/signer.crt");
+
+    const DOCUMENT_REFERENCE: &str = r#"<ds:Reference URI=""><ds:Transforms><ds:Transform Algorithm="http://www.w3.org/2000/09/xmldsig#enveloped-signature"/><ds:Transform Algorithm="http://www.w3.org/TR/2001/REC-xml-c14n-20010315"/></ds:Transforms><ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/><ds:DigestValue></ds:DigestValue></ds:Reference>"#;
+    const PROPERTIES_REFERENCE: &str = r##"<ds:Reference Type="http://uri.etsi.org/01903#SignedProperties" URI="#props"><ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/><ds:DigestValue></ds:DigestValue></ds:Reference>"##;
+
+    // A tax invoice shaped like EXAT's, signed as XAdES-BES by the test certificate.
+    fn invoice(canonicalization: &str, references: &[&str]) -> String {
+        format!(
+            r##"<?xml version="1.0" encoding="UTF-8"?>
+<rsm:TaxInvoice_CrossIndustryInvoice xmlns:rsm="urn:etda:uncefact:data:standard:TaxInvoice_CrossIndustryInvoice:2" xmlns:ram="urn:etda:uncefact:data:standard:TaxInvoice_ReusableAggregateBusinessInformationEntity:2">
+  <rsm:ExchangedDocument><ram:ID>E1</ram:ID></rsm:ExchangedDocument>
+  <rsm:SupplyChainTradeTransaction><ram:GrandTotalAmount>45.00</ram:GrandTotalAmount></rsm:SupplyChainTradeTransaction>
+<ds:Signature xmlns:ds="http://www.w3.org/2000/09/xmldsig#" Id="sig">
+  <ds:SignedInfo>
+    {}
+    <ds:SignatureMethod Algorithm="http://www.w3.org/2001/04/xmldsig-more#rsa-sha256"/>
+    {}
+  </ds:SignedInfo>
+  <ds:SignatureValue></ds:SignatureValue>
+  <ds:KeyInfo><ds:X509Data><ds:X509Certificate>{{certificate}}</ds:X509Certificate></ds:X509Data></ds:KeyInfo>
+  <ds:Object>
+    <xades:QualifyingProperties xmlns:xades="http://uri.etsi.org/01903/v1.3.2#" Target="#sig">
+      <xades:SignedProperties Id="props">
+        <xades:SignedSignatureProperties>
+          <xades:SigningTime>2026-10-14T10:00:00+07:00</xades:SigningTime>
+          <xades:SigningCertificate><xades:Cert><xades:CertDigest>
+            <ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/>
+            <ds:DigestValue>{{cert-digest}}</ds:DigestValue>
+          </xades:CertDigest></xades:Cert></xades:SigningCertificate>
+        </xades:SignedSignatureProperties>
+      </xades:SignedProperties>
+    </xades:QualifyingProperties>
+  </ds:Object>
+</ds:Signature>
+</rsm:TaxInvoice_CrossIndustryInvoice>
+"##,
+            canonicalization,
+            references.join("\n    ")
+        )
+    }
+
+    fn inclusive() -> String {
+        format!(r#"<ds:CanonicalizationMethod Algorithm="{}"/>"#, C14N)
+    }
+
+    fn certificate() -> Certificate {
+        Certificate::from_pem(CERTIFICATE).unwrap()
+    }
+
+    fn base64(data: &[u8]) -> String {
+        base64::engine::general_purpose::STANDARD.encode(data)
+    }
+
+    // Fill in the certificate, the reference digests and the signature value.
+    fn sign(template: &str, cert_digest: &[u8]) -> String {
+        let der = certificate().to_der().unwrap();
+        let mut xml = template.replace("{certificate}", &base64(&der)).replace("{cert-digest}", &base64(cert_digest));
+
+        let digests: Vec<String> = {
+            let doc = Document::parse(&xml).unwrap();
+            let signature = doc.descendants().find(|n| is_dsig(n, "Signature")).unwrap();
+            let signed_info = child(signature, "SignedInfo").unwrap();
+            signed_info.children().filter(|n| is_dsig(n, "Reference")).map(|r| base64(&reference_digest(&doc, signature, r, &xml).unwrap())).collect()
+        };
+        for digest in digests {
+            xml = xml.replacen("<ds:DigestValue></ds:DigestValue>", &format!("<ds:DigestValue>{}</ds:DigestValue>", digest), 1);
+        }
+
+        let canonical = {
+            let doc = Document::parse(&xml).unwrap();
+            canonical_signed_info(doc.descendants().find(|n| is_dsig(n, "SignedInfo")).unwrap(), &xml).unwrap()
+        };
+        let key = RsaPrivateKey::from_pkcs8_pem(KEY).unwrap();
+        let value = key.sign(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(canonical.as_bytes())).unwrap();
+        xml.replace("<ds:SignatureValue></ds:SignatureValue>", &format!("<ds:SignatureValue>{}</ds:SignatureValue>", base64(&value)))
+    }
+
+    fn signed(template: &str) -> String {
+        sign(template, &Sha256::digest(certificate().to_der().unwrap()))
+    }
+
+    fn failed(report: &Report) -> Vec<&str> {
+        report.checks.iter().filter(|c| !c.ok).map(|c| c.name).collect()
+    }
+
+    #[test]
+    fn a_signed_invoice_verifies() {
+        let report = verify(&signed(&invoice(&inclusive(), &[DOCUMENT_REFERENCE, PROPERTIES_REFERENCE])), "E1.xml", &[certificate()]);
+        assert!(report.valid, "{:?}", report.failures());
+        assert_eq!(report.signer.as_deref(), Some("CN=e-Tax Test Signer,O=Example Expressway Co.,C=TH"));
+        assert_eq!(report.signing_time.as_deref(), Some("2026-10-14T10:00:00+07:00"));
+
+        let report = verify(&signed(&invoice(&inclusive(), &[DOCUMENT_REFERENCE, PROPERTIES_REFERENCE])), "E1.xml", &[]);
+        assert_eq!(failed(&report), ["chain"]);
+    }
+
+    #[test]
+    fn an_edited_body_breaks_the_document_digest() {
+        let xml = signed(&invoice(&inclusive(), &[DOCUMENT_REFERENCE, PROPERTIES_REFERENCE])).replace(">45.00<", ">4.50<");
+        let report = verify(&xml, "E1.xml", &[certificate()]);
+        assert!(!report.valid);
+        assert_eq!(failed(&report), ["reference"]);
+        assert!(report.failures()[0].contains("digest of the document does not match"));
+    }
+
+    #[test]
+    fn a_signing_certificate_digest_of_another_certificate_is_refused() {
+        let report = verify(&sign(&invoice(&inclusive(), &[DOCUMENT_REFERENCE, PROPERTIES_REFERENCE]), &Sha256::digest(b"another certificate")), "E1.xml", &[certificate()]);
+        assert!(!report.valid);
+        assert_eq!(failed(&report), ["signedProperties"]);
+    }
+
+    #[test]
+    fn signing_only_the_signed_properties_does_not_sign_the_invoice() {
+        let report = verify(&signed(&invoice(&inclusive(), &[PROPERTIES_REFERENCE])), "E1.xml", &[certificate()]);
+        assert!(!report.valid);
+        assert_eq!(failed(&report), ["document"]);
+    }
+
+    #[test]
+    fn an_id_used_twice_is_refused() {
+        let xml = signed(&invoice(&inclusive(), &[DOCUMENT_REFERENCE, PROPERTIES_REFERENCE]))
+            .replace("<rsm:ExchangedDocument>", r#"<rsm:ExchangedDocument><ram:Note Id="props"/>"#);
+        let report = verify(&xml, "E1.xml", &[certificate()]);
+        assert!(!report.valid);
+        assert!(report.failures().iter().any(|f| f.contains("#props is the Id of more than one element")), "{:?}", report.failures());
+    }
+
+    #[test]
+    fn exclusive_canonicalization_declares_namespaces_where_used_or_listed() {
+        let method = format!(r#"<ds:CanonicalizationMethod Algorithm="{}"><ec:InclusiveNamespaces xmlns:ec="{}" PrefixList="ram"/></ds:CanonicalizationMethod>"#, EXC_C14N, EXC_C14N);
+        let report = verify(&signed(&invoice(&method, &[DOCUMENT_REFERENCE, PROPERTIES_REFERENCE])), "E1.xml", &[certificate()]);
+        assert!(report.valid, "{:?}", report.failures());
+
+        let xml = r#"<root xmlns="urn:a" xmlns:ds="http://www.w3.org/2000/09/xmldsig#" xmlns:x="urn:x"><x:props b="2" Id="p" a="1"><ds:v x:attr="&amp;&lt;">t&gt;</ds:v><w/></x:props></root>"#;
+        let doc = Document::parse(xml).unwrap();
+        let props = doc.descendants().find(|n| n.tag_name().name() == "props").unwrap();
+        let canonical = |method, prefixes: &[&str]| Canonicalizer { method, prefixes: prefixes.iter().map(|p| p.to_string()).collect(), exclude: None, input: xml }.canonicalize(props);
+        assert_eq!(
+            canonical(Method::Inclusive, &[]),
+            r#"<x:props xmlns="urn:a" xmlns:ds="http://www.w3.org/2000/09/xmldsig#" xmlns:x="urn:x" Id="p" a="1" b="2"><ds:v x:attr="&amp;&lt;">t&gt;</ds:v><w></w></x:props>"#
+        );
+        assert_eq!(
+            canonical(Method::Exclusive, &[]),
+            r#"<x:props xmlns:x="urn:x" Id="p" a="1" b="2"><ds:v xmlns:ds="http://www.w3.org/2000/09/xmldsig#" x:attr="&amp;&lt;">t&gt;</ds:v><w xmlns="urn:a"></w></x:props>"#
+        );
+        assert_eq!(
+            canonical(Method::Exclusive, &["", "ds"]),
+            r#"<x:props xmlns="urn:a" xmlns:ds="http://www.w3.org/2000/09/xmldsig#" xmlns:x="urn:x" Id="p" a="1" b="2"><ds:v x:attr="&amp;&lt;">t&gt;</ds:v><w></w></x:props>"#
+        );
+    }
+}
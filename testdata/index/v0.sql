@@ -0,0 +1,27 @@
+-- An index written before the schema was versioned: user_version 0 and no
+-- document_references table.
+CREATE TABLE documents (
+    tax_id TEXT NOT NULL,
+    doc_no TEXT NOT NULL,
+    first_seen_at TEXT NOT NULL,
+    last_seen_at TEXT NOT NULL,
+    fetched_at TEXT,
+    archive_path TEXT,
+    file_path TEXT,
+    sha256 TEXT,
+    size INTEGER,
+    PRIMARY KEY (tax_id, doc_no)
+);
+CREATE TABLE document_versions (
+    tax_id TEXT NOT NULL,
+    doc_no TEXT NOT NULL,
+    observed_at TEXT NOT NULL,
+    doc_date TEXT,
+    doc_type TEXT,
+    amount REAL,
+    file_name TEXT,
+    item TEXT NOT NULL,
+    FOREIGN KEY (tax_id, doc_no) REFERENCES documents (tax_id, doc_no)
+);
+INSERT INTO documents VALUES ('0105551234567', 'INV-001', '2026-09-01T03:00:00.000000Z', '2026-09-01T03:00:00.000000Z', '2026-09-01T03:00:05.000000Z', 'exat_0105551234567_2026-09-01_2026-09-01.zip', NULL, NULL, NULL);
+INSERT INTO document_versions VALUES ('0105551234567', 'INV-001', '2026-09-01T03:00:00.000000Z', '2026-09-01', 'Tax invoice', 100.0, 'INV-001.pdf', '{"docNo":"INV-001","docDate":"2026-09-01 10:00:00","docType":"Tax invoice","fileName":"INV-001.pdf","totalAmount":"100.00"}');